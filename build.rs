@@ -0,0 +1,25 @@
+//! Embeds the git commit the server is built from as `GEMINI_MCP_GIT_SHA`.
+//! Builds outside a git checkout (e.g. from crates.io) can set the variable
+//! themselves; otherwise it is left unset and reported as `unknown`.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GEMINI_MCP_GIT_SHA");
+    if std::env::var_os("GEMINI_MCP_GIT_SHA").is_some() {
+        return;
+    }
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|sha| !sha.is_empty());
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=GEMINI_MCP_GIT_SHA={}", sha);
+    }
+}
@@ -0,0 +1,66 @@
+//! Minimal MCP client for gemini-mcp-rs.
+//!
+//! Starts the server as a child process, lists its tools, runs the
+//! `gemini_health` check and sends one prompt through the `gemini` tool:
+//!
+//! ```text
+//! cargo run --example client -- ./target/debug/gemini-mcp-rs "Reply with OK"
+//! ```
+//!
+//! `gemini-mcp-rs selftest` performs the same steps with pass/fail reporting.
+
+use anyhow::{Context, Result};
+use rmcp::model::CallToolRequestParam;
+use rmcp::ServiceExt;
+use std::process::Stdio;
+use tokio::process::Command;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let server = args.next().unwrap_or_else(|| "gemini-mcp-rs".to_string());
+    let prompt = args
+        .next()
+        .unwrap_or_else(|| "Reply with the single word OK.".to_string());
+
+    let mut child = Command::new(&server)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", server))?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    let stdin = child.stdin.take().context("no stdin")?;
+
+    // `()` is a client handler that ignores server notifications
+    let client = ().serve((stdout, stdin)).await?;
+    if let Some(info) = client.peer_info() {
+        println!(
+            "Connected to {} {}",
+            info.server_info.name, info.server_info.version
+        );
+    }
+
+    for tool in client.list_all_tools().await? {
+        println!("tool: {}", tool.name);
+    }
+
+    let health = client
+        .call_tool(CallToolRequestParam {
+            name: "gemini_health".into(),
+            arguments: None,
+        })
+        .await?;
+    println!("gemini_health: {:#?}", health.content);
+
+    let answer = client
+        .call_tool(CallToolRequestParam {
+            name: "gemini".into(),
+            arguments: serde_json::json!({ "PROMPT": prompt }).as_object().cloned(),
+        })
+        .await?;
+    println!("gemini: {:#?}", answer.content);
+
+    client.cancel().await?;
+    Ok(())
+}
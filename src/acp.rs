@@ -0,0 +1,788 @@
+//! Interactive sessions over the CLI's Agent Client Protocol (`interactive`
+//! in the server config).
+//!
+//! Every call normally starts a new CLI process, and follow-ups resume the
+//! session with `--resume`. In interactive mode a call without `SESSION_ID`
+//! instead starts the CLI with `--experimental-acp`, which speaks JSON-RPC
+//! over stdio, and keeps the process for follow-ups: a call whose
+//! `SESSION_ID` names a live process sends its prompt over stdin as another
+//! `session/prompt`. The process keeps the arguments it was started with.
+//! Processes idle for `idle_timeout_secs` are stopped, as is the least
+//! recently used one when more than `max_sessions` are running; follow-ups
+//! to a stopped session fall back to `--resume`.
+//!
+//! The server is the ACP client. It offers no file system access, so the
+//! CLI reads and writes files itself, and answers permission requests by the
+//! call's approval mode: `yolo` allows every tool, `auto_edit` only edits,
+//! anything else declines, as in headless runs.
+
+use crate::gemini::ApprovalMode;
+use crate::hooks::{self, StderrAbort, StderrHook};
+use crate::login::{AuthRequired, LoginDetector};
+use crate::pool::PoolKey;
+use crate::process_tree::{self, ProcessTree};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot};
+
+/// Flag starting the CLI as an ACP agent
+pub const ACP_FLAG: &str = "--experimental-acp";
+const PROTOCOL_VERSION: u64 = 1;
+/// Stderr lines kept to explain an unexpected exit
+const STDERR_TAIL_LINES: usize = 20;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The `interactive` section of the server config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InteractiveConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Most CLI processes kept running at once
+    pub max_sessions: Option<usize>,
+    /// How long a process may sit between turns before it is stopped
+    pub idle_timeout_secs: Option<u64>,
+}
+
+const DEFAULT_MAX_SESSIONS: usize = 8;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 900;
+
+/// Counters reported by the `gemini_stats` tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct InteractiveStats {
+    /// CLI processes currently running
+    pub sessions: usize,
+    pub max_sessions: usize,
+    pub idle_timeout_secs: u64,
+    /// Processes started
+    pub started: u64,
+    /// Prompts sent to a running process instead of a new one
+    pub follow_ups: u64,
+    /// Processes stopped for idling or to make room
+    pub stopped: u64,
+}
+
+/// The prompt being answered
+#[derive(Debug)]
+struct Turn {
+    updates: mpsc::UnboundedSender<Value>,
+    approval: Option<ApprovalMode>,
+}
+
+/// Why the process was stopped because of a stderr line
+#[derive(Debug, Clone)]
+enum Stop {
+    Login(AuthRequired),
+    Hook(StderrAbort),
+}
+
+/// State shared with the tasks reading the process's output
+#[derive(Debug)]
+struct Shared {
+    stdin: tokio::sync::Mutex<ChildStdin>,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+    turn: Mutex<Option<Turn>>,
+    stderr: Mutex<VecDeque<String>>,
+    stop: Mutex<Option<Stop>>,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    async fn send(&self, message: &Value) -> std::io::Result<()> {
+        let mut line = message.to_string();
+        line.push('\n');
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await
+    }
+
+    /// Send a request and wait for its result
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        if self.closed.load(Ordering::Acquire) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(self.exit_error());
+        }
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if let Err(err) = self.send(&request).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(err).with_context(|| format!("Failed to send {} to gemini", method));
+        }
+        match rx.await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(message)) => anyhow::bail!("{} failed: {}", method, message),
+            Err(_) => Err(self.exit_error()),
+        }
+    }
+
+    /// Route one message from the CLI
+    async fn receive(&self, message: Value) {
+        let method = message.get("method").and_then(Value::as_str);
+        match (method, message.get("id")) {
+            (Some(method), Some(id)) => {
+                let reply = match method {
+                    "session/request_permission" => {
+                        let approval = self.turn.lock().unwrap().as_ref().and_then(|t| t.approval);
+                        let params = message.get("params").unwrap_or(&Value::Null);
+                        json!({"jsonrpc": "2.0", "id": id, "result": permission_outcome(params, approval)})
+                    }
+                    _ => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": METHOD_NOT_FOUND, "message": format!("{} is not supported", method)}
+                    }),
+                };
+                if let Err(err) = self.send(&reply).await {
+                    tracing::warn!("failed to answer gemini's {} request: {}", method, err);
+                }
+            }
+            (Some("session/update"), None) => {
+                let update = message.pointer("/params/update").cloned();
+                if let (Some(turn), Some(update)) = (self.turn.lock().unwrap().as_ref(), update) {
+                    let _ = turn.updates.send(update);
+                }
+            }
+            (Some(_), None) => {}
+            (None, Some(id)) => {
+                let Some(tx) = id
+                    .as_u64()
+                    .and_then(|id| self.pending.lock().unwrap().remove(&id))
+                else {
+                    return;
+                };
+                let outcome = match message.get("error") {
+                    Some(error) => Err(error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .map_or_else(|| error.to_string(), str::to_string)),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = tx.send(outcome);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// The process is gone: fail every waiting request
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.pending.lock().unwrap().clear();
+        self.turn.lock().unwrap().take();
+    }
+
+    /// Error of a request the process did not answer: the stderr line it
+    /// was stopped for, or its exit
+    fn exit_error(&self) -> anyhow::Error {
+        match self.stop.lock().unwrap().clone() {
+            Some(Stop::Login(error)) => return error.into(),
+            Some(Stop::Hook(abort)) => return abort.into(),
+            None => {}
+        }
+        let stderr = self.stderr.lock().unwrap();
+        if stderr.is_empty() {
+            anyhow::anyhow!("the Gemini CLI exited")
+        } else {
+            anyhow::anyhow!(
+                "the Gemini CLI exited\nStderr: {}",
+                stderr.iter().cloned().collect::<Vec<_>>().join("\n")
+            )
+        }
+    }
+}
+
+/// Answer to a `session/request_permission` request under `approval`
+pub fn permission_outcome(params: &Value, approval: Option<ApprovalMode>) -> Value {
+    let kind = params.pointer("/toolCall/kind").and_then(Value::as_str);
+    let allow = match approval {
+        Some(ApprovalMode::Yolo) => true,
+        Some(ApprovalMode::AutoEdit) => kind == Some("edit"),
+        Some(ApprovalMode::Default) | None => false,
+    };
+    let wanted: &[&str] = if allow {
+        &["allow_once", "allow_always"]
+    } else {
+        &["reject_once", "reject_always"]
+    };
+    let option = params
+        .get("options")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .find(|option| {
+            option
+                .get("kind")
+                .and_then(Value::as_str)
+                .is_some_and(|kind| wanted.contains(&kind))
+        })
+        .and_then(|option| option.get("optionId"));
+    match option {
+        Some(id) => json!({"outcome": {"outcome": "selected", "optionId": id}}),
+        None => json!({"outcome": {"outcome": "cancelled"}}),
+    }
+}
+
+/// The `stream-json` event equivalent to a `session/update`, if any
+pub fn event_for(update: &Value) -> Option<Value> {
+    let text = |value: &Value| {
+        value
+            .pointer("/content/text")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    match update.get("sessionUpdate").and_then(Value::as_str)? {
+        "agent_message_chunk" => Some(json!({
+            "type": "message",
+            "role": "assistant",
+            "content": text(update)?,
+            "delta": true,
+        })),
+        "agent_thought_chunk" => Some(json!({"type": "thought", "content": text(update)?})),
+        "tool_call" => Some(json!({
+            "type": "tool_use",
+            "tool_name": update
+                .get("title")
+                .or_else(|| update.get("kind"))
+                .and_then(Value::as_str)
+                .unwrap_or("tool"),
+            "tool_id": update.get("toolCallId")?,
+            "parameters": update.get("rawInput").cloned().unwrap_or_else(|| json!({})),
+        })),
+        "tool_call_update" => {
+            let status = update.get("status").and_then(Value::as_str)?;
+            if status != "completed" && status != "failed" {
+                return None;
+            }
+            let output: Vec<&str> = update
+                .get("content")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item.pointer("/content/text").and_then(Value::as_str))
+                .collect();
+            let mut event = json!({
+                "type": "tool_result",
+                "tool_id": update.get("toolCallId")?,
+                "status": if status == "completed" { "success" } else { "error" },
+                "output": output.join("\n"),
+            });
+            if status == "failed" {
+                event["error"] = json!({"message": output.join("\n")});
+            }
+            Some(event)
+        }
+        _ => None,
+    }
+}
+
+/// One CLI process in ACP mode, hosting one session
+#[derive(Debug)]
+pub struct AcpSession {
+    session_id: String,
+    pid: Option<u32>,
+    /// Arguments and environment the CLI was started with
+    key: PoolKey,
+    shared: Arc<Shared>,
+    /// Dropped with the session, killing the process tree
+    _process: (Child, ProcessTree),
+    /// Held for the length of a turn, so prompts to one session queue up
+    turn: tokio::sync::Mutex<()>,
+    last_used: Mutex<Instant>,
+}
+
+impl AcpSession {
+    /// Start `cmd` (which must include [`ACP_FLAG`]) and open a session in
+    /// `cwd`. Must be called within a Tokio runtime.
+    ///
+    /// Like a one-shot run, the process is stopped when it asks to log in or
+    /// a stderr line matches one of `stderr_hooks`; the request waiting then,
+    /// whether the start or a prompt, fails with [`AuthRequired`] or
+    /// [`StderrAbort`].
+    pub async fn start(
+        mut cmd: Command,
+        cwd: &Path,
+        stderr_hooks: Vec<StderrHook>,
+    ) -> Result<Self> {
+        let key = PoolKey::of(&cmd);
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        process_tree::configure(&mut cmd);
+        let mut child = cmd
+            .spawn()
+            .context("Failed to spawn gemini in interactive mode")?;
+        let tree = ProcessTree::attach(&child);
+        let pid = child.id();
+        let stdin = child.stdin.take().context("Failed to get stdin")?;
+        let stdout = child.stdout.take().context("Failed to get stdout")?;
+        let stderr = child.stderr.take().context("Failed to get stderr")?;
+
+        let shared = Arc::new(Shared {
+            stdin: tokio::sync::Mutex::new(stdin),
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            turn: Mutex::new(None),
+            stderr: Mutex::new(VecDeque::new()),
+            stop: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        });
+        let reader = Arc::clone(&shared);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str(line.trim()) {
+                    Ok(message) => reader.receive(message).await,
+                    Err(_) if line.trim().is_empty() => {}
+                    Err(_) => tracing::debug!("non-JSON output from gemini: {}", line),
+                }
+            }
+            reader.close();
+        });
+        let reader = Arc::clone(&shared);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut login = LoginDetector::default();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let stop = login
+                    .feed(&line)
+                    .map(Stop::Login)
+                    .or_else(|| hooks::check(&stderr_hooks, &line).map(Stop::Hook));
+                {
+                    let mut tail = reader.stderr.lock().unwrap();
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line);
+                }
+                if let Some(stop) = stop {
+                    *reader.stop.lock().unwrap() = Some(stop);
+                    reader.close();
+                    break;
+                }
+            }
+        });
+
+        shared
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "clientCapabilities": {"fs": {"readTextFile": false, "writeTextFile": false}},
+                }),
+            )
+            .await?;
+        let created = shared
+            .request(
+                "session/new",
+                json!({"cwd": cwd.to_string_lossy(), "mcpServers": []}),
+            )
+            .await?;
+        let session_id = created
+            .get("sessionId")
+            .and_then(Value::as_str)
+            .filter(|id| !id.is_empty())
+            .context("session/new returned no sessionId")?
+            .to_string();
+        Ok(Self {
+            session_id,
+            pid,
+            key,
+            shared,
+            _process: (child, tree),
+            turn: tokio::sync::Mutex::new(()),
+            last_used: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    pub fn key(&self) -> &PoolKey {
+        &self.key
+    }
+
+    /// Whether the process is still running
+    pub fn is_alive(&self) -> bool {
+        !self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Time since the last turn ended, or zero during a turn
+    fn idle_for(&self) -> Duration {
+        if self.turn.try_lock().is_err() {
+            return Duration::ZERO;
+        }
+        self.last_used.lock().unwrap().elapsed()
+    }
+
+    /// Send `text` as the next prompt and wait until the turn ends. The
+    /// session's updates are sent to `updates` as they arrive. Returns the
+    /// stop reason (`end_turn`, `cancelled`, `refusal`, ...).
+    pub async fn prompt(
+        &self,
+        text: &str,
+        approval: Option<ApprovalMode>,
+        updates: mpsc::UnboundedSender<Value>,
+    ) -> Result<String> {
+        let _turn = self.turn.lock().await;
+        *self.shared.turn.lock().unwrap() = Some(Turn { updates, approval });
+        let result = self
+            .shared
+            .request(
+                "session/prompt",
+                json!({
+                    "sessionId": self.session_id,
+                    "prompt": [{"type": "text", "text": text}],
+                }),
+            )
+            .await;
+        self.shared.turn.lock().unwrap().take();
+        *self.last_used.lock().unwrap() = Instant::now();
+        Ok(result?
+            .get("stopReason")
+            .and_then(Value::as_str)
+            .unwrap_or("end_turn")
+            .to_string())
+    }
+
+    /// Ask the CLI to stop the current turn
+    pub async fn cancel(&self) {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "session/cancel",
+            "params": {"sessionId": self.session_id},
+        });
+        if let Err(err) = self.shared.send(&notification).await {
+            tracing::warn!(
+                "failed to cancel gemini session {}: {}",
+                self.session_id,
+                err
+            );
+        }
+    }
+}
+
+/// Running interactive sessions by session ID
+#[derive(Debug)]
+pub struct InteractiveSessions {
+    max_sessions: usize,
+    idle_timeout: Duration,
+    sessions: Mutex<HashMap<String, Arc<AcpSession>>>,
+    stats: Mutex<InteractiveStats>,
+    watching: AtomicBool,
+}
+
+impl InteractiveSessions {
+    pub fn new(config: &InteractiveConfig) -> Self {
+        Self {
+            max_sessions: config
+                .max_sessions
+                .filter(|&n| n > 0)
+                .unwrap_or(DEFAULT_MAX_SESSIONS),
+            idle_timeout: Duration::from_secs(
+                config
+                    .idle_timeout_secs
+                    .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+            ),
+            sessions: Mutex::new(HashMap::new()),
+            stats: Mutex::new(InteractiveStats::default()),
+            watching: AtomicBool::new(false),
+        }
+    }
+
+    /// The running session `session_id`, counted as a follow-up. A session
+    /// whose process was started with other arguments or environment than
+    /// `key` cannot take the prompt; it is stopped and an error returned.
+    pub fn get(&self, session_id: &str, key: &PoolKey) -> Result<Option<Arc<AcpSession>>> {
+        self.expire();
+        let Some(session) = self.sessions.lock().unwrap().get(session_id).cloned() else {
+            return Ok(None);
+        };
+        if session.key() != key {
+            self.remove(session_id);
+            anyhow::bail!(
+                "the interactive session was started with other CLI arguments or environment, so it was stopped and the session is resumed instead"
+            );
+        }
+        self.stats.lock().unwrap().follow_ups += 1;
+        Ok(Some(session))
+    }
+
+    /// Keep `session` for follow-ups, stopping the least recently used one
+    /// when full. Must be called within a Tokio runtime.
+    pub fn insert(&'static self, session: Arc<AcpSession>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut stopped = 0;
+        while sessions.len() >= self.max_sessions {
+            let oldest = sessions
+                .iter()
+                .max_by_key(|(_, session)| session.idle_for())
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => {
+                    sessions.remove(&id);
+                    stopped += 1;
+                }
+                None => break,
+            }
+        }
+        sessions.insert(session.session_id.clone(), session);
+        drop(sessions);
+        let mut stats = self.stats.lock().unwrap();
+        stats.started += 1;
+        stats.stopped += stopped;
+        drop(stats);
+
+        if !self.watching.swap(true, Ordering::AcqRel) {
+            let period = (self.idle_timeout / 2).max(Duration::from_secs(1));
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(period).await;
+                    self.expire();
+                }
+            });
+        }
+    }
+
+    /// Forget `session_id`, stopping its process once no turn uses it
+    pub fn remove(&self, session_id: &str) {
+        if self.sessions.lock().unwrap().remove(session_id).is_some() {
+            self.stats.lock().unwrap().stopped += 1;
+        }
+    }
+
+    /// Stop the processes that exited or idled for the idle timeout
+    pub fn expire(&self) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.is_alive() && session.idle_for() < self.idle_timeout);
+        let stopped = (before - sessions.len()) as u64;
+        drop(sessions);
+        self.stats.lock().unwrap().stopped += stopped;
+    }
+
+    /// Stop every process, e.g. on shutdown
+    pub fn clear(&self) {
+        self.sessions.lock().unwrap().clear();
+    }
+
+    pub fn stats(&self) -> InteractiveStats {
+        InteractiveStats {
+            sessions: self.sessions.lock().unwrap().len(),
+            max_sessions: self.max_sessions,
+            idle_timeout_secs: self.idle_timeout.as_secs(),
+            ..*self.stats.lock().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_follows_approval_mode() {
+        let request = |kind: &str| {
+            json!({
+                "sessionId": "s",
+                "toolCall": {"toolCallId": "t1", "kind": kind},
+                "options": [
+                    {"optionId": "yes", "kind": "allow_once"},
+                    {"optionId": "always", "kind": "allow_always"},
+                    {"optionId": "no", "kind": "reject_once"},
+                ],
+            })
+        };
+        let chosen = |params: &Value, mode| {
+            permission_outcome(params, mode)
+                .pointer("/outcome/optionId")
+                .cloned()
+        };
+        assert_eq!(
+            chosen(&request("execute"), Some(ApprovalMode::Yolo)),
+            Some(json!("yes"))
+        );
+        assert_eq!(
+            chosen(&request("edit"), Some(ApprovalMode::AutoEdit)),
+            Some(json!("yes"))
+        );
+        assert_eq!(
+            chosen(&request("execute"), Some(ApprovalMode::AutoEdit)),
+            Some(json!("no"))
+        );
+        assert_eq!(chosen(&request("edit"), None), Some(json!("no")));
+        assert_eq!(
+            permission_outcome(&json!({"options": []}), None),
+            json!({"outcome": {"outcome": "cancelled"}})
+        );
+    }
+
+    #[test]
+    fn test_updates_become_stream_events() {
+        let chunk = json!({
+            "sessionUpdate": "agent_message_chunk",
+            "content": {"type": "text", "text": "Hello"},
+        });
+        assert_eq!(
+            event_for(&chunk),
+            Some(
+                json!({"type": "message", "role": "assistant", "content": "Hello", "delta": true})
+            )
+        );
+        let call = json!({
+            "sessionUpdate": "tool_call",
+            "toolCallId": "t1",
+            "title": "ReadFile",
+            "kind": "read",
+            "status": "pending",
+        });
+        assert_eq!(event_for(&call).unwrap()["tool_name"], "ReadFile");
+        let done = json!({
+            "sessionUpdate": "tool_call_update",
+            "toolCallId": "t1",
+            "status": "failed",
+            "content": [{"type": "content", "content": {"type": "text", "text": "not found"}}],
+        });
+        let event = event_for(&done).unwrap();
+        assert_eq!(event["status"], "error");
+        assert_eq!(event["error"]["message"], "not found");
+        assert_eq!(
+            event_for(
+                &json!({"sessionUpdate": "tool_call_update", "toolCallId": "t1", "status": "in_progress"})
+            ),
+            None
+        );
+        assert_eq!(event_for(&json!({"sessionUpdate": "plan"})), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_follow_ups_reuse_the_process() {
+        const AGENT: &str = r#"
+read line; echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}'
+read line; echo '{"jsonrpc":"2.0","id":2,"result":{"sessionId":"acp-1"}}'
+n=3
+while read line; do
+  echo '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"acp-1","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"text","text":"turn '$n' pid '$$'"}}}}'
+  echo '{"jsonrpc":"2.0","id":'$n',"result":{"stopReason":"end_turn"}}'
+  n=$((n+1))
+done
+"#;
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", AGENT]);
+        let dir = tempfile::TempDir::new().unwrap();
+        let session = AcpSession::start(cmd, dir.path(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(session.session_id(), "acp-1");
+
+        let pid = session.pid().unwrap();
+        for n in [3, 4] {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let stop = session.prompt("hi", None, tx).await.unwrap();
+            assert_eq!(stop, "end_turn");
+            let update = rx.recv().await.unwrap();
+            assert_eq!(
+                update.pointer("/content/text").unwrap(),
+                &json!(format!("turn {} pid {}", n, pid))
+            );
+        }
+        assert!(session.is_alive());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_a_session_started_differently_is_stopped() {
+        const AGENT: &str = r#"
+read line; echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}'
+read line; echo '{"jsonrpc":"2.0","id":2,"result":{"sessionId":"acp-1"}}'
+while read line; do :; done
+"#;
+        let command = |profile: &str| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", AGENT]).env("GEMINI_API_KEY", profile);
+            cmd
+        };
+        let dir = tempfile::TempDir::new().unwrap();
+        let session = AcpSession::start(command("work"), dir.path(), Vec::new())
+            .await
+            .unwrap();
+        let sessions: &'static InteractiveSessions =
+            Box::leak(Box::new(InteractiveSessions::new(&InteractiveConfig {
+                enabled: true,
+                ..InteractiveConfig::default()
+            })));
+        sessions.insert(Arc::new(session));
+
+        let same = PoolKey::of(&command("work"));
+        assert!(sessions.get("acp-1", &same).unwrap().is_some());
+        let other = PoolKey::of(&command("personal"));
+        assert!(sessions.get("acp-1", &other).is_err());
+        assert!(sessions.get("acp-1", &same).unwrap().is_none());
+        let stats = sessions.stats();
+        assert_eq!((stats.follow_ups, stats.stopped), (1, 1));
+    }
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_a_login_prompt_stops_the_start() {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "echo 'Waiting for authentication...' >&2; read line; sleep 30",
+        ]);
+        let dir = tempfile::TempDir::new().unwrap();
+        let started = tokio::time::timeout(
+            Duration::from_secs(10),
+            AcpSession::start(cmd, dir.path(), Vec::new()),
+        )
+        .await
+        .unwrap();
+        assert!(started
+            .unwrap_err()
+            .downcast_ref::<AuthRequired>()
+            .is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_a_stderr_hook_stops_the_turn() {
+        const AGENT: &str = r#"
+read line; echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}'
+read line; echo '{"jsonrpc":"2.0","id":2,"result":{"sessionId":"acp-1"}}'
+read line; echo 'RESOURCE_EXHAUSTED: quota' >&2; sleep 30
+"#;
+        let hooks = vec![StderrHook {
+            pattern: "resource_exhausted".to_string(),
+            category: "quota".to_string(),
+            message: None,
+        }];
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", AGENT]);
+        let dir = tempfile::TempDir::new().unwrap();
+        let session = AcpSession::start(cmd, dir.path(), hooks).await.unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ended = tokio::time::timeout(Duration::from_secs(10), session.prompt("hi", None, tx))
+            .await
+            .unwrap();
+        let err = ended.unwrap_err();
+        assert_eq!(err.downcast_ref::<StderrAbort>().unwrap().category, "quota");
+        assert!(!session.is_alive());
+    }
+}
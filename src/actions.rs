@@ -0,0 +1,327 @@
+//! Action items extracted from an answer (`EXTRACT_ACTIONS`).
+//!
+//! Gemini's suggestions usually arrive as Markdown: diffs and shell snippets
+//! in fenced code blocks, "Run `cargo test`" sentences, checklists. They are
+//! turned into typed entries so callers can act on them without parsing the
+//! prose themselves.
+
+use serde::Serialize;
+
+/// Fence languages treated as shell commands
+const SHELL_LANGUAGES: &[&str] = &[
+    "bash",
+    "sh",
+    "shell",
+    "zsh",
+    "console",
+    "terminal",
+    "powershell",
+    "ps1",
+    "cmd",
+    "bat",
+];
+
+/// One suggested action, tagged by `type`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// A unified diff to apply
+    Patch {
+        /// Files the diff touches
+        files: Vec<String>,
+        content: String,
+    },
+    /// A shell command to run
+    Command {
+        command: String,
+        /// Why the command guard flagged it (`annotate` mode)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        blocked: Option<String>,
+        /// A variant that only shows what the command would do
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dry_run: Option<String>,
+    },
+    /// New contents for a file, from a code block introduced by its path
+    FileEdit {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        content: String,
+    },
+    /// A task left for the caller (`- [ ] …` or `TODO: …`)
+    Todo { text: String },
+}
+
+impl Action {
+    /// An unchecked shell command
+    pub fn command(command: impl Into<String>) -> Self {
+        Self::Command {
+            command: command.into(),
+            blocked: None,
+            dry_run: None,
+        }
+    }
+}
+
+/// Actions suggested in `answer`, in order of appearance
+pub fn extract(answer: &str) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut lines = answer.lines();
+    let mut previous = "";
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            let language = info.split_whitespace().next().map(str::to_ascii_lowercase);
+            let mut body = Vec::new();
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with("```") {
+                    break;
+                }
+                body.push(line);
+            }
+            actions.extend(from_block(language.as_deref(), &body, previous));
+            previous = "";
+            continue;
+        }
+
+        if let Some(text) = todo_text(trimmed) {
+            actions.push(Action::Todo {
+                text: text.to_string(),
+            });
+        } else {
+            actions.extend(inline_commands(trimmed).into_iter().map(Action::command));
+        }
+        if !trimmed.is_empty() {
+            previous = trimmed;
+        }
+    }
+    actions
+}
+
+/// Actions in a fenced code block; `intro` is the last text line before it
+fn from_block(language: Option<&str>, body: &[&str], intro: &str) -> Vec<Action> {
+    let content = body.join("\n");
+    if content.trim().is_empty() {
+        return Vec::new();
+    }
+    if language == Some("diff") || language == Some("patch") || is_diff(body) {
+        return vec![Action::Patch {
+            files: diff_files(body),
+            content,
+        }];
+    }
+    if language.is_some_and(|l| SHELL_LANGUAGES.contains(&l)) {
+        return shell_commands(body)
+            .into_iter()
+            .map(Action::command)
+            .collect();
+    }
+    match intro_path(intro) {
+        Some(path) => vec![Action::FileEdit {
+            path,
+            language: language.filter(|l| !l.is_empty()).map(str::to_string),
+            content,
+        }],
+        None => Vec::new(),
+    }
+}
+
+fn is_diff(body: &[&str]) -> bool {
+    body.iter().any(|line| line.starts_with("diff --git "))
+        || body
+            .windows(2)
+            .any(|pair| pair[0].starts_with("--- ") && pair[1].starts_with("+++ "))
+}
+
+/// Target files of a unified diff, from its `+++` lines
+fn diff_files(body: &[&str]) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in body {
+        let Some(path) = line.strip_prefix("+++ ") else {
+            continue;
+        };
+        let path = path.split('\t').next().unwrap_or(path).trim();
+        if path == "/dev/null" {
+            continue;
+        }
+        let path = path.strip_prefix("b/").unwrap_or(path).to_string();
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Commands of a shell block, without prompts, comments or output lines.
+/// Lines ending in `\` are joined with the next one.
+fn shell_commands(body: &[&str]) -> Vec<String> {
+    let has_prompts = body.iter().any(|line| line.trim_start().starts_with("$ "));
+    let mut commands = Vec::new();
+    let mut pending = String::new();
+    for line in body {
+        let mut line = line.trim();
+        if has_prompts && pending.is_empty() {
+            // In a transcript, lines without a prompt are output
+            match line.strip_prefix("$ ") {
+                Some(command) => line = command.trim(),
+                None => continue,
+            }
+        }
+        if pending.is_empty() && (line.is_empty() || line.starts_with('#')) {
+            continue;
+        }
+        match line.strip_suffix('\\') {
+            Some(head) => {
+                pending.push_str(head.trim_end());
+                pending.push(' ');
+            }
+            None => {
+                pending.push_str(line);
+                commands.push(std::mem::take(&mut pending));
+            }
+        }
+    }
+    if !pending.trim().is_empty() {
+        commands.push(pending.trim().to_string());
+    }
+    commands
+}
+
+/// Inline code following "run" or "execute", as in "Run `cargo test`"
+fn inline_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('`') {
+        let before = rest[..start].trim_end().to_ascii_lowercase();
+        let Some(len) = rest[start + 1..].find('`') else {
+            break;
+        };
+        let code = &rest[start + 1..start + 1 + len];
+        let verb = before
+            .rsplit(|c: char| !c.is_ascii_alphabetic())
+            .next()
+            .unwrap_or("");
+        if matches!(verb, "run" | "execute" | "then") && !code.trim().is_empty() {
+            commands.push(code.trim());
+        }
+        rest = &rest[start + len + 2..];
+    }
+    commands
+}
+
+/// Text of a checklist item or `TODO:` line
+fn todo_text(line: &str) -> Option<&str> {
+    let item = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .unwrap_or(line);
+    let text = item
+        .strip_prefix("[ ]")
+        .or_else(|| item.strip_prefix("TODO:"))
+        .or_else(|| item.strip_prefix("TODO "))?;
+    let text = text.trim();
+    (!text.is_empty()).then_some(text)
+}
+
+/// The file a code block belongs to, when the line before it names one, as
+/// in "Update `src/main.rs`:"
+pub(crate) fn intro_path(intro: &str) -> Option<String> {
+    let intro = intro.trim_end_matches(':').trim();
+    intro
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .chain(intro.split_whitespace().last())
+        .map(|token| token.trim_matches(|c: char| "*:,()".contains(c)))
+        .find(|token| crate::verify::looks_like_path(token))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_actions() {
+        let answer = "\
+Apply this patch:
+
+```diff
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1 +1 @@
+-old
++new
+```
+
+Then install and test:
+
+```bash
+# dependencies
+npm install \\
+  --save-dev jest
+```
+
+Replace `src/config.rs` with:
+
+```rust
+pub const LIMIT: u32 = 10;
+```
+
+```text
+just an example
+```
+
+Finally, run `cargo test --workspace` and check the `Cargo.toml` version.
+
+- [ ] Update the changelog
+- [x] Done already
+TODO: bump the version
+";
+        let actions = extract(answer);
+        assert_eq!(
+            actions[0],
+            Action::Patch {
+                files: vec!["src/lib.rs".to_string()],
+                content: "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n-old\n+new".to_string(),
+            }
+        );
+        assert_eq!(
+            &actions[1..],
+            &[
+                Action::command("npm install --save-dev jest"),
+                Action::FileEdit {
+                    path: "src/config.rs".to_string(),
+                    language: Some("rust".to_string()),
+                    content: "pub const LIMIT: u32 = 10;".to_string(),
+                },
+                Action::command("cargo test --workspace"),
+                Action::Todo {
+                    text: "Update the changelog".to_string()
+                },
+                Action::Todo {
+                    text: "bump the version".to_string()
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&actions[1]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"type": "command", "command": "npm install --save-dev jest"})
+        );
+    }
+
+    #[test]
+    fn test_shell_transcript_skips_output() {
+        let body = [
+            "$ cargo build",
+            "   Compiling x v0.1.0",
+            "$ ./target/debug/x",
+        ];
+        assert_eq!(
+            shell_commands(&body),
+            vec!["cargo build", "./target/debug/x"]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Summary of the CLI tools Gemini used during a run, built from its
+//! `tool_use` and `tool_result` events, so callers can see whether it edited
+//! files or ran shell commands without reading the raw events.
+
+use crate::event::{ToolResultEvent, ToolUseEvent};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Longest argument summary, in characters
+const MAX_ARGS_CHARS: usize = 200;
+/// Longest single argument value in a summary, in characters
+const MAX_VALUE_CHARS: usize = 80;
+
+/// One tool invocation
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ToolActivity {
+    pub tool: String,
+    /// Abbreviated arguments, e.g. `file_path=src/main.rs`
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub args: String,
+    /// `None` if the run ended before the tool reported a result
+    pub success: Option<bool>,
+    /// Time between the `tool_use` and `tool_result` events, when both carry
+    /// a timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Matches the result to its invocation
+    #[serde(skip)]
+    tool_id: String,
+    #[serde(skip)]
+    started_ms: Option<i64>,
+}
+
+impl ToolActivity {
+    pub fn started(event: &ToolUseEvent) -> Self {
+        Self {
+            tool: event.tool_name.clone(),
+            args: summarize_args(&event.parameters),
+            tool_id: event.tool_id.clone(),
+            started_ms: event.timestamp.as_deref().and_then(unix_millis),
+            ..Default::default()
+        }
+    }
+}
+
+/// Record the outcome of `event` on the invocation it answers and return
+/// that invocation. Results of unknown invocations are ignored.
+pub fn finish<'a>(
+    activity: &'a mut [ToolActivity],
+    event: &ToolResultEvent,
+) -> Option<&'a ToolActivity> {
+    let entry = activity
+        .iter_mut()
+        .rev()
+        .find(|entry| entry.tool_id == event.tool_id && entry.success.is_none())?;
+    let failed = event.error.is_some()
+        || event
+            .status
+            .as_deref()
+            .is_some_and(|status| !status.eq_ignore_ascii_case("success"));
+    entry.success = Some(!failed);
+    if failed {
+        entry.error = event
+            .error
+            .as_ref()
+            .and_then(|error| error.message.clone())
+            .or_else(|| event.status.clone());
+    }
+    let finished_ms = event.timestamp.as_deref().and_then(unix_millis);
+    if let (Some(start), Some(end)) = (entry.started_ms, finished_ms) {
+        entry.duration_ms = u64::try_from(end - start).ok();
+    }
+    Some(entry)
+}
+
+/// `key=value` pairs of the scalar arguments, each value shortened
+fn summarize_args(parameters: &Value) -> String {
+    let summary = match parameters {
+        Value::Null => String::new(),
+        Value::Object(map) => {
+            let mut args: Vec<_> = map.iter().collect();
+            args.sort_by_key(|(key, _)| key.as_str());
+            args.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(text) => text.clone(),
+                        Value::Array(items) => format!("[{} items]", items.len()),
+                        Value::Object(_) => "{…}".to_string(),
+                        other => other.to_string(),
+                    };
+                    format!("{}={}", key, shorten(value.trim(), MAX_VALUE_CHARS))
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        other => other.to_string(),
+    };
+    shorten(&summary, MAX_ARGS_CHARS)
+}
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    let first_line = text.lines().next().unwrap_or_default();
+    if first_line.chars().count() <= max_chars && first_line.len() == text.len() {
+        return text.to_string();
+    }
+    let mut short: String = first_line.chars().take(max_chars - 1).collect();
+    short.push('…');
+    short
+}
+
+/// Milliseconds since the Unix epoch of an RFC 3339 timestamp such as
+/// `2025-10-10T12:00:00.123Z` or `2025-10-10T14:00:00+02:00`
+fn unix_millis(timestamp: &str) -> Option<i64> {
+    let (date, time) = timestamp.trim().split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+        date_parts.next()?.ok()?,
+    );
+
+    let (clock, offset_minutes) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => {
+            let (clock, zone) = time.split_at(at);
+            let offset = match zone {
+                "Z" | "z" => 0,
+                _ => {
+                    let sign = if zone.starts_with('-') { -1 } else { 1 };
+                    let (hours, minutes) = zone[1..].split_once(':')?;
+                    sign * (hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?)
+                }
+            };
+            (clock, offset)
+        }
+        None => (time, 0),
+    };
+    let (hms, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut hms = hms.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (hms.next()?.ok()?, hms.next()?.ok()?, hms.next()?.ok()?);
+    let millis = match fraction {
+        "" => 0,
+        digits if !digits.bytes().all(|b| b.is_ascii_digit()) => return None,
+        digits => format!("{:0<3}", &digits[..digits.len().min(3)])
+            .parse::<i64>()
+            .ok()?,
+    };
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second - offset_minutes * 60;
+    Some(seconds * 1_000 + millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool_use(value: Value) -> ToolUseEvent {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn tool_result(value: Value) -> ToolResultEvent {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_results_are_matched_to_invocations() {
+        let mut activity = vec![
+            ToolActivity::started(&tool_use(json!({
+                "tool_name": "write_file", "tool_id": "t1",
+                "parameters": {"file_path": "src/lib.rs", "content": "fn main() {}\nmore"},
+                "timestamp": "2025-10-10T12:00:00.250Z"
+            }))),
+            ToolActivity::started(&tool_use(json!({
+                "tool_name": "run_shell_command", "tool_id": "t2",
+                "parameters": {"command": "cargo test"}
+            }))),
+            ToolActivity::started(&tool_use(
+                json!({"tool_name": "read_file", "tool_id": "t3"}),
+            )),
+        ];
+        finish(
+            &mut activity,
+            &tool_result(json!({
+                "tool_id": "t1", "status": "success", "timestamp": "2025-10-10T14:00:01.5+02:00"
+            })),
+        );
+        finish(
+            &mut activity,
+            &tool_result(json!({
+                "tool_id": "t2", "status": "error", "error": {"message": "exit code 101"}
+            })),
+        );
+        finish(&mut activity, &tool_result(json!({"tool_id": "unknown"})));
+
+        assert_eq!(activity[0].tool, "write_file");
+        assert_eq!(
+            activity[0].args,
+            "content=fn main() {}… file_path=src/lib.rs"
+        );
+        assert_eq!(activity[0].success, Some(true));
+        assert_eq!(activity[0].duration_ms, Some(1_250));
+        assert_eq!(activity[1].success, Some(false));
+        assert_eq!(activity[1].error.as_deref(), Some("exit code 101"));
+        assert_eq!(activity[1].duration_ms, None);
+        assert_eq!(activity[2].success, None);
+        assert_eq!(activity[2].args, "");
+    }
+
+    #[test]
+    fn test_unix_millis() {
+        assert_eq!(unix_millis("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            unix_millis("2024-02-29T23:59:59.999Z"),
+            Some(1_709_251_199_999)
+        );
+        assert_eq!(
+            unix_millis("2024-03-01T01:00:00+01:00"),
+            Some(1_709_251_200_000)
+        );
+        assert_eq!(unix_millis("yesterday"), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Code blocks of an answer as files (`EXTRACT_ARTIFACTS`).
+//!
+//! Every fenced code block becomes an artifact with its language, content and
+//! a suggested filename: the path named on the line before the block, as in
+//! "Create `src/parser.rs`:", else `artifact-<n>` with an extension for the
+//! language. With `WRITE_ARTIFACTS` the artifacts are also saved under the
+//! configured `artifacts_dir`, one subdirectory per session.
+
+use crate::actions;
+use crate::policy::Policy;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Component, Path};
+
+/// File extensions of common fence languages
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rust", "rs"),
+    ("rs", "rs"),
+    ("python", "py"),
+    ("py", "py"),
+    ("javascript", "js"),
+    ("js", "js"),
+    ("jsx", "jsx"),
+    ("typescript", "ts"),
+    ("ts", "ts"),
+    ("tsx", "tsx"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kotlin", "kt"),
+    ("c", "c"),
+    ("cpp", "cpp"),
+    ("c++", "cpp"),
+    ("csharp", "cs"),
+    ("cs", "cs"),
+    ("ruby", "rb"),
+    ("php", "php"),
+    ("swift", "swift"),
+    ("bash", "sh"),
+    ("sh", "sh"),
+    ("shell", "sh"),
+    ("zsh", "sh"),
+    ("powershell", "ps1"),
+    ("json", "json"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("toml", "toml"),
+    ("xml", "xml"),
+    ("html", "html"),
+    ("css", "css"),
+    ("sql", "sql"),
+    ("markdown", "md"),
+    ("md", "md"),
+    ("diff", "diff"),
+    ("patch", "diff"),
+];
+
+/// One fenced code block of an answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Artifact {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    pub content: String,
+    /// Relative path to save the block as
+    pub filename: String,
+    /// Where the block was written, with `WRITE_ARTIFACTS`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// Non-empty code blocks of `answer`, in order of appearance
+pub fn extract(answer: &str) -> Vec<Artifact> {
+    let mut artifacts: Vec<Artifact> = Vec::new();
+    let mut lines = answer.lines();
+    let mut previous = "";
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        let Some(info) = trimmed.strip_prefix("```") else {
+            if !trimmed.is_empty() {
+                previous = trimmed;
+            }
+            continue;
+        };
+        let language = info
+            .split_whitespace()
+            .next()
+            .map(str::to_ascii_lowercase)
+            .filter(|l| !l.is_empty());
+        let mut body = Vec::new();
+        for line in lines.by_ref() {
+            if line.trim_start().starts_with("```") {
+                break;
+            }
+            body.push(line);
+        }
+        let content = body.join("\n");
+        if !content.trim().is_empty() {
+            let filename = actions::intro_path(previous)
+                .filter(|path| is_relative(path))
+                .unwrap_or_else(|| {
+                    format!(
+                        "artifact-{}.{}",
+                        artifacts.len() + 1,
+                        extension(language.as_deref())
+                    )
+                });
+            let filename = unique(&artifacts, filename);
+            artifacts.push(Artifact {
+                language,
+                content,
+                filename,
+                path: None,
+            });
+        }
+        previous = "";
+    }
+    artifacts
+}
+
+/// Write every artifact under `dir` and record where it went. Nothing is
+/// written when `policy` does not allow one of the paths.
+pub fn write_all(dir: &Path, artifacts: &mut [Artifact], policy: &Policy) -> Result<()> {
+    for artifact in artifacts.iter() {
+        policy.check(&dir.join(&artifact.filename))?;
+    }
+    for artifact in artifacts {
+        let path = dir.join(&artifact.filename);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut content = artifact.content.clone();
+        content.push('\n');
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        artifact.path = Some(path.display().to_string());
+    }
+    Ok(())
+}
+
+fn extension(language: Option<&str>) -> &'static str {
+    language
+        .and_then(|language| EXTENSIONS.iter().find(|(name, _)| *name == language))
+        .map_or("txt", |(_, extension)| extension)
+}
+
+/// Paths that stay below the directory they are joined to
+fn is_relative(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// `filename`, numbered if an earlier artifact already uses it
+fn unique(artifacts: &[Artifact], filename: String) -> String {
+    let taken = |name: &str| artifacts.iter().any(|a| a.filename == name);
+    if !taken(&filename) {
+        return filename;
+    }
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (filename.as_str(), String::new()),
+    };
+    (2..)
+        .map(|n| format!("{}-{}{}", stem, n, extension))
+        .find(|name| !taken(name))
+        .unwrap_or(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "\
+Create `src/parser.rs`:
+
+```rust
+pub fn parse() {}
+```
+
+And a helper script:
+
+```bash
+cargo test
+```
+
+```
+plain
+```
+
+Update src/parser.rs:
+
+```rust
+pub fn parse() -> bool { true }
+```
+
+```python
+```
+";
+
+    #[test]
+    fn test_blocks_become_artifacts() {
+        let artifacts = extract(ANSWER);
+        let names: Vec<_> = artifacts.iter().map(|a| a.filename.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "src/parser.rs",
+                "artifact-2.sh",
+                "artifact-3.txt",
+                "src/parser-2.rs"
+            ]
+        );
+        assert_eq!(artifacts[0].language.as_deref(), Some("rust"));
+        assert_eq!(artifacts[0].content, "pub fn parse() {}");
+        assert_eq!(artifacts[2].language, None);
+        assert!(extract("no code here").is_empty());
+    }
+
+    #[test]
+    fn test_write_all() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut artifacts = extract("Add `../escape.rs`:\n\n```rust\nfn a() {}\n```\n");
+        assert_eq!(artifacts[0].filename, "artifact-1.rs");
+        let policy = Policy::new(&[dir.path().to_path_buf()], &[]);
+        write_all(dir.path(), &mut artifacts, &policy).unwrap();
+        let written = dir.path().join("artifact-1.rs");
+        assert_eq!(std::fs::read_to_string(&written).unwrap(), "fn a() {}\n");
+        assert_eq!(
+            artifacts[0].path.as_deref(),
+            Some(written.display().to_string().as_str())
+        );
+
+        let mut secrets = extract("Create `deploy/secrets.yml`:\n\n```yaml\ntoken: x\n```\n");
+        assert_eq!(secrets[0].filename, "deploy/secrets.yml");
+        let denied = Policy::new(&[dir.path().to_path_buf()], &["secrets.*".to_string()]);
+        let err = write_all(dir.path(), &mut secrets, &denied).unwrap_err();
+        assert!(err.to_string().starts_with("Policy violation: "));
+        assert!(!dir.path().join("deploy").exists());
+    }
+}
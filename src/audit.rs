@@ -0,0 +1,222 @@
+//! Append-only audit log of every Gemini CLI run (`audit`).
+//!
+//! Each run that reaches the CLI, whichever tool started it, appends one JSON
+//! line: when it ran, which MCP client asked for it, the prompt (or only its
+//! SHA-256), the session, the model, how it ended, how long it took and the
+//! build that ran it. The file is opened in append mode and never rewritten,
+//! so it can be shipped or rotated by the usual log tooling.
+
+use crate::build_info::{self, BuildInfo};
+use crate::gemini::AuditPrompt;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The MCP client that made a call, as it introduced itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Client {
+    pub name: String,
+    pub version: String,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    /// The CLI ran but reported a failure
+    Failed,
+    /// The run hit its timeout
+    Timeout,
+    /// The CLI could not be run, or the run was stopped
+    Error,
+}
+
+/// One run, as handed to [`AuditLog::record`]
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    pub at: SystemTime,
+    pub client: Option<Client>,
+    pub prompt: &'a str,
+    /// Empty when the CLI failed before reporting a session
+    pub session_id: &'a str,
+    pub model: Option<&'a str>,
+    pub status: Status,
+    pub error: Option<&'a str>,
+    pub duration: Duration,
+}
+
+/// One line of the log
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<&'a Client>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt_sha256: Option<String>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    session_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    duration_ms: u64,
+    metadata: BuildInfo,
+}
+
+/// The open audit log file
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    prompt: AuditPrompt,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open (creating it and its directory if needed) the log at `path`
+    pub fn open(path: &Path, prompt: AuditPrompt) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            prompt,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append `entry` as one line
+    pub fn record(&self, entry: &Entry<'_>) -> Result<()> {
+        let (prompt, prompt_sha256) = match self.prompt {
+            AuditPrompt::Full => (Some(entry.prompt), None),
+            AuditPrompt::Hash => (None, Some(sha256_hex(entry.prompt))),
+        };
+        let record = Record {
+            timestamp: timestamp(entry.at),
+            client: entry.client.as_ref(),
+            prompt,
+            prompt_sha256,
+            session_id: entry.session_id,
+            model: entry.model,
+            status: entry.status,
+            error: entry.error,
+            duration_ms: entry.duration.as_millis() as u64,
+            metadata: build_info::current(),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        // One write per line, so concurrent runs never interleave
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .with_context(|| format!("Failed to write audit log {}", self.path.display()))
+    }
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `at` in UTC, RFC 3339 with milliseconds
+fn timestamp(at: SystemTime) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        crate::placeholders::date(at),
+        secs / 3_600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(prompt: &str) -> Entry<'_> {
+        Entry {
+            at: UNIX_EPOCH + Duration::from_millis(1_709_208_123_456),
+            client: Some(Client {
+                name: "claude-code".to_string(),
+                version: "2.0.1".to_string(),
+            }),
+            prompt,
+            session_id: "abc",
+            model: Some("gemini-2.5-pro"),
+            status: Status::Success,
+            error: None,
+            duration: Duration::from_millis(1_500),
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_records_are_appended() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("logs/audit.jsonl");
+        let log = AuditLog::open(&path, AuditPrompt::Hash).unwrap();
+        log.record(&entry("secret code")).unwrap();
+        let failed = Entry {
+            client: None,
+            session_id: "",
+            model: None,
+            status: Status::Error,
+            error: Some("spawn failed"),
+            ..entry("x")
+        };
+        AuditLog::open(&path, AuditPrompt::Full)
+            .unwrap()
+            .record(&failed)
+            .unwrap();
+
+        let records = lines(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timestamp"], "2024-02-29T12:02:03.456Z");
+        assert_eq!(records[0]["client"]["name"], "claude-code");
+        assert_eq!(records[0]["prompt_sha256"], sha256_hex("secret code"));
+        assert!(records[0].get("prompt").is_none());
+        assert_eq!(records[0]["status"], "success");
+        assert_eq!(records[0]["duration_ms"], 1_500);
+        assert_eq!(
+            records[0]["metadata"]["server_version"],
+            build_info::SERVER_VERSION
+        );
+        assert_eq!(records[1]["prompt"], "x");
+        assert_eq!(records[1]["status"], "error");
+        assert!(records[1].get("session_id").is_none());
+    }
+
+    #[test]
+    fn test_prompt_hash() {
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
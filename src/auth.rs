@@ -0,0 +1,243 @@
+//! Named authentication profiles selected with `AUTH_PROFILE`.
+//!
+//! The Gemini CLI picks its credentials from environment variables: a Gemini
+//! API key, Vertex AI settings, or a cached Google login. A profile sets the
+//! variables of one of these methods and clears those of the others, so a
+//! single server can run calls as different accounts, e.g. a personal API
+//! key and a corporate Vertex AI project.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Variables the CLI reads to choose and perform authentication. They are
+/// removed from the CLI's environment whenever a profile is in use, so the
+/// server's own credentials cannot leak into another profile's calls.
+pub const AUTH_VARS: &[&str] = &[
+    "GEMINI_API_KEY",
+    "GOOGLE_API_KEY",
+    "GOOGLE_GENAI_USE_VERTEXAI",
+    "GOOGLE_GENAI_USE_GCA",
+    "GOOGLE_APPLICATION_CREDENTIALS",
+    "GOOGLE_CLOUD_PROJECT",
+    "GOOGLE_CLOUD_LOCATION",
+];
+
+/// One way of authenticating the CLI, configured under `auth_profiles`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AuthProfile {
+    /// Gemini API key (`GEMINI_API_KEY`)
+    ApiKey {
+        /// The key itself; prefer `api_key_env` to keep it out of the config
+        api_key: Option<String>,
+        /// Server environment variable holding the key
+        api_key_env: Option<String>,
+    },
+    /// Vertex AI in a Google Cloud project
+    Vertex {
+        project: String,
+        location: String,
+        /// Service account key file (`GOOGLE_APPLICATION_CREDENTIALS`);
+        /// application default credentials otherwise
+        credentials_file: Option<PathBuf>,
+        /// Vertex AI express mode key (`GOOGLE_API_KEY`)
+        api_key: Option<String>,
+        api_key_env: Option<String>,
+    },
+    /// Login with Google, using the credentials cached by `gemini` in
+    /// `<home>/.gemini`
+    Oauth {
+        /// Home directory of this account's cached login; the server's own
+        /// otherwise. Log in once with `HOME=<home> gemini`.
+        home: Option<PathBuf>,
+        /// Google Cloud project of a Gemini Code Assist Standard or
+        /// Enterprise licence
+        project: Option<String>,
+    },
+}
+
+impl AuthProfile {
+    /// Environment variables that select this profile in the CLI
+    pub fn env(&self) -> Result<BTreeMap<String, String>> {
+        let mut env = BTreeMap::new();
+        let mut set = |key: &str, value: String| {
+            env.insert(key.to_string(), value);
+        };
+        match self {
+            Self::ApiKey {
+                api_key,
+                api_key_env,
+            } => {
+                let key = secret(api_key, api_key_env)?
+                    .context("api_key profile needs `api_key` or `api_key_env`")?;
+                set("GEMINI_API_KEY", key);
+            }
+            Self::Vertex {
+                project,
+                location,
+                credentials_file,
+                api_key,
+                api_key_env,
+            } => {
+                set("GOOGLE_GENAI_USE_VERTEXAI", "true".to_string());
+                set("GOOGLE_CLOUD_PROJECT", project.clone());
+                set("GOOGLE_CLOUD_LOCATION", location.clone());
+                if let Some(file) = credentials_file {
+                    set("GOOGLE_APPLICATION_CREDENTIALS", file.display().to_string());
+                }
+                if let Some(key) = secret(api_key, api_key_env)? {
+                    set("GOOGLE_API_KEY", key);
+                }
+            }
+            Self::Oauth { home, project } => {
+                set("GOOGLE_GENAI_USE_GCA", "true".to_string());
+                if let Some(project) = project {
+                    set("GOOGLE_CLOUD_PROJECT", project.clone());
+                }
+                if let Some(home) = home {
+                    set("HOME", home.display().to_string());
+                    set("USERPROFILE", home.display().to_string());
+                }
+            }
+        }
+        Ok(env)
+    }
+}
+
+/// The key given inline or read from the server's environment
+fn secret(value: &Option<String>, env_var: &Option<String>) -> Result<Option<String>> {
+    if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        return Ok(Some(value.to_string()));
+    }
+    let Some(var) = env_var else {
+        return Ok(None);
+    };
+    match std::env::var(var) {
+        Ok(value) if !value.trim().is_empty() => Ok(Some(value.trim().to_string())),
+        _ => bail!("environment variable `{}` is not set", var),
+    }
+}
+
+/// Profile `name`, or an error listing the configured ones
+pub fn resolve<'a>(
+    name: &str,
+    configured: &'a BTreeMap<String, AuthProfile>,
+) -> Result<&'a AuthProfile> {
+    let name = name.trim();
+    if let Some(profile) = configured.get(name) {
+        return Ok(profile);
+    }
+    if configured.is_empty() {
+        bail!(
+            "Unknown AUTH_PROFILE `{}`; no auth_profiles are configured",
+            name
+        );
+    }
+    bail!(
+        "Unknown AUTH_PROFILE `{}`; available: {}",
+        name,
+        configured.keys().cloned().collect::<Vec<_>>().join(", ")
+    )
+}
+
+/// Reject profiles with unusable names or missing settings, and a default
+/// that names no profile
+pub fn validate(configured: &BTreeMap<String, AuthProfile>, default: Option<&str>) -> Result<()> {
+    for (name, profile) in configured {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+        if name.is_empty() || name.len() > MAX_NAME_LEN || !name.chars().all(valid_char) {
+            bail!(
+                "auth profile name `{}` must be 1-{} characters of letters, digits, `-`, `_` or `.`",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        match profile {
+            AuthProfile::ApiKey {
+                api_key,
+                api_key_env,
+            } if api_key.is_none() && api_key_env.is_none() => {
+                bail!("auth profile `{}` needs `api_key` or `api_key_env`", name)
+            }
+            AuthProfile::Vertex {
+                project, location, ..
+            } if project.trim().is_empty() || location.trim().is_empty() => {
+                bail!("auth profile `{}` needs `project` and `location`", name)
+            }
+            _ => {}
+        }
+    }
+    if let Some(default) = default {
+        resolve(default, configured).context("Invalid default_auth_profile")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles(json: &str) -> BTreeMap<String, AuthProfile> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_profiles_translate_to_cli_environment() {
+        let configured = profiles(
+            r#"{
+                "personal": {"type": "api_key", "api_key": "AIza-personal"},
+                "work": {"type": "vertex", "project": "acme", "location": "europe-west4",
+                         "credentials_file": "/etc/acme/sa.json"},
+                "login": {"type": "oauth", "home": "/srv/gemini-work"}
+            }"#,
+        );
+
+        let env = resolve("personal", &configured).unwrap().env().unwrap();
+        assert_eq!(env.len(), 1);
+        assert_eq!(env["GEMINI_API_KEY"], "AIza-personal");
+
+        let env = resolve(" work ", &configured).unwrap().env().unwrap();
+        assert_eq!(env["GOOGLE_GENAI_USE_VERTEXAI"], "true");
+        assert_eq!(env["GOOGLE_CLOUD_PROJECT"], "acme");
+        assert_eq!(env["GOOGLE_CLOUD_LOCATION"], "europe-west4");
+        assert_eq!(env["GOOGLE_APPLICATION_CREDENTIALS"], "/etc/acme/sa.json");
+        assert!(!env.contains_key("GOOGLE_API_KEY"));
+
+        let env = resolve("login", &configured).unwrap().env().unwrap();
+        assert_eq!(env["GOOGLE_GENAI_USE_GCA"], "true");
+        assert_eq!(env["HOME"], "/srv/gemini-work");
+
+        let err = resolve("corp", &configured).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown AUTH_PROFILE `corp`; available: login, personal, work"
+        );
+    }
+
+    #[test]
+    fn test_missing_key_variable_is_an_error() {
+        let configured =
+            profiles(r#"{"ci": {"type": "api_key", "api_key_env": "GEMINI_MCP_TEST_UNSET_KEY"}}"#);
+        let err = configured["ci"].env().unwrap_err().to_string();
+        assert!(err.contains("GEMINI_MCP_TEST_UNSET_KEY"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate() {
+        let configured =
+            profiles(r#"{"work": {"type": "vertex", "project": "acme", "location": " "}}"#);
+        assert!(validate(&configured, None).is_err());
+
+        let configured = profiles(r#"{"empty": {"type": "api_key"}}"#);
+        assert!(validate(&configured, None).is_err());
+
+        let configured = profiles(r#"{"login": {"type": "oauth"}}"#);
+        assert!(validate(&configured, Some("login")).is_ok());
+        assert!(validate(&configured, Some("work")).is_err());
+        assert!(serde_json::from_str::<AuthProfile>(r#"{"type": "oauth", "hme": "/x"}"#).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Offline batch mode (`gemini-mcp-rs batch`): run a JSONL file of prompts
+//! through the same pipeline as the `gemini` MCP tool.
+//!
+//! Each input line is a `gemini` tool call (`{"PROMPT": ..., "SESSION_ID": ...,
+//! "EXTRA_ARGS": [...]}`) with an optional `id` that is echoed back. Results are
+//! written as JSONL in input order, whatever order the calls finish in.
+
+use crate::event::Usage;
+use crate::server::{Executed, GeminiArgs, GeminiServer};
+use crate::verify::VerificationReport;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+/// One line of batch input
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    /// Caller-provided identifier, echoed in the matching output line
+    #[serde(default)]
+    id: Option<Value>,
+    #[serde(flatten)]
+    args: GeminiArgs,
+}
+
+/// One line of batch output
+#[derive(Debug, Serialize)]
+struct BatchRecord {
+    /// 1-based line number in the input file
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+    success: bool,
+    session_id: String,
+    message: String,
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verification: Option<VerificationReport>,
+}
+
+impl BatchRecord {
+    fn from_result(line: usize, id: Option<Value>, executed: Executed) -> Self {
+        let result = executed.result;
+        Self {
+            line,
+            id,
+            success: result.success,
+            session_id: result.session_id,
+            message: result.agent_messages,
+            error: result.error,
+            usage: result.usage,
+            warnings: result.warnings,
+            verification: executed.verification,
+        }
+    }
+
+    fn failed(line: usize, id: Option<Value>, error: String) -> Self {
+        Self {
+            line,
+            id,
+            success: false,
+            session_id: String::new(),
+            message: String::new(),
+            error: Some(error),
+            usage: None,
+            warnings: Vec::new(),
+            verification: None,
+        }
+    }
+}
+
+/// Totals printed when a batch finishes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Parse the non-blank lines of a batch input, keeping their line numbers
+fn parse_input(input: &str) -> Vec<(usize, Result<BatchItem, String>)> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let parsed = serde_json::from_str::<BatchItem>(line)
+                .map_err(|e| format!("Invalid batch input line: {}", e));
+            (index + 1, parsed)
+        })
+        .collect()
+}
+
+/// Run every prompt in `input` (stdin if `None` or `-`) with at most
+/// `concurrency` calls in flight, writing results to `output` (stdout if
+/// `None` or `-`). Progress is reported on stderr.
+pub async fn run(
+    server: GeminiServer,
+    input: Option<&Path>,
+    output: Option<&Path>,
+    concurrency: usize,
+) -> Result<BatchSummary> {
+    let input = read_input(input).await?;
+    let items = parse_input(&input);
+    let total = items.len();
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let done = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = items
+        .into_iter()
+        .map(|(line, parsed)| {
+            let server = server.clone();
+            let semaphore = Arc::clone(&semaphore);
+            let done = Arc::clone(&done);
+            tokio::spawn(async move {
+                let record = match parsed {
+                    Err(e) => BatchRecord::failed(line, None, e),
+                    Ok(item) => {
+                        let _permit = semaphore
+                            .acquire_owned()
+                            .await
+                            .expect("batch semaphore is never closed");
+                        match server.execute_verified(item.args).await {
+                            Ok(executed) => BatchRecord::from_result(line, item.id, executed),
+                            Err(e) => BatchRecord::failed(line, item.id, e.message.to_string()),
+                        }
+                    }
+                };
+                let finished = done.fetch_add(1, Ordering::Relaxed) + 1;
+                eprintln!(
+                    "batch: [{}/{}] line {} {}",
+                    finished,
+                    total,
+                    line,
+                    if record.success { "ok" } else { "failed" }
+                );
+                record
+            })
+        })
+        .collect();
+
+    let mut writer = open_output(output).await?;
+    let mut summary = BatchSummary {
+        total,
+        ..Default::default()
+    };
+    for handle in handles {
+        let record = handle.await.context("Batch task panicked")?;
+        if record.success {
+            summary.succeeded += 1;
+        } else {
+            summary.failed += 1;
+        }
+        let mut line = serde_json::to_vec(&record).context("Failed to serialize batch result")?;
+        line.push(b'\n');
+        writer
+            .write_all(&line)
+            .await
+            .context("Failed to write batch output")?;
+    }
+    writer
+        .flush()
+        .await
+        .context("Failed to flush batch output")?;
+
+    eprintln!(
+        "batch: done, {} succeeded, {} failed, {} total",
+        summary.succeeded, summary.failed, summary.total
+    );
+    Ok(summary)
+}
+
+/// `None` when `path` refers to stdin/stdout (missing or `-`)
+fn file_path(path: Option<&Path>) -> Option<&Path> {
+    path.filter(|p| *p != Path::new("-"))
+}
+
+async fn read_input(path: Option<&Path>) -> Result<String> {
+    match file_path(path) {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read batch input {}", path.display())),
+        None => {
+            let mut input = String::new();
+            tokio::io::stdin()
+                .read_to_string(&mut input)
+                .await
+                .context("Failed to read batch input from stdin")?;
+            Ok(input)
+        }
+    }
+}
+
+async fn open_output(path: Option<&Path>) -> Result<Box<dyn AsyncWrite + Unpin + Send>> {
+    let Some(path) = file_path(path) else {
+        return Ok(Box::new(tokio::io::stdout()));
+    };
+    let file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("Failed to create batch output {}", path.display()))?;
+    Ok(Box::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input_keeps_line_numbers_and_ids() {
+        let input = r#"{"id": "a", "PROMPT": "first"}
+
+{"PROMPT": "second", "EXTRA_ARGS": ["--model", "gemini-2.5-flash"]}
+not json
+"#;
+
+        let items = parse_input(input);
+        assert_eq!(items.len(), 3);
+
+        let (line, first) = &items[0];
+        let first = first.as_ref().unwrap();
+        assert_eq!(*line, 1);
+        assert_eq!(first.id, Some(Value::from("a")));
+        assert_eq!(first.args.prompt, "first");
+
+        let (line, second) = &items[1];
+        let second = second.as_ref().unwrap();
+        assert_eq!(*line, 3);
+        assert!(second.id.is_none());
+        assert_eq!(second.args.extra_args, vec!["--model", "gemini-2.5-flash"]);
+
+        let (line, invalid) = &items[2];
+        assert_eq!(*line, 4);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_failed_record_serialization() {
+        let record = BatchRecord::failed(7, Some(Value::from(3)), "boom".to_string());
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"line":7,"id":3,"success":false,"session_id":"","message":"","error":"boom"}"#
+        );
+    }
+}
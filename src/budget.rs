@@ -0,0 +1,421 @@
+//! Estimated spend and spend caps (`budget`).
+//!
+//! The cost of each run is estimated from its token usage and the price of
+//! the model the CLI reported: the built-in list prices below, or those of
+//! the config's `budget.prices`, matched by the longest model name prefix.
+//! Spend is summed per UTC day and per session. Once the day's spend reaches
+//! `daily_usd`, new runs are refused until the next day; once a session's
+//! reaches `per_session_usd`, it can no longer be resumed. The day's total
+//! is saved in the state directory so a restart does not reset it.
+
+use crate::clock::SharedClock;
+use crate::event::Usage;
+use crate::gemini::{BudgetConfig, ModelPrice};
+use crate::placeholders;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File inside the state directory holding the day's spend
+pub const BUDGET_FILE: &str = "budget.json";
+
+/// List prices in USD per million prompt and output tokens, by model prefix
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gemini-2.5-pro", 1.25, 10.0),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-2.0-flash-lite", 0.075, 0.30),
+];
+
+const DAY: Duration = Duration::from_secs(86_400);
+
+/// Which cap refused a run
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetScope {
+    Daily,
+    Session(String),
+}
+
+/// A run refused because a budget is used up
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetExceeded {
+    pub scope: BudgetScope,
+    pub budget_usd: f64,
+    pub spent_usd: f64,
+    /// Time until the daily budget starts over
+    pub resets_in: Duration,
+}
+
+impl BudgetExceeded {
+    /// Machine-readable details for the MCP error's `data`
+    pub fn data(&self) -> Value {
+        let mut data = json!({
+            "error": "budget_exceeded",
+            "scope": "daily",
+            "budget_usd": self.budget_usd,
+            "spent_usd": round_cents(self.spent_usd),
+        });
+        match self.scope {
+            BudgetScope::Daily => data["resets_in_secs"] = json!(self.resets_in.as_secs()),
+            BudgetScope::Session(ref id) => {
+                data["scope"] = json!("session");
+                data["session"] = json!(id);
+            }
+        }
+        data
+    }
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.scope {
+            BudgetScope::Daily => write!(
+                f,
+                "Daily budget exceeded: an estimated ${:.2} of ${:.2} spent today; resets in {}s",
+                self.spent_usd,
+                self.budget_usd,
+                self.resets_in.as_secs()
+            ),
+            BudgetScope::Session(ref id) => write!(
+                f,
+                "Budget exceeded for SESSION_ID {}: an estimated ${:.2} of ${:.2} spent; start a new session",
+                id, self.spent_usd, self.budget_usd
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// The day's spend as saved in [`BUDGET_FILE`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct DaySpend {
+    /// UTC date, `YYYY-MM-DD`
+    day: String,
+    spent_usd: f64,
+    /// Tokens of runs on models without a price
+    #[serde(default)]
+    unpriced_tokens: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    today: DaySpend,
+    sessions: HashMap<String, f64>,
+}
+
+/// Spend against the budget, as reported by `gemini_usage`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spend {
+    pub day: String,
+    pub spent_usd: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_budget_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_session_budget_usd: Option<f64>,
+    /// Tokens whose cost is unknown because their model has no price
+    #[serde(skip_serializing_if = "is_zero")]
+    pub unpriced_tokens: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Running spend per day and session
+#[derive(Debug, Default)]
+pub struct BudgetTracker {
+    config: BudgetConfig,
+    /// `None` keeps the spend in memory only
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+    clock: SharedClock,
+}
+
+impl BudgetTracker {
+    /// A tracker without a saved day
+    pub fn new(config: BudgetConfig, clock: SharedClock) -> Self {
+        Self {
+            config,
+            path: None,
+            state: Mutex::default(),
+            clock,
+        }
+    }
+
+    /// A tracker that saves the day's spend at `path`, continuing from what
+    /// it holds; a missing file starts at zero
+    pub fn open(config: BudgetConfig, path: PathBuf, clock: SharedClock) -> Result<Self> {
+        let today = match std::fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => DaySpend::default(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+        Ok(Self {
+            config,
+            path: Some(path),
+            state: Mutex::new(State {
+                today,
+                sessions: HashMap::new(),
+            }),
+            clock,
+        })
+    }
+
+    /// Estimated cost in USD of `usage` on `model`, if the model has a price
+    pub fn cost(&self, model: Option<&str>, usage: &Usage) -> Option<f64> {
+        let price = self.price(model?)?;
+        // Thinking tokens are billed as output but only show in the total
+        let output = usage
+            .total_tokens
+            .saturating_sub(usage.prompt_tokens)
+            .max(usage.candidate_tokens);
+        Some(
+            (usage.prompt_tokens as f64 * price.input_per_million
+                + output as f64 * price.output_per_million)
+                / 1_000_000.0,
+        )
+    }
+
+    /// The price of the longest configured or built-in prefix of `model`
+    fn price(&self, model: &str) -> Option<ModelPrice> {
+        let configured = self
+            .config
+            .prices
+            .iter()
+            .map(|(prefix, price)| (prefix.as_str(), *price));
+        let built_in = DEFAULT_PRICES.iter().map(|&(prefix, input, output)| {
+            (
+                prefix,
+                ModelPrice {
+                    input_per_million: input,
+                    output_per_million: output,
+                },
+            )
+        });
+        // On equal prefixes the configured price comes first and wins
+        configured
+            .chain(built_in)
+            .filter(|(prefix, _)| model.starts_with(prefix))
+            .fold(
+                None,
+                |best: Option<(&str, ModelPrice)>, candidate| match best {
+                    Some(best) if best.0.len() >= candidate.0.len() => Some(best),
+                    _ => Some(candidate),
+                },
+            )
+            .map(|(_, price)| price)
+    }
+
+    /// Refuse a run of `session_id` (or a new session) once a budget is used
+    /// up
+    pub fn check(&self, session_id: Option<&str>) -> Result<(), BudgetExceeded> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        roll_over(&mut state, now);
+        if let Some(budget) = self.config.daily_usd {
+            if state.today.spent_usd >= budget {
+                return Err(BudgetExceeded {
+                    scope: BudgetScope::Daily,
+                    budget_usd: budget,
+                    spent_usd: state.today.spent_usd,
+                    resets_in: until_midnight(now),
+                });
+            }
+        }
+        if let (Some(budget), Some(id)) = (self.config.per_session_usd, session_id) {
+            let spent = state.sessions.get(id).copied().unwrap_or_default();
+            if spent >= budget {
+                return Err(BudgetExceeded {
+                    scope: BudgetScope::Session(id.to_string()),
+                    budget_usd: budget,
+                    spent_usd: spent,
+                    resets_in: until_midnight(now),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a finished run of `session_id` to the spend and return its
+    /// estimated cost, if its model has a price
+    pub fn record(&self, session_id: &str, model: Option<&str>, usage: &Usage) -> Option<f64> {
+        let cost = self.cost(model, usage);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        roll_over(&mut state, self.clock.now());
+        match cost {
+            Some(cost) => {
+                state.today.spent_usd += cost;
+                if !session_id.is_empty() {
+                    *state.sessions.entry(session_id.to_string()).or_default() += cost;
+                }
+            }
+            None => state.today.unpriced_tokens += usage.total_tokens,
+        }
+        if let Some(ref path) = self.path {
+            if let Err(err) = save(path, &state.today) {
+                tracing::warn!("{:#}", err);
+            }
+        }
+        cost
+    }
+
+    /// Estimated spend of `session_id` since the server started
+    pub fn session_spend(&self, session_id: &str) -> Option<f64> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.sessions.get(session_id).copied().map(round_cents)
+    }
+
+    /// Today's spend and what is left of the budget
+    pub fn spend(&self) -> Spend {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        roll_over(&mut state, self.clock.now());
+        let spent = state.today.spent_usd;
+        Spend {
+            day: state.today.day.clone(),
+            spent_usd: round_cents(spent),
+            daily_budget_usd: self.config.daily_usd,
+            remaining_usd: self
+                .config
+                .daily_usd
+                .map(|budget| round_cents((budget - spent).max(0.0))),
+            per_session_budget_usd: self.config.per_session_usd,
+            unpriced_tokens: state.today.unpriced_tokens,
+        }
+    }
+}
+
+/// Start a new day's spend once the UTC date has changed
+fn roll_over(state: &mut State, now: SystemTime) {
+    let today = placeholders::date(now);
+    if state.today.day != today {
+        state.today = DaySpend {
+            day: today,
+            ..DaySpend::default()
+        };
+    }
+}
+
+fn until_midnight(now: SystemTime) -> Duration {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    DAY - Duration::from_secs(since_epoch.as_secs() % DAY.as_secs())
+}
+
+/// `usd` to a tenth of a cent, enough for reports without float noise
+fn round_cents(usd: f64) -> f64 {
+    (usd * 1_000.0).round() / 1_000.0
+}
+
+/// Write through a temporary file so a crash never leaves half a file behind
+fn save(path: &Path, today: &DaySpend) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(today).context("Failed to serialize budget")?;
+    std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use std::sync::Arc;
+
+    fn usage(prompt: u64, output: u64) -> Usage {
+        Usage {
+            prompt_tokens: prompt,
+            candidate_tokens: output,
+            total_tokens: prompt + output,
+        }
+    }
+
+    #[test]
+    fn test_cost_uses_longest_price_prefix() {
+        let config = BudgetConfig {
+            prices: [(
+                "gemini-2.5-flash".to_string(),
+                ModelPrice {
+                    input_per_million: 1.0,
+                    output_per_million: 2.0,
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let tracker = BudgetTracker::new(config, SharedClock::default());
+        let million = usage(1_000_000, 1_000_000);
+
+        assert_eq!(tracker.cost(Some("gemini-2.5-flash"), &million), Some(3.0));
+        assert_eq!(
+            tracker.cost(Some("gemini-2.5-flash-lite-001"), &million),
+            Some(0.5)
+        );
+        assert_eq!(tracker.cost(Some("gemini-2.5-pro"), &million), Some(11.25));
+        assert_eq!(tracker.cost(Some("other-model"), &million), None);
+        assert_eq!(tracker.cost(None, &million), None);
+
+        // Thinking tokens count as output
+        let thinking = Usage {
+            prompt_tokens: 0,
+            candidate_tokens: 0,
+            total_tokens: 1_000_000,
+        };
+        assert_eq!(tracker.cost(Some("gemini-2.5-flash"), &thinking), Some(2.0));
+    }
+
+    #[test]
+    fn test_budgets_refuse_runs_until_reset() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(BUDGET_FILE);
+        let clock = Arc::new(ManualClock::new(
+            UNIX_EPOCH + Duration::from_secs(1_709_208_000),
+        ));
+        let shared = || SharedClock::from(Arc::clone(&clock) as Arc<dyn Clock>);
+        let config = BudgetConfig {
+            daily_usd: Some(20.0),
+            per_session_usd: Some(10.0),
+            ..Default::default()
+        };
+        let tracker = BudgetTracker::open(config.clone(), path.clone(), shared()).unwrap();
+        let pro = Some("gemini-2.5-pro");
+
+        assert!(tracker.check(None).is_ok());
+        tracker.record("a", pro, &usage(0, 1_000_000));
+        assert!(tracker.check(Some("a")).is_err());
+        assert!(tracker.check(Some("b")).is_ok());
+        tracker.record("b", pro, &usage(0, 1_000_000));
+        tracker.record("", Some("unknown"), &usage(10, 5));
+
+        let err = tracker.check(Some("c")).unwrap_err();
+        assert_eq!(err.scope, BudgetScope::Daily);
+        assert_eq!(err.resets_in, Duration::from_secs(43_200));
+        assert_eq!(err.data()["resets_in_secs"], 43_200);
+        let spend = tracker.spend();
+        assert_eq!(spend.day, "2024-02-29");
+        assert_eq!(spend.spent_usd, 20.0);
+        assert_eq!(spend.remaining_usd, Some(0.0));
+        assert_eq!(spend.unpriced_tokens, 15);
+        assert_eq!(tracker.session_spend("a"), Some(10.0));
+
+        // The day's spend survives a restart, and a new day starts over
+        let reopened = BudgetTracker::open(config, path, shared()).unwrap();
+        assert!(reopened.check(None).is_err());
+        clock.advance(Duration::from_secs(43_200));
+        assert!(reopened.check(None).is_ok());
+        assert_eq!(reopened.spend().day, "2024-03-01");
+    }
+}
@@ -0,0 +1,48 @@
+//! Which code produced a result: the server version, the git commit it was
+//! built from and the Gemini CLI release it drove. Attached to tool outputs
+//! and stored runs so historical results can be traced back to their build.
+
+use crate::cli_compat;
+use serde::Serialize;
+
+/// Version of this crate
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit embedded by the build script, `unknown` outside a checkout
+pub const GIT_SHA: &str = match option_env!("GEMINI_MCP_GIT_SHA") {
+    Some(sha) => sha,
+    None => "unknown",
+};
+
+/// Build and CLI versions behind a result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub server_version: &'static str,
+    pub git_sha: &'static str,
+    /// `None` until the CLI version is detected, or if detection failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cli_version: Option<String>,
+}
+
+/// Versions of the running server and of the CLI found at startup
+pub fn current() -> BuildInfo {
+    BuildInfo {
+        server_version: SERVER_VERSION,
+        git_sha: GIT_SHA,
+        cli_version: cli_compat::current().version.map(|v| v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_names_this_build() {
+        let info = current();
+        assert_eq!(info.server_version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["server_version"], SERVER_VERSION);
+    }
+}
@@ -0,0 +1,311 @@
+//! Re-executable request bundles (`SAVE_BUNDLE`, `gemini_replay_request`).
+//!
+//! A bundle records everything needed to run a `gemini` call again: the
+//! prompt as built by the server, the CLI options and command line, the
+//! per-call environment, the files the prompt refers to and a snapshot of
+//! the config file. Bundles are saved as JSON under `bundles/` in the state
+//! directory before the CLI starts, so a call that hangs or crashes can
+//! still be reproduced. Credentials are never stored: auth profiles are
+//! kept by name, and `env` and `auth_profiles` are redacted from the config
+//! snapshot.
+
+use crate::gemini::{self, ApprovalMode, GeminiClient, Options, OptionsBuilder, PromptDelivery};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Directory inside the state directory holding the bundles
+pub const BUNDLES_DIR: &str = "bundles";
+
+/// Everything needed to run a call again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub bundle_id: String,
+    /// Unix time the bundle was saved
+    pub created_at: u64,
+    /// Server and CLI versions that saved the bundle
+    pub metadata: Value,
+    /// Prompt as built by the server (persona, memory and workspace context
+    /// included); the CLI gets GEMINI.md prepended to it
+    pub prompt: String,
+    /// Prompt the CLI received, GEMINI.md included
+    pub final_prompt: String,
+    /// Program and arguments the CLI was started with
+    pub argv: Vec<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub additional_args: Vec<String>,
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Per-call environment variables; the server's own and the auth
+    /// profile's are not recorded
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Name of the auth profile the call ran as
+    #[serde(default)]
+    pub auth_profile: Option<String>,
+    #[serde(default)]
+    pub sandbox: bool,
+    #[serde(default)]
+    pub approval_mode: Option<ApprovalMode>,
+    #[serde(default)]
+    pub grounding: bool,
+    #[serde(default)]
+    pub include_directories: Vec<PathBuf>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub prompt_delivery: PromptDelivery,
+    /// Files the prompt refers to or was packed from
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// The config file, with credentials redacted
+    #[serde(default)]
+    pub config: Option<Value>,
+}
+
+/// A file or directory a bundled call depended on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub path: String,
+    /// `reference` for `@path` in the prompt, `packed` for `CONTEXT_GLOBS`,
+    /// `include_directory` for `INCLUDE_DIRECTORIES`
+    pub kind: String,
+    /// Size when the bundle was saved; `None` for directories
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Unix time of the last modification when the bundle was saved
+    #[serde(default)]
+    pub modified: Option<u64>,
+}
+
+impl Attachment {
+    fn new(path: String, kind: &str) -> Self {
+        let meta = std::fs::metadata(&path).ok();
+        Self {
+            bytes: meta.as_ref().filter(|m| m.is_file()).map(|m| m.len()),
+            modified: meta
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            path,
+            kind: kind.to_string(),
+        }
+    }
+}
+
+impl Bundle {
+    /// Record `opts` before it runs. `packed` lists the files packed from
+    /// `CONTEXT_GLOBS`.
+    pub async fn capture(
+        opts: &Options,
+        auth_profile: Option<String>,
+        packed: &[String],
+        metadata: Value,
+        now: SystemTime,
+    ) -> Self {
+        let final_prompt = gemini::final_prompt(&opts.prompt).await;
+        let argv = GeminiClient::new().argv(&Options {
+            prompt: final_prompt.clone(),
+            ..opts.clone()
+        });
+        let mut attachments: Vec<Attachment> = references(&opts.prompt)
+            .into_iter()
+            .filter(|path| Path::new(path).exists())
+            .map(|path| Attachment::new(path, "reference"))
+            .collect();
+        attachments.extend(
+            packed
+                .iter()
+                .map(|path| Attachment::new(path.clone(), "packed")),
+        );
+        attachments.extend(
+            opts.include_directories
+                .iter()
+                .map(|dir| Attachment::new(dir.display().to_string(), "include_directory")),
+        );
+        Self {
+            bundle_id: Uuid::new_v4().to_string(),
+            created_at: now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            metadata,
+            prompt: opts.prompt.clone(),
+            final_prompt,
+            argv,
+            session_id: opts.session_id.clone(),
+            model: opts.model.clone(),
+            additional_args: opts.additional_args.clone(),
+            extra_args: opts.extra_args.clone(),
+            env: opts.env.clone(),
+            auth_profile,
+            sandbox: opts.sandbox,
+            approval_mode: opts.approval_mode,
+            grounding: opts.grounding,
+            include_directories: opts.include_directories.clone(),
+            timeout_secs: opts.timeout.map(|t| t.as_secs()),
+            prompt_delivery: opts.prompt_delivery,
+            attachments,
+            config: gemini::config_snapshot(),
+        }
+    }
+
+    /// Options running the bundled call again; the caller adds the auth
+    /// profile and run handle. The response cache is bypassed.
+    pub fn options(&self) -> OptionsBuilder {
+        let mut builder = Options::builder()
+            .prompt(self.prompt.clone())
+            .additional_args(self.additional_args.iter().cloned())
+            .extra_args(self.extra_args.iter().cloned())
+            .envs(self.env.clone())
+            .sandbox(self.sandbox)
+            .grounding(self.grounding)
+            .include_directories(
+                self.include_directories
+                    .iter()
+                    .map(|dir| dir.display().to_string()),
+            )
+            .prompt_delivery(self.prompt_delivery)
+            .no_cache(true);
+        if let Some(ref session_id) = self.session_id {
+            builder = builder.session(session_id.clone());
+        }
+        if let Some(ref model) = self.model {
+            builder = builder.model(model.clone());
+        }
+        if let Some(mode) = self.approval_mode {
+            builder = builder.approval_mode(mode);
+        }
+        if let Some(secs) = self.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+        builder
+    }
+}
+
+/// Saves and loads bundles as `<id>.json` in one directory
+#[derive(Debug, Clone)]
+pub struct BundleStore {
+    dir: PathBuf,
+}
+
+impl BundleStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn save(&self, bundle: &Bundle) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(format!("{}.json", bundle.bundle_id));
+        let json = serde_json::to_string_pretty(bundle)?;
+        std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(&self, bundle_id: &str) -> Result<Bundle> {
+        let bundle_id = bundle_id.trim();
+        if Uuid::parse_str(bundle_id).is_err() {
+            bail!("BUNDLE_ID must be a valid UUID string");
+        }
+        let path = self.dir.join(format!("{}.json", bundle_id));
+        let raw = std::fs::read_to_string(&path)
+            .with_context(|| format!("No bundle {} in {}", bundle_id, self.dir.display()))?;
+        serde_json::from_str(&raw).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+/// Paths referenced as `@path` in `prompt`, with `\ ` unescaped
+fn references(prompt: &str) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    let mut chars = prompt.chars().peekable();
+    let mut previous = ' ';
+    while let Some(c) = chars.next() {
+        if c == '@' && previous.is_whitespace() {
+            let mut path = String::new();
+            while let Some(&next) = chars.peek() {
+                if next == '\\' {
+                    chars.next();
+                    if let Some(escaped) = chars.next() {
+                        path.push(escaped);
+                    }
+                } else if next.is_whitespace() {
+                    break;
+                } else {
+                    path.push(next);
+                    chars.next();
+                }
+            }
+            let path = path.trim_end_matches([',', '.', ';', ':', ')']).to_string();
+            if !path.is_empty() && !paths.contains(&path) {
+                paths.push(path);
+            }
+            previous = ' ';
+            continue;
+        }
+        previous = c;
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references() {
+        assert_eq!(
+            references("Review @src/my\\ file.rs and @README.md\n@docs/a.md, not me@example.com"),
+            ["src/my file.rs", "README.md", "docs/a.md"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bundle_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let opts = Options::builder()
+            .prompt("Explain the build")
+            .extra_args(["--model", "gemini-2.5-flash"])
+            .extra_args_allowlist(["--model"])
+            .approval_mode(ApprovalMode::AutoEdit)
+            .max_approval_mode(ApprovalMode::Yolo)
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        let bundle = Bundle::capture(
+            &opts,
+            Some("work".to_string()),
+            &[],
+            Value::Null,
+            UNIX_EPOCH + Duration::from_secs(42),
+        )
+        .await;
+        assert_eq!(bundle.created_at, 42);
+        assert!(bundle.argv.iter().any(|arg| arg == "gemini-2.5-flash"));
+
+        let store = BundleStore::new(dir.path().to_path_buf());
+        store.save(&bundle).unwrap();
+        let loaded = store.load(&bundle.bundle_id).unwrap();
+        assert_eq!(loaded, bundle);
+        assert!(store.load("../secrets").is_err());
+        assert!(store.load(&Uuid::new_v4().to_string()).is_err());
+
+        let replayed = loaded
+            .options()
+            .extra_args_allowlist(["--model"])
+            .max_approval_mode(ApprovalMode::Yolo)
+            .build()
+            .unwrap();
+        assert_eq!(replayed.prompt, opts.prompt);
+        assert_eq!(replayed.extra_args, opts.extra_args);
+        assert_eq!(replayed.approval_mode, Some(ApprovalMode::AutoEdit));
+        assert_eq!(replayed.timeout, Some(Duration::from_secs(30)));
+        assert!(replayed.no_cache);
+    }
+}
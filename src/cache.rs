@@ -0,0 +1,222 @@
+//! Optional cache of one-shot answers (`cache` in the server config).
+//!
+//! Agents often retry the exact same prompt. Runs that start a new session
+//! are cached by normalized prompt, model and CLI arguments, so a repeat
+//! within the TTL is answered instantly without spending quota. Resumed
+//! sessions are never cached: their answer depends on the conversation.
+
+use crate::gemini::GeminiResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The `cache` section of the server config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Least recently used answers are evicted beyond this many
+    pub max_entries: Option<usize>,
+    /// How long an answer stays valid
+    pub ttl_secs: Option<u64>,
+}
+
+const DEFAULT_MAX_ENTRIES: usize = 256;
+const DEFAULT_TTL_SECS: u64 = 600;
+
+/// Counters reported by the `gemini_stats` tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for newer ones
+    pub evictions: u64,
+    /// Lookups that found an entry past its TTL
+    pub expired: u64,
+}
+
+/// What identifies a cacheable run
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    prompt: String,
+    model: Option<String>,
+    args: Vec<String>,
+}
+
+impl CacheKey {
+    /// Whitespace differences in the prompt do not change the key
+    pub fn new(prompt: &str, model: Option<&str>, args: Vec<String>) -> Self {
+        Self {
+            prompt: prompt.split_whitespace().collect::<Vec<_>>().join(" "),
+            model: model.map(str::to_string),
+            args,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: GeminiResult,
+    stored_at: Instant,
+    /// Tick of the last hit or insert, for LRU eviction
+    last_used: u64,
+}
+
+/// LRU cache with a TTL
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<CacheKey, Entry>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        let max_entries = config
+            .max_entries
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        let ttl = Duration::from_secs(config.ttl_secs.unwrap_or(DEFAULT_TTL_SECS));
+        Self {
+            max_entries,
+            ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// The cached result for `key` and its age, if still fresh
+    pub fn get(&self, key: &CacheKey) -> Option<(GeminiResult, Duration)> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let age = match state.entries.get_mut(key) {
+            Some(entry) if entry.stored_at.elapsed() <= self.ttl => {
+                entry.last_used = tick;
+                Some((entry.result.clone(), entry.stored_at.elapsed()))
+            }
+            Some(_) => {
+                state.entries.remove(key);
+                state.stats.expired += 1;
+                None
+            }
+            None => None,
+        };
+        match age {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1,
+        }
+        age
+    }
+
+    /// Store a successful result, evicting the least recently used entry
+    /// when full
+    pub fn insert(&self, key: CacheKey, result: GeminiResult) {
+        if !result.success {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+                state.stats.evictions += 1;
+            }
+        }
+        state.entries.insert(
+            key,
+            Entry {
+                result,
+                stored_at: Instant::now(),
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            entries: state.entries.len(),
+            max_entries: self.max_entries,
+            ttl_secs: self.ttl.as_secs(),
+            ..state.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(text: &str) -> GeminiResult {
+        GeminiResult {
+            success: true,
+            agent_messages: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction_and_normalized_keys() {
+        let cache = ResponseCache::new(&CacheConfig {
+            enabled: true,
+            max_entries: Some(2),
+            ttl_secs: None,
+        });
+        let key = |prompt: &str| CacheKey::new(prompt, Some("flash"), Vec::new());
+
+        cache.insert(key("one"), answer("1"));
+        cache.insert(key("two"), answer("2"));
+        // Touch "one" so "two" is the least recently used
+        let (hit, _) = cache.get(&key("  one\n")).unwrap();
+        assert_eq!(hit.agent_messages, "1");
+        cache.insert(key("three"), answer("3"));
+
+        assert!(cache.get(&key("two")).is_none());
+        assert!(cache.get(&key("one")).is_some());
+        assert!(cache
+            .get(&CacheKey::new("one", Some("pro"), Vec::new()))
+            .is_none());
+
+        let mut failed = answer("");
+        failed.success = false;
+        cache.insert(key("four"), failed);
+        assert!(cache.get(&key("four")).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.max_entries), (2, 2));
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 3, 1));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = ResponseCache::new(&CacheConfig {
+            enabled: true,
+            max_entries: None,
+            ttl_secs: Some(0),
+        });
+        let key = CacheKey::new("x", None, Vec::new());
+        cache.insert(key.clone(), answer("x"));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.stats().expired, 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
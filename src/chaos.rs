@@ -0,0 +1,206 @@
+//! Failure injection ("chaos mode") for resilience testing.
+//!
+//! When `chaos.enabled` is set in the server config, runs are randomly
+//! delayed, get spurious stderr lines, or time out, each at its configured
+//! rate. Agent frameworks can then exercise their retry and fallback paths
+//! against a real server. Injected timeouts never start the CLI, and the
+//! other faults leave its invocation unchanged.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bound of the injected latency unless `latency_max_ms` is configured
+const DEFAULT_LATENCY_MAX_MS: u64 = 5_000;
+/// Stderr line injected unless `stderr_line` is configured
+const DEFAULT_STDERR_LINE: &str =
+    "[chaos] Error: transient failure talking to the Gemini API, retrying";
+
+/// Fault rates, as configured under `chaos`. Rates are probabilities per run
+/// between 0 and 1.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Share of runs delayed before the CLI starts; the delay counts against
+    /// the run's timeout
+    #[serde(default)]
+    pub latency_rate: f64,
+    #[serde(default)]
+    pub latency_min_ms: u64,
+    /// Longest injected delay (default 5000)
+    pub latency_max_ms: Option<u64>,
+    /// Share of runs that get a spurious stderr line
+    #[serde(default)]
+    pub stderr_rate: f64,
+    pub stderr_line: Option<String>,
+    /// Share of runs that time out: the CLI is not started and the run
+    /// fails like a real timeout once its timeout has passed
+    #[serde(default)]
+    pub timeout_rate: f64,
+    /// Seed for reproducible fault sequences; random otherwise
+    pub seed: Option<u64>,
+}
+
+impl ChaosConfig {
+    fn latency_max_ms(&self) -> u64 {
+        self.latency_max_ms.unwrap_or(DEFAULT_LATENCY_MAX_MS)
+    }
+}
+
+/// Reject rates outside 0..=1 and an empty latency range
+pub fn validate(config: &ChaosConfig) -> Result<()> {
+    for (name, rate) in [
+        ("latency_rate", config.latency_rate),
+        ("stderr_rate", config.stderr_rate),
+        ("timeout_rate", config.timeout_rate),
+    ] {
+        if !(0.0..=1.0).contains(&rate) {
+            bail!("chaos.{} must be between 0 and 1, got {}", name, rate);
+        }
+    }
+    if config.latency_min_ms > config.latency_max_ms() {
+        bail!(
+            "chaos.latency_min_ms ({}) is greater than chaos.latency_max_ms ({})",
+            config.latency_min_ms,
+            config.latency_max_ms()
+        );
+    }
+    Ok(())
+}
+
+/// Faults drawn for one run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Faults {
+    pub delay: Option<Duration>,
+    pub stderr: Option<String>,
+    pub timeout: bool,
+}
+
+impl Faults {
+    /// Warnings naming the injected faults, so they are not mistaken for
+    /// real ones while debugging
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(delay) = self.delay {
+            warnings.push(format!(
+                "chaos mode: injected {}ms of latency",
+                delay.as_millis()
+            ));
+        }
+        if self.stderr.is_some() {
+            warnings.push("chaos mode: injected a stderr line".to_string());
+        }
+        if self.timeout {
+            warnings.push("chaos mode: injected a timeout".to_string());
+        }
+        warnings
+    }
+}
+
+/// Draws the faults of each run
+#[derive(Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+    state: Mutex<u64>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        let seed = config
+            .seed
+            .unwrap_or_else(|| uuid::Uuid::new_v4().as_u128() as u64);
+        Self {
+            config,
+            state: Mutex::new(seed),
+        }
+    }
+
+    /// Faults of the next run; none unless chaos mode is enabled
+    pub fn draw(&self) -> Faults {
+        if !self.config.enabled {
+            return Faults::default();
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut chance = |rate: f64| unit(&mut state) < rate;
+        let timeout = chance(self.config.timeout_rate);
+        let stderr = chance(self.config.stderr_rate).then(|| {
+            self.config
+                .stderr_line
+                .clone()
+                .unwrap_or_else(|| DEFAULT_STDERR_LINE.to_string())
+        });
+        let delay = chance(self.config.latency_rate).then(|| {
+            let (min, max) = (self.config.latency_min_ms, self.config.latency_max_ms());
+            let span = max.saturating_sub(min);
+            Duration::from_millis(min + (unit(&mut state) * span as f64) as u64)
+        });
+        Faults {
+            delay,
+            stderr,
+            timeout,
+        }
+    }
+}
+
+/// Next number in `[0, 1)` of a SplitMix64 sequence
+fn unit(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> ChaosConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_faults_follow_rates() {
+        let disabled = Chaos::new(config(r#"{"timeout_rate": 1.0, "stderr_rate": 1.0}"#));
+        assert_eq!(disabled.draw(), Faults::default());
+
+        let always = Chaos::new(config(
+            r#"{"enabled": true, "latency_rate": 1.0, "latency_min_ms": 10,
+                "latency_max_ms": 20, "stderr_rate": 1.0, "stderr_line": "boom",
+                "timeout_rate": 1.0}"#,
+        ));
+        let faults = always.draw();
+        assert!(faults.timeout);
+        assert_eq!(faults.stderr.as_deref(), Some("boom"));
+        let delay = faults.delay.unwrap();
+        assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+        assert_eq!(faults.warnings().len(), 3);
+
+        let sometimes = Chaos::new(config(
+            r#"{"enabled": true, "timeout_rate": 0.25, "seed": 7}"#,
+        ));
+        let timeouts = (0..1000).filter(|_| sometimes.draw().timeout).count();
+        assert!((150..350).contains(&timeouts), "{}", timeouts);
+    }
+
+    #[test]
+    fn test_seeded_runs_repeat() {
+        let json = r#"{"enabled": true, "latency_rate": 0.5, "stderr_rate": 0.5, "seed": 42}"#;
+        let (a, b) = (Chaos::new(config(json)), Chaos::new(config(json)));
+        for _ in 0..20 {
+            assert_eq!(a.draw(), b.draw());
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&config(r#"{"enabled": true, "timeout_rate": 0.1}"#)).is_ok());
+        assert!(validate(&config(r#"{"stderr_rate": 1.5}"#)).is_err());
+        assert!(validate(&config(r#"{"latency_min_ms": 9000}"#)).is_err());
+        assert!(serde_json::from_str::<ChaosConfig>(r#"{"timeout": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Conversation history kept by the server for the `gemini_chat` tool.
+//!
+//! Instead of resuming a CLI session, every `gemini_chat` call starts a new
+//! one with the earlier turns of the conversation replayed into the prompt.
+//! Conversations therefore survive CLI sessions that expire or fail to
+//! resume. Each conversation is bounded by a number of turns and a number of
+//! characters; the oldest turns are dropped first. Histories live in memory
+//! and are lost when the server restarts.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Turns kept per conversation unless `chat.max_turns` is configured
+pub const DEFAULT_MAX_TURNS: usize = 20;
+/// Characters kept per conversation unless `chat.max_chars` is configured
+pub const DEFAULT_MAX_CHARS: usize = 32_000;
+
+/// One exchange of a conversation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChatTurn {
+    pub user: String,
+    pub assistant: String,
+}
+
+impl ChatTurn {
+    fn chars(&self) -> usize {
+        self.user.chars().count() + self.assistant.chars().count()
+    }
+}
+
+/// Bounded histories of all conversations, by label
+#[derive(Debug)]
+pub struct ChatHistory {
+    conversations: Mutex<HashMap<String, VecDeque<ChatTurn>>>,
+    max_turns: usize,
+    max_chars: usize,
+}
+
+impl Default for ChatHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_TURNS, DEFAULT_MAX_CHARS)
+    }
+}
+
+impl ChatHistory {
+    pub fn new(max_turns: usize, max_chars: usize) -> Self {
+        Self {
+            conversations: Mutex::new(HashMap::new()),
+            max_turns: max_turns.max(1),
+            max_chars: max_chars.max(1),
+        }
+    }
+
+    /// Forget a conversation. Returns how many turns it had.
+    pub fn reset(&self, label: &str) -> usize {
+        self.conversations
+            .lock()
+            .unwrap()
+            .remove(label)
+            .map_or(0, |turns| turns.len())
+    }
+
+    /// Turns currently kept for `label`
+    pub fn turns(&self, label: &str) -> usize {
+        self.conversations
+            .lock()
+            .unwrap()
+            .get(label)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// The prompt for `message` with the history of `label` replayed before
+    /// it
+    pub fn prompt(&self, label: &str, message: &str) -> String {
+        let conversations = self.conversations.lock().unwrap();
+        match conversations.get(label).filter(|turns| !turns.is_empty()) {
+            Some(turns) => render(turns, message),
+            None => message.to_string(),
+        }
+    }
+
+    /// Append a completed turn and trim the conversation to its bounds.
+    /// Returns how many old turns were dropped.
+    pub fn record(&self, label: &str, user: &str, assistant: &str) -> usize {
+        let mut conversations = self.conversations.lock().unwrap();
+        let turns = conversations.entry(label.to_string()).or_default();
+        turns.push_back(ChatTurn {
+            user: user.to_string(),
+            assistant: assistant.to_string(),
+        });
+        let mut chars: usize = turns.iter().map(ChatTurn::chars).sum();
+        let mut dropped = 0;
+        while turns.len() > self.max_turns || (chars > self.max_chars && !turns.is_empty()) {
+            if let Some(turn) = turns.pop_front() {
+                chars -= turn.chars();
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+}
+
+fn render(turns: &VecDeque<ChatTurn>, message: &str) -> String {
+    let mut prompt = String::from(
+        "Below is our conversation so far. Continue it by answering my last message.\n",
+    );
+    for turn in turns {
+        prompt.push_str(&format!(
+            "\nUser: {}\n\nAssistant: {}\n",
+            turn.user.trim(),
+            turn.assistant.trim()
+        ));
+    }
+    prompt.push_str(&format!("\nUser: {}", message.trim()));
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_is_replayed_into_the_prompt() {
+        let history = ChatHistory::default();
+        assert_eq!(history.prompt("plan", "Hi"), "Hi");
+
+        history.record("plan", "What is 2+2?", "4");
+        history.record("other", "Unrelated", "Yes");
+        let prompt = history.prompt("plan", "And times 3?");
+        assert!(prompt.contains("User: What is 2+2?\n\nAssistant: 4\n"));
+        assert!(prompt.ends_with("\nUser: And times 3?"));
+        assert!(!prompt.contains("Unrelated"));
+
+        assert_eq!(history.reset("plan"), 1);
+        assert_eq!(history.prompt("plan", "Hi"), "Hi");
+        assert_eq!(history.turns("other"), 1);
+    }
+
+    #[test]
+    fn test_oldest_turns_are_dropped_first() {
+        let history = ChatHistory::new(2, 1_000);
+        assert_eq!(history.record("c", "one", "1"), 0);
+        assert_eq!(history.record("c", "two", "2"), 0);
+        assert_eq!(history.record("c", "three", "3"), 1);
+        let prompt = history.prompt("c", "next");
+        assert!(!prompt.contains("one"));
+        assert!(prompt.contains("two") && prompt.contains("three"));
+
+        let history = ChatHistory::new(10, 10);
+        history.record("c", "aaaa", "bbbb");
+        assert_eq!(history.record("c", "cccc", "dddd"), 1);
+        assert_eq!(history.turns("c"), 1);
+        // A turn larger than the whole budget is not kept at all
+        assert_eq!(history.record("c", &"x".repeat(20), "y"), 2);
+        assert_eq!(history.turns("c"), 0);
+    }
+}
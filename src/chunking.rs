@@ -0,0 +1,378 @@
+//! Splitting text into chunks for the workspace index and any other feature
+//! that feeds files to Gemini piece by piece.
+//!
+//! Fixed-size splitting cuts functions in half, which makes retrieved
+//! excerpts hard to use. The `tokens` strategy at least keeps chunks of a
+//! similar cost, and `syntax` (with the `tree-sitter` feature) cuts between
+//! definitions, keeping doc comments and attributes with the item they
+//! describe.
+
+use serde::{Deserialize, Serialize};
+
+/// Rough number of characters per token in code and English prose
+const CHARS_PER_TOKEN: usize = 4;
+
+/// How [`ChunkingConfig::split`] cuts text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    /// `max_lines` lines per chunk
+    #[default]
+    Lines,
+    /// As many whole lines as fit in `max_tokens`
+    Tokens,
+    /// Whole definitions packed up to `max_lines` and `max_tokens`; larger
+    /// ones are split at nested definitions, then by lines. Files without a
+    /// supported grammar fall back to `tokens` with the `max_lines` cap.
+    Syntax,
+}
+
+/// The `chunking` section of the server config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkingConfig {
+    #[serde(default)]
+    pub strategy: ChunkStrategy,
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+    /// Budget in estimated tokens (about four characters each)
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+}
+
+fn default_max_lines() -> usize {
+    60
+}
+
+fn default_max_tokens() -> usize {
+    512
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            strategy: ChunkStrategy::default(),
+            max_lines: default_max_lines(),
+            max_tokens: default_max_tokens(),
+        }
+    }
+}
+
+/// Lines `start_line..=end_line` (1-based) of the split text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+}
+
+/// Approximate token count of `text`
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+impl ChunkingConfig {
+    /// Split `text`, the contents of `path` (whose extension selects the
+    /// grammar for `syntax`). Chunks holding only blank lines are dropped.
+    pub fn split(&self, path: &str, text: &str) -> Vec<TextChunk> {
+        let lines: Vec<&str> = text.lines().collect();
+        let budget = Budget::new(&lines, self.max_lines, self.max_tokens);
+        let ranges = match self.strategy {
+            ChunkStrategy::Lines => (0..lines.len())
+                .step_by(budget.max_lines)
+                .map(|start| (start, (start + budget.max_lines).min(lines.len())))
+                .collect(),
+            ChunkStrategy::Tokens => Budget {
+                max_lines: usize::MAX,
+                ..budget
+            }
+            .by_lines(0, lines.len()),
+            ChunkStrategy::Syntax => syntax_ranges(path, text, &budget)
+                .unwrap_or_else(|| budget.by_lines(0, lines.len())),
+        };
+
+        ranges
+            .into_iter()
+            .filter(|&(start, end)| lines[start..end].iter().any(|l| !l.trim().is_empty()))
+            .map(|(start, end)| TextChunk {
+                start_line: start + 1,
+                end_line: end,
+                text: lines[start..end].join("\n"),
+            })
+            .collect()
+    }
+}
+
+/// Size limits for a chunk of `lines[start..end]`
+#[derive(Debug, Clone)]
+struct Budget {
+    max_lines: usize,
+    max_tokens: usize,
+    /// `token_prefix[i]`: estimated tokens of the first `i` lines
+    token_prefix: Vec<usize>,
+}
+
+impl Budget {
+    fn new(lines: &[&str], max_lines: usize, max_tokens: usize) -> Self {
+        let mut token_prefix = Vec::with_capacity(lines.len() + 1);
+        token_prefix.push(0);
+        for line in lines {
+            // One extra token for the line break
+            token_prefix.push(token_prefix.last().unwrap_or(&0) + estimate_tokens(line) + 1);
+        }
+        Self {
+            max_lines: max_lines.max(1),
+            max_tokens: max_tokens.max(1),
+            token_prefix,
+        }
+    }
+
+    fn fits(&self, start: usize, end: usize) -> bool {
+        end - start <= self.max_lines
+            && self.token_prefix[end] - self.token_prefix[start] <= self.max_tokens
+    }
+
+    /// Greedy split of `start..end` at line boundaries; a single line over
+    /// the budget becomes its own chunk
+    fn by_lines(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut chunk_start = start;
+        for line in start..end {
+            if line > chunk_start && !self.fits(chunk_start, line + 1) {
+                ranges.push((chunk_start, line));
+                chunk_start = line;
+            }
+        }
+        if chunk_start < end {
+            ranges.push((chunk_start, end));
+        }
+        ranges
+    }
+}
+
+#[cfg(not(feature = "tree-sitter"))]
+fn syntax_ranges(_path: &str, _text: &str, _budget: &Budget) -> Option<Vec<(usize, usize)>> {
+    None
+}
+
+#[cfg(feature = "tree-sitter")]
+fn syntax_ranges(path: &str, text: &str, budget: &Budget) -> Option<Vec<(usize, usize)>> {
+    let tree = crate::syntax::parse(path, text)?;
+    let lines = budget.token_prefix.len() - 1;
+    let mut segments = Vec::new();
+    syntax::split_node(tree.root_node(), 0, lines, budget, &mut segments);
+    Some(syntax::pack(segments, budget))
+}
+
+#[cfg(feature = "tree-sitter")]
+mod syntax {
+    use super::Budget;
+    use tree_sitter::Node;
+
+    /// Run of lines that should stay together where possible
+    #[derive(Debug, Clone, Copy)]
+    pub(super) struct Segment {
+        start: usize,
+        end: usize,
+        /// Comments and attributes that belong to the following item
+        leading: bool,
+    }
+
+    /// Cut `start..end`, spanned by `node`, before each nested definition,
+    /// recursing into those that are still too large
+    pub(super) fn split_node(
+        node: Node<'_>,
+        start: usize,
+        end: usize,
+        budget: &Budget,
+        out: &mut Vec<Segment>,
+    ) {
+        let (head, cuts) = cuts(node, start, end);
+        let mut pieces = Vec::with_capacity(cuts.len() + 1);
+        let mut piece_start = (start, head);
+        for (row, child) in cuts {
+            pieces.push((piece_start.0, row, piece_start.1));
+            piece_start = (row, Some(child));
+        }
+        pieces.push((piece_start.0, end, piece_start.1));
+
+        for (piece_start, piece_end, child) in pieces {
+            if piece_start == piece_end {
+                continue;
+            }
+            // A piece without a node is the header of the node being split,
+            // like `impl Limits {`, and belongs with what follows
+            let leading = child.is_none_or(|c| crate::syntax::is_leading(c.kind()));
+            if budget.fits(piece_start, piece_end) {
+                out.push(Segment {
+                    start: piece_start,
+                    end: piece_end,
+                    leading,
+                });
+            } else if let Some(child) = child.filter(|c| c.named_child_count() > 0) {
+                split_node(child, piece_start, piece_end, budget, out);
+            } else {
+                out.extend(budget.by_lines(piece_start, piece_end).into_iter().map(
+                    |(start, end)| Segment {
+                        start,
+                        end,
+                        leading: false,
+                    },
+                ));
+            }
+        }
+    }
+
+    /// Named children of `node` starting inside `start..end`: the largest one
+    /// starting on the first row (the part to recurse into, such as a
+    /// function or impl body) and one per later row where a child starts
+    fn cuts<'t>(
+        node: Node<'t>,
+        start: usize,
+        end: usize,
+    ) -> (Option<Node<'t>>, Vec<(usize, Node<'t>)>) {
+        let span = |n: &Node<'_>| n.end_byte() - n.start_byte();
+        let mut head: Option<Node<'t>> = None;
+        let mut cuts: Vec<(usize, Node<'t>)> = Vec::new();
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            let row = child.start_position().row;
+            if row >= end {
+                break;
+            }
+            if row <= start {
+                if head.is_none_or(|h| span(&child) > span(&h)) {
+                    head = Some(child);
+                }
+                continue;
+            }
+            match cuts.last_mut() {
+                Some((last, node)) if *last == row => {
+                    if span(&child) > span(node) {
+                        *node = child;
+                    }
+                }
+                _ => cuts.push((row, child)),
+            }
+        }
+        (head, cuts)
+    }
+
+    /// Merge leading comments into the item they precede, then pack
+    /// consecutive segments into chunks within the budget
+    pub(super) fn pack(segments: Vec<Segment>, budget: &Budget) -> Vec<(usize, usize)> {
+        let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+        for segment in segments {
+            match merged.last_mut() {
+                Some(last) if last.leading && budget.fits(last.start, segment.end) => {
+                    last.end = segment.end;
+                    last.leading = segment.leading;
+                }
+                _ => merged.push(segment),
+            }
+        }
+
+        let mut ranges = Vec::new();
+        let mut current: Option<(usize, usize)> = None;
+        for segment in merged {
+            current = match current {
+                Some((start, _)) if budget.fits(start, segment.end) => Some((start, segment.end)),
+                _ => {
+                    ranges.extend(current);
+                    Some((segment.start, segment.end))
+                }
+            };
+        }
+        ranges.extend(current);
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(strategy: ChunkStrategy, max_lines: usize, max_tokens: usize) -> ChunkingConfig {
+        ChunkingConfig {
+            strategy,
+            max_lines,
+            max_tokens,
+        }
+    }
+
+    fn spans(chunks: &[TextChunk]) -> Vec<(usize, usize)> {
+        chunks.iter().map(|c| (c.start_line, c.end_line)).collect()
+    }
+
+    #[test]
+    fn test_lines_and_tokens() {
+        let chunks = config(ChunkStrategy::Lines, 3, 0).split("notes.txt", "a\nb\nc\nd\n\n\n\ne");
+        assert_eq!(spans(&chunks), vec![(1, 3), (4, 6), (7, 8)]);
+        assert_eq!(chunks[1].text, "d\n\n");
+
+        // 8 characters = 2 tokens, plus one per line break
+        let text = "aaaaaaaa\nbbbbbbbb\ncccccccc\n".repeat(2);
+        let chunks = config(ChunkStrategy::Tokens, 1, 7).split("notes.txt", &text);
+        assert_eq!(spans(&chunks), vec![(1, 2), (3, 4), (5, 6)]);
+        let chunks = config(ChunkStrategy::Tokens, 1, 1).split("notes.txt", "a\nlong line here\n");
+        assert_eq!(spans(&chunks), vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let parsed: ChunkingConfig = serde_json::from_str(r#"{"strategy": "syntax"}"#).unwrap();
+        assert_eq!(parsed.strategy, ChunkStrategy::Syntax);
+        assert_eq!(parsed.max_lines, 60);
+        assert_eq!(parsed.max_tokens, 512);
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn test_syntax_keeps_definitions_whole() {
+        let source = "\
+use std::fmt;
+
+/// First
+fn first() {
+    one();
+    two();
+}
+
+/// Second
+#[inline]
+fn second() {
+    three();
+}
+
+struct Limits {
+    a: u32,
+    b: u32,
+}
+
+impl Limits {
+    fn new() -> Self {
+        Self { a: 1, b: 2 }
+    }
+
+    fn total(&self) -> u32 {
+        self.a + self.b
+    }
+}
+";
+        let syntax = config(ChunkStrategy::Syntax, 8, 1_000);
+        let chunks = syntax.split("src/limits.rs", source);
+        assert_eq!(
+            spans(&chunks),
+            vec![(1, 8), (9, 14), (15, 19), (20, 24), (25, 28)]
+        );
+        assert!(chunks[1]
+            .text
+            .starts_with("/// Second\n#[inline]\nfn second()"));
+        assert!(chunks[3].text.starts_with("impl Limits {\n    fn new()"));
+        assert!(chunks[4].text.starts_with("    fn total(&self)"));
+
+        // Files without a grammar are split by tokens within `max_lines`
+        let chunks = syntax.split("notes.txt", source);
+        assert_eq!(chunks[0].end_line, 8);
+    }
+}
@@ -0,0 +1,106 @@
+//! Sources of a grounded answer, gathered from the CLI's `citation` events
+//! and from the source list of its `google_web_search` tool results.
+
+use crate::event::CitationEvent;
+use serde::Serialize;
+
+/// Name of the CLI's Google Search tool
+pub const WEB_SEARCH_TOOL: &str = "google_web_search";
+
+/// One source the answer is grounded in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Citation {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Character offsets of the supported passage in the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<u64>,
+}
+
+impl From<&CitationEvent> for Citation {
+    fn from(event: &CitationEvent) -> Self {
+        Self {
+            url: event.url.trim().to_string(),
+            title: event
+                .title
+                .as_deref()
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .map(str::to_string),
+            start_index: event.start_index,
+            end_index: event.end_index,
+        }
+    }
+}
+
+/// Add `citation` unless it has no URL or is already listed
+pub fn push_unique(citations: &mut Vec<Citation>, citation: Citation) {
+    if !citation.url.is_empty() && !citations.contains(&citation) {
+        citations.push(citation);
+    }
+}
+
+/// Sources listed at the end of a `google_web_search` result:
+///
+/// ```text
+/// Sources:
+/// [1] Rust Blog (https://blog.rust-lang.org/...)
+/// ```
+pub fn from_search_output(output: &str) -> Vec<Citation> {
+    let Some((_, sources)) = output.rsplit_once("Sources:") else {
+        return Vec::new();
+    };
+    sources
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let rest = line.strip_prefix('[')?;
+            let (_, entry) = rest.split_once(']')?;
+            let entry = entry.trim().strip_suffix(')')?;
+            let (title, url) = entry.rsplit_once(" (")?;
+            Some(Citation {
+                url: url.trim().to_string(),
+                title: Some(title.trim().to_string()).filter(|t| !t.is_empty()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_search_output() {
+        let output = "Web search results for \"rust 2024 edition\":\n\nThe edition shipped in Rust 1.85 [1].\n\nSources:\n[1] Announcing Rust 1.85.0 (https://blog.rust-lang.org/2025/02/20/Rust-1.85.0.html)\n[2] Editions (Guide) (https://doc.rust-lang.org/edition-guide/)\nnot a source";
+        let citations = from_search_output(output);
+        assert_eq!(citations.len(), 2);
+        assert_eq!(
+            citations[0].url,
+            "https://blog.rust-lang.org/2025/02/20/Rust-1.85.0.html"
+        );
+        assert_eq!(
+            citations[0].title.as_deref(),
+            Some("Announcing Rust 1.85.0")
+        );
+        assert_eq!(citations[1].title.as_deref(), Some("Editions (Guide)"));
+        assert!(from_search_output("no sources here").is_empty());
+    }
+
+    #[test]
+    fn test_push_unique() {
+        let mut citations = Vec::new();
+        let citation = Citation {
+            url: "https://example.com".to_string(),
+            ..Default::default()
+        };
+        push_unique(&mut citations, citation.clone());
+        push_unique(&mut citations, citation);
+        push_unique(&mut citations, Citation::default());
+        assert_eq!(citations.len(), 1);
+    }
+}
@@ -0,0 +1,85 @@
+//! The Gemini CLI's own chat files (`clean_cli_chats`).
+//!
+//! Besides the server's registry, the CLI keeps every session it ran as
+//! `~/.gemini/tmp/<project hash>/chats/session-*.json`, which is what
+//! `--resume` reads. Nothing ever deletes them, so on a long-running server
+//! they pile up. When idle sessions are forgotten, their chat files can be
+//! removed as well; files are matched by the `sessionId` they record.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct ChatFile {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+}
+
+/// Chat files under `gemini_home` recording one of `session_ids`
+pub fn find(gemini_home: &Path, session_ids: &[String]) -> Vec<PathBuf> {
+    let Ok(projects) = std::fs::read_dir(gemini_home.join("tmp")) else {
+        return Vec::new();
+    };
+    let mut found = Vec::new();
+    for project in projects.flatten() {
+        let Ok(chats) = std::fs::read_dir(project.path().join("chats")) else {
+            continue;
+        };
+        for chat in chats.flatten() {
+            let path = chat.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let recorded = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|raw| serde_json::from_str::<ChatFile>(&raw).ok());
+            if recorded.is_some_and(|chat| session_ids.contains(&chat.session_id)) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Delete the chat files of `session_ids`. Returns how many were removed.
+pub fn remove(gemini_home: &Path, session_ids: &[String]) -> Result<usize> {
+    let files = find(gemini_home, session_ids);
+    for file in &files {
+        std::fs::remove_file(file)
+            .with_context(|| format!("Failed to remove {}", file.display()))?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removes_only_chats_of_given_sessions() {
+        let home = tempfile::TempDir::new().unwrap();
+        let chats = home.path().join("tmp").join("abc123").join("chats");
+        std::fs::create_dir_all(&chats).unwrap();
+        std::fs::write(
+            chats.join("session-1.json"),
+            r#"{"sessionId": "s1", "messages": []}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            chats.join("session-2.json"),
+            r#"{"sessionId": "s2", "messages": []}"#,
+        )
+        .unwrap();
+        std::fs::write(chats.join("notes.txt"), "s1").unwrap();
+        std::fs::write(chats.join("broken.json"), "{").unwrap();
+
+        let ids = vec!["s1".to_string()];
+        assert_eq!(find(home.path(), &ids), vec![chats.join("session-1.json")]);
+        assert_eq!(remove(home.path(), &ids).unwrap(), 1);
+        assert!(!chats.join("session-1.json").exists());
+        assert!(chats.join("session-2.json").exists());
+        assert_eq!(remove(&home.path().join("missing"), &ids).unwrap(), 0);
+    }
+}
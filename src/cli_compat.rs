@@ -0,0 +1,213 @@
+//! Flag differences between Gemini CLI releases.
+//!
+//! The server runs `gemini --version` once at startup ([`detect`]) and
+//! `build_command` asks [`current`] how to spell the arguments. When the
+//! version is unknown, the flags of current releases are used.
+
+use serde::Deserialize;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// First release accepting `-o` for `--output-format`
+const SHORT_OUTPUT_FLAG_SINCE: CliVersion = CliVersion::new(0, 10, 0);
+/// First release with `stream-json` output; older ones print a single JSON
+/// document at the end of the run
+const STREAM_JSON_SINCE: CliVersion = CliVersion::new(0, 10, 0);
+/// First release treating a positional argument as a headless prompt; older
+/// ones need `--prompt`
+const POSITIONAL_PROMPT_SINCE: CliVersion = CliVersion::new(0, 11, 0);
+/// First release with `--resume`
+const RESUME_SINCE: CliVersion = CliVersion::new(0, 13, 0);
+
+static DETECTED: OnceLock<CliCompat> = OnceLock::new();
+
+/// `major.minor.patch` of a Gemini CLI release; pre-release suffixes such
+/// as `-nightly.20251020` are ignored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CliVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl CliVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// First `x.y[.z]` token of `gemini --version` output
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|token| {
+            let token = token.trim_start_matches('v');
+            let core = token.split(['-', '+']).next()?;
+            let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+            let major = parts.next()??;
+            let minor = parts.next()??;
+            let patch = parts.next().unwrap_or(Some(0))?;
+            parts
+                .next()
+                .is_none()
+                .then_some(Self::new(major, minor, patch))
+        })
+    }
+}
+
+impl fmt::Display for CliVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What the CLI prints on stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// One JSON event per line, as the run goes
+    #[default]
+    StreamJson,
+    /// One JSON document with the answer and statistics once the run ends
+    Json,
+}
+
+impl OutputFormat {
+    /// Value of the output format flag
+    pub fn as_arg(self) -> &'static str {
+        match self {
+            Self::StreamJson => "stream-json",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// How to build a command line for one CLI version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CliCompat {
+    /// `None` when detection failed; current flags are assumed
+    pub version: Option<CliVersion>,
+}
+
+impl CliCompat {
+    pub fn for_version(version: CliVersion) -> Self {
+        Self {
+            version: Some(version),
+        }
+    }
+
+    /// Flag selecting the output format
+    pub fn output_format_flag(&self) -> &'static str {
+        if self.older_than(SHORT_OUTPUT_FLAG_SINCE) {
+            "--output-format"
+        } else {
+            "-o"
+        }
+    }
+
+    /// Output format to ask for: `stream-json` where the release has it
+    pub fn output_format(&self) -> OutputFormat {
+        if self.older_than(STREAM_JSON_SINCE) {
+            OutputFormat::Json
+        } else {
+            OutputFormat::StreamJson
+        }
+    }
+
+    /// Whether a new session's prompt may be passed positionally
+    pub fn positional_prompt(&self) -> bool {
+        !self.older_than(POSITIONAL_PROMPT_SINCE)
+    }
+
+    /// Whether `--resume` exists
+    pub fn supports_resume(&self) -> bool {
+        !self.older_than(RESUME_SINCE)
+    }
+
+    /// Error for a resume on a CLI without `--resume`
+    pub fn resume_unsupported(&self) -> Option<String> {
+        let version = self.version.filter(|_| !self.supports_resume())?;
+        Some(format!(
+            "Gemini CLI {} cannot resume sessions; upgrade to {} or newer",
+            version, RESUME_SINCE
+        ))
+    }
+
+    fn older_than(&self, since: CliVersion) -> bool {
+        self.version.is_some_and(|v| v < since)
+    }
+}
+
+/// Run `gemini --version` and cache the result for [`current`]. Later calls
+/// return the cached value.
+pub async fn detect() -> CliCompat {
+    if let Some(compat) = DETECTED.get() {
+        return *compat;
+    }
+    let version = match crate::gemini::cli_version().await {
+        Ok(output) => {
+            let version = CliVersion::parse(&output);
+            if version.is_none() {
+                tracing::warn!(
+                    "could not parse Gemini CLI version {:?}; assuming a current release",
+                    output
+                );
+            }
+            version
+        }
+        Err(err) => {
+            tracing::warn!(
+                "could not detect the Gemini CLI version ({:#}); assuming a current release",
+                err
+            );
+            None
+        }
+    };
+    *DETECTED.get_or_init(|| CliCompat { version })
+}
+
+/// The compatibility settings found by [`detect`], or current flags if it
+/// has not run
+pub fn current() -> CliCompat {
+    DETECTED.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(CliVersion::parse("0.13.0"), Some(CliVersion::new(0, 13, 0)));
+        assert_eq!(
+            CliVersion::parse("gemini v0.12.1-nightly.20251020\n"),
+            Some(CliVersion::new(0, 12, 1))
+        );
+        assert_eq!(CliVersion::parse("1.2"), Some(CliVersion::new(1, 2, 0)));
+        assert_eq!(CliVersion::parse("unknown"), None);
+        assert_eq!(CliVersion::parse("1.2.3.4"), None);
+    }
+
+    #[test]
+    fn test_compat_flags() {
+        let unknown = CliCompat::default();
+        assert_eq!(unknown.output_format_flag(), "-o");
+        assert_eq!(unknown.output_format(), OutputFormat::StreamJson);
+        assert!(unknown.positional_prompt());
+        assert!(unknown.supports_resume());
+
+        let old = CliCompat::for_version(CliVersion::new(0, 9, 4));
+        assert_eq!(old.output_format_flag(), "--output-format");
+        assert_eq!(old.output_format().as_arg(), "json");
+        assert!(!old.positional_prompt());
+        assert_eq!(
+            old.resume_unsupported().as_deref(),
+            Some("Gemini CLI 0.9.4 cannot resume sessions; upgrade to 0.13.0 or newer")
+        );
+
+        let new = CliCompat::for_version(CliVersion::new(0, 13, 0));
+        assert!(new.supports_resume());
+        assert_eq!(new.resume_unsupported(), None);
+    }
+}
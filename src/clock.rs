@@ -0,0 +1,146 @@
+//! Injectable time and ID sources.
+//!
+//! Timestamps (session records, memory facts, deletion and purge times,
+//! uptime and request ages) and request IDs come from a [`Clock`] and an
+//! [`IdGenerator`] instead of the system directly. Tests and snapshot
+//! harnesses swap in a [`ManualClock`] and a fixed [`SequentialIds`] to get
+//! byte-identical outputs across runs. Timeouts are measured by Tokio's
+//! timer; pause it with `tokio::time::pause()` to control them.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Wall-clock time, for timestamps
+    fn now(&self) -> SystemTime;
+    /// Monotonic time, for ages and durations
+    fn instant(&self) -> Instant;
+}
+
+/// The system's clocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when advanced
+#[derive(Debug)]
+pub struct ManualClock {
+    start: SystemTime,
+    base: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// A clock reading `start` until advanced
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            start,
+            base: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn instant(&self) -> Instant {
+        self.base + *self.elapsed.lock().unwrap()
+    }
+}
+
+/// A shareable [`Clock`], the system's by default
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.0.now()
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0.instant()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl From<Arc<dyn Clock>> for SharedClock {
+    fn from(clock: Arc<dyn Clock>) -> Self {
+        Self(clock)
+    }
+}
+
+/// Source of the IDs the server assigns to tool calls
+pub trait IdGenerator: fmt::Debug + Send + Sync {
+    fn next_id(&self) -> u64;
+}
+
+/// Consecutive IDs from a starting value
+#[derive(Debug)]
+pub struct SequentialIds(AtomicU64);
+
+impl SequentialIds {
+    pub fn starting_at(first: u64) -> Self {
+        Self(AtomicU64::new(first))
+    }
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        Self::starting_at(1)
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_only_when_advanced() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let before = clock.instant();
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.instant(), before);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+        assert_eq!(clock.instant() - before, Duration::from_secs(90));
+
+        let ids = SequentialIds::starting_at(7);
+        assert_eq!((ids.next_id(), ids.next_id()), (7, 8));
+    }
+}
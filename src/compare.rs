@@ -0,0 +1,267 @@
+//! Side-by-side comparison of answers to the same prompt, used by the
+//! `gemini_compare` tool to validate model upgrades and prompt changes.
+
+use serde::Serialize;
+
+/// Answers longer than this (in lines) are compared by similarity only
+const MAX_DIFF_LINES: usize = 2000;
+
+/// One profile's answer, or the reason it has none
+#[derive(Debug, Clone, Copy)]
+pub struct Answer<'a> {
+    pub profile: &'a str,
+    pub outcome: Result<&'a str, &'a str>,
+}
+
+/// Differences between the baseline answer and one other answer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnswerDiff {
+    pub profile: String,
+    /// Share of lines both answers have in common, from 0.0 to 1.0
+    pub similarity: f64,
+    pub lines_removed: usize,
+    pub lines_added: usize,
+    /// `-`/`+` prefixed lines relative to the baseline; empty when identical
+    /// or too long to diff
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub diff: String,
+}
+
+/// How the answers disagree, relative to the first successful one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    /// Profile whose answer the others are compared to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<String>,
+    /// Every profile succeeded with the same answer (ignoring surrounding
+    /// whitespace)
+    pub agree: bool,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diffs: Vec<AnswerDiff>,
+}
+
+/// Compare every successful answer against the first successful one
+pub fn compare(answers: &[Answer<'_>]) -> Comparison {
+    let failed: Vec<String> = answers
+        .iter()
+        .filter_map(|a| a.outcome.err().map(|e| format!("{} ({})", a.profile, e)))
+        .collect();
+    let mut succeeded = answers
+        .iter()
+        .filter_map(|a| a.outcome.ok().map(|text| (a.profile, text.trim())));
+
+    let Some((baseline, baseline_text)) = succeeded.next() else {
+        return Comparison {
+            baseline: None,
+            agree: false,
+            summary: format!("No profile succeeded: {}", failed.join("; ")),
+            diffs: Vec::new(),
+        };
+    };
+
+    let diffs: Vec<AnswerDiff> = succeeded
+        .filter(|(_, text)| text != &baseline_text)
+        .map(|(profile, text)| diff_answers(profile, baseline_text, text))
+        .collect();
+
+    let mut parts = Vec::new();
+    if diffs.is_empty() {
+        parts.push(format!(
+            "All {} successful answers are identical",
+            answers.len() - failed.len()
+        ));
+    } else {
+        let differing: Vec<String> = diffs
+            .iter()
+            .map(|d| {
+                format!(
+                    "{} ({:.0}% similar, -{}/+{} lines)",
+                    d.profile,
+                    d.similarity * 100.0,
+                    d.lines_removed,
+                    d.lines_added
+                )
+            })
+            .collect();
+        parts.push(format!(
+            "{} of {} answers differ from `{}`: {}",
+            diffs.len(),
+            answers.len(),
+            baseline,
+            differing.join(", ")
+        ));
+    }
+    if !failed.is_empty() {
+        parts.push(format!("failed: {}", failed.join("; ")));
+    }
+
+    Comparison {
+        baseline: Some(baseline.to_string()),
+        agree: diffs.is_empty() && failed.is_empty(),
+        summary: parts.join("; "),
+        diffs,
+    }
+}
+
+fn diff_answers(profile: &str, baseline: &str, other: &str) -> AnswerDiff {
+    let old: Vec<&str> = baseline.lines().collect();
+    let new: Vec<&str> = other.lines().collect();
+    let total = old.len() + new.len();
+
+    if old.len() > MAX_DIFF_LINES || new.len() > MAX_DIFF_LINES {
+        let common = old.iter().filter(|line| new.contains(line)).count();
+        return AnswerDiff {
+            profile: profile.to_string(),
+            similarity: ratio(2 * common, total),
+            lines_removed: old.len() - common,
+            lines_added: new.len().saturating_sub(common),
+            diff: String::new(),
+        };
+    }
+
+    let ops = line_diff(&old, &new);
+    let mut diff = String::new();
+    let (mut removed, mut added) = (0, 0);
+    for op in &ops {
+        match op {
+            DiffOp::Same(_) => continue,
+            DiffOp::Removed(line) => {
+                removed += 1;
+                diff.push('-');
+                diff.push_str(line);
+            }
+            DiffOp::Added(line) => {
+                added += 1;
+                diff.push('+');
+                diff.push_str(line);
+            }
+        }
+        diff.push('\n');
+    }
+    let common = ops.len() - removed - added;
+
+    AnswerDiff {
+        profile: profile.to_string(),
+        similarity: ratio(2 * common, total),
+        lines_removed: removed,
+        lines_added: added,
+        diff,
+    }
+}
+
+fn ratio(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum DiffOp<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line diff based on the longest common subsequence
+fn line_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    // lcs[i][j]: length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            ops.push(DiffOp::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(DiffOp::Removed(old[i]));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Added(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|line| DiffOp::Removed(line)));
+    ops.extend(new[j..].iter().map(|line| DiffOp::Added(line)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer<'a>(profile: &'a str, text: &'a str) -> Answer<'a> {
+        Answer {
+            profile,
+            outcome: Ok(text),
+        }
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let ops = line_diff(&["a", "b", "c"], &["a", "x", "c", "d"]);
+        assert_eq!(
+            ops,
+            vec![
+                DiffOp::Same("a"),
+                DiffOp::Removed("b"),
+                DiffOp::Added("x"),
+                DiffOp::Same("c"),
+                DiffOp::Added("d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_reports_disagreements() {
+        let comparison = compare(&[
+            answer("pro", "use a mutex\nthen lock it\n"),
+            answer("flash", "use a mutex\nthen lock it"),
+            answer("lite", "use a mutex\nor a channel"),
+            Answer {
+                profile: "broken",
+                outcome: Err("quota exceeded"),
+            },
+        ]);
+
+        assert_eq!(comparison.baseline.as_deref(), Some("pro"));
+        assert!(!comparison.agree);
+        assert_eq!(comparison.diffs.len(), 1);
+        let diff = &comparison.diffs[0];
+        assert_eq!(diff.profile, "lite");
+        assert_eq!(diff.similarity, 0.5);
+        assert_eq!(diff.diff, "-then lock it\n+or a channel\n");
+        assert_eq!(
+            comparison.summary,
+            "1 of 4 answers differ from `pro`: lite (50% similar, -1/+1 lines); \
+             failed: broken (quota exceeded)"
+        );
+    }
+
+    #[test]
+    fn test_compare_agreement_and_total_failure() {
+        let agree = compare(&[answer("a", "42"), answer("b", " 42\n")]);
+        assert!(agree.agree);
+        assert_eq!(agree.summary, "All 2 successful answers are identical");
+
+        let failed = compare(&[Answer {
+            profile: "a",
+            outcome: Err("timeout"),
+        }]);
+        assert_eq!(failed.baseline, None);
+        assert_eq!(failed.summary, "No profile succeeded: a (timeout)");
+    }
+}
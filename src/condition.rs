@@ -0,0 +1,201 @@
+//! Conditions on an answer, used by scripted multi-turn calls (`TURNS`) and
+//! workflow steps to stop early or pick a branch without a round trip
+//! through the client.
+//!
+//! A condition either searches the answer with a regular expression or
+//! looks up a path in the JSON the answer contains, e.g. `$.status` in
+//! `{"status": "done"}` or in a fenced ```json block of a Markdown answer.
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use rmcp::schemars;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A test on the previous answer. Workflow files may spell the fields in
+/// lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, schemars::JsonSchema)]
+pub struct Condition {
+    /// Regular expression searched in the answer
+    #[serde(rename = "REGEX", alias = "regex", default)]
+    pub regex: Option<String>,
+    /// Path into the JSON of the answer: `$.field`, `$.items[0].name`
+    #[serde(rename = "JSON_PATH", alias = "json_path", default)]
+    pub json_path: Option<String>,
+    /// Value found at `JSON_PATH` must equal this; without it any value
+    /// other than `null` and `false` matches
+    #[serde(rename = "EQUALS", alias = "equals", default)]
+    pub equals: Option<Value>,
+    /// Invert the result
+    #[serde(rename = "NOT", alias = "not", default)]
+    pub negate: bool,
+}
+
+/// A validated [`Condition`], ready to test answers
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    test: Test,
+    negate: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Test {
+    Regex(Regex),
+    JsonPath {
+        path: Vec<Segment>,
+        equals: Option<Value>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl Condition {
+    /// Check that exactly one test is given and that it parses
+    pub fn compile(&self) -> Result<Compiled> {
+        let test = match (&self.regex, &self.json_path) {
+            (Some(regex), None) => Test::Regex(
+                Regex::new(regex).with_context(|| format!("invalid REGEX `{}`", regex))?,
+            ),
+            (None, Some(path)) => Test::JsonPath {
+                path: parse_path(path)?,
+                equals: self.equals.clone(),
+            },
+            _ => bail!("a condition needs exactly one of REGEX and JSON_PATH"),
+        };
+        if self.equals.is_some() && self.json_path.is_none() {
+            bail!("EQUALS only applies to JSON_PATH conditions");
+        }
+        Ok(Compiled {
+            test,
+            negate: self.negate,
+        })
+    }
+}
+
+impl Compiled {
+    pub fn matches(&self, answer: &str) -> bool {
+        let matched = match &self.test {
+            Test::Regex(regex) => regex.is_match(answer),
+            Test::JsonPath { path, equals } => {
+                let found = answer_json(answer).and_then(|json| lookup(&json, path).cloned());
+                match (found, equals) {
+                    (Some(found), Some(expected)) => &found == expected,
+                    (Some(found), None) => !matches!(found, Value::Null | Value::Bool(false)),
+                    (None, _) => false,
+                }
+            }
+        };
+        matched != self.negate
+    }
+}
+
+/// `$.a.b[0]` (the `$` is optional) into its keys and indexes
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let rest = path.trim();
+    let rest = rest.strip_prefix('$').unwrap_or(rest);
+    let mut segments = Vec::new();
+    for part in rest.split('.') {
+        let (key, mut indexes) = match part.find('[') {
+            Some(at) => (&part[..at], &part[at..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(Segment::Key(key.to_string()));
+        }
+        while let Some(inner) = indexes.strip_prefix('[') {
+            let Some((index, after)) = inner.split_once(']') else {
+                bail!("unclosed `[` in JSON_PATH `{}`", path);
+            };
+            let index = index
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid index `{}` in JSON_PATH `{}`", index, path))?;
+            segments.push(Segment::Index(index));
+            indexes = after;
+        }
+        if !indexes.is_empty() {
+            bail!("unexpected `{}` in JSON_PATH `{}`", indexes, path);
+        }
+    }
+    if segments.is_empty() {
+        bail!("JSON_PATH `{}` selects nothing", path);
+    }
+    Ok(segments)
+}
+
+fn lookup<'a>(json: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(json, |value, segment| match segment {
+        Segment::Key(key) => value.get(key),
+        Segment::Index(index) => value.get(index),
+    })
+}
+
+/// The JSON in an answer: the whole answer, else its first fenced ```json
+/// block, else the span from the first `{` to the last `}`
+fn answer_json(answer: &str) -> Option<Value> {
+    let answer = answer.trim();
+    if let Ok(json) = serde_json::from_str(answer) {
+        return Some(json);
+    }
+    if let Some(start) = answer.find("```json") {
+        let block = &answer[start + "```json".len()..];
+        if let Some(end) = block.find("```") {
+            if let Ok(json) = serde_json::from_str(block[..end].trim()) {
+                return Some(json);
+            }
+        }
+    }
+    let start = answer.find('{')?;
+    let end = answer.rfind('}')?;
+    serde_json::from_str(answer.get(start..=end)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn condition(value: Value) -> Compiled {
+        serde_json::from_value::<Condition>(value)
+            .unwrap()
+            .compile()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_regex_and_json_path_conditions() {
+        let answer = "All checks ran.\n```json\n{\"status\": \"done\", \"failures\": [], \"files\": [{\"name\": \"a.rs\"}]}\n```";
+
+        assert!(condition(json!({"REGEX": "(?i)all CHECKS"})).matches(answer));
+        assert!(!condition(json!({"REGEX": "FAILED", "NOT": false})).matches(answer));
+        assert!(condition(json!({"REGEX": "FAILED", "NOT": true})).matches(answer));
+
+        assert!(condition(json!({"JSON_PATH": "$.status", "EQUALS": "done"})).matches(answer));
+        assert!(condition(json!({"JSON_PATH": "files[0].name", "EQUALS": "a.rs"})).matches(answer));
+        assert!(condition(json!({"JSON_PATH": "$.failures", "EQUALS": []})).matches(answer));
+        assert!(!condition(json!({"JSON_PATH": "$.missing"})).matches(answer));
+        assert!(!condition(json!({"JSON_PATH": "$.status"})).matches("no json here"));
+        assert!(condition(json!({"JSON_PATH": "$.ok"})).matches("Result: {\"ok\": true} (final)"));
+    }
+
+    #[test]
+    fn test_invalid_conditions_are_rejected() {
+        let compile = |value: Value| {
+            serde_json::from_value::<Condition>(value)
+                .unwrap()
+                .compile()
+                .map(|_| ())
+        };
+        assert!(compile(json!({})).is_err());
+        assert!(compile(json!({"REGEX": "a", "JSON_PATH": "$.a"})).is_err());
+        assert!(compile(json!({"REGEX": "("})).is_err());
+        assert!(compile(json!({"REGEX": "a", "EQUALS": 1})).is_err());
+        assert!(compile(json!({"JSON_PATH": "$"})).is_err());
+        assert!(compile(json!({"JSON_PATH": "$.items[x]"})).is_err());
+        assert!(compile(json!({"JSON_PATH": "$.items[0"})).is_err());
+    }
+}
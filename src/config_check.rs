@@ -0,0 +1,238 @@
+//! Strict parsing of the server config with actionable errors.
+//!
+//! Every config section rejects unknown fields, so a typo such as
+//! `timeoutSecs` fails instead of being silently ignored. Errors name the
+//! offending value by its JSON pointer (`/rate_limit/global_per_minute`),
+//! and unknown fields come with the closest known name as a hint. Checks on
+//! value ranges report through the same [`ConfigError`], all at once.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// One problem in the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// JSON pointer of the offending value; empty for the whole document
+    pub pointer: String,
+    pub message: String,
+    /// Suggested fix, e.g. the field name a typo was probably meant to be
+    pub hint: Option<String>,
+}
+
+impl Issue {
+    pub fn new(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            message: message.into(),
+            hint: None,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}", pointer, self.message)?;
+        if let Some(ref hint) = self.hint {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// A config file that failed to parse or validate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub issues: Vec<Issue>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config {}", self.path.display())?;
+        match self.issues.as_slice() {
+            [issue] => write!(f, ": {}", issue),
+            issues => {
+                for issue in issues {
+                    write!(f, "\n  {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Parse `raw`, the contents of the config file at `path`, reporting where
+/// it went wrong
+pub fn parse<T: DeserializeOwned>(path: &Path, raw: &str) -> Result<T, ConfigError> {
+    let mut deserializer = serde_json::Deserializer::from_str(raw);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| error(path, err))
+}
+
+/// Like [`parse`], for config values already read from `path` in any format
+pub fn parse_value<T: DeserializeOwned>(path: &Path, value: Value) -> Result<T, ConfigError> {
+    serde_path_to_error::deserialize(value).map_err(|err| error(path, err))
+}
+
+fn error<E: fmt::Display>(path: &Path, err: serde_path_to_error::Error<E>) -> ConfigError {
+    let mut pointer = pointer(err.path());
+    let message = err.inner().to_string();
+    let unknown = unknown_field(&message);
+    // Depending on where deserialization failed, the path may end at the map
+    // holding the unknown field rather than at the field
+    if let Some(field) = unknown.map(escape) {
+        if !pointer.ends_with(&format!("/{}", field)) {
+            pointer = format!("{}/{}", pointer, field);
+        }
+    }
+    ConfigError {
+        path: path.to_path_buf(),
+        issues: vec![Issue {
+            pointer,
+            hint: unknown
+                .and_then(|field| closest(field, &expected_fields(&message)))
+                .map(|name| format!("did you mean `{}`?", name)),
+            message,
+        }],
+    }
+}
+
+/// JSON pointer of a `serde_path_to_error` path
+fn pointer(path: &serde_path_to_error::Path) -> String {
+    use serde_path_to_error::Segment;
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Seq { index } => Some(index.to_string()),
+            Segment::Map { key } => Some(escape(key)),
+            Segment::Enum { .. } | Segment::Unknown => None,
+        })
+        .map(|segment| format!("/{}", segment))
+        .collect()
+}
+
+/// `key` escaped as a JSON pointer segment
+pub fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// The field named in serde's "unknown field `x`, expected ..." message
+fn unknown_field(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("unknown field `")?;
+    rest.split('`').next()
+}
+
+/// The field names serde lists after "expected"
+fn expected_fields(message: &str) -> Vec<&str> {
+    let Some((_, expected)) = message.split_once("expected") else {
+        return Vec::new();
+    };
+    expected.split('`').skip(1).step_by(2).collect()
+}
+
+/// The known name `field` was most likely meant to be: the same name in
+/// another case or separator style, or one at most two edits away
+fn closest<'a>(field: &str, known: &[&'a str]) -> Option<&'a str> {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let wanted = normalize(field);
+    if let Some(same) = known.iter().find(|name| normalize(name) == wanted) {
+        return Some(same);
+    }
+    known
+        .iter()
+        .map(|name| (edit_distance(&wanted, &normalize(name)), *name))
+        .filter(|&(distance, _)| distance <= 2)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Config {
+        timeout_secs: Option<u64>,
+        #[serde(default)]
+        limits: Vec<Limit>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Limit {
+        per_minute: u32,
+    }
+
+    fn issue(raw: &str) -> Issue {
+        let err = parse::<Config>(Path::new("c.json"), raw).unwrap_err();
+        assert_eq!(err.issues.len(), 1);
+        err.issues[0].clone()
+    }
+
+    #[test]
+    fn test_unknown_fields_are_pointed_out() {
+        let typo = issue(r#"{"timeoutSecs": 60}"#);
+        assert_eq!(typo.pointer, "/timeoutSecs");
+        assert_eq!(typo.hint.as_deref(), Some("did you mean `timeout_secs`?"));
+
+        let nested = issue(r#"{"limits": [{"per_minute": 1}, {"per_minte": 2}]}"#);
+        assert_eq!(nested.pointer, "/limits/1/per_minte");
+        assert_eq!(nested.hint.as_deref(), Some("did you mean `per_minute`?"));
+
+        assert_eq!(issue(r#"{"colour": 1}"#).hint, None);
+    }
+
+    #[test]
+    fn test_type_errors_are_pointed_out() {
+        let wrong = issue(r#"{"limits": [{"per_minute": "ten"}]}"#);
+        assert_eq!(wrong.pointer, "/limits/0/per_minute");
+        assert!(wrong.message.starts_with("invalid type: string \"ten\""));
+
+        let err = ConfigError {
+            path: PathBuf::from("c.json"),
+            issues: vec![wrong, Issue::new("/timeout_secs", "must be at most 3600")],
+        };
+        assert!(err
+            .to_string()
+            .ends_with("\n  /timeout_secs: must be at most 3600"));
+        assert!(parse::<Config>(Path::new("c.json"), r#"{"timeout_secs": 5}"#).is_ok());
+
+        let from_value = parse_value::<Config>(
+            Path::new("c.toml"),
+            serde_json::json!({"limits": [{"per_minte": 2}]}),
+        )
+        .unwrap_err();
+        assert_eq!(from_value.issues[0].pointer, "/limits/0/per_minte");
+    }
+}
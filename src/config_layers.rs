@@ -0,0 +1,338 @@
+//! Layered server configuration in JSON, TOML or YAML.
+//!
+//! The effective config is merged from, lowest precedence first: the
+//! built-in defaults, the user config in `~/.config/gemini-mcp/`, the project
+//! config in the working directory (or `GEMINI_MCP_CONFIG_PATH`),
+//! `GEMINI_MCP__*` environment variables and `--set key=value` flags.
+//! Objects are merged key by key; any other value, arrays included, replaces
+//! the lower layer's value as a whole. Every layer is checked on its own, so
+//! an error names the file (or variable) that caused it.
+
+use crate::config_check::{ConfigError, Issue};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Project config file names, in the order they are looked for
+pub const PROJECT_FILES: &[&str] = &[
+    "gemini-mcp.config.json",
+    "gemini-mcp.toml",
+    "gemini-mcp.yaml",
+    "gemini-mcp.yml",
+];
+/// User config file names inside [`user_config_dir`]
+pub const USER_FILES: &[&str] = &["config.json", "config.toml", "config.yaml", "config.yml"];
+/// Prefix of the variables overriding config values, e.g.
+/// `GEMINI_MCP__RATE_LIMIT__GLOBAL_PER_MINUTE=30`
+pub const ENV_PREFIX: &str = "GEMINI_MCP__";
+/// Source name of the environment layer
+pub const ENV_SOURCE: &str = "$GEMINI_MCP__*";
+/// Source name of the `--set` layer
+pub const CLI_SOURCE: &str = "--set";
+
+/// One source of config values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// File the values came from, or [`ENV_SOURCE`] / [`CLI_SOURCE`]
+    pub source: PathBuf,
+    pub value: Value,
+}
+
+/// `~/.config/gemini-mcp`, or `$XDG_CONFIG_HOME/gemini-mcp`
+pub fn user_config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| crate::gemini::home_dir().map(|home| home.join(".config")))?;
+    Some(base.join("gemini-mcp"))
+}
+
+/// The first of `names` that exists in `dir`
+pub fn find_file(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Read the config file at `path`, in the format its extension names
+/// (JSON unless `.toml`, `.yaml` or `.yml`)
+pub fn read_file(path: &Path) -> Result<Layer, ConfigError> {
+    let raw = std::fs::read_to_string(path).map_err(|err| ConfigError {
+        path: path.to_path_buf(),
+        issues: vec![Issue::new("", format!("failed to read: {}", err))],
+    })?;
+    Ok(Layer {
+        source: path.to_path_buf(),
+        value: parse_file(path, &raw)?,
+    })
+}
+
+fn parse_file(path: &Path, raw: &str) -> Result<Value, ConfigError> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let parsed = match extension.as_deref() {
+        Some("toml") => parse_toml(raw),
+        Some("yaml" | "yml") => parse_yaml(raw),
+        _ => serde_json::from_str(raw).map_err(|err| err.to_string()),
+    };
+    let value = parsed.map_err(|message| ConfigError {
+        path: path.to_path_buf(),
+        issues: vec![Issue::new("", message)],
+    })?;
+    // An empty YAML document is an empty config, not a type error
+    Ok(match value {
+        Value::Null => Value::Object(Map::new()),
+        value => value,
+    })
+}
+
+#[cfg(feature = "server")]
+fn parse_toml(raw: &str) -> Result<Value, String> {
+    toml::from_str(raw).map_err(|err| err.to_string().trim_end().to_string())
+}
+
+#[cfg(not(feature = "server"))]
+fn parse_toml(_raw: &str) -> Result<Value, String> {
+    Err("TOML configs require the `server` feature".to_string())
+}
+
+#[cfg(feature = "server")]
+fn parse_yaml(raw: &str) -> Result<Value, String> {
+    if raw.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_yaml::from_str(raw).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "server"))]
+fn parse_yaml(_raw: &str) -> Result<Value, String> {
+    Err("YAML configs require the `server` feature".to_string())
+}
+
+/// Layer of the `GEMINI_MCP__*` variables among `vars`. `__` separates
+/// nested keys, which are lowercased: `GEMINI_MCP__CHAT__MAX_TURNS=20` sets
+/// `chat.max_turns`. `None` when no variable has the prefix.
+pub fn env_layer(vars: impl IntoIterator<Item = (String, String)>) -> Option<Layer> {
+    let mut root = Value::Object(Map::new());
+    let mut any = false;
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<String> = key.split("__").map(str::to_ascii_lowercase).collect();
+        if path.iter().any(String::is_empty) {
+            continue;
+        }
+        set(&mut root, &path, scalar(&raw));
+        any = true;
+    }
+    any.then(|| Layer {
+        source: PathBuf::from(ENV_SOURCE),
+        value: root,
+    })
+}
+
+/// Layer of `--set` flags, each `dotted.key=value`
+pub fn cli_layer(assignments: &[String]) -> Result<Option<Layer>, ConfigError> {
+    if assignments.is_empty() {
+        return Ok(None);
+    }
+    let mut root = Value::Object(Map::new());
+    let mut issues = Vec::new();
+    for assignment in assignments {
+        let Some((key, raw)) = assignment.split_once('=') else {
+            issues.push(Issue::new(
+                "",
+                format!("`{}` is not of the form key=value", assignment),
+            ));
+            continue;
+        };
+        let path: Vec<String> = key.trim().split('.').map(str::to_string).collect();
+        if path.iter().any(String::is_empty) {
+            issues.push(Issue::new("", format!("`{}` has an empty key", assignment)));
+            continue;
+        }
+        set(&mut root, &path, scalar(raw));
+    }
+    if !issues.is_empty() {
+        return Err(ConfigError {
+            path: PathBuf::from(CLI_SOURCE),
+            issues,
+        });
+    }
+    Ok(Some(Layer {
+        source: PathBuf::from(CLI_SOURCE),
+        value: root,
+    }))
+}
+
+/// A value given as text: JSON if it parses (`30`, `true`, `["a"]`), else
+/// the text itself
+fn scalar(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+fn set(root: &mut Value, path: &[String], value: Value) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+    let mut node = root;
+    for key in parents {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .expect("just made an object")
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+    node.as_object_mut()
+        .expect("just made an object")
+        .insert(last.clone(), value);
+}
+
+/// `layers` merged in order, later ones taking precedence
+pub fn merge(layers: &[Layer]) -> Value {
+    let mut merged = Value::Object(Map::new());
+    for layer in layers {
+        merge_into(&mut merged, &layer.value);
+    }
+    merged
+}
+
+fn merge_into(base: &mut Value, over: &Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(key) {
+                    Some(existing) => merge_into(existing, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layer(value: Value) -> Layer {
+        Layer {
+            source: PathBuf::from("test"),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_later_layers_take_precedence() {
+        let merged = merge(&[
+            layer(
+                json!({"timeout_secs": 60, "rate_limit": {"global_per_minute": 10}, "additional_args": ["-m", "a"]}),
+            ),
+            layer(
+                json!({"rate_limit": {"per_session_per_minute": 2}, "additional_args": ["-m", "b"]}),
+            ),
+            layer(json!({"timeout_secs": 120})),
+        ]);
+        assert_eq!(
+            merged,
+            json!({
+                "timeout_secs": 120,
+                "rate_limit": {"global_per_minute": 10, "per_session_per_minute": 2},
+                "additional_args": ["-m", "b"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_env_and_cli_layers() {
+        let env = env_layer([
+            ("GEMINI_MCP__TIMEOUT_SECS".to_string(), "90".to_string()),
+            ("GEMINI_MCP__CHAT__MAX_TURNS".to_string(), "20".to_string()),
+            (
+                "GEMINI_MCP__ARTIFACTS_DIR".to_string(),
+                "/tmp/out".to_string(),
+            ),
+            ("GEMINI_MCP_CONFIG_PATH".to_string(), "x.json".to_string()),
+            ("GEMINI_MCP____".to_string(), "1".to_string()),
+        ])
+        .unwrap();
+        assert_eq!(
+            env.value,
+            json!({"timeout_secs": 90, "chat": {"max_turns": 20}, "artifacts_dir": "/tmp/out"})
+        );
+        assert_eq!(env_layer([("HOME".to_string(), "/root".to_string())]), None);
+
+        let cli = cli_layer(&[
+            "sandbox=true".to_string(),
+            "env.GOOGLE_CLOUD_PROJECT=my-project".to_string(),
+            "fanout_models=[\"a\", \"b\"]".to_string(),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            cli.value,
+            json!({"sandbox": true, "env": {"GOOGLE_CLOUD_PROJECT": "my-project"}, "fanout_models": ["a", "b"]})
+        );
+        let err = cli_layer(&["sandbox".to_string(), ".x=1".to_string()]).unwrap_err();
+        assert_eq!(err.issues.len(), 2);
+    }
+
+    #[test]
+    fn test_reads_every_format() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let expected = json!({"timeout_secs": 60, "rate_limit": {"global_per_minute": 10}});
+        // TOML and YAML are read only with the `server` feature
+        let formats = [
+            (
+                "a.json",
+                r#"{"timeout_secs": 60, "rate_limit": {"global_per_minute": 10}}"#,
+            ),
+            #[cfg(feature = "server")]
+            (
+                "a.toml",
+                "timeout_secs = 60\n\n[rate_limit]\nglobal_per_minute = 10\n",
+            ),
+            #[cfg(feature = "server")]
+            (
+                "a.yaml",
+                "timeout_secs: 60\nrate_limit:\n  global_per_minute: 10\n",
+            ),
+        ];
+        for (name, raw) in formats {
+            let path = dir.path().join(name);
+            std::fs::write(&path, raw).unwrap();
+            assert_eq!(read_file(&path).unwrap().value, expected, "{}", name);
+        }
+
+        #[cfg(feature = "server")]
+        {
+            let empty = dir.path().join("empty.yml");
+            std::fs::write(&empty, "").unwrap();
+            assert_eq!(read_file(&empty).unwrap().value, json!({}));
+
+            let broken = dir.path().join("broken.toml");
+            std::fs::write(&broken, "timeout_secs = ").unwrap();
+            let err = read_file(&broken).unwrap_err();
+            assert_eq!(err.path, broken);
+            assert_eq!(err.issues[0].pointer, "");
+        }
+
+        std::fs::write(dir.path().join("b.toml"), "").unwrap();
+        assert_eq!(
+            find_file(dir.path(), &["missing.json", "b.toml", "a.json"]),
+            Some(dir.path().join("b.toml"))
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Prompts and parsing of the `gemini_consensus` pipeline.
+//!
+//! Several candidate answers to one prompt are generated independently, then
+//! a judging call critiques them against each other and writes the final
+//! answer, keeping what the candidates get right and dropping their
+//! mistakes. The judge is asked for its critique and its answer under fixed
+//! headings so the two can be returned separately.
+
+/// Candidates generated unless the call sets `CANDIDATES`
+pub const DEFAULT_CANDIDATES: usize = 3;
+/// Bounds on `CANDIDATES`
+pub const MIN_CANDIDATES: usize = 2;
+pub const MAX_CANDIDATES: usize = 5;
+
+const CRITIQUE_HEADING: &str = "## Critique";
+const FINAL_HEADING: &str = "## Final answer";
+
+/// Prompt asking the judge to critique `candidates` (name and answer) to
+/// `prompt` and merge them into one answer
+pub fn judge_prompt(prompt: &str, candidates: &[(&str, &str)]) -> String {
+    let mut out = format!(
+        "You are judging {} candidate answers to the same request. Critique them: \
+         point out errors, omissions and contradictions, and which claims the \
+         candidates agree on. Then write the best possible answer to the request, \
+         merging the correct parts of the candidates and fixing their mistakes. Do \
+         not mention the candidates in the final answer.\n\n\
+         Reply in exactly this format:\n{}\n<your critique>\n{}\n<the final answer>\n\n\
+         <request>\n{}\n</request>\n",
+        candidates.len(),
+        CRITIQUE_HEADING,
+        FINAL_HEADING,
+        prompt
+    );
+    for (name, answer) in candidates {
+        out.push_str(&format!(
+            "\n<candidate name=\"{}\">\n{}\n</candidate>\n",
+            name,
+            answer.trim()
+        ));
+    }
+    out
+}
+
+/// The judge's critique and final answer. Without the expected headings the
+/// whole reply is the answer.
+pub fn split_verdict(reply: &str) -> (Option<String>, String) {
+    let Some(at) = reply.find(FINAL_HEADING) else {
+        return (None, reply.trim().to_string());
+    };
+    let critique = reply[..at].trim();
+    let critique = critique
+        .strip_prefix(CRITIQUE_HEADING)
+        .unwrap_or(critique)
+        .trim();
+    let answer = reply[at + FINAL_HEADING.len()..].trim().to_string();
+    if answer.is_empty() {
+        return (None, reply.trim().to_string());
+    }
+    ((!critique.is_empty()).then(|| critique.to_string()), answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_judge_prompt_lists_candidates() {
+        let prompt = judge_prompt(
+            "What is 2 + 2?",
+            &[("candidate-1", "4\n"), ("candidate-2", "5")],
+        );
+        assert!(prompt.contains("judging 2 candidate answers"));
+        assert!(prompt.contains("<request>\nWhat is 2 + 2?\n</request>"));
+        assert!(prompt.contains("<candidate name=\"candidate-1\">\n4\n</candidate>"));
+        assert!(prompt.contains("<candidate name=\"candidate-2\">\n5\n</candidate>"));
+    }
+
+    #[test]
+    fn test_split_verdict() {
+        let (critique, answer) =
+            split_verdict("## Critique\nCandidate 2 is wrong.\n\n## Final answer\n4\n");
+        assert_eq!(critique.as_deref(), Some("Candidate 2 is wrong."));
+        assert_eq!(answer, "4");
+
+        assert_eq!(split_verdict("  just 4 "), (None, "just 4".to_string()));
+        assert_eq!(
+            split_verdict("## Critique\nfine\n## Final answer\n"),
+            (None, "## Critique\nfine\n## Final answer".to_string())
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Setup diagnostics behind the `gemini_doctor` tool: is the Gemini CLI
+//! installed and runnable, is it authenticated, and does the server config
+//! parse. Every check carries a hint for fixing it.
+
+use crate::gemini::{self, Options};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Prompt of the opt-in live authentication probe
+const AUTH_PROBE_PROMPT: &str = "Reply with the single word OK.";
+const AUTH_PROBE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Environment variables the Gemini CLI authenticates with
+const AUTH_ENV_VARS: &[&str] = &[
+    "GEMINI_API_KEY",
+    "GOOGLE_API_KEY",
+    "GOOGLE_APPLICATION_CREDENTIALS",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Likely fine, but worth a look
+    Warn,
+    Fail,
+    /// Not run because an earlier check failed or it was not requested
+    Skip,
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+            CheckStatus::Skip => "skip",
+        }
+    }
+}
+
+/// Outcome of one diagnostic
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How to fix a failing or suspicious check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Output from the gemini_doctor tool
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    /// No check failed
+    pub ok: bool,
+    pub server_version: String,
+    pub checks: Vec<Check>,
+}
+
+/// Run every check. `probe_auth` sends a one-line prompt to the CLI, which
+/// uses a little quota but proves the credentials actually work.
+pub async fn diagnose(probe_auth: bool) -> DoctorReport {
+    let mut checks = Vec::new();
+
+    let binary = crate::init::find_gemini_binary();
+    checks.push(match &binary {
+        Some(path) => Check::new("binary", CheckStatus::Pass, path.display().to_string()),
+        None => {
+            let name = std::env::var("GEMINI_BIN").unwrap_or_else(|_| "gemini".to_string());
+            let detail = if Path::new(&name).components().count() > 1 {
+                format!("GEMINI_BIN `{}` does not exist", name)
+            } else {
+                format!("`{}` not found on PATH", name)
+            };
+            Check::new("binary", CheckStatus::Fail, detail)
+            .hint("Install the CLI with `npm install -g @google/gemini-cli`, or set GEMINI_BIN to its full path")
+        }
+    });
+
+    let runnable = match gemini::cli_version().await {
+        Ok(version) => {
+            checks.push(Check::new("version", CheckStatus::Pass, version));
+            true
+        }
+        Err(err) => {
+            checks.push(
+                Check::new("version", CheckStatus::Fail, format!("{:#}", err)).hint(
+                    "Run `gemini --version` in the server's environment; the CLI needs Node.js 20 or newer",
+                ),
+            );
+            false
+        }
+    };
+
+    let home = gemini::home_dir();
+    checks.push(auth_check(home.as_deref(), |name| std::env::var(name).ok()));
+
+    checks.push(if !probe_auth {
+        Check::new(
+            "auth_probe",
+            CheckStatus::Skip,
+            "not requested; set PROBE_AUTH to send a test prompt",
+        )
+    } else if !runnable {
+        Check::new(
+            "auth_probe",
+            CheckStatus::Skip,
+            "the CLI cannot be executed",
+        )
+    } else {
+        probe().await
+    });
+
+    checks.push(match gemini::validate_config_file() {
+        Ok(sources) if !sources.is_empty() => Check::new(
+            "config",
+            CheckStatus::Pass,
+            sources
+                .iter()
+                .map(|source| source.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" + "),
+        ),
+        Ok(_) => match std::env::var("GEMINI_MCP_CONFIG_PATH") {
+            Ok(path) if !path.trim().is_empty() => Check::new(
+                "config",
+                CheckStatus::Warn,
+                format!(
+                    "GEMINI_MCP_CONFIG_PATH points to a missing file: {}",
+                    path.trim()
+                ),
+            )
+            .hint("Fix the path or unset the variable"),
+            _ => Check::new(
+                "config",
+                CheckStatus::Pass,
+                "no config file, using defaults",
+            ),
+        },
+        Err(err) => Check::new("config", CheckStatus::Fail, format!("{:#}", err))
+            .hint("The server refuses to start with an invalid config; fix the reported field"),
+    });
+
+    if gemini::chaos_enabled() {
+        checks.push(
+            Check::new(
+                "chaos",
+                CheckStatus::Warn,
+                "chaos mode is injecting latency, stderr noise and timeouts",
+            )
+            .hint("Set `chaos.enabled` to false outside resilience tests"),
+        );
+    }
+
+    checks.push(match gemini::state_dir() {
+        Some(dir) => match std::fs::create_dir_all(&dir) {
+            Ok(()) => Check::new("state_dir", CheckStatus::Pass, dir.display().to_string()),
+            Err(err) => Check::new(
+                "state_dir",
+                CheckStatus::Warn,
+                format!("{}: {}", dir.display(), err),
+            )
+            .hint("Agent memory and the workspace index cannot be saved; set `state_dir` in the config"),
+        },
+        None => Check::new("state_dir", CheckStatus::Warn, "no state directory found")
+            .hint("Set `state_dir` in the config or HOME in the environment"),
+    });
+
+    DoctorReport {
+        ok: checks.iter().all(|c| c.status != CheckStatus::Fail),
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        checks,
+    }
+}
+
+/// Look for credentials the way the Gemini CLI does: API keys in the
+/// environment, Vertex AI settings, or a cached Google login
+fn auth_check(home: Option<&Path>, env: impl Fn(&str) -> Option<String>) -> Check {
+    let set = |name: &str| env(name).is_some_and(|v| !v.trim().is_empty());
+
+    if let Some(var) = AUTH_ENV_VARS.iter().find(|var| set(var)) {
+        return Check::new("auth", CheckStatus::Pass, format!("{} is set", var));
+    }
+    if env("GOOGLE_GENAI_USE_VERTEXAI").is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+        return if set("GOOGLE_CLOUD_PROJECT") {
+            Check::new(
+                "auth",
+                CheckStatus::Pass,
+                "Vertex AI with GOOGLE_CLOUD_PROJECT",
+            )
+        } else {
+            Check::new(
+                "auth",
+                CheckStatus::Warn,
+                "GOOGLE_GENAI_USE_VERTEXAI is set without GOOGLE_CLOUD_PROJECT",
+            )
+            .hint("Set GOOGLE_CLOUD_PROJECT and GOOGLE_CLOUD_LOCATION, or use an API key")
+        };
+    }
+    if let Some(creds) = home
+        .map(|home| home.join(".gemini").join("oauth_creds.json"))
+        .filter(|path| path.is_file())
+    {
+        return Check::new(
+            "auth",
+            CheckStatus::Pass,
+            format!("cached Google login in {}", creds.display()),
+        );
+    }
+    Check::new("auth", CheckStatus::Warn, "no credentials found")
+        .hint("Run `gemini` once interactively to log in, or set GEMINI_API_KEY in the server's environment")
+}
+
+async fn probe() -> Check {
+    let opts = Options::builder()
+        .prompt(AUTH_PROBE_PROMPT)
+        .additional_args(gemini::default_additional_args())
+        .keep_raw_events(false)
+        .build();
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(err) => return Check::new("auth_probe", CheckStatus::Fail, format!("{:#}", err)),
+    };
+    match tokio::time::timeout(AUTH_PROBE_TIMEOUT, gemini::run(opts)).await {
+        Ok(Ok(result)) if result.success => Check::new(
+            "auth_probe",
+            CheckStatus::Pass,
+            format!(
+                "answered with model {}",
+                result.model.as_deref().unwrap_or("(unknown)")
+            ),
+        ),
+        Ok(Ok(result)) => Check::new(
+            "auth_probe",
+            CheckStatus::Fail,
+            result
+                .error
+                .unwrap_or_else(|| "the CLI reported a failure".to_string()),
+        )
+        .hint("Check the credentials and quota of the account the CLI uses"),
+        Ok(Err(err)) => Check::new("auth_probe", CheckStatus::Fail, format!("{:#}", err)),
+        Err(_) => Check::new(
+            "auth_probe",
+            CheckStatus::Fail,
+            format!("no answer within {}s", AUTH_PROBE_TIMEOUT.as_secs()),
+        )
+        .hint("The CLI may be waiting for an interactive login; run `gemini` once in a terminal"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_auth_check() {
+        let home = tempfile::TempDir::new().unwrap();
+
+        let check = auth_check(Some(home.path()), env(&[("GEMINI_API_KEY", "k")]));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(check.detail, "GEMINI_API_KEY is set");
+
+        let check = auth_check(
+            Some(home.path()),
+            env(&[
+                ("GEMINI_API_KEY", " "),
+                ("GOOGLE_GENAI_USE_VERTEXAI", "true"),
+            ]),
+        );
+        assert_eq!(check.status, CheckStatus::Warn);
+
+        let check = auth_check(Some(home.path()), env(&[]));
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.hint.is_some());
+
+        std::fs::create_dir(home.path().join(".gemini")).unwrap();
+        std::fs::write(home.path().join(".gemini/oauth_creds.json"), "{}").unwrap();
+        let check = auth_check(Some(home.path()), env(&[]));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.detail.starts_with("cached Google login"));
+    }
+}
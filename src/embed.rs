@@ -0,0 +1,235 @@
+//! Text embedding backends for the workspace index.
+//!
+//! The built-in backend hashes identifiers and words into a fixed-size vector
+//! (feature hashing). It needs no network or model and works well for finding
+//! code by the names it mentions. For semantic embeddings, configure
+//! `retrieval.embed_command`: a program that reads `{"texts": [...]}` as JSON
+//! on stdin and writes `{"embeddings": [[...], ...]}` to stdout.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Dimensions of the built-in hashing embedder
+const HASHING_DIMENSIONS: usize = 256;
+/// Texts sent to an embed command per invocation
+const COMMAND_BATCH_SIZE: usize = 64;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Backend turning texts into unit-length vectors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Embedder {
+    /// Offline feature hashing of words and identifier parts
+    Hashing,
+    /// External program speaking the JSON protocol described in the module docs
+    Command(Vec<String>),
+}
+
+#[derive(Serialize)]
+struct CommandRequest<'a> {
+    texts: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct CommandResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl Embedder {
+    /// `Command` when `command` is non-empty, `Hashing` otherwise
+    pub fn from_command(command: Option<Vec<String>>) -> Self {
+        match command {
+            Some(command) if !command.is_empty() => Self::Command(command),
+            _ => Self::Hashing,
+        }
+    }
+
+    /// Identifies the backend in a saved index; vectors from different
+    /// backends cannot be compared
+    pub fn id(&self) -> String {
+        match self {
+            Self::Hashing => format!("hashing-{}", HASHING_DIMENSIONS),
+            Self::Command(command) => format!("command:{}", command.join(" ")),
+        }
+    }
+
+    /// One normalized vector per text, in order
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        match self {
+            Self::Hashing => Ok(texts.iter().map(|t| hash_embed(t)).collect()),
+            Self::Command(command) => {
+                let mut vectors = Vec::with_capacity(texts.len());
+                for batch in texts.chunks(COMMAND_BATCH_SIZE) {
+                    let batch_vectors = run_command(command, batch).await?;
+                    vectors.extend(batch_vectors.into_iter().map(normalize));
+                }
+                Ok(vectors)
+            }
+        }
+    }
+}
+
+/// Cosine similarity of two normalized vectors
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+async fn run_command(command: &[String], texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let (program, args) = command.split_first().context("embed_command is empty")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run embed_command `{}`", program))?;
+
+    let request = serde_json::to_vec(&CommandRequest { texts })?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("embed_command stdin not captured")?;
+    let writer = tokio::spawn(async move { stdin.write_all(&request).await });
+
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+        .await
+        .context("embed_command timed out")??;
+    writer.await?.context("Failed to write to embed_command")?;
+    if !output.status.success() {
+        bail!(
+            "embed_command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let response: CommandResponse =
+        serde_json::from_slice(&output.stdout).context("embed_command returned invalid JSON")?;
+    if response.embeddings.len() != texts.len() {
+        bail!(
+            "embed_command returned {} embeddings for {} texts",
+            response.embeddings.len(),
+            texts.len()
+        );
+    }
+    Ok(response.embeddings)
+}
+
+/// Hash every word, and the parts of `camelCase`/`snake_case` identifiers,
+/// into a signed bucket, weighting repeated terms sub-linearly
+fn hash_embed(text: &str) -> Vec<f32> {
+    let mut counts = std::collections::HashMap::<String, u32>::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| w.len() > 1)
+    {
+        let parts = identifier_parts(word);
+        if parts.len() > 1 {
+            for part in parts {
+                *counts.entry(part).or_default() += 1;
+            }
+        }
+        *counts.entry(word.to_lowercase()).or_default() += 1;
+    }
+
+    let mut vector = vec![0f32; HASHING_DIMENSIONS];
+    for (term, count) in counts {
+        let hash = fnv1a(term.as_bytes());
+        let bucket = (hash % HASHING_DIMENSIONS as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[bucket] += sign * (1.0 + (count as f32).ln());
+    }
+    normalize(vector)
+}
+
+/// `parseHttpRequest` / `parse_http_request` -> `parse`, `http`, `request`
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in word.chars() {
+        if c == '_' || (c.is_uppercase() && prev_lower) {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.clear();
+        }
+        if c != '_' {
+            current.extend(c.to_lowercase());
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+    }
+    if current.len() > 1 {
+        parts.push(current);
+    }
+    parts
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier_parts() {
+        assert_eq!(
+            identifier_parts("parseHttpRequest"),
+            vec!["parse", "http", "request"]
+        );
+        assert_eq!(
+            identifier_parts("run_with_child"),
+            vec!["run", "with", "child"]
+        );
+        assert_eq!(identifier_parts("x"), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_hashing_embedder_ranks_related_text_higher() {
+        let texts = vec![
+            "fn parse_session_uri(uri: &str) -> Option<SessionResource>".to_string(),
+            "Sliding one-minute caps on started calls".to_string(),
+            "where is the session uri parsed?".to_string(),
+        ];
+        let vectors = Embedder::Hashing.embed(&texts).await.unwrap();
+
+        assert_eq!(vectors[0].len(), HASHING_DIMENSIONS);
+        let norm: f32 = similarity(&vectors[0], &vectors[0]);
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert!(similarity(&vectors[2], &vectors[0]) > similarity(&vectors[2], &vectors[1]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_embedder() {
+        let script = r#"read -r _; echo '{"embeddings": [[3, 4], [0, 2]]}'"#;
+        let embedder = Embedder::from_command(Some(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+        ]));
+        let vectors = embedder
+            .embed(&["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vectors, vec![vec![0.6, 0.8], vec![0.0, 1.0]]);
+
+        let err = embedder.embed(&["only one".to_string()]).await.unwrap_err();
+        assert!(err.to_string().contains("2 embeddings for 1 texts"));
+    }
+}
@@ -0,0 +1,577 @@
+//! Typed model of the events emitted by `gemini -o stream-json`.
+//!
+//! Every stdout line is one JSON object tagged by `type`. Known types are
+//! deserialized into dedicated structs; anything else is kept verbatim as
+//! [`GeminiEvent::Unknown`] so new CLI event types never break parsing.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+
+const KEY_TYPE: &str = "type";
+const KEY_SESSION_ID: &str = "session_id";
+const KEY_ERROR: &str = "error";
+const KEY_MESSAGE: &str = "message";
+
+/// Detail object attached to failed events (`{"type": ..., "message": ...}`)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ErrorDetail {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub message: Option<String>,
+}
+
+/// Author of a `message` event
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    #[default]
+    #[serde(other)]
+    Other,
+}
+
+/// First event of a run, announcing the session and model
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct InitEvent {
+    pub session_id: Option<String>,
+    pub model: Option<String>,
+    pub timestamp: Option<String>,
+}
+
+/// A user or assistant message (or a fragment of one when `delta` is set)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MessageEvent {
+    /// Shared by the fragments of one streamed message, when the CLI sends it
+    #[serde(alias = "message_id")]
+    pub id: Option<String>,
+    pub role: Role,
+    pub content: String,
+    pub delta: bool,
+    pub timestamp: Option<String>,
+}
+
+/// The model invoking one of the CLI's tools
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ToolUseEvent {
+    pub tool_name: String,
+    pub tool_id: String,
+    pub parameters: Value,
+    pub timestamp: Option<String>,
+}
+
+/// Outcome of a tool invocation, matched to its [`ToolUseEvent`] by `tool_id`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ToolResultEvent {
+    pub tool_id: String,
+    pub status: Option<String>,
+    pub output: Option<String>,
+    pub error: Option<ErrorDetail>,
+    pub timestamp: Option<String>,
+}
+
+/// Reasoning emitted by thinking models
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ThoughtEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(alias = "description", alias = "text")]
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+/// A source the answer is grounded in, reported when the model used Google
+/// Search
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CitationEvent {
+    #[serde(alias = "uri")]
+    pub url: String,
+    pub title: Option<String>,
+    /// Character offsets of the supported passage in the answer
+    #[serde(alias = "startIndex")]
+    pub start_index: Option<u64>,
+    #[serde(alias = "endIndex")]
+    pub end_index: Option<u64>,
+    pub timestamp: Option<String>,
+}
+
+/// A warning or error reported by the CLI during the run
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ErrorEvent {
+    pub severity: Option<String>,
+    pub message: Option<String>,
+    pub error: Option<ErrorDetail>,
+    pub timestamp: Option<String>,
+}
+
+impl ErrorEvent {
+    /// `severity: "warning"` events are non-fatal
+    pub fn is_warning(&self) -> bool {
+        self.severity
+            .as_deref()
+            .is_some_and(|s| s.eq_ignore_ascii_case("warning"))
+    }
+}
+
+/// Token counts for a run. Accepts both the CLI's `stats` names and the
+/// Gemini API's `usageMetadata` names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Usage {
+    #[serde(alias = "input_tokens", alias = "promptTokenCount")]
+    pub prompt_tokens: u64,
+    #[serde(alias = "output_tokens", alias = "candidatesTokenCount")]
+    pub candidate_tokens: u64,
+    #[serde(alias = "totalTokenCount")]
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.candidate_tokens += other.candidate_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// Statistics attached to the final `result` event
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResultStats {
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub duration_ms: Option<u64>,
+    pub tool_calls: Option<u64>,
+}
+
+/// Final event of a run with its status and statistics
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResultEvent {
+    pub status: Option<String>,
+    pub error: Option<ErrorDetail>,
+    pub stats: Option<ResultStats>,
+    pub timestamp: Option<String>,
+}
+
+/// One line of `stream-json` output
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeminiEvent {
+    Init(InitEvent),
+    Message(MessageEvent),
+    ToolUse(ToolUseEvent),
+    ToolResult(ToolResultEvent),
+    Thought(ThoughtEvent),
+    Citation(CitationEvent),
+    Error(ErrorEvent),
+    Result(ResultEvent),
+    /// Any event type this crate does not model (yet)
+    Unknown(Value),
+}
+
+/// A stdout line parsed straight from its text, without a [`Value`] of the
+/// whole line in between
+#[derive(Debug)]
+pub struct ParsedLine {
+    /// Non-empty `session_id`, carried by events of any type
+    pub session_id: Option<String>,
+    pub event: Result<GeminiEvent, EventParseError>,
+}
+
+/// The fields of a line read before its payload
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(rename = "type", default, borrow)]
+    event_type: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    session_id: Option<Cow<'a, str>>,
+}
+
+impl ParsedLine {
+    /// Parse one line of output; `None` when it is not JSON
+    pub fn parse(text: &str) -> Option<Self> {
+        fn typed<'a, T: Deserialize<'a>>(
+            text: &'a str,
+            event_type: &str,
+        ) -> Result<T, EventParseError> {
+            serde_json::from_str(text).map_err(|source| EventParseError {
+                event_type: event_type.to_string(),
+                source,
+            })
+        }
+
+        let Ok(header) = serde_json::from_str::<Header>(text) else {
+            // Not an object with a string `type`; kept as is if it is JSON
+            let value: Value = serde_json::from_str(text).ok()?;
+            return Some(Self {
+                session_id: session_id(&value).map(str::to_string),
+                event: Ok(GeminiEvent::Unknown(value)),
+            });
+        };
+        let event_type = header.event_type.as_deref().unwrap_or("");
+        let event = match event_type {
+            "init" => typed(text, event_type).map(GeminiEvent::Init),
+            "message" => typed(text, event_type).map(GeminiEvent::Message),
+            "tool_use" => typed(text, event_type).map(GeminiEvent::ToolUse),
+            "tool_result" => typed(text, event_type).map(GeminiEvent::ToolResult),
+            "thought" | "thinking" => typed(text, event_type).map(GeminiEvent::Thought),
+            "citation" => typed(text, event_type).map(GeminiEvent::Citation),
+            "error" => typed(text, event_type).map(GeminiEvent::Error),
+            "result" => typed(text, event_type).map(GeminiEvent::Result),
+            _ => Ok(GeminiEvent::Unknown(serde_json::from_str(text).ok()?)),
+        };
+        Some(Self {
+            session_id: header
+                .session_id
+                .filter(|id| !id.is_empty())
+                .map(Cow::into_owned),
+            event,
+        })
+    }
+}
+
+/// A known event type whose payload did not match the expected shape
+#[derive(Debug)]
+pub struct EventParseError {
+    pub event_type: String,
+    pub source: serde_json::Error,
+}
+
+impl fmt::Display for EventParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed `{}` event: {}", self.event_type, self.source)
+    }
+}
+
+impl std::error::Error for EventParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl GeminiEvent {
+    /// Convert a raw JSON line into a typed event. Unknown types become
+    /// [`GeminiEvent::Unknown`]; known types with an invalid payload are errors.
+    pub fn from_value(value: &Value) -> Result<Self, EventParseError> {
+        fn typed<'a, T: Deserialize<'a>>(
+            value: &'a Value,
+            event_type: &str,
+        ) -> Result<T, EventParseError> {
+            T::deserialize(value).map_err(|source| EventParseError {
+                event_type: event_type.to_string(),
+                source,
+            })
+        }
+
+        let event_type = event_type(value);
+        Ok(match event_type {
+            "init" => Self::Init(typed(value, event_type)?),
+            "message" => Self::Message(typed(value, event_type)?),
+            "tool_use" => Self::ToolUse(typed(value, event_type)?),
+            "tool_result" => Self::ToolResult(typed(value, event_type)?),
+            "thought" | "thinking" => Self::Thought(typed(value, event_type)?),
+            "citation" => Self::Citation(typed(value, event_type)?),
+            "error" => Self::Error(typed(value, event_type)?),
+            "result" => Self::Result(typed(value, event_type)?),
+            _ => Self::Unknown(value.clone()),
+        })
+    }
+
+    /// Like [`Self::from_value`], but moves the payload out of `value` instead
+    /// of copying it. The payload of a malformed event is lost with the
+    /// error.
+    pub fn from_owned(value: Value) -> Result<Self, EventParseError> {
+        fn typed<T: DeserializeOwned>(
+            value: Value,
+            event_type: &str,
+        ) -> Result<T, EventParseError> {
+            T::deserialize(value).map_err(|source| EventParseError {
+                event_type: event_type.to_string(),
+                source,
+            })
+        }
+
+        let event_type = match event_type(&value) {
+            "init" => "init",
+            "message" => "message",
+            "tool_use" => "tool_use",
+            "tool_result" => "tool_result",
+            "thought" => "thought",
+            "thinking" => "thinking",
+            "citation" => "citation",
+            "error" => "error",
+            "result" => "result",
+            _ => return Ok(Self::Unknown(value)),
+        };
+        Ok(match event_type {
+            "init" => Self::Init(typed(value, event_type)?),
+            "message" => Self::Message(typed(value, event_type)?),
+            "tool_use" => Self::ToolUse(typed(value, event_type)?),
+            "tool_result" => Self::ToolResult(typed(value, event_type)?),
+            "thought" | "thinking" => Self::Thought(typed(value, event_type)?),
+            "citation" => Self::Citation(typed(value, event_type)?),
+            "error" => Self::Error(typed(value, event_type)?),
+            _ => Self::Result(typed(value, event_type)?),
+        })
+    }
+
+    /// Whether this event reports a failure of the run
+    pub fn is_failure(&self) -> bool {
+        match self {
+            Self::Error(ev) => !ev.is_warning(),
+            Self::ToolResult(ev) => ev.error.is_some(),
+            Self::Result(ev) => {
+                ev.error.is_some() || ev.status.as_deref().is_some_and(is_failure_type)
+            }
+            Self::Unknown(value) => {
+                is_failure_type(event_type(value)) || value.get(KEY_ERROR).is_some()
+            }
+            Self::Init(_)
+            | Self::Message(_)
+            | Self::ToolUse(_)
+            | Self::Thought(_)
+            | Self::Citation(_) => false,
+        }
+    }
+
+    /// Human-readable failure message, if the event carries one
+    pub fn failure_message(&self) -> Option<&str> {
+        match self {
+            Self::Error(ev) => detail(&ev.error).or(ev.message.as_deref()),
+            Self::ToolResult(ev) => detail(&ev.error),
+            Self::Result(ev) => detail(&ev.error),
+            Self::Unknown(value) => match value.get(KEY_ERROR).and_then(|v| v.as_object()) {
+                Some(error) => error.get(KEY_MESSAGE).and_then(|v| v.as_str()),
+                None => value.get(KEY_MESSAGE).and_then(|v| v.as_str()),
+            },
+            Self::Init(_)
+            | Self::Message(_)
+            | Self::ToolUse(_)
+            | Self::Thought(_)
+            | Self::Citation(_) => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for GeminiEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_value(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The `type` tag of a raw event, or `""` if missing
+pub fn event_type(value: &Value) -> &str {
+    value.get(KEY_TYPE).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+/// Non-empty `session_id` carried by a raw event of any type
+pub fn session_id(value: &Value) -> Option<&str> {
+    value
+        .get(KEY_SESSION_ID)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+}
+
+/// Message of an optional error detail
+fn detail(error: &Option<ErrorDetail>) -> Option<&str> {
+    error.as_ref().and_then(|e| e.message.as_deref())
+}
+
+/// Case-insensitive check for failure-like type/status names
+fn is_failure_type(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower.contains("fail") || lower.contains("error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_owned_parsing_matches_borrowed() {
+        for line in [
+            json!({"type": "init", "session_id": "abc", "model": "gemini-2.5-pro"}),
+            json!({"type": "message", "role": "assistant", "content": "hi", "delta": true}),
+            json!({"type": "thinking", "text": "hmm"}),
+            json!({"type": "result", "status": "success", "stats": {"total_tokens": 3}}),
+            json!({"type": "checkpoint", "id": 7}),
+        ] {
+            assert_eq!(
+                GeminiEvent::from_owned(line.clone()).unwrap(),
+                GeminiEvent::from_value(&line).unwrap()
+            );
+        }
+        let err = GeminiEvent::from_owned(json!({"type": "message", "content": 1})).unwrap_err();
+        assert_eq!(err.event_type, "message");
+    }
+
+    #[test]
+    fn test_line_parsing_matches_values() {
+        for line in [
+            json!({"type": "init", "session_id": "abc", "model": "gemini-2.5-pro"}),
+            json!({"type": "message", "role": "assistant", "content": "a \"quoted\" line"}),
+            json!({"type": "thinking", "text": "hmm"}),
+            json!({"type": "result", "status": "success", "stats": {"total_tokens": 3}}),
+            json!({"type": "checkpoint", "id": 7, "session_id": "abc"}),
+            json!({"type": 7}),
+            json!([1, 2]),
+        ] {
+            let parsed = ParsedLine::parse(&line.to_string()).unwrap();
+            assert_eq!(
+                parsed.event.unwrap(),
+                GeminiEvent::from_value(&line).unwrap()
+            );
+            assert_eq!(parsed.session_id.as_deref(), session_id(&line));
+        }
+        let parsed = ParsedLine::parse(r#"{"type": "message", "content": 1}"#).unwrap();
+        assert_eq!(parsed.event.unwrap_err().event_type, "message");
+        assert!(ParsedLine::parse("plain text").is_none());
+    }
+
+    #[test]
+    fn test_parses_known_event_types() {
+        let init = GeminiEvent::from_value(&json!({
+            "type": "init", "session_id": "abc", "model": "gemini-2.5-pro"
+        }))
+        .unwrap();
+        assert_eq!(
+            init,
+            GeminiEvent::Init(InitEvent {
+                session_id: Some("abc".to_string()),
+                model: Some("gemini-2.5-pro".to_string()),
+                timestamp: None,
+            })
+        );
+
+        let message = GeminiEvent::from_value(&json!({
+            "type": "message", "role": "assistant", "content": "hi", "delta": true
+        }))
+        .unwrap();
+        let GeminiEvent::Message(message) = message else {
+            panic!("expected message event");
+        };
+        assert_eq!(message.role, Role::Assistant);
+        assert!(message.delta);
+
+        let tool = GeminiEvent::from_value(&json!({
+            "type": "tool_use", "tool_name": "read_file", "tool_id": "t1",
+            "parameters": {"path": "a.rs"}
+        }))
+        .unwrap();
+        assert!(matches!(tool, GeminiEvent::ToolUse(ref ev) if ev.tool_name == "read_file"));
+    }
+
+    #[test]
+    fn test_unknown_types_are_preserved() {
+        let raw = json!({"type": "brand_new", "payload": 1});
+        assert_eq!(
+            GeminiEvent::from_value(&raw).unwrap(),
+            GeminiEvent::Unknown(raw)
+        );
+    }
+
+    #[test]
+    fn test_malformed_known_event_is_an_error() {
+        let err = GeminiEvent::from_value(&json!({"type": "message", "content": 42})).unwrap_err();
+        assert_eq!(err.event_type, "message");
+    }
+
+    #[test]
+    fn test_failure_detection() {
+        let error = GeminiEvent::from_value(&json!({
+            "type": "error", "severity": "error", "message": "quota exceeded"
+        }))
+        .unwrap();
+        assert!(error.is_failure());
+        assert_eq!(error.failure_message(), Some("quota exceeded"));
+
+        let warning = GeminiEvent::from_value(&json!({
+            "type": "error", "severity": "warning", "message": "falling back to flash"
+        }))
+        .unwrap();
+        assert!(!warning.is_failure());
+        assert_eq!(warning.failure_message(), Some("falling back to flash"));
+
+        let result = GeminiEvent::from_value(&json!({
+            "type": "result", "status": "error", "error": {"type": "FatalError", "message": "boom"}
+        }))
+        .unwrap();
+        assert!(result.is_failure());
+        assert_eq!(result.failure_message(), Some("boom"));
+
+        let ok = GeminiEvent::from_value(&json!({"type": "result", "status": "success"})).unwrap();
+        assert!(!ok.is_failure());
+
+        let legacy =
+            GeminiEvent::from_value(&json!({"type": "turn.failed", "message": "x"})).unwrap();
+        assert!(legacy.is_failure());
+        assert_eq!(legacy.failure_message(), Some("x"));
+    }
+
+    #[test]
+    fn test_result_stats_usage() {
+        let cli = GeminiEvent::from_value(&json!({
+            "type": "result", "status": "success",
+            "stats": {"total_tokens": 30, "input_tokens": 20, "output_tokens": 10, "duration_ms": 1200}
+        }))
+        .unwrap();
+        let GeminiEvent::Result(ResultEvent {
+            stats: Some(stats), ..
+        }) = cli
+        else {
+            panic!("expected result event with stats");
+        };
+        assert_eq!(
+            stats.usage,
+            Usage {
+                prompt_tokens: 20,
+                candidate_tokens: 10,
+                total_tokens: 30,
+            }
+        );
+        assert_eq!(stats.duration_ms, Some(1200));
+
+        let api: Usage = serde_json::from_value(json!({
+            "promptTokenCount": 5, "candidatesTokenCount": 7, "totalTokenCount": 12
+        }))
+        .unwrap();
+        assert_eq!(api.total_tokens, 12);
+
+        let mut total = stats.usage;
+        total += api;
+        assert_eq!(total.prompt_tokens, 25);
+        assert_eq!(total.candidate_tokens, 17);
+    }
+
+    #[test]
+    fn test_deserialize_from_str() {
+        let event: GeminiEvent =
+            serde_json::from_str(r#"{"type":"thought","subject":"Plan","description":"step 1"}"#)
+                .unwrap();
+        let GeminiEvent::Thought(thought) = event else {
+            panic!("expected thought event");
+        };
+        assert_eq!(thought.subject.as_deref(), Some("Plan"));
+        assert_eq!(thought.content, "step 1");
+    }
+}
@@ -0,0 +1,297 @@
+//! Excerpts of a single definition for `SYMBOLS` (requires the `tree-sitter`
+//! feature).
+//!
+//! Instead of attaching a whole file, only the definition of the requested
+//! symbol is sent, with its doc comments and attributes, plus the signatures
+//! of the definitions around it so the model still sees where it lives.
+
+use anyhow::{bail, Result};
+use tree_sitter::Node;
+
+/// Signatures of this many definitions before and after the symbol are
+/// included by default
+pub const DEFAULT_NEIGHBORS: usize = 1;
+
+/// Wrappers whose only purpose is to decorate or export the definition
+/// inside them; they are attached together with it
+const WRAPPERS: &[&str] = &["decorated_definition", "export_statement"];
+/// Declarations that hold a definition, attached with it when it is their
+/// only one (`const f = () => …`, Go `type X struct …`)
+const SINGLE_WRAPPERS: &[&str] = &[
+    "lexical_declaration",
+    "variable_declaration",
+    "type_declaration",
+];
+
+/// The definition of one symbol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Excerpt {
+    /// Tree-sitter node kind, e.g. `function_item`
+    pub kind: String,
+    /// 1-based, inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    /// Signatures of the surrounding definitions, in file order
+    pub neighbors: Vec<String>,
+}
+
+impl Excerpt {
+    /// Section of the context block for `symbol` in `path`
+    pub fn render(&self, path: &str, symbol: &str) -> String {
+        let mut section = format!(
+            "\n--- {} lines {}-{}: {} ---\n{}\n",
+            path,
+            self.start_line,
+            self.end_line,
+            symbol,
+            self.text.trim_end()
+        );
+        if !self.neighbors.is_empty() {
+            section.push_str("Nearby definitions (bodies omitted):\n");
+            for signature in &self.neighbors {
+                section.push_str(&format!("  {}\n", signature));
+            }
+        }
+        section
+    }
+}
+
+/// Find the definition of `symbol` in `text`. A qualified name such as
+/// `Server::run` or `Server.run` only matches inside the named type, impl or
+/// class. The first definition in file order wins.
+pub fn extract(path: &str, text: &str, symbol: &str, neighbors: usize) -> Result<Excerpt> {
+    let Some(tree) = crate::syntax::parse(path, text) else {
+        bail!("No syntax support for {}", path);
+    };
+    let (scope, name) = match symbol.rsplit_once("::").or_else(|| symbol.rsplit_once('.')) {
+        Some((scope, name)) => (Some(scope.rsplit(['.', ':']).next().unwrap_or(scope)), name),
+        None => (None, symbol),
+    };
+    let source = text.as_bytes();
+    let Some(definition) = find(tree.root_node(), source, name, scope, scope.is_none()) else {
+        bail!("Symbol `{}` not found in {}", symbol, path);
+    };
+    let node = attached_root(definition);
+
+    let mut start = node;
+    while let Some(prev) = start.prev_named_sibling() {
+        if !crate::syntax::is_leading(prev.kind())
+            || prev.end_position().row + 1 < start.start_position().row
+        {
+            break;
+        }
+        start = prev;
+    }
+
+    let start_line = start.start_position().row;
+    let end_line = node.end_position().row;
+    let excerpt_text = text
+        .lines()
+        .skip(start_line)
+        .take(end_line - start_line + 1)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut before = Vec::new();
+    let mut sibling = start.prev_named_sibling();
+    while let Some(node) = sibling.filter(|_| before.len() < neighbors) {
+        before.extend(signature(node, source));
+        sibling = node.prev_named_sibling();
+    }
+    before.reverse();
+    let mut after = Vec::new();
+    let mut sibling = node.next_named_sibling();
+    while let Some(node) = sibling.filter(|_| after.len() < neighbors) {
+        after.extend(signature(node, source));
+        sibling = node.next_named_sibling();
+    }
+
+    Ok(Excerpt {
+        kind: definition.kind().to_string(),
+        start_line: start_line + 1,
+        end_line: end_line + 1,
+        text: excerpt_text,
+        neighbors: before.into_iter().chain(after).collect(),
+    })
+}
+
+fn find<'t>(
+    node: Node<'t>,
+    source: &[u8],
+    name: &str,
+    scope: Option<&str>,
+    in_scope: bool,
+) -> Option<Node<'t>> {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        let child_name = name_of(child, source);
+        if in_scope && is_definition(child) && child_name == Some(name) {
+            return Some(child);
+        }
+        let child_in_scope = in_scope || (scope.is_some() && child_name == scope);
+        if let Some(found) = find(child, source, name, scope, child_in_scope) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Name of a definition; for a Rust `impl` block, the implemented type
+fn name_of<'s>(node: Node<'_>, source: &'s [u8]) -> Option<&'s str> {
+    let field = match node.kind() {
+        "impl_item" => "type",
+        _ => "name",
+    };
+    let text = node.child_by_field_name(field)?.utf8_text(source).ok()?;
+    Some(text.split('<').next().unwrap_or(text).trim())
+}
+
+fn is_definition(node: Node<'_>) -> bool {
+    let kind = node.kind();
+    node.child_by_field_name("name").is_some()
+        && !kind.contains("field")
+        && !kind.contains("parameter")
+        && [
+            "_item",
+            "_definition",
+            "_declaration",
+            "_declarator",
+            "_spec",
+            "_signature",
+        ]
+        .iter()
+        .any(|suffix| kind.ends_with(suffix))
+}
+
+/// `definition` together with the decorators, `export` or declaration
+/// around it
+fn attached_root(definition: Node<'_>) -> Node<'_> {
+    let mut node = definition;
+    while let Some(parent) = node.parent() {
+        let wraps = WRAPPERS.contains(&parent.kind())
+            || (SINGLE_WRAPPERS.contains(&parent.kind()) && parent.named_child_count() == 1);
+        if !wraps {
+            break;
+        }
+        node = parent;
+    }
+    node
+}
+
+/// The definition in `node` (possibly wrapped), if any
+fn definition_in(node: Node<'_>) -> Option<Node<'_>> {
+    if is_definition(node) || node.kind() == "impl_item" {
+        return Some(node);
+    }
+    if !WRAPPERS.contains(&node.kind()) && !SINGLE_WRAPPERS.contains(&node.kind()) {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let found = node.named_children(&mut cursor).find_map(definition_in);
+    found
+}
+
+/// One-line summary of the definition in `node`: its text up to the body
+fn signature(node: Node<'_>, source: &[u8]) -> Option<String> {
+    let definition = definition_in(node)?;
+    let text = definition.utf8_text(source).ok()?;
+    let (head, elided) = match definition.child_by_field_name("body") {
+        Some(body) => {
+            let head = &text[..body.start_byte() - definition.start_byte()];
+            let braces = source.get(body.start_byte()) == Some(&b'{');
+            (head, if braces { "{ … }" } else { "…" })
+        }
+        None => match text.split_once('\n') {
+            Some((first, _)) => (first, "…"),
+            None => (text, ""),
+        },
+    };
+    let head = head.split_whitespace().collect::<Vec<_>>().join(" ");
+    Some(format!("{} {}", head, elided).trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST: &str = r#"use std::fmt;
+
+/// First
+fn first() -> u32 {
+    1
+}
+
+struct Server {
+    port: u16,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self { port: 0 }
+    }
+
+    /// Start listening
+    #[inline]
+    fn run(&self,
+           verbose: bool) -> bool {
+        verbose
+    }
+
+    fn stop(&self) {}
+}
+
+fn run() {}
+"#;
+
+    #[test]
+    fn test_extract_rust_method() {
+        let excerpt = extract("lib.rs", RUST, "Server::run", 1).unwrap();
+        assert_eq!(excerpt.kind, "function_item");
+        assert_eq!((excerpt.start_line, excerpt.end_line), (17, 22));
+        assert!(excerpt
+            .text
+            .starts_with("    /// Start listening\n    #[inline]"));
+        assert_eq!(
+            excerpt.neighbors,
+            vec!["fn new() -> Self { … }", "fn stop(&self) { … }"]
+        );
+
+        // Unqualified names match the first definition in file order
+        let excerpt = extract("lib.rs", RUST, "run", 0).unwrap();
+        assert_eq!(excerpt.start_line, 17);
+        assert!(excerpt.neighbors.is_empty());
+
+        let excerpt = extract("lib.rs", RUST, "Server", 1).unwrap();
+        assert_eq!(excerpt.kind, "struct_item");
+        assert_eq!(
+            excerpt.neighbors,
+            vec!["fn first() -> u32 { … }", "impl Server { … }"]
+        );
+
+        let rendered = excerpt.render("src/lib.rs", "Server");
+        assert!(rendered.contains("--- src/lib.rs lines 8-10: Server ---"));
+        assert!(rendered.contains("Nearby definitions (bodies omitted):\n  fn first()"));
+
+        let err = extract("lib.rs", RUST, "Client::run", 1).unwrap_err();
+        assert_eq!(err.to_string(), "Symbol `Client::run` not found in lib.rs");
+        assert!(extract("notes.txt", "text", "x", 1).is_err());
+    }
+
+    #[test]
+    fn test_extract_python_decorated() {
+        let source = "import os\n\n\ndef helper(x):\n    return x\n\n\n@cached\ndef load(path):\n    return open(path).read()\n\n\nclass Store:\n    def save(self):\n        pass\n";
+        let excerpt = extract("store.py", source, "load", 1).unwrap();
+        assert_eq!(
+            excerpt.text,
+            "@cached\ndef load(path):\n    return open(path).read()"
+        );
+        assert_eq!(
+            excerpt.neighbors,
+            vec!["def helper(x): …", "class Store: …"]
+        );
+
+        let excerpt = extract("store.py", source, "Store.save", 1).unwrap();
+        assert_eq!(excerpt.start_line, 14);
+    }
+}
@@ -1,39 +1,611 @@
+use crate::acp::{self, AcpSession, InteractiveConfig, InteractiveSessions, InteractiveStats};
+use crate::activity::{self, ToolActivity};
+use crate::auth::{self, AuthProfile};
+use crate::cache::{CacheConfig, CacheKey, CacheStats, ResponseCache};
+use crate::chaos::{self, Chaos, ChaosConfig};
+use crate::chunking::ChunkingConfig;
+use crate::citation::{self, Citation};
+use crate::cli_compat::{self, CliCompat, OutputFormat};
+use crate::config_check::{self, ConfigError, Issue};
+use crate::config_layers::{self, Layer};
+use crate::event::{
+    self, ErrorEvent, EventParseError, GeminiEvent, MessageEvent, ParsedLine, ResultEvent, Role,
+    ThoughtEvent, Usage,
+};
+use crate::hooks::{self, StderrHook};
+use crate::json_output::DocumentBuffer;
+use crate::launcher::{self, Launcher};
+use crate::login::{AuthRequired, LoginDetector};
+use crate::placeholders;
+use crate::policy::Policy;
+use crate::pool::{PoolConfig, PoolKey, PoolStats, WarmPool};
+use crate::preset::ModelPreset;
+use crate::process_tree::{self, ProcessTree};
+use crate::singleflight::{Flight, Singleflight};
+use crate::stderr::StderrCapture;
+use crate::template::Template;
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::OnceLock;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
+use tokio::sync::{mpsc, watch, Notify};
 use tokio::time::timeout;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 const PROMPT_DEPRECATION_WARNING: &str = "The --prompt (-p) flag has been deprecated";
-const KEY_SESSION_ID: &str = "session_id";
-const KEY_TYPE: &str = "type";
-const KEY_ROLE: &str = "role";
-const KEY_CONTENT: &str = "content";
-const KEY_ERROR: &str = "error";
-const KEY_MESSAGE: &str = "message";
-const TYPE_MESSAGE: &str = "message";
-const ROLE_ASSISTANT: &str = "assistant";
-const DEFAULT_TIMEOUT_SECS: u64 = 600; // 10 minutes
-const MAX_TIMEOUT_SECS: u64 = 3600; // 1 hour
+pub(crate) const DEFAULT_TIMEOUT_SECS: u64 = 600; // 10 minutes
+pub(crate) const MAX_TIMEOUT_SECS: u64 = 3600; // 1 hour
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30; // Wait for in-flight runs on shutdown
+const DEFAULT_SESSION_RETENTION_SECS: u64 = 7 * 24 * 60 * 60; // Deleted sessions stay restorable
+const DEFAULT_STDIN_PROMPT_THRESHOLD: usize = 8_000; // Larger prompts go through stdin
+const CLI_VERSION_TIMEOUT: Duration = Duration::from_secs(10); // `gemini --version` probe
 const MAX_MESSAGES_LIMIT: usize = 10000; // Maximum number of messages to store
 const MAX_NON_JSON_LINES: usize = 1000; // Maximum non-JSON lines to store
 const MAX_STDERR_BYTES: usize = 100_000; // Maximum stderr output to capture (100KB)
-const GEMINI_CONFIG_FILE: &str = "GEMINI.md"; // Configuration file name
+const MAX_JSON_DOCUMENT_BYTES: usize = 64 * 1024 * 1024; // Maximum `-o json` document (64MB)
+const STREAM_BUFFER_EVENTS: usize = 64; // Parsed events buffered ahead of a stream's consumer
+pub(crate) const GEMINI_CONFIG_FILE: &str = "GEMINI.md"; // Configuration file name
+pub(crate) const SERVER_CONFIG_FILE: &str = "gemini-mcp.config.json"; // Default server config
+const GEMINI_HOME_DIR: &str = ".gemini"; // Home-level directory holding the global GEMINI.md
 const MAX_CONFIG_SIZE: usize = 100_000; // Maximum GEMINI.md file size (100KB)
 
+/// Flags that the wrapper manages itself and that callers must not override
+/// through `additional_args`.
+const RESERVED_FLAGS: &[&str] = &["-o", "--output-format", "-p", "--prompt", "-r", "--resume"];
+const MODEL_FLAGS: &[&str] = &["-m", "--model"];
+const SANDBOX_FLAGS: &[&str] = &["-s", "--sandbox"];
+const YOLO_FLAGS: &[&str] = &["-y", "--yolo"];
+const APPROVAL_MODE_FLAG: &str = "--approval-mode";
+/// Flags that disable confirmations, widen the CLI's tool access or send
+/// prompts to a telemetry collector; refused in `additional_args` and
+/// `extra_args` unless the config sets `allow_dangerous_args`
+const DANGEROUS_FLAGS: &[&str] = &[
+    "-y",
+    "--yolo",
+    "--allowed-tools",
+    "--telemetry-target",
+    "--telemetry-otlp-endpoint",
+    "--telemetry-outfile",
+    "--telemetry-log-prompts",
+];
+/// Flag prefixes callers may pass per call via `extra_args` unless the config overrides the list
+const DEFAULT_EXTRA_ARGS_ALLOWLIST: &[&str] = &["--model", "-m"];
+/// Flags naming paths, refused in `extra_args` even when allowlisted: paths
+/// only reach the CLI through options checked against the [`Policy`]
+const PATH_FLAGS: &[&str] = &["--include-directories"];
+/// Variables callers may set per call via `env` unless the config overrides the list
+const DEFAULT_ENV_ALLOWLIST: &[&str] = &["GOOGLE_CLOUD_PROJECT", "GOOGLE_CLOUD_LOCATION"];
+
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct ServerConfig {
     #[serde(default)]
     additional_args: Vec<String>,
+    /// Accept flags from [`DANGEROUS_FLAGS`] (and `--approval-mode yolo`) in
+    /// `additional_args` and per-call `extra_args`
+    #[serde(default)]
+    allow_dangerous_args: bool,
+    /// Model of calls that choose none; replaces a `--model` in
+    /// `additional_args`
+    model: Option<String>,
     timeout_secs: Option<u64>,
+    /// Flag prefixes accepted in per-call `extra_args`; `None` uses the
+    /// built-in list
+    extra_args_allowlist: Option<Vec<String>>,
+    /// Environment variables set for every CLI process
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Variables accepted in per-call `env`; `None` uses the built-in list
+    env_allowlist: Option<Vec<String>>,
+    /// Discover GEMINI.md files in parent directories and `~/.gemini/`
+    #[serde(default = "default_true")]
+    hierarchical_gemini_md: bool,
+    /// Where to write the JSON shutdown report, if anywhere
+    shutdown_report_path: Option<PathBuf>,
+    /// What to do when a resume would switch a session's model
+    #[serde(default)]
+    model_pinning: ModelPinning,
+    /// Upper bound on concurrently running CLI processes; extra calls queue
+    max_concurrent_runs: Option<usize>,
+    /// Caps on how many calls may start per minute
+    #[serde(default)]
+    rate_limit: RateLimitConfig,
+    /// SQLite database recording every run (requires the `sqlite` feature)
+    session_store_path: Option<PathBuf>,
+    /// How long a deleted session can be restored before it is purged
+    session_retention_secs: Option<u64>,
+    /// Sessions idle longer than this are forgotten; kept forever otherwise
+    session_ttl_secs: Option<u64>,
+    /// Also delete the CLI's own chat files of forgotten sessions
+    #[serde(default)]
+    clean_cli_chats: bool,
+    /// How long shutdown waits for in-flight runs before killing them
+    shutdown_grace_secs: Option<u64>,
+    /// Run tool executions in the CLI's sandbox unless a call sets `SANDBOX`
+    #[serde(default)]
+    sandbox: bool,
+    /// Quiet hours during which new calls are rejected or rerouted
+    #[serde(default)]
+    maintenance_windows: Vec<MaintenanceWindowConfig>,
+    /// Most permissive approval mode a call may request
+    #[serde(default = "default_max_approval_mode")]
+    max_approval_mode: ApprovalMode,
+    /// How the prompt reaches the CLI
+    #[serde(default)]
+    prompt_delivery: PromptDelivery,
+    /// Prompt size in bytes above which `auto` delivery switches to stdin
+    stdin_prompt_threshold_bytes: Option<usize>,
+    /// Directory for server state such as the agent memory
+    state_dir: Option<PathBuf>,
+    /// Prepend remembered facts to the prompt of new sessions
+    #[serde(default)]
+    inject_memory: bool,
+    /// Workspace index used by `USE_RETRIEVAL`
+    #[serde(default)]
+    retrieval: RetrievalConfig,
+    /// How files are split for the workspace index
+    #[serde(default)]
+    chunking: ChunkingConfig,
+    /// Log level and optional JSON log file
+    #[serde(default)]
+    logging: LoggingConfig,
+    /// Audit log of every CLI run
+    #[serde(default)]
+    audit: AuditConfig,
+    /// Cost estimates and spend caps
+    #[serde(default)]
+    budget: BudgetConfig,
+    /// Model of the `VERIFY` pass
+    verify_model: Option<String>,
+    /// Persona presets selectable with `PERSONA`, by name
+    #[serde(default)]
+    personas: BTreeMap<String, String>,
+    /// Conversation starters advertised as MCP prompts, by name
+    #[serde(default)]
+    templates: BTreeMap<String, Template>,
+    /// Reuse answers to repeated one-shot prompts
+    #[serde(default)]
+    cache: CacheConfig,
+    /// Check shell commands in `suggested_actions`
+    #[serde(default)]
+    command_guard: CommandGuardConfig,
+    /// Let concurrent identical one-shot calls share one CLI run
+    #[serde(default = "default_true")]
+    dedup_in_flight: bool,
+    /// Keep the raw CLI events of `gemini` tool calls for the session
+    /// `events` resources, besides the typed fields parsed from them
+    #[serde(default)]
+    keep_raw_events: bool,
+    /// How many raw events of one run are kept
+    #[serde(default)]
+    event_buffer: EventBufferConfig,
+    /// Take the stdout of a run that printed no JSON but exited cleanly as
+    /// the answer, for CLI versions that print plain text
+    #[serde(default)]
+    plain_text_fallback: bool,
+    /// Output format asked of the CLI; by default `stream-json`, or `json`
+    /// for releases without it
+    output_format: Option<OutputFormat>,
+    /// Preview of the answer in the tool output
+    #[serde(default)]
+    summary: SummaryConfig,
+    /// Placeholders expanded in GEMINI.md and prompts
+    #[serde(default)]
+    placeholders: PlaceholderConfig,
+    /// Longest answer returned; longer ones are cut down
+    max_output_chars: Option<usize>,
+    /// How answers over `max_output_chars` are cut down
+    #[serde(default)]
+    output_truncation: TruncationStrategy,
+    /// Save every call as a replayable bundle unless it sets `SAVE_BUNDLE`
+    #[serde(default)]
+    save_bundles: bool,
+    /// Named credentials selectable with `AUTH_PROFILE`
+    #[serde(default)]
+    auth_profiles: BTreeMap<String, AuthProfile>,
+    /// Profile of calls without `AUTH_PROFILE`; the server's own environment
+    /// otherwise
+    default_auth_profile: Option<String>,
+    /// Models `gemini_fanout` runs a prompt on unless the call sets `MODELS`
+    #[serde(default)]
+    fanout_models: Vec<String>,
+    /// Fixed-argument variants of the gemini tool, each its own tool
+    #[serde(default)]
+    presets: Vec<ModelPreset>,
+    /// Directory of the YAML workflow files; `workflows` next to the config
+    /// file otherwise
+    workflows_dir: Option<PathBuf>,
+    /// Directory `WRITE_ARTIFACTS` saves code blocks to
+    artifacts_dir: Option<PathBuf>,
+    /// Limits of `CONTEXT_GLOBS`
+    #[serde(default)]
+    context_pack: ContextPackConfig,
+    /// Directories calls may read and write paths in; the server's working
+    /// directory when empty
+    #[serde(default)]
+    allowed_roots: Vec<PathBuf>,
+    /// Path patterns calls may never read or write, even inside an allowed
+    /// root
+    #[serde(default)]
+    denied_paths: Vec<String>,
+    /// Bounds of the histories kept by `gemini_chat`
+    #[serde(default)]
+    chat: ChatConfig,
+    /// Stderr patterns that stop a run early with an error category
+    #[serde(default)]
+    stderr_hooks: Vec<StderrHook>,
+    /// Failure injection for resilience testing
+    #[serde(default)]
+    chaos: ChaosConfig,
+    /// Keep CLI processes started ahead of one-shot runs
+    #[serde(default)]
+    pool: PoolConfig,
+    /// Keep one CLI process per session and send follow-ups over ACP
+    #[serde(default)]
+    interactive: InteractiveConfig,
+}
+
+/// Estimated spend and its caps, in USD
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    /// Spend per UTC day; new runs are refused beyond it
+    pub daily_usd: Option<f64>,
+    /// Spend per session; the session cannot be resumed beyond it
+    pub per_session_usd: Option<f64>,
+    /// Prices by model name prefix, replacing built-in ones with the same
+    /// prefix
+    #[serde(default)]
+    pub prices: BTreeMap<String, ModelPrice>,
+}
+
+/// What a model's tokens cost
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    /// USD per million prompt tokens
+    pub input_per_million: f64,
+    /// USD per million output tokens, thinking included
+    pub output_per_million: f64,
+}
+
+/// Append-only JSON-lines record of every CLI run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// File the records are appended to; no audit log when unset
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub prompt: AuditPrompt,
+}
+
+/// How the prompt of a run is recorded in the audit log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditPrompt {
+    /// Only its SHA-256, so the log holds no source code
+    #[default]
+    Hash,
+    /// The whole prompt, GEMINI.md excluded
+    Full,
+}
+
+/// Server log output; `RUST_LOG` takes precedence over `level`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `tracing` filter such as `info` or `gemini_mcp_rs=debug`
+    pub level: Option<String>,
+    /// Also append JSON-lines logs to this file
+    pub file: Option<PathBuf>,
+}
+
+/// A recurring period during which new calls are rejected with a retry hint,
+/// or run on `route_to_model` instead
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindowConfig {
+    /// Days the window starts on (`mon`..`sun`); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Local start time, `HH:MM`
+    pub start: String,
+    /// Local end time, `HH:MM`; earlier than `start` to span midnight
+    pub end: String,
+    /// Offset of the local times from UTC, `+HH:MM` or `-HH:MM` (default UTC)
+    pub utc_offset: Option<String>,
+    /// Run calls on this model instead of rejecting them
+    pub route_to_model: Option<String>,
+}
+
+/// How far the CLI may act without asking for confirmation, ordered from least
+/// to most permissive (`--approval-mode`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(rmcp::schemars::JsonSchema),
+    schemars(crate = "rmcp::schemars")
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalMode {
+    /// Ask before every tool call; in headless runs such calls are declined
+    Default,
+    /// Apply file edits without asking
+    AutoEdit,
+    /// Approve every tool call, including shell commands (`--yolo`)
+    Yolo,
+}
+
+impl ApprovalMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::AutoEdit => "auto_edit",
+            Self::Yolo => "yolo",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "default" => Some(Self::Default),
+            "auto_edit" => Some(Self::AutoEdit),
+            "yolo" => Some(Self::Yolo),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApprovalMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
+/// How the final prompt (GEMINI.md included) is handed to the CLI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptDelivery {
+    /// Use stdin once the prompt exceeds `stdin_prompt_threshold_bytes`
+    #[default]
+    Auto,
+    /// Always pass the prompt as a command-line argument
+    Argv,
+    /// Always pipe the prompt through stdin, keeping it out of `ps` output
+    Stdin,
+}
+
+/// Per-minute caps on started calls and used tokens, enforced as token
+/// buckets. `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Calls per minute across all sessions
+    pub global_per_minute: Option<u32>,
+    /// Turns per minute within a single resumed session
+    pub per_session_per_minute: Option<u32>,
+    /// Tokens per minute across all calls
+    pub global_tokens_per_minute: Option<u64>,
+    /// Calls per minute of each MCP client, by the name it connects with
+    pub per_client_per_minute: Option<u32>,
+    /// Tokens per minute of each MCP client
+    pub per_client_tokens_per_minute: Option<u64>,
+    /// Caps of particular clients, by name, replacing the `per_client_*` ones
+    #[serde(default)]
+    pub clients: BTreeMap<String, ClientRateLimit>,
+}
+
+/// Caps of one MCP client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientRateLimit {
+    pub per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+}
+
+/// Opt-in workspace index (`gemini_index_workspace`, `USE_RETRIEVAL`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetrievalConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Chunks prepended to a prompt
+    pub top_k: Option<usize>,
+    /// Lines per indexed chunk; overrides `chunking.max_lines`
+    pub chunk_lines: Option<usize>,
+    /// Files larger than this are not indexed
+    pub max_file_bytes: Option<u64>,
+    /// External embedding program; the built-in hashing embedder otherwise
+    pub embed_command: Option<Vec<String>>,
+    /// Check an existing index for changed files this often and update it
+    pub watch_interval_secs: Option<u64>,
+}
+
+/// Validation of the shell commands in `suggested_actions`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandGuardConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub mode: CommandGuardMode,
+    /// Extra regular expressions (case-insensitive) of forbidden commands
+    #[serde(default)]
+    pub deny_patterns: Vec<String>,
+    /// Attach a dry-run variant to commands that have one
+    #[serde(default = "default_true")]
+    pub dry_run: bool,
+}
+
+impl Default for CommandGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: CommandGuardMode::default(),
+            deny_patterns: Vec::new(),
+            dry_run: default_true(),
+        }
+    }
+}
+
+/// What the command guard does with a dangerous command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandGuardMode {
+    /// Drop it from `suggested_actions`
+    #[default]
+    Remove,
+    /// Keep it, with the reason in `blocked`
+    Annotate,
+}
+
+/// The short `summary` sent alongside every answer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SummaryConfig {
+    #[serde(default)]
+    pub mode: SummaryMode,
+    /// Longest summary in characters; answers this short are their own summary
+    pub max_chars: Option<usize>,
+    /// Model writing `model` summaries (defaults to `verify_model`)
+    pub model: Option<String>,
+}
+
+/// Expansion of `{{date}}`, `{{cwd}}`, `{{git_branch}}` and `{{env:VAR}}` in
+/// the final prompt
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaceholderConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Variables `{{env:VAR}}` may read
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+/// How an answer over `max_output_chars` is cut down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TruncationStrategy {
+    /// Keep the beginning
+    Head,
+    /// Keep the end
+    Tail,
+    /// Keep the beginning and the end
+    #[default]
+    MiddleEllipsis,
+    /// Have a second, cheap Gemini call condense the answer, falling back to
+    /// `middle-ellipsis`
+    SummarizeWithFlash,
+}
+
+/// Bounds of each `gemini_chat` conversation; the oldest turns are dropped
+/// first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Turns replayed into the prompt (default 20)
+    pub max_turns: Option<usize>,
+    /// Characters of history replayed into the prompt (default 32000)
+    pub max_chars: Option<usize>,
+}
+
+/// Raw events kept per run: the first `head` and the last `tail`, with a
+/// marker counting the ones dropped in between
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventBufferConfig {
+    /// Events kept from the start of a run (default 5000)
+    pub head: Option<usize>,
+    /// Events kept from the end of a run (default 5000)
+    pub tail: Option<usize>,
+}
+
+impl EventBufferConfig {
+    pub fn head(&self) -> usize {
+        self.head.unwrap_or(DEFAULT_EVENT_BUFFER_HEAD)
+    }
+
+    pub fn tail(&self) -> usize {
+        self.tail.unwrap_or(DEFAULT_EVENT_BUFFER_TAIL)
+    }
+}
+
+const DEFAULT_EVENT_BUFFER_HEAD: usize = 5000;
+const DEFAULT_EVENT_BUFFER_TAIL: usize = 5000;
+
+/// Limits of the block packed from `CONTEXT_GLOBS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextPackConfig {
+    /// Files packed (default 50)
+    pub max_files: Option<usize>,
+    /// Larger files are skipped (default 100000)
+    pub max_file_bytes: Option<u64>,
+    /// Bytes of all packed files together (default 400000)
+    pub max_total_bytes: Option<u64>,
+}
+
+/// How the `summary` field is produced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryMode {
+    /// No `summary` field
+    Off,
+    /// The beginning and end of the answer
+    #[default]
+    Excerpt,
+    /// A one-line summary written by a second, cheap Gemini call
+    Model,
+}
+
+/// Policy for resumes that would change the model a session was created with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelPinning {
+    /// Do not track or enforce session models
+    Off,
+    /// Keep the session on its original model unless the caller asks for
+    /// another one, and log a warning when the model changes
+    #[default]
+    Warn,
+    /// Reject resumes that request a different model
+    Refuse,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Without a configured maximum every mode may be requested
+fn default_max_approval_mode() -> ApprovalMode {
+    ApprovalMode::Yolo
+}
+
+/// `--set` assignments, the highest-precedence config layer
+static CONFIG_OVERRIDES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Apply `key=value` assignments (`--set`) on top of every other config
+/// layer. Only takes effect before the config is first read.
+pub fn set_config_overrides(assignments: Vec<String>) {
+    if CONFIG_OVERRIDES.set(assignments).is_err() {
+        tracing::warn!("config overrides were set after the config was read; ignoring them");
+    }
+}
+
+/// The project config file: `GEMINI_MCP_CONFIG_PATH`, else the first of
+/// [`config_layers::PROJECT_FILES`] in the working directory, else where
+/// `gemini-mcp.config.json` would be
 fn resolve_config_path() -> Option<PathBuf> {
     if let Ok(env_path) = std::env::var("GEMINI_MCP_CONFIG_PATH") {
         let trimmed = env_path.trim();
@@ -42,95 +614,308 @@ fn resolve_config_path() -> Option<PathBuf> {
         }
     }
 
-    std::env::current_dir()
-        .ok()
-        .map(|cwd| cwd.join("gemini-mcp.config.json"))
+    let cwd = std::env::current_dir().ok()?;
+    Some(
+        config_layers::find_file(&cwd, config_layers::PROJECT_FILES)
+            .unwrap_or_else(|| cwd.join(SERVER_CONFIG_FILE)),
+    )
 }
 
-fn load_additional_args_from_config() -> Vec<String> {
-    let mut base: Vec<String> = Vec::new();
-    let Some(config_path) = resolve_config_path() else {
-        return base;
-    };
+/// Every source of config values that is present, lowest precedence first
+fn read_config_layers() -> std::result::Result<Vec<Layer>, ConfigError> {
+    let mut layers = Vec::new();
+    let user_file = config_layers::user_config_dir()
+        .and_then(|dir| config_layers::find_file(&dir, config_layers::USER_FILES));
+    let project_file = resolve_config_path().filter(|path| path.is_file());
+    for path in user_file.iter().chain(project_file.iter()) {
+        layers.push(config_layers::read_file(path)?);
+    }
+    layers.extend(config_layers::env_layer(std::env::vars()));
+    let overrides = CONFIG_OVERRIDES.get().map_or(&[][..], Vec::as_slice);
+    layers.extend(config_layers::cli_layer(overrides)?);
+    Ok(layers)
+}
+
+/// The config layers and the config merged from them. Each layer is checked
+/// on its own first, so an error names the layer that caused it; dangerous
+/// `additional_args` are only checked once merged, since another layer may
+/// set `allow_dangerous_args`.
+fn merged_config() -> std::result::Result<(Vec<Layer>, ServerConfig), ConfigError> {
+    let layers = read_config_layers()?;
+    for layer in &layers {
+        parse_config(&layer.source, layer.value.clone())?;
+    }
+    let sources = layers
+        .iter()
+        .map(|layer| layer.source.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let path = Path::new(&sources);
+    let parsed = parse_config(path, config_layers::merge(&layers))?;
+    if let Some(issue) = dangerous_args_issue(&parsed) {
+        return Err(ConfigError {
+            path: path.to_path_buf(),
+            issues: vec![issue],
+        });
+    }
+    Ok((layers, parsed))
+}
 
-    if !config_path.is_file() {
-        return base;
+/// A dangerous flag in `additional_args` the config does not allow
+fn dangerous_args_issue(cfg: &ServerConfig) -> Option<Issue> {
+    if cfg.allow_dangerous_args {
+        return None;
     }
+    let i = dangerous_arg(&cfg.additional_args)?;
+    Some(Issue {
+        pointer: format!("/additional_args/{}", i),
+        message: format!("`{}` is a dangerous flag", cfg.additional_args[i].trim()),
+        hint: Some("set allow_dangerous_args to pass it anyway".to_string()),
+    })
+}
 
-    match std::fs::read_to_string(&config_path) {
-        Ok(raw) => match serde_json::from_str::<ServerConfig>(&raw) {
-            Ok(parsed) => {
-                let cleaned = parsed
-                    .additional_args
-                    .into_iter()
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect::<Vec<_>>();
+/// Read and check every config layer the server would use, without caching
+/// the result. Returns the layers' sources, empty when only the defaults
+/// apply.
+pub fn validate_config_file() -> Result<Vec<PathBuf>> {
+    let (layers, parsed) = merged_config()?;
+    let sources: Vec<PathBuf> = layers.into_iter().map(|layer| layer.source).collect();
+    let context = || {
+        let names: Vec<String> = sources.iter().map(|s| s.display().to_string()).collect();
+        format!("Invalid config {}", names.join(" + "))
+    };
+    crate::persona::validate(&parsed.personas).with_context(context)?;
+    crate::template::validate(&parsed.templates).with_context(context)?;
+    crate::preset::validate(&parsed.presets).with_context(context)?;
+    auth::validate(
+        &parsed.auth_profiles,
+        parsed.default_auth_profile.as_deref(),
+    )
+    .with_context(context)?;
+    hooks::validate(&parsed.stderr_hooks).with_context(context)?;
+    chaos::validate(&parsed.chaos).with_context(context)?;
+    Ok(sources)
+}
 
-                if !cleaned.is_empty() {
-                    base = cleaned;
-                }
-            }
-            Err(err) => eprintln!(
-                "gemini-mcp-rs: failed to parse config {}: {}",
-                config_path.display(),
-                err
-            ),
-        },
-        Err(err) => eprintln!(
-            "gemini-mcp-rs: failed to read config {}: {}",
-            config_path.display(),
-            err
-        ),
+/// Parse config values strictly: unknown fields, wrong types and values out
+/// of range are all errors
+fn parse_config(path: &Path, value: Value) -> std::result::Result<ServerConfig, ConfigError> {
+    let parsed: ServerConfig = config_check::parse_value(path, value)?;
+    let issues = range_issues(&parsed);
+    if !issues.is_empty() {
+        return Err(ConfigError {
+            path: path.to_path_buf(),
+            issues,
+        });
     }
+    Ok(parsed)
+}
 
-    base
+/// Values the config's types accept but the server cannot use
+fn range_issues(cfg: &ServerConfig) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut positive = |pointer: &str, value: Option<u64>| {
+        if value == Some(0) {
+            issues.push(Issue::new(pointer, "must be greater than 0"));
+        }
+    };
+    positive("/timeout_secs", cfg.timeout_secs);
+    positive(
+        "/max_concurrent_runs",
+        cfg.max_concurrent_runs.map(|n| n as u64),
+    );
+    positive("/session_ttl_secs", cfg.session_ttl_secs);
+    positive("/max_output_chars", cfg.max_output_chars.map(|n| n as u64));
+    positive(
+        "/rate_limit/global_per_minute",
+        cfg.rate_limit.global_per_minute.map(u64::from),
+    );
+    positive(
+        "/rate_limit/per_session_per_minute",
+        cfg.rate_limit.per_session_per_minute.map(u64::from),
+    );
+    positive(
+        "/rate_limit/global_tokens_per_minute",
+        cfg.rate_limit.global_tokens_per_minute,
+    );
+    positive(
+        "/rate_limit/per_client_per_minute",
+        cfg.rate_limit.per_client_per_minute.map(u64::from),
+    );
+    positive(
+        "/rate_limit/per_client_tokens_per_minute",
+        cfg.rate_limit.per_client_tokens_per_minute,
+    );
+    for (name, limit) in &cfg.rate_limit.clients {
+        let pointer = format!("/rate_limit/clients/{}", config_check::escape(name));
+        positive(
+            &format!("{}/per_minute", pointer),
+            limit.per_minute.map(u64::from),
+        );
+        positive(
+            &format!("{}/tokens_per_minute", pointer),
+            limit.tokens_per_minute,
+        );
+    }
+    positive("/retrieval/top_k", cfg.retrieval.top_k.map(|n| n as u64));
+    positive("/chat/max_turns", cfg.chat.max_turns.map(|n| n as u64));
+    positive(
+        "/context_pack/max_files",
+        cfg.context_pack.max_files.map(|n| n as u64),
+    );
+    positive("/chunking/max_lines", Some(cfg.chunking.max_lines as u64));
+    positive("/chunking/max_tokens", Some(cfg.chunking.max_tokens as u64));
+    positive("/pool/size", cfg.pool.size.map(|n| n as u64));
+    positive("/pool/idle_timeout_secs", cfg.pool.idle_timeout_secs);
+    positive(
+        "/interactive/max_sessions",
+        cfg.interactive.max_sessions.map(|n| n as u64),
+    );
+    positive(
+        "/interactive/idle_timeout_secs",
+        cfg.interactive.idle_timeout_secs,
+    );
+    let mut amount = |pointer: &str, value: f64, allow_zero: bool| {
+        if !value.is_finite() || value < 0.0 || (value == 0.0 && !allow_zero) {
+            let bound = if allow_zero {
+                "0 or more"
+            } else {
+                "greater than 0"
+            };
+            issues.push(Issue::new(pointer, format!("must be {}", bound)));
+        }
+    };
+    if let Some(usd) = cfg.budget.daily_usd {
+        amount("/budget/daily_usd", usd, false);
+    }
+    if let Some(usd) = cfg.budget.per_session_usd {
+        amount("/budget/per_session_usd", usd, false);
+    }
+    for (model, price) in &cfg.budget.prices {
+        let pointer = format!("/budget/prices/{}", config_check::escape(model));
+        amount(
+            &format!("{}/input_per_million", pointer),
+            price.input_per_million,
+            true,
+        );
+        amount(
+            &format!("{}/output_per_million", pointer),
+            price.output_per_million,
+            true,
+        );
+    }
+    if let Some(secs) = cfg.timeout_secs.filter(|&t| t > MAX_TIMEOUT_SECS) {
+        issues.push(Issue::new(
+            "/timeout_secs",
+            format!("{} is above the maximum of {}", secs, MAX_TIMEOUT_SECS),
+        ));
+    }
+    issues
 }
 
 pub fn default_additional_args() -> Vec<String> {
     static CACHED_ARGS: OnceLock<Vec<String>> = OnceLock::new();
     CACHED_ARGS
-        .get_or_init(load_additional_args_from_config)
+        .get_or_init(|| {
+            let cfg = server_config();
+            match cfg
+                .model
+                .as_deref()
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+            {
+                Some(model) => {
+                    let mut args = strip_model_args(&cfg.additional_args);
+                    args.extend(["--model".to_string(), model.to_string()]);
+                    args
+                }
+                None => cfg.additional_args.clone(),
+            }
+        })
         .clone()
 }
 
 fn load_server_config() -> ServerConfig {
     let mut cfg = ServerConfig {
         additional_args: Vec::new(),
+        allow_dangerous_args: false,
+        model: None,
         timeout_secs: None,
+        extra_args_allowlist: None,
+        env: BTreeMap::new(),
+        env_allowlist: None,
+        hierarchical_gemini_md: true,
+        shutdown_report_path: None,
+        model_pinning: ModelPinning::default(),
+        max_concurrent_runs: None,
+        rate_limit: RateLimitConfig::default(),
+        session_store_path: None,
+        session_retention_secs: None,
+        session_ttl_secs: None,
+        clean_cli_chats: false,
+        shutdown_grace_secs: None,
+        sandbox: false,
+        maintenance_windows: Vec::new(),
+        max_approval_mode: default_max_approval_mode(),
+        prompt_delivery: PromptDelivery::default(),
+        stdin_prompt_threshold_bytes: None,
+        state_dir: None,
+        inject_memory: false,
+        retrieval: RetrievalConfig::default(),
+        chunking: ChunkingConfig::default(),
+        logging: LoggingConfig::default(),
+        audit: AuditConfig::default(),
+        budget: BudgetConfig::default(),
+        verify_model: None,
+        personas: BTreeMap::new(),
+        templates: BTreeMap::new(),
+        cache: CacheConfig::default(),
+        command_guard: CommandGuardConfig::default(),
+        dedup_in_flight: true,
+        keep_raw_events: false,
+        event_buffer: EventBufferConfig::default(),
+        plain_text_fallback: false,
+        output_format: None,
+        summary: SummaryConfig::default(),
+        placeholders: PlaceholderConfig::default(),
+        max_output_chars: None,
+        output_truncation: TruncationStrategy::default(),
+        save_bundles: false,
+        auth_profiles: BTreeMap::new(),
+        default_auth_profile: None,
+        fanout_models: Vec::new(),
+        presets: Vec::new(),
+        workflows_dir: None,
+        artifacts_dir: None,
+        context_pack: ContextPackConfig::default(),
+        allowed_roots: Vec::new(),
+        denied_paths: Vec::new(),
+        chat: ChatConfig::default(),
+        stderr_hooks: Vec::new(),
+        chaos: ChaosConfig::default(),
+        pool: PoolConfig::default(),
+        interactive: InteractiveConfig::default(),
     };
 
-    let Some(config_path) = resolve_config_path() else {
-        return cfg;
-    };
-
-    if !config_path.is_file() {
-        return cfg;
-    }
-
-    match std::fs::read_to_string(&config_path) {
-        Ok(raw) => match serde_json::from_str::<ServerConfig>(&raw) {
-            Ok(parsed) => {
-                let mut cleaned = parsed;
-                cleaned.additional_args = cleaned
-                    .additional_args
-                    .into_iter()
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                cfg = cleaned;
-            }
-            Err(err) => eprintln!(
-                "gemini-mcp-rs: failed to parse config {}: {}",
-                config_path.display(),
-                err
-            ),
-        },
-        Err(err) => eprintln!(
-            "gemini-mcp-rs: failed to read config {}: {}",
-            config_path.display(),
-            err
-        ),
+    match merged_config() {
+        Ok((_, parsed)) => {
+            let mut cleaned = parsed;
+            cleaned.additional_args = cleaned
+                .additional_args
+                .into_iter()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            cleaned.env.retain(|key, _| {
+                let valid = validate_env_name(key).is_ok();
+                if !valid {
+                    tracing::warn!("ignoring invalid env variable name {:?} in config", key);
+                }
+                valid
+            });
+            cfg = cleaned;
+        }
+        Err(err) => tracing::warn!("{}; using the default config", err),
     }
 
     cfg
@@ -153,238 +938,2649 @@ pub fn default_timeout_secs() -> u64 {
     })
 }
 
-#[derive(Debug, Clone)]
-pub struct Options {
-    pub prompt: String,
-    pub session_id: Option<String>,
-    pub additional_args: Vec<String>,
+/// Configured policy for model changes within a session
+pub fn model_pinning() -> ModelPinning {
+    server_config().model_pinning
 }
 
-#[derive(Debug)]
-pub struct GeminiResult {
-    pub success: bool,
-    pub session_id: String,
-    pub agent_messages: String,
-    pub all_messages: Vec<Value>,
-    pub error: Option<String>,
+/// Configured limit on concurrently running CLI processes (`None` = unlimited)
+pub fn max_concurrent_runs() -> Option<usize> {
+    server_config().max_concurrent_runs.filter(|&n| n > 0)
 }
 
-/// Attempt to read GEMINI.md configuration file from the current directory
-/// Returns the content if found and readable, otherwise returns None
-/// Logs warnings for read errors (except file not found) and size limit violations
-async fn read_gemini_config() -> Option<String> {
-    read_gemini_config_from_path(&PathBuf::from(GEMINI_CONFIG_FILE)).await
+/// Configured per-minute call and token caps
+pub fn rate_limit() -> RateLimitConfig {
+    server_config().rate_limit.clone()
 }
 
-/// Internal function to read GEMINI.md configuration from a specific path
-/// This is separated to allow for testing with custom paths
-/// Exposed publicly for integration tests
-pub async fn read_gemini_config_from_path(config_path: &PathBuf) -> Option<String> {
-    // First check if file exists and get metadata
-    let metadata = match fs::metadata(config_path).await {
-        Ok(meta) => meta,
-        Err(e) => {
-            // Only log if it's not a "file not found" error
-            if e.kind() != std::io::ErrorKind::NotFound {
-                eprintln!("Warning: Cannot access GEMINI.md configuration file: {}", e);
-            }
-            return None;
-        }
-    };
+/// Optional SQLite database that persists runs across restarts
+pub fn session_store_path() -> Option<PathBuf> {
+    server_config().session_store_path.clone()
+}
 
-    // Check file size before reading
-    let file_size = metadata.len() as usize;
-    if file_size > MAX_CONFIG_SIZE {
-        eprintln!(
-            "Warning: GEMINI.md file is too large ({} bytes, max {} bytes). Configuration will be ignored.",
-            file_size, MAX_CONFIG_SIZE
-        );
-        return None;
-    }
+/// How long `gemini_restore_session` can bring back a deleted session
+pub fn session_retention() -> Duration {
+    Duration::from_secs(
+        server_config()
+            .session_retention_secs
+            .unwrap_or(DEFAULT_SESSION_RETENTION_SECS),
+    )
+}
 
-    // Read the file content
-    match fs::read_to_string(config_path).await {
-        Ok(content) => {
-            // Check if content is effectively empty (only whitespace)
-            if content.trim().is_empty() {
-                eprintln!("Warning: GEMINI.md file is empty and will be ignored.");
-                None
-            } else {
-                // Return original content to preserve formatting, not trimmed version
-                Some(content)
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "Warning: Failed to read GEMINI.md configuration file: {}",
-                e
-            );
-            None
-        }
-    }
+/// How long a session may go unused before it is forgotten, if limited
+pub fn session_ttl() -> Option<Duration> {
+    server_config()
+        .session_ttl_secs
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
 }
 
-/// Prepare the final prompt by prepending GEMINI.md content if it exists
-async fn prepare_prompt(user_prompt: &str) -> String {
-    match read_gemini_config().await {
-        Some(config_content) => {
-            format!("{}\n\n{}", config_content, user_prompt)
-        }
-        None => user_prompt.to_string(),
-    }
+/// Whether forgetting an idle session also deletes the CLI's chat files
+pub fn clean_cli_chats() -> bool {
+    server_config().clean_cli_chats
 }
 
-/// Process a single JSON line from the gemini CLI output
-fn process_json_line(line_data: &Value, result: &mut GeminiResult) {
-    // Collect all messages - store the raw Value to handle objects, arrays, and primitives.
-    // Limit the number of messages to prevent memory exhaustion.
-    if result.all_messages.len() < MAX_MESSAGES_LIMIT {
-        result.all_messages.push(line_data.clone());
-    }
+/// How long a SIGINT/SIGTERM shutdown waits for in-flight runs to finish
+pub fn shutdown_grace_period() -> Duration {
+    Duration::from_secs(
+        server_config()
+            .shutdown_grace_secs
+            .unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECS),
+    )
+}
 
-    // Extract session_id
-    if let Some(session_id) = line_data.get(KEY_SESSION_ID).and_then(|v| v.as_str()) {
-        if !session_id.is_empty() {
-            result.session_id = session_id.to_string();
-        }
-    }
+/// Whether calls run in the CLI's sandbox by default
+pub fn default_sandbox() -> bool {
+    server_config().sandbox
+}
+
+/// Most permissive approval mode calls may request (`max_approval_mode`)
+pub fn max_approval_mode() -> ApprovalMode {
+    server_config().max_approval_mode
+}
 
-    // Extract agent messages
-    let item_type = line_data
-        .get(KEY_TYPE)
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
-    let item_role = line_data
-        .get(KEY_ROLE)
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
+/// Configured prompt delivery (`prompt_delivery`)
+pub fn prompt_delivery() -> PromptDelivery {
+    server_config().prompt_delivery
+}
 
-    if item_type == TYPE_MESSAGE && item_role == ROLE_ASSISTANT {
-        if let Some(content) = line_data.get(KEY_CONTENT).and_then(|v| v.as_str()) {
-            // Skip the CLI's own deprecation warning about --prompt
-            if content.contains(PROMPT_DEPRECATION_WARNING) {
-                return;
-            }
-            if !result.agent_messages.is_empty() {
-                result.agent_messages.push('\n');
-            }
-            result.agent_messages.push_str(content);
-        }
+/// Prompt size above which `auto` delivery pipes the prompt through stdin
+pub fn stdin_prompt_threshold_bytes() -> usize {
+    server_config()
+        .stdin_prompt_threshold_bytes
+        .unwrap_or(DEFAULT_STDIN_PROMPT_THRESHOLD)
+}
+
+/// Directory for persistent server state: `state_dir` from the config, else
+/// `$XDG_STATE_HOME/gemini-mcp-rs`, `%LOCALAPPDATA%\gemini-mcp-rs` or
+/// `~/.local/state/gemini-mcp-rs`
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(ref dir) = server_config().state_dir {
+        return Some(dir.clone());
     }
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .or_else(|| std::env::var_os("LOCALAPPDATA"))
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".local").join("state")))?;
+    Some(base.join("gemini-mcp-rs"))
+}
+
+/// Whether new sessions get the remembered facts unless a call sets
+/// `INJECT_MEMORY`
+pub fn inject_memory() -> bool {
+    server_config().inject_memory
+}
 
-    // Check for errors (case-insensitive) - look for explicit error indicators
-    let item_type_lower = item_type.to_lowercase();
-    let has_explicit_error = item_type_lower.contains("fail") || item_type_lower.contains("error");
-    let has_error_obj = line_data.get(KEY_ERROR).is_some();
+/// Configured workspace retrieval settings
+pub fn retrieval() -> RetrievalConfig {
+    server_config().retrieval.clone()
+}
 
-    if has_explicit_error || has_error_obj {
-        result.success = false;
-        if let Some(error_obj) = line_data.get(KEY_ERROR).and_then(|v| v.as_object()) {
-            if let Some(msg) = error_obj.get(KEY_MESSAGE).and_then(|v| v.as_str()) {
-                result.error = Some(format!("gemini error: {}", msg));
+/// Configured model for answer verification
+pub fn verify_model() -> Option<String> {
+    server_config()
+        .verify_model
+        .as_deref()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+}
+
+/// The response cache, when `cache.enabled` is set
+fn response_cache() -> Option<&'static ResponseCache> {
+    static CACHE: OnceLock<Option<ResponseCache>> = OnceLock::new();
+    CACHE
+        .get_or_init(|| {
+            let config = server_config().cache;
+            config.enabled.then(|| ResponseCache::new(&config))
+        })
+        .as_ref()
+}
+
+/// Fault injection of `chaos`; draws no faults unless enabled
+fn chaos_mode() -> &'static Chaos {
+    static CHAOS: OnceLock<Chaos> = OnceLock::new();
+    CHAOS.get_or_init(|| {
+        let config = server_config().chaos.clone();
+        if config.enabled {
+            match chaos::validate(&config) {
+                Ok(()) => tracing::warn!(
+                    "chaos mode is enabled: runs will randomly be delayed, get spurious stderr or time out"
+                ),
+                Err(err) => tracing::warn!("{:#}; out-of-range rates are clamped", err),
             }
-        } else if let Some(msg) = line_data.get(KEY_MESSAGE).and_then(|v| v.as_str()) {
-            result.error = Some(format!("gemini error: {}", msg));
         }
-    }
+        Chaos::new(config)
+    })
 }
 
-/// Build the gemini command with the given options
-fn build_command(opts: &Options) -> Command {
-    let gemini_bin = std::env::var("GEMINI_BIN").unwrap_or_else(|_| "gemini".to_string());
+/// Configured stderr patterns that stop a run early
+pub fn stderr_hooks() -> Vec<StderrHook> {
+    server_config().stderr_hooks.clone()
+}
 
-    let mut cmd = Command::new(gemini_bin);
-    // Always stream JSON output
-    cmd.arg("-o");
-    cmd.arg("stream-json");
+/// Whether the server keeps the raw CLI events of its calls for the session
+/// `events` resources
+pub fn keep_raw_events() -> bool {
+    server_config().keep_raw_events
+}
 
-    // Additional arguments configured at the server level
-    for arg in &opts.additional_args {
-        cmd.arg(arg);
-    }
+/// How many raw events of one run are kept
+pub fn event_buffer() -> EventBufferConfig {
+    server_config().event_buffer
+}
 
-    // Resume session if provided; otherwise, pass the prompt positionally.
-    if let Some(ref session_id) = opts.session_id {
-        // For resume, Gemini CLI currently requires a prompt via --prompt (-p) or stdin.
-        // We use --prompt here (and filter out the deprecation warning in process_json_line).
-        cmd.arg("--prompt");
-        cmd.arg(&opts.prompt);
-        cmd.args(["--resume", session_id]);
+/// Whether plain-text stdout is taken as the answer of runs without JSON
+pub fn plain_text_fallback() -> bool {
+    server_config().plain_text_fallback
+}
+
+/// Output format to ask of a CLI with the flags of `compat`
+pub fn output_format(compat: CliCompat) -> OutputFormat {
+    server_config()
+        .output_format
+        .unwrap_or_else(|| compat.output_format())
+}
+
+/// Whether chaos mode is injecting faults
+pub fn chaos_enabled() -> bool {
+    server_config().chaos.enabled
+}
+
+/// Hit/miss counters of the response cache, if enabled
+pub fn cache_stats() -> Option<CacheStats> {
+    response_cache().map(ResponseCache::stats)
+}
+
+/// The pool of waiting CLI processes, when `pool.enabled` is set
+fn warm_pool() -> Option<&'static WarmPool> {
+    static POOL: OnceLock<Option<WarmPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let config = server_config().pool;
+        config.enabled.then(|| WarmPool::new(&config))
+    })
+    .as_ref()
+}
+
+/// Counters of the process pool, if enabled
+pub fn pool_stats() -> Option<PoolStats> {
+    warm_pool().map(WarmPool::stats)
+}
+
+/// The CLI processes of interactive sessions, when `interactive.enabled` is
+/// set
+fn interactive_sessions() -> Option<&'static InteractiveSessions> {
+    static SESSIONS: OnceLock<Option<InteractiveSessions>> = OnceLock::new();
+    SESSIONS
+        .get_or_init(|| {
+            let config = server_config().interactive;
+            config.enabled.then(|| InteractiveSessions::new(&config))
+        })
+        .as_ref()
+}
+
+/// Counters of the interactive sessions, if enabled
+pub fn interactive_stats() -> Option<InteractiveStats> {
+    interactive_sessions().map(InteractiveSessions::stats)
+}
+
+/// Kill the processes waiting in the pool and those of interactive
+/// sessions; call before exiting
+pub fn shutdown_processes() {
+    if let Some(pool) = warm_pool() {
+        pool.clear();
+    }
+    if let Some(sessions) = interactive_sessions() {
+        sessions.clear();
+    }
+}
+
+/// Runs currently in flight, by the same key as the response cache
+fn in_flight() -> &'static Singleflight<CacheKey, Result<GeminiResult, String>> {
+    static IN_FLIGHT: OnceLock<Singleflight<CacheKey, Result<GeminiResult, String>>> =
+        OnceLock::new();
+    IN_FLIGHT.get_or_init(Singleflight::new)
+}
+
+/// Calls answered by sharing an identical in-flight run
+pub fn coalesced_runs() -> u64 {
+    in_flight().coalesced()
+}
+
+/// Configured command guard for `suggested_actions`
+pub fn command_guard() -> CommandGuardConfig {
+    server_config().command_guard.clone()
+}
+
+/// How the `summary` output field is produced
+pub fn summary() -> SummaryConfig {
+    server_config().summary.clone()
+}
+
+/// Configured prompt placeholders
+pub fn placeholders() -> &'static PlaceholderConfig {
+    &server_config().placeholders
+}
+
+/// Longest answer returned, if limited
+pub fn max_output_chars() -> Option<usize> {
+    server_config().max_output_chars.filter(|&n| n > 0)
+}
+
+/// How answers over [`max_output_chars`] are cut down
+pub fn output_truncation() -> TruncationStrategy {
+    server_config().output_truncation
+}
+
+/// Whether calls are saved as bundles unless they set `SAVE_BUNDLE`
+pub fn save_bundles() -> bool {
+    server_config().save_bundles
+}
+
+/// Models of `gemini_fanout` calls without `MODELS`
+pub fn fanout_models() -> &'static [String] {
+    &server_config().fanout_models
+}
+
+/// Configured auth profiles
+pub fn auth_profiles() -> &'static BTreeMap<String, AuthProfile> {
+    &server_config().auth_profiles
+}
+
+/// Profile used by calls that do not set `AUTH_PROFILE`
+pub fn default_auth_profile() -> Option<&'static str> {
+    server_config()
+        .default_auth_profile
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// Directory `gemini_run_workflow` loads workflows from: `workflows_dir`,
+/// else `workflows` next to the config file
+pub fn workflows_dir() -> Option<PathBuf> {
+    if let Some(ref dir) = server_config().workflows_dir {
+        return Some(dir.clone());
+    }
+    let config_path = resolve_config_path()?;
+    Some(config_path.parent()?.join("workflows"))
+}
+
+/// Directory `WRITE_ARTIFACTS` saves code blocks to, if configured
+pub fn artifacts_dir() -> Option<PathBuf> {
+    server_config().artifacts_dir.clone()
+}
+
+/// Limits of the block packed from `CONTEXT_GLOBS`
+pub fn context_pack() -> ContextPackConfig {
+    server_config().context_pack
+}
+
+/// Directories calls may read and write paths in: the configured
+/// `allowed_roots`, else the server's working directory
+pub fn allowed_roots() -> Vec<PathBuf> {
+    let roots = &server_config().allowed_roots;
+    if !roots.is_empty() {
+        return roots.clone();
+    }
+    std::env::current_dir().into_iter().collect()
+}
+
+/// Path patterns calls may never touch (see [`crate::policy`])
+pub fn denied_paths() -> &'static [String] {
+    &server_config().denied_paths
+}
+
+/// Bounds of the `gemini_chat` histories
+pub fn chat() -> ChatConfig {
+    server_config().chat
+}
+
+/// Configured model presets
+pub fn presets() -> &'static [ModelPreset] {
+    &server_config().presets
+}
+
+/// Configured persona presets
+pub fn personas() -> &'static BTreeMap<String, String> {
+    &server_config().personas
+}
+
+/// Configured conversation templates
+pub fn templates() -> &'static BTreeMap<String, Template> {
+    &server_config().templates
+}
+
+/// Configured log level and file
+pub fn logging() -> LoggingConfig {
+    server_config().logging.clone()
+}
+
+/// Configured audit log
+pub fn audit() -> AuditConfig {
+    server_config().audit.clone()
+}
+
+/// Configured prices and spend caps
+pub fn budget() -> BudgetConfig {
+    server_config().budget.clone()
+}
+
+/// Configured chunking strategy
+pub fn chunking() -> ChunkingConfig {
+    server_config().chunking
+}
+
+/// Configured maintenance windows / quiet hours
+pub fn maintenance_windows() -> Vec<MaintenanceWindowConfig> {
+    server_config().maintenance_windows.clone()
+}
+
+/// The effective config merged from every layer, with the values under
+/// `env` and `auth_profiles` replaced by `"<redacted>"`. Fields no layer sets
+/// keep their built-in defaults and are left out. `None` when a layer cannot
+/// be read.
+pub fn config_snapshot() -> Option<Value> {
+    let layers = read_config_layers().ok()?;
+    let mut config = config_layers::merge(&layers);
+    for key in ["env", "auth_profiles"] {
+        if let Some(section) = config.get_mut(key) {
+            redact(section);
+        }
+    }
+    Some(config)
+}
+
+/// The sources of the effective config, lowest precedence first
+pub fn config_sources() -> Vec<PathBuf> {
+    read_config_layers()
+        .map(|layers| layers.into_iter().map(|layer| layer.source).collect())
+        .unwrap_or_default()
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(redact),
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::Null => {}
+        other => *other = Value::String("<redacted>".to_string()),
+    }
+}
+
+/// Optional path for the JSON report written on shutdown
+pub fn shutdown_report_path() -> Option<PathBuf> {
+    server_config().shutdown_report_path.clone()
+}
+
+/// Flag prefixes accepted in per-call `extra_args`, from config or the
+/// built-in default
+pub fn extra_args_allowlist() -> Vec<String> {
+    match server_config().extra_args_allowlist {
+        Some(ref list) => list.clone(),
+        None => DEFAULT_EXTRA_ARGS_ALLOWLIST
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    }
+}
+
+/// Whether `additional_args` and `extra_args` may contain dangerous flags
+pub fn allow_dangerous_args() -> bool {
+    server_config().allow_dangerous_args
+}
+
+/// Configured environment variables of every CLI process
+pub fn env() -> &'static BTreeMap<String, String> {
+    &server_config().env
+}
+
+/// Variables accepted in per-call `env`, from config or the built-in default
+pub fn env_allowlist() -> Vec<String> {
+    match server_config().env_allowlist {
+        Some(ref list) => list.clone(),
+        None => DEFAULT_ENV_ALLOWLIST
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    }
+}
+
+/// Options for a single Gemini CLI invocation.
+///
+/// Construct with [`Options::builder`] so that the same validation applies to
+/// library callers and the MCP tool path.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Options {
+    pub prompt: String,
+    pub session_id: Option<String>,
+    /// Model override; takes precedence over any `--model` in `additional_args`
+    pub model: Option<String>,
+    pub additional_args: Vec<String>,
+    /// Per-call arguments, validated against the extra-args allowlist and
+    /// appended after `additional_args`
+    pub extra_args: Vec<String>,
+    /// Run the CLI's tool executions inside its sandbox (`--sandbox`)
+    pub sandbox: bool,
+    /// `--approval-mode` for this run; replaces any approval flag in the
+    /// configured or per-call arguments
+    pub approval_mode: Option<ApprovalMode>,
+    /// Whether the prompt is passed as an argument or through stdin
+    pub prompt_delivery: PromptDelivery,
+    /// Reports the child PID and allows killing the run from another task
+    pub run_handle: Option<RunHandle>,
+    /// Stops the run with a [`Cancelled`] error when triggered
+    pub cancellation: Option<CancellationToken>,
+    /// Bypass the response cache, even for a one-shot prompt
+    pub no_cache: bool,
+    /// Per-call environment variables of the CLI, validated against the env
+    /// allowlist; they override the config's and the client's
+    pub env: BTreeMap<String, String>,
+    /// Credentials of an [`AuthProfile`]; when set, every variable in
+    /// [`auth::AUTH_VARS`] is cleared before these are applied
+    pub auth_env: Option<BTreeMap<String, String>>,
+    /// Timeout of this run instead of the client's
+    pub timeout: Option<Duration>,
+    /// Let the model ground its answer with Google Search: the CLI's
+    /// `google_web_search` tool runs without asking for approval
+    pub grounding: bool,
+    /// Directories added to the CLI's workspace (`--include-directories`),
+    /// canonicalized and checked against the allowed roots
+    pub include_directories: Vec<PathBuf>,
+    /// Stderr patterns that stop the run with [`hooks::StderrAbort`]
+    pub stderr_hooks: Vec<StderrHook>,
+    /// Keep the raw events in [`GeminiResult::all_messages`]; without, each
+    /// line is dropped once parsed into the typed fields
+    pub keep_raw_events: bool,
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+/// Builder for [`Options`] that validates everything at `build()` time
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+    prompt: Option<String>,
+    session_id: Option<String>,
+    model: Option<String>,
+    additional_args: Vec<String>,
+    extra_args: Vec<String>,
+    extra_args_allowlist: Option<Vec<String>>,
+    allow_dangerous_args: Option<bool>,
+    sandbox: bool,
+    approval_mode: Option<ApprovalMode>,
+    max_approval_mode: Option<ApprovalMode>,
+    prompt_delivery: Option<PromptDelivery>,
+    run_handle: Option<RunHandle>,
+    cancellation: Option<CancellationToken>,
+    no_cache: bool,
+    env: BTreeMap<String, String>,
+    env_allowlist: Option<Vec<String>>,
+    auth_profile: Option<AuthProfile>,
+    timeout: Option<Duration>,
+    grounding: bool,
+    include_directories: Vec<String>,
+    allowed_roots: Option<Vec<PathBuf>>,
+    stderr_hooks: Option<Vec<StderrHook>>,
+    keep_raw_events: bool,
+}
+
+impl OptionsBuilder {
+    /// Task instruction to send to Gemini (required)
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    /// Resume an existing session. An empty string is treated as "no session".
+    pub fn session(mut self, session_id: impl Into<String>) -> Self {
+        let session_id = session_id.into();
+        self.session_id = (!session_id.is_empty()).then_some(session_id);
+        self
+    }
+
+    /// Model to use for this invocation. An empty string is treated as unset.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        let model = model.into();
+        self.model = (!model.trim().is_empty()).then(|| model.trim().to_string());
+        self
+    }
+
+    /// Run the CLI's tool executions inside its sandbox
+    pub fn sandbox(mut self, sandbox: bool) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Approval mode for this run, checked against the maximum at `build()`
+    pub fn approval_mode(mut self, mode: ApprovalMode) -> Self {
+        self.approval_mode = Some(mode);
+        self
+    }
+
+    /// Override the most permissive approval mode the run may use, including
+    /// via `extra_args` (defaults to [`max_approval_mode`])
+    pub fn max_approval_mode(mut self, mode: ApprovalMode) -> Self {
+        self.max_approval_mode = Some(mode);
+        self
+    }
+
+    /// How to hand the prompt to the CLI (defaults to [`prompt_delivery`])
+    pub fn prompt_delivery(mut self, delivery: PromptDelivery) -> Self {
+        self.prompt_delivery = Some(delivery);
+        self
+    }
+
+    /// Attach a [`RunHandle`] for PID reporting and external termination
+    pub fn run_handle(mut self, handle: RunHandle) -> Self {
+        self.run_handle = Some(handle);
+        self
+    }
+
+    /// Kill the run's process tree and fail with [`Cancelled`] once `token`
+    /// is cancelled
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Always run the CLI instead of answering from the response cache
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
+
+    /// Append a single extra CLI argument
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.additional_args.push(arg.into());
+        self
+    }
+
+    /// Append extra CLI arguments
+    pub fn additional_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.additional_args
+            .extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Append per-call arguments that must pass the extra-args allowlist
+    pub fn extra_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Override the allowlist used to validate `extra_args`
+    /// (defaults to [`extra_args_allowlist`])
+    pub fn extra_args_allowlist<I, S>(mut self, flags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra_args_allowlist = Some(flags.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Accept dangerous flags such as `--yolo` in `additional_args` and
+    /// `extra_args` (defaults to [`allow_dangerous_args`])
+    pub fn allow_dangerous_args(mut self, allow: bool) -> Self {
+        self.allow_dangerous_args = Some(allow);
+        self
+    }
+
+    /// Set a per-call environment variable that must pass the env allowlist
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set several per-call environment variables
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Override the allowlist used to validate `env`
+    /// (defaults to [`env_allowlist`])
+    pub fn env_allowlist<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.env_allowlist = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Authenticate the CLI with `profile` instead of the inherited
+    /// credentials
+    pub fn auth_profile(mut self, profile: AuthProfile) -> Self {
+        self.auth_profile = Some(profile);
+        self
+    }
+
+    /// Timeout of this run, overriding the client's
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Allow Google Search grounding (see [`Options::grounding`])
+    pub fn grounding(mut self, grounding: bool) -> Self {
+        self.grounding = grounding;
+        self
+    }
+
+    /// Add directories to the CLI's workspace; relative paths are resolved
+    /// against the server's working directory
+    pub fn include_directories<I, S>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.include_directories
+            .extend(dirs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Override the roots `include_directories` must lie in
+    /// (defaults to [`allowed_roots`])
+    pub fn allowed_roots<I, P>(mut self, roots: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.allowed_roots = Some(roots.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Override the stderr patterns that stop the run early
+    /// (defaults to [`stderr_hooks`])
+    pub fn stderr_hooks(mut self, hooks: impl IntoIterator<Item = StderrHook>) -> Self {
+        self.stderr_hooks = Some(hooks.into_iter().collect());
+        self
+    }
+
+    /// Whether to keep the raw events in [`GeminiResult::all_messages`]
+    /// (off by default)
+    pub fn keep_raw_events(mut self, keep: bool) -> Self {
+        self.keep_raw_events = keep;
+        self
+    }
+
+    /// Validate and build the options
+    pub fn build(self) -> Result<Options> {
+        let prompt = self.prompt.unwrap_or_default();
+        if prompt.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Prompt must be a non-empty, non-whitespace string"
+            ));
+        }
+
+        if let Some(ref id) = self.session_id {
+            if Uuid::parse_str(id).is_err() {
+                return Err(anyhow::anyhow!("SESSION_ID must be a valid UUID string"));
+            }
+        }
+
+        validate_additional_args(&self.additional_args)?;
+        if self.timeout.is_some_and(|t| t.is_zero()) {
+            return Err(anyhow::anyhow!("Timeout must be greater than zero"));
+        }
+        if !self.extra_args.is_empty() {
+            let allowlist = self
+                .extra_args_allowlist
+                .unwrap_or_else(extra_args_allowlist);
+            validate_extra_args(&self.extra_args, &allowlist)?;
+        }
+        if !self
+            .allow_dangerous_args
+            .unwrap_or_else(allow_dangerous_args)
+        {
+            for (name, args) in [
+                ("additional_args", &self.additional_args),
+                ("EXTRA_ARGS", &self.extra_args),
+            ] {
+                if let Some(i) = dangerous_arg(args) {
+                    return Err(anyhow::anyhow!(
+                        "{} flag `{}` is not allowed: it is dangerous and the server \
+                         config does not set allow_dangerous_args",
+                        name,
+                        args[i].trim()
+                    ));
+                }
+            }
+        }
+        if !self.env.is_empty() {
+            let allowlist = self.env_allowlist.unwrap_or_else(env_allowlist);
+            validate_env(&self.env, &allowlist)?;
+        }
+        let include_directories = if self.include_directories.is_empty() {
+            Vec::new()
+        } else {
+            let roots = self.allowed_roots.unwrap_or_else(allowed_roots);
+            let policy = Policy::new(&roots, denied_paths());
+            resolve_include_directories(&self.include_directories, &policy)?
+        };
+        let stderr_hooks = self.stderr_hooks.unwrap_or_else(stderr_hooks);
+        hooks::validate(&stderr_hooks)?;
+
+        let auth_env = self
+            .auth_profile
+            .as_ref()
+            .map(AuthProfile::env)
+            .transpose()
+            .context("Invalid auth profile")?;
+
+        let requested = self
+            .approval_mode
+            .or_else(|| approval_mode_from_args(&self.extra_args));
+        if let Some(requested) = requested {
+            let max = self.max_approval_mode.unwrap_or_else(max_approval_mode);
+            if requested > max {
+                return Err(anyhow::anyhow!(
+                    "APPROVAL_MODE `{}` is not allowed; this server allows at most `{}`",
+                    requested,
+                    max
+                ));
+            }
+        }
+
+        Ok(Options {
+            prompt,
+            session_id: self.session_id,
+            model: self.model,
+            additional_args: self.additional_args,
+            extra_args: self.extra_args,
+            sandbox: self.sandbox,
+            approval_mode: self.approval_mode,
+            prompt_delivery: self.prompt_delivery.unwrap_or_else(prompt_delivery),
+            run_handle: self.run_handle,
+            cancellation: self.cancellation,
+            no_cache: self.no_cache,
+            env: self.env,
+            auth_env,
+            timeout: self.timeout,
+            grounding: self.grounding,
+            include_directories,
+            stderr_hooks,
+            keep_raw_events: self.keep_raw_events,
+        })
+    }
+}
+
+/// Something that happened during a CLI run, reported through
+/// [`RunHandle::events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEvent {
+    Spawned {
+        pid: Option<u32>,
+    },
+    /// One line the CLI wrote to stderr
+    Stderr(String),
+    TimedOut {
+        after_secs: u64,
+    },
+    Killed,
+    /// Stopped through the run's [`CancellationToken`]
+    Cancelled,
+    Exited {
+        code: Option<i32>,
+    },
+}
+
+/// Error of a run stopped through its [`CancellationToken`]. Downcast the
+/// `anyhow::Error` to get at the output gathered until then.
+#[derive(Debug)]
+pub struct Cancelled {
+    /// PID of the killed CLI process; `None` if it never started
+    pub pid: Option<u32>,
+    /// Events and messages the CLI had produced so far
+    pub partial: GeminiResult,
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "Gemini command (pid {}) was cancelled", pid),
+            None => write!(f, "Gemini command was cancelled before it started"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Cancelled {
+    fn before_start() -> anyhow::Error {
+        Self {
+            pid: None,
+            partial: GeminiResult {
+                error: Some("cancelled before the CLI started".to_string()),
+                ..Default::default()
+            },
+        }
+        .into()
+    }
+}
+
+/// Resolves once `token` is cancelled; never without a token
+async fn cancelled(token: Option<&CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Shared handle to a single CLI run: exposes the child's PID once spawned and
+/// lets another task (e.g. an admin tool) terminate it.
+#[derive(Debug, Clone)]
+pub struct RunHandle {
+    inner: Arc<RunHandleInner>,
+}
+
+#[derive(Debug)]
+struct RunHandleInner {
+    pid: watch::Sender<Option<u32>>,
+    killed: AtomicBool,
+    kill: Notify,
+    events: Mutex<Option<mpsc::UnboundedSender<RunEvent>>>,
+}
+
+impl Default for RunHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunHandle {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(RunHandleInner {
+                pid: watch::Sender::new(None),
+                killed: AtomicBool::new(false),
+                kill: Notify::new(),
+                events: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// PID of the spawned CLI process, if it has been spawned
+    pub fn pid(&self) -> Option<u32> {
+        *self.inner.pid.borrow()
+    }
+
+    /// Wait until the CLI process has been spawned and return its PID
+    pub async fn spawned(&self) -> Option<u32> {
+        let mut rx = self.inner.pid.subscribe();
+        let pid = rx.wait_for(Option::is_some).await.ok()?;
+        *pid
+    }
+
+    /// Terminate the run. A run that has not spawned yet is never started.
+    pub fn kill(&self) {
+        self.inner.killed.store(true, Ordering::SeqCst);
+        self.inner.kill.notify_one();
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.inner.killed.load(Ordering::SeqCst)
+    }
+
+    /// Receive the events of the run. Only the latest receiver gets them;
+    /// the channel ends after [`Self::close_events`].
+    pub fn events(&self) -> mpsc::UnboundedReceiver<RunEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.inner.events.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+        rx
+    }
+
+    /// End the event channel once the run is over
+    pub fn close_events(&self) {
+        self.inner
+            .events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+
+    fn emit(&self, event: RunEvent) {
+        if let Some(tx) = self
+            .inner
+            .events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            let _ = tx.send(event);
+        }
+    }
+
+    fn set_pid(&self, pid: Option<u32>) {
+        self.inner.pid.send_replace(pid);
+    }
+}
+
+/// Returns the flag name of an argument, stripping any `=value` suffix
+fn flag_name(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(name, _)| name)
+}
+
+/// Reject arguments that would conflict with the flags the wrapper manages
+fn validate_additional_args(args: &[String]) -> Result<()> {
+    for arg in args {
+        if RESERVED_FLAGS.contains(&flag_name(arg)) {
+            return Err(anyhow::anyhow!(
+                "Argument `{}` is managed by gemini-mcp-rs and cannot be passed explicitly",
+                arg
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Index of the first flag in `args` that is in [`DANGEROUS_FLAGS`] or
+/// selects the `yolo` approval mode
+fn dangerous_arg(args: &[String]) -> Option<usize> {
+    let mut iter = args.iter().map(|arg| arg.trim()).enumerate().peekable();
+    while let Some((i, arg)) = iter.next() {
+        let name = flag_name(arg);
+        let yolo = if arg == APPROVAL_MODE_FLAG {
+            iter.peek().is_some_and(|(_, value)| *value == "yolo")
+        } else {
+            name == APPROVAL_MODE_FLAG && arg.ends_with("=yolo")
+        };
+        if yolo || DANGEROUS_FLAGS.contains(&name) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Check per-call arguments against an allowlist of flag prefixes. Each flag
+/// (without its `=value` suffix) must start with an allowlisted prefix; bare
+/// values are only accepted directly after an allowlisted flag. Flags in
+/// [`PATH_FLAGS`] are never accepted, whatever the allowlist says.
+pub fn validate_extra_args(args: &[String], allowlist: &[String]) -> Result<()> {
+    validate_additional_args(args)?;
+
+    let mut value_allowed = false;
+    for arg in args {
+        if arg.starts_with('-') {
+            let name = flag_name(arg);
+            if PATH_FLAGS.iter().any(|flag| name.starts_with(flag)) {
+                return Err(anyhow::anyhow!(
+                    "EXTRA_ARGS flag `{}` is not allowed; use INCLUDE_DIRECTORIES, which is \
+                     checked against allowed_roots and denied_paths",
+                    name
+                ));
+            }
+            if !allowlist
+                .iter()
+                .any(|prefix| !prefix.is_empty() && name.starts_with(prefix.as_str()))
+            {
+                return Err(anyhow::anyhow!(
+                    "EXTRA_ARGS flag `{}` is not allowed (allowed: {})",
+                    name,
+                    allowlist.join(", ")
+                ));
+            }
+            value_allowed = !arg.contains('=');
+        } else if value_allowed {
+            value_allowed = false;
+        } else {
+            return Err(anyhow::anyhow!(
+                "EXTRA_ARGS value `{}` must follow an allowed flag",
+                arg
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Canonicalize `dirs` (relative to the working directory) and check that each
+/// is an existing directory `policy` allows
+pub fn resolve_include_directories(dirs: &[String], policy: &Policy) -> Result<Vec<PathBuf>> {
+    let mut resolved: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let trimmed = dir.trim();
+        if trimmed.is_empty() {
+            return Err(anyhow::anyhow!(
+                "INCLUDE_DIRECTORIES entries must not be empty"
+            ));
+        }
+        if !Path::new(trimmed).is_dir() {
+            return Err(anyhow::anyhow!(
+                "INCLUDE_DIRECTORIES entry {} is not a directory",
+                trimmed
+            ));
+        }
+        let path = policy
+            .check(Path::new(trimmed))
+            .context("INCLUDE_DIRECTORIES")?;
+        if !resolved.contains(&path) {
+            resolved.push(path);
+        }
+    }
+    Ok(resolved)
+}
+
+/// Reject names the OS cannot take as an environment variable
+fn validate_env_name(key: &str) -> Result<()> {
+    if key.is_empty() || key.contains(['=', '\0']) {
+        anyhow::bail!("Invalid environment variable name {:?}", key);
+    }
+    Ok(())
+}
+
+/// Check per-call environment variables against an allowlist of names
+pub fn validate_env(vars: &BTreeMap<String, String>, allowlist: &[String]) -> Result<()> {
+    for (key, value) in vars {
+        validate_env_name(key)?;
+        if !allowlist.iter().any(|allowed| allowed == key) {
+            anyhow::bail!(
+                "ENV variable `{}` is not allowed (allowed: {})",
+                key,
+                allowlist.join(", ")
+            );
+        }
+        if value.contains('\0') {
+            anyhow::bail!("ENV variable `{}` contains a NUL byte", key);
+        }
+    }
+    Ok(())
+}
+
+/// Model selected by a `--model`/`-m` flag in `args`, if any (last one wins)
+pub fn model_from_args(args: &[String]) -> Option<String> {
+    let mut model = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if MODEL_FLAGS.contains(&arg.as_str()) {
+            model = iter.next().cloned();
+        } else if let Some((name, value)) = arg.split_once('=') {
+            if MODEL_FLAGS.contains(&name) {
+                model = Some(value.to_string());
+            }
+        }
+    }
+    model
+}
+
+/// Approval mode selected by `--approval-mode` or `--yolo`/`-y` in `args`, if
+/// any (last one wins). Unknown modes are left for the CLI to reject.
+pub fn approval_mode_from_args(args: &[String]) -> Option<ApprovalMode> {
+    let mut mode = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if YOLO_FLAGS.contains(&arg.as_str()) {
+            mode = Some(ApprovalMode::Yolo);
+        } else if arg == APPROVAL_MODE_FLAG {
+            mode = iter.next().and_then(|v| ApprovalMode::parse(v)).or(mode);
+        } else if let Some(value) = arg.strip_prefix("--approval-mode=") {
+            mode = ApprovalMode::parse(value).or(mode);
+        }
+    }
+    mode
+}
+
+fn has_model_arg(args: &[String]) -> bool {
+    args.iter().any(|a| MODEL_FLAGS.contains(&flag_name(a)))
+}
+
+fn has_sandbox_arg(args: &[String]) -> bool {
+    args.iter().any(|a| SANDBOX_FLAGS.contains(&flag_name(a)))
+}
+
+/// Drop `--model`/`-m` (and its value) from `args`, used when an explicit model is set
+fn strip_model_args(args: &[String]) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len());
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if MODEL_FLAGS.contains(&arg.as_str()) {
+            // Skip the flag's value as well
+            iter.next();
+            continue;
+        }
+        if arg.contains('=') && MODEL_FLAGS.contains(&flag_name(arg)) {
+            continue;
+        }
+        out.push(arg.clone());
+    }
+    out
+}
+
+/// Drop `--approval-mode` (and its value) and `--yolo`/`-y` from `args`, used
+/// when an explicit approval mode is set
+fn strip_approval_args(args: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len());
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == APPROVAL_MODE_FLAG {
+            iter.next();
+            continue;
+        }
+        if YOLO_FLAGS.contains(&arg.as_str()) || arg.starts_with("--approval-mode=") {
+            continue;
+        }
+        out.push(arg);
+    }
+    out
+}
+
+/// One assistant message, with its streamed fragments merged
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AgentMessage {
+    /// Message id reported by the CLI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// The message was still streaming when the run ended
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub delta: bool,
+    /// Tools the model invoked between the previous message and this one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools_before: Vec<String>,
+    /// Length of `tool_activity` when the message arrived
+    #[serde(skip)]
+    tools_seen: usize,
+    /// Byte offset of `content` in `agent_messages`
+    #[serde(skip)]
+    start: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GeminiResult {
+    pub success: bool,
+    pub session_id: String,
+    pub agent_messages: String,
+    /// The assistant messages joined in `agent_messages`
+    pub messages: Vec<AgentMessage>,
+    /// Raw CLI events, when [`Options::keep_raw_events`] is set. Long runs
+    /// keep only their first and last events (see [`EventBufferConfig`]),
+    /// with a `dropped` marker between them.
+    pub all_messages: Vec<Value>,
+    /// Raw events dropped from the middle of the run
+    pub dropped_events: usize,
+    pub error: Option<String>,
+    /// The CLI reported the failure as an event of its own
+    pub failure_reported: bool,
+    /// The CLI printed plain text instead of `stream-json`, which was taken
+    /// as the answer; such runs have no `session_id`
+    pub plain_text: bool,
+    /// Exact model version reported by the CLI's `init` event
+    pub model: Option<String>,
+    /// Token counts reported by the CLI's `result` event(s)
+    pub usage: Option<Usage>,
+    /// Reasoning of thinking models, in the order it was emitted
+    pub thoughts: Vec<ThoughtEvent>,
+    /// Tools the model invoked, in order, with their outcome
+    pub tool_activity: Vec<ToolActivity>,
+    /// Sources of a grounded answer
+    pub citations: Vec<Citation>,
+    /// Non-fatal degradations (skipped GEMINI.md, truncated output, dropped
+    /// events, model fallback) that clients should know about
+    pub warnings: Vec<String>,
+    /// The run was stopped (timed out or cancelled) before the CLI finished;
+    /// the messages are what it produced until then
+    pub partial: bool,
+}
+
+/// Attempt to read GEMINI.md configuration for `cwd` (the current directory
+/// if `None`).
+/// With hierarchical discovery enabled (the default), all discovered files are
+/// concatenated from most general to most specific; otherwise only
+/// `./GEMINI.md` is considered.
+async fn read_gemini_config(cwd: Option<&Path>, warnings: &mut Vec<String>) -> Option<String> {
+    if !server_config().hierarchical_gemini_md {
+        let path = cwd.map_or_else(
+            || PathBuf::from(GEMINI_CONFIG_FILE),
+            |cwd| cwd.join(GEMINI_CONFIG_FILE),
+        );
+        return load_gemini_config_file(&path, warnings).await;
+    }
+
+    let cwd = match cwd {
+        Some(cwd) => cwd.to_path_buf(),
+        None => std::env::current_dir().ok()?,
+    };
+    let home = home_dir();
+    let mut sections = Vec::new();
+    for path in discover_gemini_config_paths(&cwd, home.as_deref()) {
+        if let Some(content) = load_gemini_config_file(&path, warnings).await {
+            sections.push(content);
+        }
+    }
+
+    if sections.is_empty() {
+        None
+    } else {
+        Some(sections.join("\n\n"))
+    }
+}
+
+/// The CLI's own directory in the home directory (`~/.gemini`)
+#[cfg(feature = "server")]
+pub(crate) fn gemini_home_dir() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(GEMINI_HOME_DIR))
+}
+
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Find GEMINI.md files that apply to `start`, ordered from most general to
+/// most specific: `<home>/.gemini/GEMINI.md` first, then one per directory from
+/// the project root down to `start`. Like the Gemini CLI, the upward search stops
+/// at the nearest directory containing `.git` or at the home directory.
+pub fn discover_gemini_config_paths(start: &Path, home: Option<&Path>) -> Vec<PathBuf> {
+    let mut project_files = Vec::new();
+    for dir in start.ancestors() {
+        if home == Some(dir) {
+            break;
+        }
+        let candidate = dir.join(GEMINI_CONFIG_FILE);
+        if candidate.is_file() {
+            project_files.push(candidate);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    project_files.reverse();
+
+    let mut paths = Vec::with_capacity(project_files.len() + 1);
+    if let Some(home) = home {
+        let global = home.join(GEMINI_HOME_DIR).join(GEMINI_CONFIG_FILE);
+        if global.is_file() {
+            paths.push(global);
+        }
+    }
+    paths.extend(project_files);
+    paths
+}
+
+/// Internal function to read GEMINI.md configuration from a specific path
+/// This is separated to allow for testing with custom paths
+/// Exposed publicly for integration tests
+pub async fn read_gemini_config_from_path(config_path: &Path) -> Option<String> {
+    load_gemini_config_file(config_path, &mut Vec::new()).await
+}
+
+/// Read one GEMINI.md file. Problems are logged and recorded in `warnings`.
+async fn load_gemini_config_file(config_path: &Path, warnings: &mut Vec<String>) -> Option<String> {
+    let mut warn = |message: String| {
+        tracing::warn!("{}", message);
+        warnings.push(message);
+    };
+
+    // First check if file exists and get metadata
+    let metadata = match fs::metadata(config_path).await {
+        Ok(meta) => meta,
+        Err(e) => {
+            // Only log if it's not a "file not found" error
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn(format!(
+                    "Cannot access GEMINI.md configuration file {}: {}",
+                    config_path.display(),
+                    e
+                ));
+            }
+            return None;
+        }
+    };
+
+    // Check file size before reading
+    let file_size = metadata.len() as usize;
+    if file_size > MAX_CONFIG_SIZE {
+        warn(format!(
+            "GEMINI.md file {} is too large ({} bytes, max {} bytes). Configuration will be ignored.",
+            config_path.display(),
+            file_size,
+            MAX_CONFIG_SIZE
+        ));
+        return None;
+    }
+
+    // Read the file content
+    match fs::read_to_string(config_path).await {
+        Ok(content) => {
+            // Check if content is effectively empty (only whitespace)
+            if content.trim().is_empty() {
+                warn(format!(
+                    "GEMINI.md file {} is empty and will be ignored.",
+                    config_path.display()
+                ));
+                None
+            } else {
+                // Return original content to preserve formatting, not trimmed version
+                Some(content)
+            }
+        }
+        Err(e) => {
+            warn(format!(
+                "Failed to read GEMINI.md configuration file {}: {}",
+                config_path.display(),
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// The prompt the default client hands to the CLI for `prompt`, with the
+/// GEMINI.md content of the working directory first
+pub async fn final_prompt(prompt: &str) -> String {
+    prepare_prompt(None, prompt, &mut Vec::new()).await
+}
+
+/// Prepare the final prompt by prepending GEMINI.md content if it exists,
+/// then expanding the configured placeholders
+async fn prepare_prompt(
+    cwd: Option<&Path>,
+    user_prompt: &str,
+    warnings: &mut Vec<String>,
+) -> String {
+    let prompt = match read_gemini_config(cwd, warnings).await {
+        Some(config_content) => {
+            format!("{}\n\n{}", config_content, user_prompt)
+        }
+        None => user_prompt.to_string(),
+    };
+    let config = placeholders();
+    if !config.enabled {
+        return prompt;
+    }
+    let dir = cwd
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    let context = placeholders::Context::new(dir, SystemTime::now());
+    placeholders::expand(&prompt, &context, &config.env, warnings)
+}
+
+/// Process one stdout line of the gemini CLI, parsed from its `text` into
+/// `line`. Only with `keep_raw` is the text also read into a raw event for
+/// `all_messages`.
+fn process_stdout_line(
+    text: &str,
+    line: ParsedLine,
+    keep_raw: bool,
+    result: &mut GeminiResult,
+) -> Option<GeminiEvent> {
+    if keep_raw {
+        if let Ok(raw) = serde_json::from_str(text) {
+            push_raw_event(result, raw, event_buffer());
+        }
+    }
+    record_parsed_event(line.session_id.as_deref(), line.event, result)
+}
+
+/// Process a single JSON event, as converted from ACP updates or a `-o json`
+/// document. With `keep_raw` the value is moved into `all_messages` and the
+/// typed event reads from it; otherwise its payload is moved into the typed
+/// event.
+fn process_json_line(
+    line_data: Value,
+    keep_raw: bool,
+    result: &mut GeminiResult,
+) -> Option<GeminiEvent> {
+    let session_id = event::session_id(&line_data).map(str::to_string);
+    let parsed = if keep_raw {
+        let parsed = GeminiEvent::from_value(&line_data);
+        push_raw_event(result, line_data, event_buffer());
+        parsed
+    } else {
+        GeminiEvent::from_owned(line_data)
+    };
+    record_parsed_event(session_id.as_deref(), parsed, result)
+}
+
+/// Record a parsed event in `result`. Returns the typed event, or `None` for
+/// the CLI's own prompt deprecation warning.
+fn record_parsed_event(
+    session_id: Option<&str>,
+    parsed: Result<GeminiEvent, EventParseError>,
+    result: &mut GeminiResult,
+) -> Option<GeminiEvent> {
+    // Extract session_id from whichever event carries it
+    if let Some(session_id) = session_id {
+        result.session_id = session_id.to_string();
+    }
+
+    // A malformed event's payload goes with the error; only its type remains
+    let event = parsed.unwrap_or_else(|e| {
+        tracing::warn!("{}", e);
+        result.warnings.push(e.to_string());
+        GeminiEvent::Unknown(serde_json::json!({ "type": e.event_type }))
+    });
+
+    match &event {
+        // Record the model version announced when the session starts
+        GeminiEvent::Init(init) => {
+            if let Some(model) = init.model.as_deref().filter(|m| !m.is_empty()) {
+                result.model = Some(model.to_string());
+            }
+        }
+        // Extract agent messages
+        GeminiEvent::Message(message) if message.role == Role::Assistant => {
+            // Skip the CLI's own deprecation warning about --prompt
+            if message.content.contains(PROMPT_DEPRECATION_WARNING) {
+                return None;
+            }
+            record_message(result, message);
+        }
+        // Keep reasoning apart from the answer
+        GeminiEvent::Thought(thought) if result.thoughts.len() < MAX_MESSAGES_LIMIT => {
+            result.thoughts.push(thought.clone());
+        }
+        GeminiEvent::ToolUse(tool_use) if result.tool_activity.len() < MAX_MESSAGES_LIMIT => {
+            result.tool_activity.push(ToolActivity::started(tool_use));
+        }
+        GeminiEvent::ToolResult(tool_result) => {
+            let finished = activity::finish(&mut result.tool_activity, tool_result);
+            if finished.is_some_and(|tool| tool.tool == citation::WEB_SEARCH_TOOL) {
+                let sources =
+                    citation::from_search_output(tool_result.output.as_deref().unwrap_or(""));
+                for source in sources {
+                    citation::push_unique(&mut result.citations, source);
+                }
+            }
+        }
+        GeminiEvent::Citation(event) => {
+            citation::push_unique(&mut result.citations, Citation::from(event));
+        }
+        // Accumulate token usage from the run statistics
+        GeminiEvent::Result(ResultEvent {
+            stats: Some(stats), ..
+        }) if !stats.usage.is_empty() => {
+            *result.usage.get_or_insert_with(Usage::default) += stats.usage;
+        }
+        // Non-fatal problems reported by the CLI itself
+        GeminiEvent::Error(error) if error.is_warning() => {
+            if let Some(msg) = event.failure_message() {
+                result.warnings.push(format!("gemini warning: {}", msg));
+            }
+        }
+        _ => {}
+    }
+
+    if event.is_failure() {
+        result.success = false;
+        result.failure_reported = true;
+        if let Some(msg) = event.failure_message() {
+            result.error = Some(format!("gemini error: {}", msg));
+        }
+    }
+    Some(event)
+}
+
+/// Add an assistant message to `agent_messages` and `messages`. Streamed
+/// fragments are merged into the message they belong to: by `id` when the
+/// CLI sends one, else into the previous fragment unless a tool ran in
+/// between. Deltas are always appended; a complete message with the `id` of
+/// earlier fragments replaces them.
+fn record_message(result: &mut GeminiResult, message: &MessageEvent) {
+    let tools_seen = result.tool_activity.len();
+    let target = match message.id.as_deref() {
+        Some(id) => result
+            .messages
+            .iter()
+            .rposition(|m| m.id.as_deref() == Some(id)),
+        None => result
+            .messages
+            .last()
+            .filter(|last| {
+                message.delta && last.delta && last.id.is_none() && last.tools_seen == tools_seen
+            })
+            .map(|_| result.messages.len() - 1),
+    };
+    // Merge only where the text can be laid out again: into the last
+    // message, or into any while every message is still kept
+    let merge = target.filter(|&index| {
+        let entry = &result.messages[index];
+        let ends_text = entry.start + entry.content.len() == result.agent_messages.len();
+        if index + 1 == result.messages.len() {
+            ends_text
+        } else {
+            result.messages.len() < MAX_MESSAGES_LIMIT
+        }
+    });
+
+    let Some(index) = merge else {
+        if !result.agent_messages.is_empty() {
+            result.agent_messages.push('\n');
+        }
+        let start = result.agent_messages.len();
+        result.agent_messages.push_str(&message.content);
+        if result.messages.len() < MAX_MESSAGES_LIMIT {
+            let since = result.messages.last().map_or(0, |m| m.tools_seen);
+            result.messages.push(AgentMessage {
+                id: message.id.clone(),
+                content: message.content.clone(),
+                timestamp: message.timestamp.clone(),
+                delta: message.delta,
+                tools_before: result.tool_activity[since.min(tools_seen)..]
+                    .iter()
+                    .map(|tool| tool.tool.clone())
+                    .collect(),
+                tools_seen,
+                start,
+            });
+        }
+        return;
+    };
+
+    let entry = &mut result.messages[index];
+    if message.delta {
+        entry.content.push_str(&message.content);
+    } else {
+        entry.content = message.content.clone();
+    }
+    entry.delta = message.delta;
+    if entry.timestamp.is_none() {
+        entry.timestamp = message.timestamp.clone();
+    }
+    let start = entry.start;
+    if index + 1 == result.messages.len() {
+        result.agent_messages.truncate(start);
+        result
+            .agent_messages
+            .push_str(&result.messages[index].content);
     } else {
+        // A message other than the last changed; lay the text out again
+        result.agent_messages.clear();
+        for entry in &mut result.messages {
+            if !result.agent_messages.is_empty() {
+                result.agent_messages.push('\n');
+            }
+            entry.start = result.agent_messages.len();
+            result.agent_messages.push_str(&entry.content);
+        }
+    }
+}
+
+/// Build the gemini command for `launcher` with the given options and the
+/// flag spelling of a specific CLI version
+fn build_command_for(launcher: &Launcher, opts: &Options, compat: CliCompat) -> Command {
+    let mut cmd = launcher.command();
+    // Always ask for JSON output, streamed where the CLI can
+    cmd.arg(compat.output_format_flag());
+    cmd.arg(output_format(compat).as_arg());
+    push_run_args(&mut cmd, opts);
+
+    // A prompt delivered through stdin is read by the CLI on its own; only the
+    // session flag is needed.
+    if uses_stdin(launcher, opts) {
+        if let Some(ref session_id) = opts.session_id {
+            cmd.args(["--resume", session_id]);
+        }
+        return cmd;
+    }
+
+    // Resume session if provided; otherwise, pass the prompt positionally.
+    if let Some(ref session_id) = opts.session_id {
+        // For resume, Gemini CLI currently requires a prompt via --prompt (-p) or stdin.
+        // We use --prompt here (and filter out the deprecation warning in process_json_line).
+        cmd.arg("--prompt");
+        cmd.arg(&opts.prompt);
+        cmd.args(["--resume", session_id]);
+    } else if compat.positional_prompt() {
         // Command::arg() on all platforms already does correct shell quoting,
         // so we pass the prompt as a positional argument without manual escaping
         cmd.arg(&opts.prompt);
+    } else {
+        cmd.arg("--prompt");
+        cmd.arg(&opts.prompt);
+    }
+
+    cmd
+}
+
+/// Build the gemini command for an interactive session started with `opts`;
+/// prompts are sent to it over ACP
+fn build_acp_command(launcher: &Launcher, opts: &Options) -> Command {
+    let mut cmd = launcher.command();
+    push_run_args(&mut cmd, opts);
+    cmd.arg(acp::ACP_FLAG);
+    cmd
+}
+
+/// The arguments of a run besides the output format, prompt and session
+fn push_run_args(cmd: &mut Command, opts: &Options) {
+    // Additional arguments configured at the server level, followed by the
+    // per-call extra arguments. A per-call model (explicit or via extra args)
+    // replaces any `--model` coming from the server configuration.
+    let mut additional_args = if opts.model.is_some() || has_model_arg(&opts.extra_args) {
+        strip_model_args(&opts.additional_args)
+    } else {
+        opts.additional_args.clone()
+    };
+    let mut extra_args = if opts.model.is_some() {
+        strip_model_args(&opts.extra_args)
+    } else {
+        opts.extra_args.clone()
+    };
+    // The CLI refuses `--yolo` together with `--approval-mode`, so an explicit
+    // mode replaces whatever the other arguments select
+    if opts.approval_mode.is_some() {
+        additional_args = strip_approval_args(additional_args);
+        extra_args = strip_approval_args(extra_args);
+    }
+    cmd.args(additional_args);
+    cmd.args(extra_args);
+    if let Some(ref model) = opts.model {
+        cmd.args(["--model", model]);
+    }
+    if let Some(mode) = opts.approval_mode {
+        cmd.args([APPROVAL_MODE_FLAG, mode.as_str()]);
+    }
+    if opts.sandbox && !has_sandbox_arg(&opts.additional_args) && !has_sandbox_arg(&opts.extra_args)
+    {
+        cmd.arg("--sandbox");
+    }
+    if opts.grounding {
+        cmd.args(["--allowed-tools", citation::WEB_SEARCH_TOOL]);
+    }
+    for dir in &opts.include_directories {
+        cmd.arg("--include-directories");
+        cmd.arg(dir);
+    }
+}
+
+/// Whether `opts.prompt` is piped through the child's stdin instead of argv
+fn uses_stdin(launcher: &Launcher, opts: &Options) -> bool {
+    match opts.prompt_delivery {
+        PromptDelivery::Argv => false,
+        PromptDelivery::Stdin => true,
+        // Batch and PowerShell shims cannot pass multi-line arguments through
+        PromptDelivery::Auto => {
+            opts.prompt.len() > stdin_prompt_threshold_bytes()
+                || (opts.prompt.contains(['\n', '\r']) && launcher.prefers_stdin())
+        }
+    }
+}
+
+/// Run `gemini --version` (honouring `GEMINI_BIN`) and return its output
+pub async fn cli_version() -> Result<String> {
+    let launcher = launcher::resolve();
+    let gemini_bin = launcher.path.display().to_string();
+    let mut cmd = launcher.command();
+    cmd.arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let output = timeout(CLI_VERSION_TIMEOUT, cmd.output())
+        .await
+        .with_context(|| format!("`{} --version` timed out", gemini_bin))?
+        .with_context(|| format!("Failed to execute `{}`", gemini_bin))?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || version.is_empty() {
+        anyhow::bail!(
+            "`{} --version` failed ({}): {}",
+            gemini_bin,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(version)
+}
+
+/// Drives the Gemini CLI directly, for programs that embed this crate
+/// instead of going through MCP
+#[derive(Debug, Clone)]
+pub struct GeminiClient {
+    launcher: Launcher,
+    /// Placed before each run's `additional_args`
+    default_args: Vec<String>,
+    timeout: Duration,
+    /// Extra environment variables of the CLI process
+    env: BTreeMap<String, String>,
+    /// Working directory of the CLI, also where GEMINI.md discovery starts
+    cwd: Option<PathBuf>,
+}
+
+impl Default for GeminiClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for [`GeminiClient`]; unset fields fall back to `GEMINI_BIN` and
+/// the server config
+#[derive(Debug, Clone, Default)]
+pub struct GeminiClientBuilder {
+    binary: Option<PathBuf>,
+    default_args: Vec<String>,
+    timeout: Option<Duration>,
+    env: BTreeMap<String, String>,
+    cwd: Option<PathBuf>,
+}
+
+impl GeminiClientBuilder {
+    /// Gemini CLI executable (defaults to `GEMINI_BIN`, then `gemini`)
+    pub fn binary(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.binary = Some(binary.into());
+        self
+    }
+
+    /// Append a single argument passed to every run
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.default_args.push(arg.into());
+        self
+    }
+
+    /// Append arguments passed to every run
+    pub fn default_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.default_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Per-run timeout (defaults to [`default_timeout_secs`])
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set an environment variable of the CLI process
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set several environment variables of the CLI process
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Working directory of the CLI (defaults to the current directory)
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Validate and build the client
+    pub fn build(self) -> Result<GeminiClient> {
+        validate_additional_args(&self.default_args)?;
+        if self.timeout.is_some_and(|t| t.is_zero()) {
+            anyhow::bail!("Timeout must be greater than zero");
+        }
+        if let Some(ref cwd) = self.cwd {
+            if !cwd.is_dir() {
+                anyhow::bail!("Working directory {} does not exist", cwd.display());
+            }
+        }
+        for key in self.env.keys() {
+            validate_env_name(key)?;
+        }
+        Ok(GeminiClient {
+            launcher: match self.binary {
+                Some(binary) => launcher::resolve_path(binary),
+                None => launcher::resolve(),
+            },
+            default_args: self.default_args,
+            timeout: self
+                .timeout
+                .unwrap_or_else(|| Duration::from_secs(default_timeout_secs())),
+            env: self.env,
+            cwd: self.cwd,
+        })
+    }
+}
+
+/// Execute Gemini CLI with the given options and return the result,
+/// using the binary and timeout from the environment and server config
+pub async fn run(opts: Options) -> Result<GeminiResult> {
+    GeminiClient::new().run(opts).await
+}
+
+impl GeminiClient {
+    /// Client for `GEMINI_BIN` with the server config's timeout
+    pub fn new() -> Self {
+        Self {
+            launcher: launcher::resolve(),
+            default_args: Vec::new(),
+            timeout: Duration::from_secs(default_timeout_secs()),
+            env: BTreeMap::new(),
+            cwd: None,
+        }
+    }
+
+    pub fn builder() -> GeminiClientBuilder {
+        GeminiClientBuilder::default()
+    }
+
+    /// The CLI command for `opts`, before stdio is configured. Flags are
+    /// spelled for the CLI version found by [`cli_compat::detect`].
+    fn command(&self, opts: &Options) -> Command {
+        let cmd = build_command_for(&self.launcher, opts, cli_compat::current());
+        self.with_env(cmd, opts)
+    }
+
+    /// `cmd` with the environment and working directory of a run for `opts`
+    fn with_env(&self, mut cmd: Command, opts: &Options) -> Command {
+        // Per call over auth profile over client over config
+        cmd.envs(env());
+        cmd.envs(&self.env);
+        if let Some(ref auth_env) = opts.auth_env {
+            for var in auth::AUTH_VARS {
+                cmd.env_remove(var);
+            }
+            cmd.envs(auth_env);
+        }
+        cmd.envs(&opts.env);
+        if let Some(ref cwd) = self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd
+    }
+
+    /// Command starting the CLI as an ACP agent for `opts`
+    fn acp_command(&self, opts: &Options) -> Command {
+        self.with_env(build_acp_command(&self.launcher, opts), opts)
+    }
+
+    /// Program and arguments the CLI would be started with for `opts`
+    pub fn argv(&self, opts: &Options) -> Vec<String> {
+        let cmd = self.command(opts);
+        let cmd = cmd.as_std();
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Execute Gemini CLI with the given options and return the result. A
+    /// run that times out still returns what the CLI produced so far, with
+    /// `success == false` and [`GeminiResult::partial`] set.
+    pub async fn run(&self, opts: Options) -> Result<GeminiResult> {
+        let (opts, requested_model, warnings) = self.prepare(opts).await?;
+
+        // One-shot runs may be answered from the response cache or share an
+        // identical run already in flight
+        let one_shot = opts.session_id.is_none() && !opts.no_cache;
+        let cache = response_cache().filter(|_| one_shot);
+        let key = one_shot.then(|| {
+            CacheKey::new(
+                &opts.prompt,
+                requested_model.as_deref(),
+                self.cache_key_args(&opts),
+            )
+        });
+        if let (Some(cache), Some(key)) = (cache, key.as_ref()) {
+            if let Some((mut result, age)) = cache.get(key) {
+                result.warnings.push(format!(
+                    "served from the response cache (cached {}s ago)",
+                    age.as_secs()
+                ));
+                return Ok(result);
+            }
+        }
+
+        let remember = |key: &CacheKey, result: &Result<GeminiResult>| {
+            if let (Some(cache), Ok(result)) = (cache, result) {
+                if result.partial {
+                    return;
+                }
+                cache.insert(key.clone(), result.clone());
+            }
+        };
+
+        let Some(key) = key else {
+            return self.execute(opts, requested_model, warnings, None).await;
+        };
+        if !server_config().dedup_in_flight {
+            let result = self.execute(opts, requested_model, warnings, None).await;
+            remember(&key, &result);
+            return result;
+        }
+        let handle = opts.run_handle.clone();
+        loop {
+            match in_flight().join(key.clone()) {
+                Flight::Leader(leader) => {
+                    let result = self.execute(opts, requested_model, warnings, None).await;
+                    remember(&key, &result);
+                    leader.finish(result.as_ref().cloned().map_err(|e| format!("{:#}", e)));
+                    return result;
+                }
+                Flight::Follower(follower) => {
+                    let killed = async {
+                        match handle.as_ref() {
+                            Some(handle) => handle.inner.kill.notified().await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    let shared = tokio::select! {
+                        shared = follower.wait() => shared,
+                        _ = killed => anyhow::bail!("Gemini command was killed on request"),
+                        _ = cancelled(opts.cancellation.as_ref()) => {
+                            return Err(Cancelled::before_start())
+                        }
+                    };
+                    match shared {
+                        Some(Ok(mut result)) => {
+                            result.warnings.push(
+                                "shared the run of an identical in-flight request".to_string(),
+                            );
+                            return Ok(result);
+                        }
+                        Some(Err(err)) => return Err(anyhow::anyhow!(err)),
+                        // The leading call was cancelled; run again
+                        None => continue,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the CLI and yield its events as they are parsed from stdout. The
+    /// CLI is read only as fast as the stream is consumed (at most
+    /// `STREAM_BUFFER_EVENTS` events are buffered, and time spent waiting
+    /// counts against the timeout); dropping the stream kills the run.
+    /// Failures the CLI does not report as an event (invalid options, spawn
+    /// errors, timeouts, a failed exit) end the stream with a
+    /// [`GeminiEvent::Error`]. Streamed runs bypass the response cache.
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn stream(&self, opts: Options) -> impl Stream<Item = GeminiEvent> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_EVENTS);
+        let client = self.clone();
+        tokio::spawn(async move {
+            let run = async {
+                let (opts, requested_model, warnings) = client.prepare(opts).await?;
+                client
+                    .execute(opts, requested_model, warnings, Some(&tx))
+                    .await
+            };
+            let failure = tokio::select! {
+                result = run => unreported_failure(result),
+                _ = tx.closed() => return,
+            };
+            if let Some(message) = failure {
+                let event = GeminiEvent::Error(ErrorEvent {
+                    severity: Some("error".to_string()),
+                    message: Some(message),
+                    ..Default::default()
+                });
+                let _ = tx.send(event).await;
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    /// Validate `opts` and apply the client's default arguments and GEMINI.md.
+    /// Returns the options to run, the requested model and early warnings.
+    async fn prepare(&self, mut opts: Options) -> Result<(Options, Option<String>, Vec<String>)> {
+        // Validate options
+        if opts.prompt.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Prompt must be a non-empty, non-whitespace string"
+            ));
+        }
+
+        if opts.session_id.is_some() {
+            if let Some(err) = cli_compat::current().resume_unsupported() {
+                anyhow::bail!(err);
+            }
+        }
+        if !self.default_args.is_empty() {
+            opts.additional_args
+                .splice(0..0, self.default_args.iter().cloned());
+        }
+
+        // Prepare the final prompt by prepending GEMINI.md content if it exists
+        let mut warnings = Vec::new();
+        let final_prompt = prepare_prompt(self.cwd.as_deref(), &opts.prompt, &mut warnings).await;
+        let requested_model = opts
+            .model
+            .clone()
+            .or_else(|| model_from_args(&opts.extra_args))
+            .or_else(|| model_from_args(&opts.additional_args));
+        let opts = Options {
+            prompt: final_prompt,
+            ..opts
+        };
+        Ok((opts, requested_model, warnings))
+    }
+
+    /// Spawn the CLI for `opts` and collect its result; `warnings` come first in
+    /// the result's warnings. Parsed events are also sent to `events`.
+    async fn execute(
+        &self,
+        opts: Options,
+        requested_model: Option<String>,
+        mut warnings: Vec<String>,
+        events: Option<&mpsc::Sender<GeminiEvent>>,
+    ) -> Result<GeminiResult> {
+        let timeout_duration = opts.timeout.unwrap_or(self.timeout);
+
+        // New sessions and follow-ups to a running one go over ACP; other
+        // follow-ups resume the session as usual
+        if let Some(sessions) = interactive_sessions() {
+            let key = PoolKey::of(&self.acp_command(&opts));
+            let running = match opts.session_id.as_deref() {
+                Some(id) => sessions.get(id, &key).unwrap_or_else(|stopped| {
+                    warnings.push(stopped.to_string());
+                    None
+                }),
+                None => None,
+            };
+            if opts.session_id.is_none() || running.is_some() {
+                return self
+                    .execute_interactive(sessions, running, opts, requested_model, warnings, events)
+                    .await;
+            }
+        }
+
+        // Pooled runs get their prompt through stdin, so a process started
+        // before the prompt was known can take it
+        let pool = warm_pool().filter(|_| opts.session_id.is_none());
+        let opts = if pool.is_some() {
+            Options {
+                prompt_delivery: PromptDelivery::Stdin,
+                ..opts
+            }
+        } else {
+            opts
+        };
+
+        // Build the command and configure stdio.
+        let mut cmd = self.command(&opts);
+        let stdin_prompt = uses_stdin(&self.launcher, &opts);
+        cmd.stdin(if stdin_prompt {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        process_tree::configure(&mut cmd);
+
+        let handle = opts.run_handle.as_ref();
+        if handle.is_some_and(RunHandle::is_killed) {
+            return Err(anyhow::anyhow!(
+                "Gemini command was killed before it started"
+            ));
+        }
+        let cancellation = opts.cancellation.clone();
+        if cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(Cancelled::before_start());
+        }
+
+        // Injected latency counts against the timeout, like a slow start would
+        let faults = chaos_mode().draw();
+        warnings.extend(faults.warnings());
+        let delay = faults.delay.unwrap_or_default();
+        if faults.timeout || delay >= timeout_duration {
+            return injected_timeout(timeout_duration, handle, cancellation.as_ref(), warnings)
+                .await;
+        }
+        if !delay.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = kill_requested(handle) => {
+                    anyhow::bail!("Gemini command was killed before it started")
+                }
+                _ = cancelled(cancellation.as_ref()) => return Err(Cancelled::before_start()),
+            }
+        }
+
+        let pool_key = pool.map(|_| PoolKey::of(&cmd));
+        let warm = pool
+            .zip(pool_key.as_ref())
+            .and_then(|(pool, key)| pool.take(key));
+        let mut child = match warm {
+            Some(child) => child,
+            None => cmd.spawn().context("Failed to spawn gemini command")?,
+        };
+        // Start the process the next identical run will take
+        if let (Some(pool), Some(key)) = (pool, pool_key) {
+            pool.refill(key, &mut cmd);
+        }
+        // Dropped with the future if the call is cancelled, killing the tree
+        let mut tree = ProcessTree::attach(&child);
+        let pid = child.id();
+        if let Some(handle) = handle {
+            handle.set_pid(pid);
+            handle.emit(RunEvent::Spawned { pid });
+            if let Some(line) = faults.stderr {
+                handle.emit(RunEvent::Stderr(line));
+            }
+        }
+        let pid_label = pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+
+        // Write from a separate task so a CLI that produces output before reading
+        // all of its input cannot deadlock against us; closing stdin marks the end
+        // of the prompt
+        if let Some(mut stdin) = child.stdin.take().filter(|_| stdin_prompt) {
+            let prompt = opts.prompt;
+            tokio::spawn(async move {
+                if let Err(err) = stdin.write_all(prompt.as_bytes()).await {
+                    tracing::warn!("failed to write prompt to gemini stdin: {}", err);
+                }
+            });
+        }
+
+        let killed = async {
+            match handle {
+                Some(handle) => handle.inner.kill.notified().await,
+                None => std::future::pending().await,
+            }
+        };
+        // Collects the output as it arrives, so it survives an interruption
+        let mut partial = GeminiResult {
+            success: true,
+            ..Default::default()
+        };
+        let reading = run_with_child(
+            &mut child,
+            &opts.stderr_hooks,
+            opts.keep_raw_events,
+            handle,
+            events,
+            &mut partial,
+        );
+        let outcome = tokio::select! {
+            result = timeout(timeout_duration - delay, reading) => Ok(result),
+            _ = killed => Err(RunEvent::Killed),
+            _ = cancelled(cancellation.as_ref()) => Err(RunEvent::Cancelled),
+        };
+        let stopped = match outcome {
+            Ok(Ok(result)) => {
+                return result.map(|mut result| {
+                    tree.disarm();
+                    if let Some(warning) =
+                        model_fallback_warning(requested_model.as_deref(), result.model.as_deref())
+                    {
+                        tracing::warn!("{}", warning);
+                        warnings.push(warning);
+                    }
+                    warnings.append(&mut result.warnings);
+                    result.warnings = warnings;
+                    result
+                })
+            }
+            Ok(Err(_)) => RunEvent::TimedOut {
+                after_secs: timeout_duration.as_secs(),
+            },
+            Err(event) => event,
+        };
+
+        // Explicitly kill the process tree to avoid zombies and orphaned CLI
+        // sub-processes
+        tree.kill();
+        let _ = child.kill().await;
+        let _ = child.wait().await;
+        if let Some(handle) = handle {
+            handle.emit(stopped.clone());
+        }
+        partial.success = false;
+        partial.partial = true;
+        finish_raw_events(&mut partial, event_buffer());
+        warnings.append(&mut partial.warnings);
+        partial.warnings = warnings;
+        match stopped {
+            RunEvent::Cancelled => {
+                partial.error = Some("cancelled before the CLI finished".to_string());
+                Err(Cancelled { pid, partial }.into())
+            }
+            RunEvent::Killed => Err(anyhow::anyhow!(
+                "Gemini command (pid {}) was killed on request",
+                pid_label
+            )),
+            // What the model wrote before the deadline is still worth having
+            _ => {
+                partial.error = Some(format!(
+                    "Gemini command (pid {}) timed out after {} seconds",
+                    pid_label,
+                    timeout_duration.as_secs()
+                ));
+                Ok(partial)
+            }
+        }
+    }
+
+    /// Send the prompt of `opts` to the interactive session `running`, or to a
+    /// new one, recording the session's updates as `stream-json` events
+    async fn execute_interactive(
+        &self,
+        sessions: &'static InteractiveSessions,
+        running: Option<Arc<AcpSession>>,
+        opts: Options,
+        requested_model: Option<String>,
+        mut warnings: Vec<String>,
+        events: Option<&mpsc::Sender<GeminiEvent>>,
+    ) -> Result<GeminiResult> {
+        let timeout_duration = opts.timeout.unwrap_or(self.timeout);
+        let deadline = tokio::time::Instant::now() + timeout_duration;
+        let handle = opts.run_handle.as_ref();
+        if handle.is_some_and(RunHandle::is_killed) {
+            return Err(anyhow::anyhow!(
+                "Gemini command was killed before it started"
+            ));
+        }
+        let cancellation = opts.cancellation.clone();
+        if cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(Cancelled::before_start());
+        }
+
+        let session = match running {
+            Some(session) => session,
+            None => {
+                let cmd = self.acp_command(&opts);
+                let cwd = match self.cwd {
+                    Some(ref cwd) => cwd.clone(),
+                    None => {
+                        std::env::current_dir().context("Failed to get the working directory")?
+                    }
+                };
+                let session = tokio::select! {
+                    started = tokio::time::timeout_at(deadline, AcpSession::start(cmd, &cwd, opts.stderr_hooks.clone())) => {
+                        started.map_err(|_| {
+                            anyhow::anyhow!(
+                                "Gemini CLI did not open an interactive session within {} seconds",
+                                timeout_duration.as_secs()
+                            )
+                        })??
+                    }
+                    _ = kill_requested(handle) => {
+                        anyhow::bail!("Gemini command was killed before it started")
+                    }
+                    _ = cancelled(cancellation.as_ref()) => return Err(Cancelled::before_start()),
+                };
+                let session = Arc::new(session);
+                sessions.insert(Arc::clone(&session));
+                session
+            }
+        };
+        let pid = session.pid();
+        if let Some(handle) = handle {
+            handle.set_pid(pid);
+            handle.emit(RunEvent::Spawned { pid });
+        }
+        let pid_label = pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+
+        let mut result = GeminiResult {
+            success: true,
+            ..Default::default()
+        };
+        let init = serde_json::json!({
+            "type": "init",
+            "session_id": session.session_id(),
+            "model": requested_model,
+        });
+        record_event(init, opts.keep_raw_events, &mut result, events).await;
+
+        let (tx, mut updates) = mpsc::unbounded_channel();
+        let turn = session.prompt(&opts.prompt, opts.approval_mode, tx);
+        tokio::pin!(turn);
+        let killed = kill_requested(handle);
+        tokio::pin!(killed);
+        let outcome = loop {
+            tokio::select! {
+                Some(update) = updates.recv() => {
+                    if let Some(event) = acp::event_for(&update) {
+                        record_event(event, opts.keep_raw_events, &mut result, events).await;
+                    }
+                }
+                ended = &mut turn => break Ok(ended),
+                _ = tokio::time::sleep_until(deadline) => {
+                    break Err(RunEvent::TimedOut {
+                        after_secs: timeout_duration.as_secs(),
+                    })
+                }
+                _ = &mut killed => break Err(RunEvent::Killed),
+                _ = cancelled(cancellation.as_ref()) => break Err(RunEvent::Cancelled),
+            }
+        };
+        while let Ok(update) = updates.try_recv() {
+            if let Some(event) = acp::event_for(&update) {
+                record_event(event, opts.keep_raw_events, &mut result, events).await;
+            }
+        }
+
+        let stopped = match outcome {
+            Ok(Ok(stop_reason)) => {
+                let end = match stop_reason.as_str() {
+                    "end_turn" => serde_json::json!({"type": "result", "status": "success"}),
+                    "refusal" | "cancelled" => serde_json::json!({
+                        "type": "result",
+                        "status": "error",
+                        "error": {"message": format!("the turn ended with stop reason {}", stop_reason)},
+                    }),
+                    _ => {
+                        result
+                            .warnings
+                            .push(format!("the turn ended early ({})", stop_reason));
+                        serde_json::json!({"type": "result", "status": "success"})
+                    }
+                };
+                record_event(end, opts.keep_raw_events, &mut result, events).await;
+                None
+            }
+            Ok(Err(err)) => {
+                if !session.is_alive() {
+                    sessions.remove(session.session_id());
+                }
+                // Stopped for a login prompt or a stderr hook, as a one-shot
+                // run would be
+                if err.is::<AuthRequired>() || err.is::<hooks::StderrAbort>() {
+                    return Err(err);
+                }
+                result.success = false;
+                result.error = Some(format!("gemini error: {:#}", err));
+                None
+            }
+            Err(stopped) => Some(stopped),
+        };
+        let Some(stopped) = stopped else {
+            finish_raw_events(&mut result, event_buffer());
+            let mut result = enforce_required_fields(result);
+            if let Some(warning) =
+                model_fallback_warning(requested_model.as_deref(), result.model.as_deref())
+            {
+                warnings.push(warning);
+            }
+            warnings.append(&mut result.warnings);
+            result.warnings = warnings;
+            return Ok(result);
+        };
+
+        // A killed session's process goes with the last turn using it; other
+        // interruptions only stop the turn
+        match stopped {
+            RunEvent::Killed => sessions.remove(session.session_id()),
+            _ => session.cancel().await,
+        }
+        if let Some(handle) = handle {
+            handle.emit(stopped.clone());
+        }
+        result.success = false;
+        result.partial = true;
+        finish_raw_events(&mut result, event_buffer());
+        warnings.append(&mut result.warnings);
+        result.warnings = warnings;
+        match stopped {
+            RunEvent::Cancelled => {
+                result.error = Some("cancelled before the CLI finished".to_string());
+                Err(Cancelled {
+                    pid,
+                    partial: result,
+                }
+                .into())
+            }
+            RunEvent::Killed => Err(anyhow::anyhow!(
+                "Gemini command (pid {}) was killed on request",
+                pid_label
+            )),
+            _ => {
+                result.error = Some(format!(
+                    "Gemini command (pid {}) timed out after {} seconds",
+                    pid_label,
+                    timeout_duration.as_secs()
+                ));
+                Ok(result)
+            }
+        }
+    }
+
+    /// Arguments besides the prompt and model that change a run's answer,
+    /// including the client's binary, environment and working directory
+    fn cache_key_args(&self, opts: &Options) -> Vec<String> {
+        let mut args = vec![format!("--binary={}", self.launcher.path.display())];
+        if let Some(ref cwd) = self.cwd {
+            args.push(format!("--cwd={}", cwd.display()));
+        }
+        args.extend(
+            self.env
+                .iter()
+                .chain(opts.auth_env.iter().flatten())
+                .chain(&opts.env)
+                .map(|(key, value)| format!("--env={}={}", key, value)),
+        );
+        args.extend(opts.additional_args.iter().cloned());
+        args.extend(opts.extra_args.iter().cloned());
+        if opts.sandbox {
+            args.push("--sandbox".to_string());
+        }
+        if opts.grounding {
+            args.push("--grounding".to_string());
+        }
+        args.extend(
+            opts.include_directories
+                .iter()
+                .map(|dir| format!("--include-directories={}", dir.display())),
+        );
+        if let Some(mode) = opts.approval_mode {
+            args.push(format!("--approval-mode={}", mode));
+        }
+        args
+    }
+}
+
+/// Fail a run like a timeout once `after` has passed, without starting the
+/// CLI (chaos mode)
+async fn injected_timeout(
+    after: Duration,
+    handle: Option<&RunHandle>,
+    cancellation: Option<&CancellationToken>,
+    warnings: Vec<String>,
+) -> Result<GeminiResult> {
+    tokio::select! {
+        _ = tokio::time::sleep(after) => {}
+        _ = kill_requested(handle) => anyhow::bail!("Gemini command was killed on request"),
+        _ = cancelled(cancellation) => return Err(Cancelled::before_start()),
+    }
+    if let Some(handle) = handle {
+        handle.emit(RunEvent::TimedOut {
+            after_secs: after.as_secs(),
+        });
+    }
+    Ok(GeminiResult {
+        success: false,
+        partial: true,
+        error: Some(format!(
+            "Gemini command (pid unknown) timed out after {} seconds",
+            after.as_secs()
+        )),
+        warnings,
+        ..Default::default()
+    })
+}
+
+/// Keep `value` among the raw events of `result`. Past the head, events
+/// pile up until twice the tail is held and the oldest of them are then
+/// dropped at once, so each event costs amortized constant time.
+fn push_raw_event(result: &mut GeminiResult, value: Value, bounds: EventBufferConfig) {
+    let (head, tail) = (bounds.head(), bounds.tail());
+    if result.all_messages.len() < head {
+        result.all_messages.push(value);
+    } else if tail == 0 {
+        result.dropped_events += 1;
+    } else {
+        result.all_messages.push(value);
+        if result.all_messages.len() >= head + 2 * tail {
+            drop_middle_events(result, head, tail);
+        }
     }
+}
 
-    cmd
+/// Drop the raw events between the first `head` and the last `tail`
+fn drop_middle_events(result: &mut GeminiResult, head: usize, tail: usize) {
+    let excess = result.all_messages.len().saturating_sub(head + tail);
+    if excess > 0 {
+        result.all_messages.drain(head..head + excess);
+        result.dropped_events += excess;
+    }
 }
 
-/// Execute Gemini CLI with the given options and return the result
-pub async fn run(opts: Options) -> Result<GeminiResult> {
-    // Validate options
-    if opts.prompt.trim().is_empty() {
-        return Err(anyhow::anyhow!(
-            "Prompt must be a non-empty, non-whitespace string"
-        ));
+/// Trim the raw events of a finished run to `bounds` and mark where events
+/// were dropped, so a reader sees both the start and the end of long runs
+fn finish_raw_events(result: &mut GeminiResult, bounds: EventBufferConfig) {
+    let (head, tail) = (bounds.head(), bounds.tail());
+    drop_middle_events(result, head, tail);
+    if result.dropped_events == 0 {
+        return;
     }
+    let at = head.min(result.all_messages.len());
+    result.all_messages.insert(
+        at,
+        serde_json::json!({ "type": "dropped", "count": result.dropped_events }),
+    );
+    result.warnings.push(format!(
+        "{} events dropped from the middle of the run; kept the first {} and the last {}",
+        result.dropped_events, head, tail
+    ));
+}
 
-    let timeout_duration = Duration::from_secs(default_timeout_secs());
+/// Record a `stream-json` event in `result` and pass it on to `events`
+async fn record_event(
+    value: Value,
+    keep_raw: bool,
+    result: &mut GeminiResult,
+    events: Option<&mpsc::Sender<GeminiEvent>>,
+) {
+    let event = process_json_line(value, keep_raw, result);
+    if let (Some(events), Some(event)) = (events, event) {
+        let _ = events.send(event).await;
+    }
+}
 
-    // Prepare the final prompt by prepending GEMINI.md content if it exists
-    let final_prompt = prepare_prompt(&opts.prompt).await;
+/// Resolves once a kill of the run is requested through `handle`
+async fn kill_requested(handle: Option<&RunHandle>) {
+    match handle {
+        Some(handle) => handle.inner.kill.notified().await,
+        None => std::future::pending().await,
+    }
+}
 
-    // Create modified options with the final prompt
-    let modified_opts = Options {
-        prompt: final_prompt,
-        session_id: opts.session_id,
-        additional_args: opts.additional_args,
-    };
+/// Words of a model version suffix besides numbers, as in
+/// `-preview-06-05`, `-exp-1206` or `-latest`
+const MODEL_VERSION_WORDS: &[&str] = &["preview", "exp", "latest"];
 
-    // Build the command and configure stdio.
-    let mut cmd = build_command(&modified_opts);
-    cmd.stdin(Stdio::null());
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
-    cmd.kill_on_drop(true);
-    let mut child = cmd.spawn().context("Failed to spawn gemini command")?;
+/// Whether `reported`, an exact model version such as
+/// `gemini-2.5-pro-preview-06-05`, is the model `requested` names: the same
+/// name, or that name followed by a version or date suffix. Another model of
+/// the family (`gemini-2.5-flash-lite` for `gemini-2.5-flash`) is not.
+pub fn is_same_model(requested: &str, reported: &str) -> bool {
+    match reported.strip_prefix(requested) {
+        Some("") => true,
+        Some(suffix) => suffix.strip_prefix('-').is_some_and(|suffix| {
+            suffix.split('-').all(|part| {
+                MODEL_VERSION_WORDS.contains(&part)
+                    || (!part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+            })
+        }),
+        None => false,
+    }
+}
 
-    match timeout(timeout_duration, run_with_child(&mut child)).await {
-        Ok(result) => result,
-        Err(_) => {
-            // Explicitly kill the child process on timeout to avoid zombies
-            let _ = child.kill().await;
-            let _ = child.wait().await;
-            Err(anyhow::anyhow!(
-                "Gemini command timed out after {} seconds",
-                timeout_duration.as_secs()
-            ))
-        }
+/// Warning for a run whose reported model differs from the one requested,
+/// e.g. when the CLI falls back to another model on quota errors
+fn model_fallback_warning(requested: Option<&str>, reported: Option<&str>) -> Option<String> {
+    let (requested, reported) = (requested?, reported?);
+    if is_same_model(requested, reported) {
+        return None;
     }
+    Some(format!(
+        "Requested model {} but the CLI ran {} (model fallback)",
+        requested, reported
+    ))
 }
 
-/// Inner function that reads from a spawned child process
-async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResult> {
+/// Inner function that reads from a spawned child process into `result`,
+/// reporting stderr lines and the exit to `handle` and parsed events to
+/// `events`; raw events are kept only with `keep_raw`. A stderr line matching
+/// one of `stderr_hooks` kills the CLI.
+async fn run_with_child(
+    child: &mut tokio::process::Child,
+    stderr_hooks: &[StderrHook],
+    keep_raw: bool,
+    handle: Option<&RunHandle>,
+    events: Option<&mpsc::Sender<GeminiEvent>>,
+    result: &mut GeminiResult,
+) -> Result<GeminiResult> {
     // Read stdout and stderr
     let stdout = child.stdout.take().context("Failed to get stdout")?;
     let stderr = child.stderr.take().context("Failed to get stderr")?;
 
-    let mut result = GeminiResult {
-        success: true,
-        session_id: String::new(),
-        agent_messages: String::new(),
-        all_messages: Vec::new(),
-        error: None,
-    };
-
     // Read stdout and stderr concurrently
     let mut stdout_reader = BufReader::new(stdout).lines();
     let mut stderr_reader = BufReader::new(stderr).lines();
-    let mut stderr_output = String::new();
-    let mut stderr_truncated = false;
+    let mut stderr_output = StderrCapture::new(MAX_STDERR_BYTES);
     let mut non_json_lines = Vec::with_capacity(100); // Start with reasonable capacity
+    let mut dropped_non_json = 0usize;
+    // Stdout as printed, blank lines and indentation included, in case it
+    // turns out to be a plain-text answer
+    let mut plain_lines: Option<Vec<String>> = plain_text_fallback().then(Vec::new);
+    // With `-o json` no single line is an event; the document is parsed once
+    // stdout closes
+    let line_events = output_format(cli_compat::current()) == OutputFormat::StreamJson;
+    let mut document = DocumentBuffer::new(MAX_JSON_DOCUMENT_BYTES);
     let mut valid_json_seen = false;
     let mut stdout_closed = false;
     let mut stderr_closed = false;
+    // Stops the run when the CLI asks to log in, since nobody can answer
+    let mut login = LoginDetector::default();
     while !stdout_closed || !stderr_closed {
         tokio::select! {
             line = stdout_reader.next_line(), if !stdout_closed => {
@@ -394,26 +3590,59 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
                     Some(line) => {
                         let trimmed = line.trim();
                         if trimmed.is_empty() {
+                            if let Some(plain) = plain_lines.as_mut().filter(|p| !p.is_empty()) {
+                                if plain.len() < MAX_NON_JSON_LINES {
+                                    plain.push(String::new());
+                                }
+                            }
                             continue;
                         }
 
                         // Parse JSON line
-                        let line_data: Value = match serde_json::from_str(trimmed) {
-                            Ok(data) => {
+                        let parsed = if line_events {
+                            ParsedLine::parse(trimmed)
+                        } else {
+                            None
+                        };
+                        let parsed_line = match parsed {
+                            Some(parsed_line) => {
                                 valid_json_seen = true;
-                                data
+                                plain_lines = None;
+                                parsed_line
                             }
-                            Err(_) => {
+                            None => {
+                                let login_error = if line_events {
+                                    login.feed(trimmed)
+                                } else {
+                                    login.feed_before_document(trimmed)
+                                };
+                                if let Some(error) = login_error {
+                                    let _ = child.start_kill();
+                                    return Err(error.into());
+                                }
+                                if let Some(plain) = plain_lines.as_mut() {
+                                    if plain.len() < MAX_NON_JSON_LINES {
+                                        plain.push(line.trim_end().to_string());
+                                    }
+                                }
+                                document.push_line(trimmed);
                                 // Collect non-JSON lines for potential logging (with limit)
                                 if non_json_lines.len() < MAX_NON_JSON_LINES {
                                     non_json_lines.push(trimmed.to_string());
+                                } else {
+                                    dropped_non_json += 1;
                                 }
                                 continue;
                             }
                         };
 
                         // Process the parsed JSON line
-                        process_json_line(&line_data, &mut result);
+                        let event = process_stdout_line(trimmed, parsed_line, keep_raw, result);
+                        if let (Some(events), Some(event)) = (events, event) {
+                            // Waiting for room stops reading stdout, which in
+                            // turn pauses the CLI: backpressure
+                            let _ = events.send(event).await;
+                        }
                     }
                     None => stdout_closed = true,
                 }
@@ -421,24 +3650,22 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
             line = stderr_reader.next_line(), if !stderr_closed => {
                 match line {
                     Ok(Some(line)) => {
-                        // Only capture stderr up to the limit
-                        if stderr_output.len() < MAX_STDERR_BYTES && !stderr_truncated {
-                            if !stderr_output.is_empty() {
-                                stderr_output.push('\n');
-                            }
-                            let remaining = MAX_STDERR_BYTES - stderr_output.len();
-                            if line.len() <= remaining {
-                                stderr_output.push_str(&line);
-                            } else {
-                                stderr_output.push_str(&line[..remaining]);
-                                stderr_output.push_str("\n... (stderr truncated)");
-                                stderr_truncated = true;
-                            }
+                        if let Some(handle) = handle {
+                            handle.emit(RunEvent::Stderr(line.clone()));
+                        }
+                        if let Some(error) = login.feed(&line) {
+                            let _ = child.start_kill();
+                            return Err(error.into());
+                        }
+                        if let Some(abort) = hooks::check(stderr_hooks, &line) {
+                            let _ = child.start_kill();
+                            return Err(abort.into());
                         }
+                        stderr_output.push_line(&line);
                     }
                     Ok(None) => stderr_closed = true,
                     Err(e) => {
-                        eprintln!("Warning: Failed to read from stderr: {}", e);
+                        tracing::warn!("Failed to read from stderr: {}", e);
                         stderr_closed = true;
                     }
                 }
@@ -446,11 +3673,49 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
         }
     }
 
+    // A JSON document instead of events: asked for with `-o json`, or
+    // printed by a CLI that ignored `stream-json`
+    if !valid_json_seen {
+        if let Some(document_events) = document.events() {
+            valid_json_seen = true;
+            non_json_lines.clear();
+            plain_lines = None;
+            for value in document_events {
+                let event = process_json_line(value, keep_raw, result);
+                if let (Some(events), Some(event)) = (events, event) {
+                    let _ = events.send(event).await;
+                }
+            }
+        }
+    }
+
+    if !valid_json_seen && document.is_overflowed() {
+        result.warnings.push(format!(
+            "stdout passed the {} byte limit of a JSON document and was not parsed as one",
+            document.limit()
+        ));
+    }
+    if let Some(warning) = stderr_output.warning() {
+        result.warnings.push(warning);
+    }
+    finish_raw_events(result, event_buffer());
+    if dropped_non_json > 0 {
+        result.warnings.push(format!(
+            "{} non-JSON output lines dropped past the {} line limit",
+            dropped_non_json, MAX_NON_JSON_LINES
+        ));
+    }
+
     // Wait for process to finish
     let status = child
         .wait()
         .await
         .context("Failed to wait for gemini command")?;
+    if let Some(handle) = handle {
+        handle.emit(RunEvent::Exited {
+            code: status.code(),
+        });
+    }
 
     if !status.success() {
         result.success = false;
@@ -462,7 +3727,7 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
 
         let mut full_error = error_msg;
         if !stderr_output.is_empty() {
-            full_error = format!("{}\nStderr: {}", full_error, stderr_output);
+            full_error = format!("{}\nStderr: {}", full_error, stderr_output.report());
         }
         // Always include non-JSON output on failure to help with diagnosis
         if !non_json_lines.is_empty() {
@@ -473,6 +3738,11 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
             );
         }
         result.error = Some(full_error);
+    } else if let Some(answer) = plain_lines
+        .map(|lines| lines.join("\n").trim_end().to_string())
+        .filter(|answer| !answer.is_empty())
+    {
+        take_plain_text(result, answer);
     } else if !non_json_lines.is_empty() && !valid_json_seen {
         // Process succeeded but no valid JSON was seen
         result.success = false;
@@ -482,13 +3752,40 @@ async fn run_with_child(child: &mut tokio::process::Child) -> Result<GeminiResul
         ));
     }
 
-    Ok(enforce_required_fields(result))
+    Ok(enforce_required_fields(std::mem::take(result)))
+}
+
+/// Take `answer`, the plain-text stdout of a run that exited cleanly, as its
+/// only assistant message
+fn take_plain_text(result: &mut GeminiResult, answer: String) {
+    result.plain_text = true;
+    result.messages.push(AgentMessage {
+        content: answer.clone(),
+        ..Default::default()
+    });
+    result.agent_messages = answer;
+    result.warnings.push(
+        "The CLI printed plain text instead of stream-json; it was taken as the answer, \
+         and the session cannot be resumed"
+            .to_string(),
+    );
+}
+
+/// The error to end a stream with, unless the CLI already reported it as an
+/// event
+fn unreported_failure(result: Result<GeminiResult>) -> Option<String> {
+    match result {
+        Err(err) => Some(format!("{:#}", err)),
+        Ok(result) if result.success => None,
+        Ok(result) if result.failure_reported => None,
+        Ok(result) => result.error,
+    }
 }
 
 fn enforce_required_fields(mut result: GeminiResult) -> GeminiResult {
     let mut errors = Vec::new();
 
-    if result.session_id.is_empty() {
+    if result.session_id.is_empty() && !result.plain_text {
         errors.push("Failed to get `SESSION_ID` from the gemini session.".to_string());
     }
 
@@ -512,38 +3809,142 @@ fn enforce_required_fields(mut result: GeminiResult) -> GeminiResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_config_values_out_of_range() {
+        let path = Path::new("gemini-mcp.config.json");
+        let err = parse_config(
+            path,
+            json!({"timeout_secs": 0, "rate_limit": {"global_per_minute": 0}, "chat": {"max_turns": 5}}),
+        )
+        .unwrap_err();
+        let pointers: Vec<&str> = err.issues.iter().map(|i| i.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/timeout_secs", "/rate_limit/global_per_minute"]);
+
+        let err = parse_config(path, json!({"timeout_secs": 7200})).unwrap_err();
+        assert_eq!(err.issues[0].message, "7200 is above the maximum of 3600");
+        let err = parse_config(path, json!({"summary": {"max_char": 80}})).unwrap_err();
+        assert_eq!(err.issues[0].pointer, "/summary/max_char");
+        assert!(parse_config(path, json!({"timeout_secs": 600, "chunking": {}})).is_ok());
+    }
+
+    #[test]
+    fn test_dangerous_args_need_opt_in() {
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(dangerous_arg(&args(&["--model", "x", " --yolo"])), Some(2));
+        assert_eq!(
+            dangerous_arg(&args(&["--allowed-tools=run_shell_command"])),
+            Some(0)
+        );
+        assert_eq!(dangerous_arg(&args(&["--approval-mode", "yolo"])), Some(0));
+        assert_eq!(dangerous_arg(&args(&["--approval-mode=yolo"])), Some(0));
+        assert_eq!(
+            dangerous_arg(&args(&["--approval-mode", "auto_edit"])),
+            None
+        );
+        assert_eq!(dangerous_arg(&args(&["-m", "--telemetry-target"])), Some(1));
+
+        let path = Path::new("gemini-mcp.config.json");
+        let cfg = parse_config(path, json!({"additional_args": ["-m", "x", "-y"]})).unwrap();
+        let issue = dangerous_args_issue(&cfg).unwrap();
+        assert_eq!(issue.pointer, "/additional_args/2");
+        let cfg = parse_config(
+            path,
+            json!({"additional_args": ["-y"], "allow_dangerous_args": true}),
+        )
+        .unwrap();
+        assert_eq!(dangerous_args_issue(&cfg), None);
+
+        let builder = Options::builder().prompt("task");
+        let err = builder
+            .clone()
+            .additional_args(["--telemetry-otlp-endpoint=http://collector"])
+            .allow_dangerous_args(false)
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("additional_args flag"));
+        let extra = builder
+            .clone()
+            .extra_args(["--allowed-tools", "run_shell_command"])
+            .extra_args_allowlist(["--allowed-tools"]);
+        let err = extra
+            .clone()
+            .allow_dangerous_args(false)
+            .build()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("EXTRA_ARGS flag `--allowed-tools`"));
+        assert!(extra.allow_dangerous_args(true).build().is_ok());
+    }
 
     #[test]
     fn test_options_creation() {
-        let opts = Options {
-            prompt: "test prompt".to_string(),
-            session_id: None,
-            additional_args: Vec::new(),
-        };
+        let opts = Options::builder().prompt("test prompt").build().unwrap();
 
         assert_eq!(opts.prompt, "test prompt");
+        assert!(opts.model.is_none());
+        assert!(!opts.keep_raw_events);
     }
 
     #[test]
     fn test_options_with_session() {
-        let opts = Options {
-            prompt: "resume task".to_string(),
-            session_id: Some("test-session-123".to_string()),
-            additional_args: vec!["--model".to_string(), "gemini-pro".to_string()],
-        };
+        let opts = Options::builder()
+            .prompt("resume task")
+            .session("89473362-3f12-46e8-adce-05388980dcca")
+            .additional_args(["--model", "gemini-pro"])
+            .build()
+            .unwrap();
 
-        assert_eq!(opts.session_id, Some("test-session-123".to_string()));
+        assert_eq!(
+            opts.session_id,
+            Some("89473362-3f12-46e8-adce-05388980dcca".to_string())
+        );
         assert_eq!(opts.additional_args.len(), 2);
     }
 
+    #[test]
+    fn test_options_builder_rejects_invalid_input() {
+        assert!(Options::builder().build().is_err());
+        assert!(Options::builder().prompt("   ").build().is_err());
+        assert!(Options::builder()
+            .prompt("task")
+            .session("skinbase-tradeit-metrics")
+            .build()
+            .is_err());
+        assert!(Options::builder()
+            .prompt("task")
+            .additional_args(["--output-format=json"])
+            .build()
+            .is_err());
+        assert!(Options::builder()
+            .prompt("task")
+            .arg("--resume")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_options_builder_empty_session_is_none() {
+        let opts = Options::builder()
+            .prompt("task")
+            .session("")
+            .model("  ")
+            .build()
+            .unwrap();
+
+        assert!(opts.session_id.is_none());
+        assert!(opts.model.is_none());
+    }
+
     #[test]
     fn test_enforce_required_fields_requires_session_id() {
         let result = GeminiResult {
             success: true,
             session_id: String::new(),
             agent_messages: "msg".to_string(),
-            all_messages: Vec::new(),
-            error: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result);
@@ -562,8 +3963,7 @@ mod tests {
             success: true,
             session_id: "session".to_string(),
             agent_messages: String::new(),
-            all_messages: Vec::new(),
-            error: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result);
@@ -583,7 +3983,7 @@ mod tests {
             session_id: "session".to_string(),
             agent_messages: String::new(),
             all_messages: vec![serde_json::json!({"type": "tool_use"})],
-            error: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result);
@@ -594,13 +3994,9 @@ mod tests {
 
     #[test]
     fn test_build_command_basic() {
-        let opts = Options {
-            prompt: "test prompt".to_string(),
-            session_id: None,
-            additional_args: Vec::new(),
-        };
+        let opts = Options::builder().prompt("test prompt").build().unwrap();
 
-        let cmd = build_command(&opts);
+        let cmd = GeminiClient::new().command(&opts);
         let program = cmd.as_std().get_program();
 
         // Should use "gemini" as the binary name (or GEMINI_BIN env var)
@@ -609,13 +4005,14 @@ mod tests {
 
     #[test]
     fn test_build_command_with_all_options() {
-        let opts = Options {
-            prompt: "complex prompt".to_string(),
-            session_id: Some("session-123".to_string()),
-            additional_args: vec!["--model".to_string(), "gemini-pro".to_string()],
-        };
+        let opts = Options::builder()
+            .prompt("complex prompt")
+            .session("89473362-3f12-46e8-adce-05388980dcca")
+            .additional_args(["--model", "gemini-pro"])
+            .build()
+            .unwrap();
 
-        let cmd = build_command(&opts);
+        let cmd = GeminiClient::new().command(&opts);
         let program = cmd.as_std().get_program();
 
         // Should use "gemini" as the binary name
@@ -624,26 +4021,314 @@ mod tests {
 
     #[test]
     fn test_build_command_with_session_only() {
-        let opts = Options {
-            prompt: "resume".to_string(),
-            session_id: Some("abc-123".to_string()),
-            additional_args: Vec::new(),
-        };
+        let opts = Options::builder()
+            .prompt("resume")
+            .session("89473362-3f12-46e8-adce-05388980dcca")
+            .build()
+            .unwrap();
 
-        let cmd = build_command(&opts);
+        let cmd = GeminiClient::new().command(&opts);
         let program = cmd.as_std().get_program();
 
         assert!(program == "gemini" || program.to_string_lossy().contains("gemini"));
     }
 
+    #[test]
+    fn test_build_command_model_overrides_configured_model() {
+        let opts = Options::builder()
+            .prompt("task")
+            .additional_args(["--yolo", "--model", "gemini-pro", "-m=other"])
+            .allow_dangerous_args(true)
+            .model("gemini-2.5-flash")
+            .build()
+            .unwrap();
+
+        let cmd = GeminiClient::new().command(&opts);
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        assert!(args.contains(&"--yolo".to_string()));
+        assert!(!args.contains(&"gemini-pro".to_string()));
+        assert!(!args.contains(&"-m=other".to_string()));
+        let idx = args.iter().position(|a| a == "--model").unwrap();
+        assert_eq!(args[idx + 1], "gemini-2.5-flash");
+    }
+
+    #[test]
+    fn test_build_command_sandbox() {
+        let args = |opts: &Options| -> Vec<String> {
+            GeminiClient::new()
+                .command(opts)
+                .as_std()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        let builder = Options::builder().prompt("task");
+
+        let plain = builder.clone().build().unwrap();
+        assert!(!args(&plain).contains(&"--sandbox".to_string()));
+
+        let sandboxed = builder.clone().sandbox(true).build().unwrap();
+        assert!(args(&sandboxed).contains(&"--sandbox".to_string()));
+
+        // Not repeated when the server config already passes it
+        let configured = builder.arg("-s").sandbox(true).build().unwrap();
+        let configured = args(&configured);
+        assert!(configured.contains(&"-s".to_string()));
+        assert!(!configured.contains(&"--sandbox".to_string()));
+    }
+
+    #[test]
+    fn test_build_command_for_old_cli() {
+        use crate::cli_compat::CliVersion;
+
+        let args = |opts: &Options, compat| -> Vec<String> {
+            build_command_for(&launcher::resolve(), opts, compat)
+                .as_std()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        let opts = Options::builder().prompt("hi").build().unwrap();
+
+        let old = CliCompat::for_version(CliVersion::new(0, 9, 0));
+        assert_eq!(
+            args(&opts, old),
+            vec!["--output-format", "json", "--prompt", "hi"]
+        );
+        let current = CliCompat::for_version(CliVersion::new(0, 13, 0));
+        assert_eq!(args(&opts, current), vec!["-o", "stream-json", "hi"]);
+    }
+
+    #[test]
+    fn test_build_command_stdin_prompt() {
+        let args = |opts: &Options| -> Vec<String> {
+            GeminiClient::new()
+                .command(opts)
+                .as_std()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        let id = "89473362-3f12-46e8-adce-05388980dcca";
+        let builder = Options::builder()
+            .prompt("secret task")
+            .prompt_delivery(PromptDelivery::Stdin);
+
+        let fresh = builder.clone().build().unwrap();
+        assert!(uses_stdin(&launcher::resolve(), &fresh));
+        assert_eq!(args(&fresh), vec!["-o", "stream-json"]);
+
+        let resumed = builder.session(id).build().unwrap();
+        assert_eq!(args(&resumed), vec!["-o", "stream-json", "--resume", id]);
+
+        // `auto` switches over once the prompt is larger than the threshold
+        let auto = |prompt: String| {
+            Options::builder()
+                .prompt(prompt)
+                .prompt_delivery(PromptDelivery::Auto)
+                .build()
+                .unwrap()
+        };
+        assert!(!uses_stdin(
+            &launcher::resolve(),
+            &auto("short".to_string())
+        ));
+        let long = "x".repeat(stdin_prompt_threshold_bytes() + 1);
+        assert!(uses_stdin(&launcher::resolve(), &auto(long.clone())));
+        assert!(!args(&auto(long)).iter().any(|a| a.starts_with("xxx")));
+    }
+
+    #[test]
+    fn test_build_command_approval_mode() {
+        let args = |opts: &Options| -> Vec<String> {
+            GeminiClient::new()
+                .command(opts)
+                .as_std()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        let builder = Options::builder()
+            .prompt("task")
+            .additional_args(["--yolo", "--model", "gemini-pro"])
+            .allow_dangerous_args(true);
+
+        // An explicit mode replaces the configured `--yolo`
+        let opts = builder
+            .clone()
+            .approval_mode(ApprovalMode::AutoEdit)
+            .build()
+            .unwrap();
+        let built = args(&opts);
+        assert!(!built.contains(&"--yolo".to_string()));
+        let idx = built.iter().position(|a| a == "--approval-mode").unwrap();
+        assert_eq!(built[idx + 1], "auto_edit");
+        assert!(built.contains(&"gemini-pro".to_string()));
+
+        assert!(!args(&builder.build().unwrap()).contains(&"--approval-mode".to_string()));
+    }
+
+    #[test]
+    fn test_approval_mode_is_capped() {
+        let builder = Options::builder()
+            .prompt("task")
+            .max_approval_mode(ApprovalMode::AutoEdit);
+
+        assert!(builder
+            .clone()
+            .approval_mode(ApprovalMode::AutoEdit)
+            .build()
+            .is_ok());
+        let err = builder
+            .clone()
+            .approval_mode(ApprovalMode::Yolo)
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("at most `auto_edit`"));
+
+        // Also enforced for flags smuggled through an allowlisted EXTRA_ARGS
+        let extra = |args: &[&str]| {
+            builder
+                .clone()
+                .extra_args(args.iter().copied())
+                .extra_args_allowlist(["--yolo", "-y", "--approval-mode"])
+                .allow_dangerous_args(true)
+                .build()
+        };
+        assert!(extra(&["-y"]).is_err());
+        assert!(extra(&["--approval-mode=yolo"]).is_err());
+        assert!(extra(&["--approval-mode", "auto_edit"]).is_ok());
+
+        let parsed: ApprovalMode = serde_json::from_str(r#""auto_edit""#).unwrap();
+        assert_eq!(parsed, ApprovalMode::AutoEdit);
+        assert!(ApprovalMode::Default < ApprovalMode::AutoEdit);
+    }
+
+    #[test]
+    fn test_validate_extra_args_against_allowlist() {
+        let allowlist = vec!["--model".to_string(), "--include-directories".to_string()];
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert!(validate_extra_args(&args(&["--model", "gemini-2.5-flash"]), &allowlist).is_ok());
+        // Entries are prefixes
+        let prefixes = vec!["--sandbox".to_string(), String::new()];
+        assert!(validate_extra_args(&args(&["--sandbox=true"]), &prefixes).is_ok());
+        assert!(validate_extra_args(&args(&["--sandbox-image", "img"]), &prefixes).is_ok());
+        assert!(validate_extra_args(&args(&["--model", "x"]), &prefixes).is_err());
+        // Paths go through INCLUDE_DIRECTORIES and the policy, even when allowlisted
+        for include in [
+            &["--include-directories=/etc"][..],
+            &["--include-directories", "/etc"],
+            &["--include-directories-extra=/etc"],
+        ] {
+            let err = validate_extra_args(&args(include), &allowlist).unwrap_err();
+            assert!(err.to_string().contains("INCLUDE_DIRECTORIES"));
+        }
+        assert!(!DEFAULT_EXTRA_ARGS_ALLOWLIST.contains(&"--include-directories"));
+        assert!(validate_extra_args(&args(&["--yolo"]), &allowlist).is_err());
+        assert!(validate_extra_args(&args(&["stray-value"]), &allowlist).is_err());
+        assert!(validate_extra_args(&args(&["--model=x", "stray"]), &allowlist).is_err());
+        assert!(validate_extra_args(&args(&["--resume", "id"]), &allowlist).is_err());
+    }
+
+    #[test]
+    fn test_per_call_env_is_allowlisted_and_overrides_client_env() {
+        let opts = |key: &str| {
+            Options::builder()
+                .prompt("task")
+                .env(key, "my-project")
+                .env_allowlist(["GOOGLE_CLOUD_PROJECT"])
+                .build()
+        };
+        assert!(opts("LD_PRELOAD").is_err());
+        assert!(opts("BAD=NAME").is_err());
+
+        let client = GeminiClient::builder()
+            .env("GOOGLE_CLOUD_PROJECT", "default-project")
+            .env("GOOGLE_CLOUD_LOCATION", "us-central1")
+            .build()
+            .unwrap();
+        let cmd = client.command(&opts("GOOGLE_CLOUD_PROJECT").unwrap());
+        let envs: BTreeMap<_, _> = cmd
+            .as_std()
+            .get_envs()
+            .filter_map(|(k, v)| Some((k.to_str()?, v?.to_str()?)))
+            .collect();
+        assert_eq!(envs.get("GOOGLE_CLOUD_PROJECT"), Some(&"my-project"));
+        assert_eq!(envs.get("GOOGLE_CLOUD_LOCATION"), Some(&"us-central1"));
+    }
+
+    #[test]
+    fn test_include_directories_are_checked_against_roots() {
+        let root = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let lib = root.path().join("lib");
+        std::fs::create_dir(&lib).unwrap();
+        let roots = [root.path().to_path_buf()];
+        let policy = Policy::new(&roots, &[]);
+        let dir = |path: &Path| path.display().to_string();
+
+        let resolved =
+            resolve_include_directories(&[dir(&lib), dir(&lib.join("."))], &policy).unwrap();
+        assert_eq!(resolved, [lib.canonicalize().unwrap()]);
+        let err = resolve_include_directories(&[dir(outside.path())], &policy).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("INCLUDE_DIRECTORIES: Policy violation: "));
+        let lib_only = Policy::new(std::slice::from_ref(&lib), &[]);
+        assert!(resolve_include_directories(&[dir(&lib.join(".."))], &lib_only).is_err());
+        assert!(resolve_include_directories(&[dir(&lib.join("missing"))], &policy).is_err());
+        let denied = Policy::new(&roots, &["lib".to_string()]);
+        assert!(resolve_include_directories(&[dir(&lib)], &denied).is_err());
+
+        let opts = Options::builder()
+            .prompt("task")
+            .include_directories([dir(&lib)])
+            .allowed_roots(roots)
+            .build()
+            .unwrap();
+        let cmd = GeminiClient::new().command(&opts);
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let flag = args
+            .iter()
+            .position(|a| a == "--include-directories")
+            .unwrap();
+        assert_eq!(args[flag + 1], dir(&lib.canonicalize().unwrap()));
+    }
+
+    #[test]
+    fn test_build_command_extra_model_replaces_configured_model() {
+        let opts = Options::builder()
+            .prompt("task")
+            .additional_args(["--model", "gemini-pro"])
+            .extra_args(["--model", "gemini-2.5-flash"])
+            .extra_args_allowlist(["--model"])
+            .build()
+            .unwrap();
+
+        let cmd = GeminiClient::new().command(&opts);
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(args.iter().filter(|a| *a == "--model").count(), 1);
+        assert!(args.contains(&"gemini-2.5-flash".to_string()));
+    }
+
     #[test]
     fn test_process_json_line_skips_prompt_deprecation_warning() {
         let mut result = GeminiResult {
             success: true,
-            session_id: String::new(),
-            agent_messages: String::new(),
-            all_messages: Vec::new(),
-            error: None,
+            ..Default::default()
         };
 
         let line = serde_json::json!({
@@ -656,7 +4341,7 @@ mod tests {
             )
         });
 
-        process_json_line(&line, &mut result);
+        process_json_line(line, true, &mut result);
 
         // Warning should not be treated as an agent message, but session_id should still be set.
         assert_eq!(result.session_id, "test-session");
@@ -664,6 +4349,349 @@ mod tests {
         assert!(result.error.is_none());
     }
 
+    #[test]
+    fn test_process_json_line_records_init_model() {
+        let mut result = GeminiResult::default();
+
+        let line = serde_json::json!({
+            "type": "init",
+            "session_id": "test-session",
+            "model": "gemini-2.5-pro-preview-06-05"
+        });
+        process_json_line(line, true, &mut result);
+
+        assert_eq!(
+            result.model.as_deref(),
+            Some("gemini-2.5-pro-preview-06-05")
+        );
+        assert_eq!(result.session_id, "test-session");
+    }
+
+    #[test]
+    fn test_process_json_line_records_usage() {
+        let mut result = GeminiResult::default();
+
+        let line = serde_json::json!({
+            "type": "result",
+            "status": "success",
+            "stats": {"total_tokens": 150, "input_tokens": 100, "output_tokens": 50}
+        });
+        process_json_line(line, true, &mut result);
+
+        let usage = result.usage.expect("usage should be recorded");
+        assert_eq!(usage.prompt_tokens, 100);
+        assert_eq!(usage.candidate_tokens, 50);
+        assert_eq!(usage.total_tokens, 150);
+
+        let mut empty = GeminiResult::default();
+        process_json_line(
+            serde_json::json!({"type": "result", "status": "success"}),
+            true,
+            &mut empty,
+        );
+        assert!(empty.usage.is_none());
+    }
+
+    #[test]
+    fn test_process_json_line_separates_thoughts() {
+        let mut result = GeminiResult::default();
+
+        for line in [
+            serde_json::json!({"type": "thought", "subject": "Plan", "description": "Read the file first"}),
+            serde_json::json!({"type": "message", "role": "assistant", "content": "Done."}),
+            serde_json::json!({"type": "thinking", "text": "Double-check"}),
+        ] {
+            process_json_line(line, true, &mut result);
+        }
+
+        assert_eq!(result.agent_messages, "Done.");
+        assert_eq!(result.thoughts.len(), 2);
+        assert_eq!(result.thoughts[0].subject.as_deref(), Some("Plan"));
+        assert_eq!(result.thoughts[0].content, "Read the file first");
+        assert_eq!(result.thoughts[1].content, "Double-check");
+        assert_eq!(result.all_messages.len(), 3);
+    }
+
+    #[test]
+    fn test_process_stdout_line_keeps_text_only_when_asked() {
+        let lines = [
+            r#"{"type": "init", "session_id": "s1", "model": "gemini-2.5-pro"}"#,
+            r#"{"type": "message", "role": "assistant", "content": "Done."}"#,
+            r#"{"type": "message", "content": 42}"#,
+        ];
+        for keep_raw in [false, true] {
+            let mut result = GeminiResult::default();
+            for text in lines {
+                let line = ParsedLine::parse(text).unwrap();
+                process_stdout_line(text, line, keep_raw, &mut result);
+            }
+            assert_eq!(result.session_id, "s1");
+            assert_eq!(result.agent_messages, "Done.");
+            assert_eq!(result.warnings.len(), 1);
+            let kept: Vec<Value> = if keep_raw {
+                lines
+                    .iter()
+                    .map(|l| serde_json::from_str(l).unwrap())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            assert_eq!(result.all_messages, kept);
+        }
+    }
+
+    #[test]
+    fn test_process_json_line_can_drop_raw_events() {
+        let mut result = GeminiResult {
+            success: true,
+            ..Default::default()
+        };
+
+        for line in [
+            serde_json::json!({"type": "init", "session_id": "s1", "model": "gemini-2.5-pro"}),
+            serde_json::json!({"type": "message", "role": "assistant", "content": "Partial"}),
+            serde_json::json!({"type": "error", "message": "Quota exceeded"}),
+        ] {
+            process_json_line(line, false, &mut result);
+        }
+
+        assert!(result.all_messages.is_empty());
+        assert_eq!(result.session_id, "s1");
+        assert_eq!(result.agent_messages, "Partial");
+        assert!(!result.success);
+        assert!(result.failure_reported);
+        assert_eq!(unreported_failure(Ok(result)), None);
+    }
+
+    #[test]
+    fn test_raw_events_keep_the_start_and_end_of_long_runs() {
+        let bounds = EventBufferConfig {
+            head: Some(2),
+            tail: Some(3),
+        };
+        let mut result = GeminiResult::default();
+        for n in 0..20 {
+            push_raw_event(&mut result, serde_json::json!({ "n": n }), bounds);
+            assert!(result.all_messages.len() < 2 + 2 * 3);
+        }
+        finish_raw_events(&mut result, bounds);
+
+        let kept: Vec<_> = result.all_messages.iter().map(|v| v["n"].clone()).collect();
+        assert_eq!(
+            kept,
+            [0, 1]
+                .map(Value::from)
+                .into_iter()
+                .chain([Value::Null])
+                .chain([17, 18, 19].map(Value::from))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            result.all_messages[2],
+            serde_json::json!({"type": "dropped", "count": 15})
+        );
+        assert_eq!(result.dropped_events, 15);
+        assert_eq!(result.warnings.len(), 1);
+
+        // Short runs are left alone
+        let mut short = GeminiResult::default();
+        push_raw_event(&mut short, serde_json::json!({}), bounds);
+        finish_raw_events(&mut short, bounds);
+        assert_eq!(short.all_messages.len(), 1);
+        assert!(short.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_plain_text_answer_needs_no_session() {
+        let mut result = GeminiResult {
+            success: true,
+            ..Default::default()
+        };
+        take_plain_text(&mut result, "Line one\n\n    indented".to_string());
+        let result = enforce_required_fields(result);
+
+        assert!(result.success);
+        assert!(result.plain_text);
+        assert_eq!(result.error, None);
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(result.agent_messages, "Line one\n\n    indented");
+        assert_eq!(result.warnings.len(), 1);
+
+        let failed = enforce_required_fields(GeminiResult {
+            success: true,
+            agent_messages: "Answer".to_string(),
+            ..Default::default()
+        });
+        assert!(!failed.success);
+    }
+
+    #[test]
+    fn test_process_json_line_keeps_message_boundaries() {
+        let mut result = GeminiResult::default();
+
+        for line in [
+            serde_json::json!({"type": "message", "role": "assistant", "content": "Plan: read the file.", "timestamp": "2025-10-10T12:00:00Z"}),
+            serde_json::json!({"type": "tool_use", "tool_name": "read_file", "tool_id": "t1"}),
+            serde_json::json!({"type": "tool_result", "tool_id": "t1", "status": "success"}),
+            serde_json::json!({"type": "message", "role": "user", "content": "ignored"}),
+            serde_json::json!({"type": "message", "role": "assistant", "content": "The answer is 42."}),
+        ] {
+            process_json_line(line, true, &mut result);
+        }
+
+        assert_eq!(
+            result.agent_messages,
+            "Plan: read the file.\nThe answer is 42."
+        );
+        assert_eq!(result.messages.len(), 2);
+        assert_eq!(
+            result.messages[0].timestamp.as_deref(),
+            Some("2025-10-10T12:00:00Z")
+        );
+        assert!(result.messages[0].tools_before.is_empty());
+        assert_eq!(result.messages[1].content, "The answer is 42.");
+        assert_eq!(result.messages[1].tools_before, ["read_file"]);
+    }
+
+    #[test]
+    fn test_process_json_line_merges_deltas() {
+        let mut result = GeminiResult::default();
+        let message = |content: &str, delta: bool, id: Option<&str>| {
+            serde_json::json!({
+                "type": "message", "role": "assistant", "content": content,
+                "delta": delta, "id": id
+            })
+        };
+
+        for line in [
+            // Incremental fragments without an id
+            message("Let me ", true, None),
+            message("check.", true, None),
+            serde_json::json!({"type": "tool_use", "tool_name": "read_file", "tool_id": "t1"}),
+            // Fragments, then the complete message, keyed by id
+            message("The answer", true, Some("m2")),
+            message(" is", true, Some("m2")),
+            message(" 42", true, Some("m2")),
+            message("The answer is 42.", false, Some("m2")),
+        ] {
+            process_json_line(line, true, &mut result);
+        }
+
+        assert_eq!(result.agent_messages, "Let me check.\nThe answer is 42.");
+        assert_eq!(result.messages.len(), 2);
+        assert!(result.messages[0].delta);
+        assert_eq!(result.messages[1].id.as_deref(), Some("m2"));
+        assert!(!result.messages[1].delta);
+        assert_eq!(result.messages[1].tools_before, ["read_file"]);
+
+        // A late fragment of an earlier message
+        process_json_line(message("Done", false, Some("m3")), true, &mut result);
+        process_json_line(
+            message("The answer is 42!", false, Some("m2")),
+            true,
+            &mut result,
+        );
+        assert_eq!(
+            result.agent_messages,
+            "Let me check.\nThe answer is 42!\nDone"
+        );
+
+        // A delta starting with the text so far is still appended
+        let mut result = GeminiResult::default();
+        process_json_line(message("I", true, Some("m4")), true, &mut result);
+        process_json_line(message("I think", true, Some("m4")), true, &mut result);
+        assert_eq!(result.agent_messages, "II think");
+    }
+
+    #[test]
+    fn test_process_json_line_collects_citations() {
+        let mut result = GeminiResult::default();
+
+        for line in [
+            serde_json::json!({"type": "tool_use", "tool_name": "google_web_search", "tool_id": "s1",
+                               "parameters": {"query": "rust 1.85"}}),
+            serde_json::json!({"type": "tool_result", "tool_id": "s1", "status": "success",
+                               "output": "Rust 1.85 shipped [1].\n\nSources:\n[1] Rust Blog (https://blog.rust-lang.org/)"}),
+            serde_json::json!({"type": "citation", "uri": "https://blog.rust-lang.org/", "title": "Rust Blog",
+                               "startIndex": 0, "endIndex": 17}),
+        ] {
+            process_json_line(line, true, &mut result);
+        }
+
+        assert_eq!(result.citations.len(), 2);
+        assert_eq!(result.citations[0].title.as_deref(), Some("Rust Blog"));
+        assert_eq!(result.citations[0].start_index, None);
+        assert_eq!(result.citations[1].end_index, Some(17));
+        assert_eq!(result.tool_activity[0].success, Some(true));
+    }
+
+    #[test]
+    fn test_process_json_line_collects_cli_warnings() {
+        let mut result = GeminiResult {
+            success: true,
+            ..Default::default()
+        };
+
+        let line = serde_json::json!({
+            "type": "error",
+            "severity": "warning",
+            "message": "Quota exceeded, switching to gemini-2.5-flash"
+        });
+        process_json_line(line, true, &mut result);
+        process_json_line(
+            serde_json::json!({"type": "message", "content": 1}),
+            false,
+            &mut result,
+        );
+
+        assert!(result.success);
+        assert!(result.error.is_none());
+        assert_eq!(result.warnings.len(), 2);
+        assert!(result.warnings[0].contains("switching to gemini-2.5-flash"));
+    }
+
+    #[test]
+    fn test_model_fallback_warning() {
+        assert_eq!(model_fallback_warning(None, Some("gemini-2.5-pro")), None);
+        assert_eq!(model_fallback_warning(Some("gemini-2.5-pro"), None), None);
+        assert_eq!(
+            model_fallback_warning(Some("gemini-2.5-pro"), Some("gemini-2.5-pro-preview")),
+            None
+        );
+        let warning =
+            model_fallback_warning(Some("gemini-2.5-pro"), Some("gemini-2.5-flash")).unwrap();
+        assert!(warning.contains("model fallback"));
+
+        assert!(is_same_model(
+            "gemini-2.5-pro",
+            "gemini-2.5-pro-preview-06-05"
+        ));
+        assert!(is_same_model("gemini-2.5-pro", "gemini-2.5-pro"));
+        assert!(is_same_model("gemini-1.5-flash", "gemini-1.5-flash-001"));
+        assert!(is_same_model("gemini-2.0-pro", "gemini-2.0-pro-exp-02-05"));
+        assert!(!is_same_model("gemini-2.5-pro", "gemini-2.5-flash"));
+        assert!(!is_same_model("gemini-2.5-flash", "gemini-2.5-flash-lite"));
+        assert!(!is_same_model("gemini-pro", "gemini-pro-vision"));
+        assert!(!is_same_model("gemini-2", "gemini-2.5-pro"));
+        assert!(!is_same_model("gemini-2.5-pro", "gemini-2.5-pro-"));
+        assert!(
+            model_fallback_warning(Some("gemini-2.5-flash"), Some("gemini-2.5-flash-lite"))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_model_from_args() {
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(model_from_args(&args(&["--yolo"])), None);
+        assert_eq!(
+            model_from_args(&args(&["--model", "a", "-m=b"])).as_deref(),
+            Some("b")
+        );
+        assert_eq!(model_from_args(&args(&["-m", "c"])).as_deref(), Some("c"));
+    }
+
     #[tokio::test]
     async fn test_read_gemini_config_nonexistent_file() {
         use tempfile::TempDir;
@@ -724,8 +4752,41 @@ mod tests {
         let large_content = "x".repeat(MAX_CONFIG_SIZE + 1);
         fs::write(&config_path, large_content).await.unwrap();
 
-        let result = read_gemini_config_from_path(&config_path).await;
+        let mut warnings = Vec::new();
+        let result = load_gemini_config_file(&config_path, &mut warnings).await;
         assert!(result.is_none());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("too large"));
+    }
+
+    #[test]
+    fn test_discover_gemini_config_paths_orders_general_to_specific() {
+        use tempfile::TempDir;
+        let home = TempDir::new().unwrap();
+        let global_dir = home.path().join(".gemini");
+        std::fs::create_dir_all(&global_dir).unwrap();
+        std::fs::write(global_dir.join("GEMINI.md"), "global").unwrap();
+
+        let workspace = TempDir::new().unwrap();
+        let outside = workspace.path();
+        let root = outside.join("repo");
+        let nested = root.join("crates").join("core");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        // Above the project root: must not be picked up
+        std::fs::write(outside.join("GEMINI.md"), "outside").unwrap();
+        std::fs::write(root.join("GEMINI.md"), "root").unwrap();
+        std::fs::write(nested.join("GEMINI.md"), "nested").unwrap();
+
+        let paths = discover_gemini_config_paths(&nested, Some(home.path()));
+        assert_eq!(
+            paths,
+            vec![
+                global_dir.join("GEMINI.md"),
+                root.join("GEMINI.md"),
+                nested.join("GEMINI.md"),
+            ]
+        );
     }
 
     #[tokio::test]
@@ -734,7 +4795,7 @@ mod tests {
         let _temp_dir = TempDir::new().unwrap();
 
         let user_prompt = "Test user prompt";
-        let result = prepare_prompt(user_prompt).await;
+        let result = prepare_prompt(None, user_prompt, &mut Vec::new()).await;
 
         // Without config, prompt should be unchanged
         assert!(result.contains(user_prompt));
@@ -743,7 +4804,7 @@ mod tests {
     #[tokio::test]
     async fn test_prepare_prompt_preserves_user_prompt() {
         let user_prompt = "What is 2+2?";
-        let result = prepare_prompt(user_prompt).await;
+        let result = prepare_prompt(None, user_prompt, &mut Vec::new()).await;
 
         assert!(result.contains(user_prompt));
     }
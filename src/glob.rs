@@ -0,0 +1,54 @@
+//! Path globs shared by `CONTEXT_GLOBS` and the `denied_paths` policy.
+
+/// Whether `path` matches `glob`. `*` and `?` stay within one path segment
+/// and `**` spans any number of them; a glob without `/` matches the file
+/// name in any directory, as in `.gitignore`.
+pub fn matches(glob: &str, path: &str) -> bool {
+    if !glob.contains('/') {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        return segment_matches(glob.as_bytes(), name.as_bytes());
+    }
+    let globs: Vec<&str> = glob.split('/').collect();
+    let parts: Vec<&str> = path.split('/').collect();
+    segments_match(&globs, &parts)
+}
+
+fn segments_match(globs: &[&str], parts: &[&str]) -> bool {
+    match globs.split_first() {
+        None => parts.is_empty(),
+        Some((&"**", rest)) => (0..=parts.len()).any(|skip| segments_match(rest, &parts[skip..])),
+        Some((glob, rest)) => match parts.split_first() {
+            Some((part, parts)) => {
+                segment_matches(glob.as_bytes(), part.as_bytes()) && segments_match(rest, parts)
+            }
+            None => false,
+        },
+    }
+}
+
+fn segment_matches(glob: &[u8], name: &[u8]) -> bool {
+    match glob.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| segment_matches(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && segment_matches(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && segment_matches(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.rs", "src/server.rs"));
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(matches("src/**/*.rs", "src/lib.rs"));
+        assert!(matches("src/**/*.rs", "src/bin/main.rs"));
+        assert!(matches("**/Cargo.toml", "Cargo.toml"));
+        assert!(matches("docs/?.md", "docs/a.md"));
+        assert!(!matches("docs/?.md", "docs/ab.md"));
+        assert!(!matches("*.rs", "src/lib.rs.orig"));
+    }
+}
@@ -0,0 +1,129 @@
+//! Stderr patterns that stop a run early (`stderr_hooks`).
+//!
+//! Some CLI failures are obvious from the first lines of stderr, like a DNS
+//! failure (`FetchError: ENOTFOUND`) or an exhausted quota, yet the CLI keeps
+//! retrying until the timeout. Each configured hook names a pattern and an
+//! error category; stderr is checked line by line while the run is in
+//! progress, and the first line matching a hook kills the CLI and fails the
+//! run with [`StderrAbort`].
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// One pattern of `stderr_hooks`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StderrHook {
+    /// Text to look for in a stderr line, matched case-insensitively
+    pub pattern: String,
+    /// Error category reported to the client, e.g. `network_error`
+    pub category: String,
+    /// Explanation for the client; the matching line otherwise
+    pub message: Option<String>,
+}
+
+impl StderrHook {
+    fn matches(&self, lower_line: &str) -> bool {
+        lower_line.contains(&self.pattern.to_lowercase())
+    }
+}
+
+/// Reject hooks without a pattern or category
+pub fn validate(hooks: &[StderrHook]) -> Result<()> {
+    for (i, hook) in hooks.iter().enumerate() {
+        if hook.pattern.trim().is_empty() {
+            bail!("stderr_hooks[{}]: pattern must not be empty", i);
+        }
+        if hook.category.trim().is_empty() {
+            bail!("stderr_hooks[{}]: category must not be empty", i);
+        }
+    }
+    Ok(())
+}
+
+/// Error of a run stopped because a stderr line matched a hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StderrAbort {
+    pub category: String,
+    pub pattern: String,
+    /// The stderr line that matched
+    pub line: String,
+    pub message: Option<String>,
+}
+
+impl std::fmt::Display for StderrAbort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gemini CLI stopped early ({}): {}",
+            self.category,
+            self.message.as_deref().unwrap_or(&self.line)
+        )
+    }
+}
+
+impl std::error::Error for StderrAbort {}
+
+/// The abort for the first hook `line` matches, if any. Hooks with an empty
+/// pattern never match.
+pub fn check(hooks: &[StderrHook], line: &str) -> Option<StderrAbort> {
+    if hooks.is_empty() {
+        return None;
+    }
+    let lower = line.to_lowercase();
+    let hook = hooks
+        .iter()
+        .filter(|hook| !hook.pattern.trim().is_empty())
+        .find(|hook| hook.matches(&lower))?;
+    Some(StderrAbort {
+        category: hook.category.trim().to_string(),
+        pattern: hook.pattern.clone(),
+        line: line.trim().to_string(),
+        message: hook.message.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(pattern: &str, category: &str) -> StderrHook {
+        StderrHook {
+            pattern: pattern.to_string(),
+            category: category.to_string(),
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_first_matching_hook_wins() {
+        let hooks = [
+            hook("FetchError: ENOTFOUND", "network_error"),
+            hook("quota", "quota_exceeded"),
+            hook("", "never"),
+        ];
+        assert_eq!(check(&hooks, "Loaded cached credentials."), None);
+        let abort = check(
+            &hooks,
+            "  fetcherror: enotfound generativelanguage.googleapis.com (quota) ",
+        )
+        .unwrap();
+        assert_eq!(abort.category, "network_error");
+        assert_eq!(
+            abort.line,
+            "fetcherror: enotfound generativelanguage.googleapis.com (quota)"
+        );
+        assert_eq!(
+            abort.to_string(),
+            "Gemini CLI stopped early (network_error): fetcherror: enotfound generativelanguage.googleapis.com (quota)"
+        );
+        assert!(check(&[], "anything").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[hook("ENOTFOUND", "network_error")]).is_ok());
+        assert!(validate(&[hook(" ", "network_error")]).is_err());
+        assert!(validate(&[hook("ENOTFOUND", "")]).is_err());
+    }
+}
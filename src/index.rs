@@ -0,0 +1,560 @@
+//! Workspace index for retrieval (RAG-lite): text files split into line
+//! chunks, embedded with an [`Embedder`] and saved in the state directory.
+//! Prompts sent with `USE_RETRIEVAL` get the most similar chunks prepended.
+//!
+//! Updates are incremental: only files whose modification time or size
+//! changed since they were indexed are read and embedded again.
+
+use crate::chunking::ChunkingConfig;
+use crate::embed::{self, Embedder};
+use crate::policy::Policy;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Directories never indexed when the workspace is not a git repository
+const SKIP_DIRS: &[&str] = &["target", "node_modules", "dist", "build", "vendor"];
+
+/// Chunks prepended to a prompt unless `retrieval.top_k` says otherwise
+pub const DEFAULT_TOP_K: usize = 5;
+
+/// Limits for [`WorkspaceIndex::build`]
+#[derive(Debug, Clone, Copy)]
+pub struct IndexOptions {
+    pub chunking: ChunkingConfig,
+    /// Larger files are skipped
+    pub max_file_bytes: u64,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            chunking: ChunkingConfig::default(),
+            max_file_bytes: 200_000,
+        }
+    }
+}
+
+/// Saved embedding index of one workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceIndex {
+    pub root: PathBuf,
+    /// [`Embedder::id`] of the backend that produced the vectors
+    pub embedder: String,
+    /// How files were split; changing it re-embeds every file
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+    /// Unix time of the last build or update
+    pub built_at: u64,
+    /// Indexed files by path relative to `root`
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    /// Unix time of the file's last modification when it was indexed
+    pub modified: u64,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// First line, 1-based
+    pub start_line: usize,
+    /// Last line, inclusive
+    pub end_line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// Counts reported after a build or update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct IndexSummary {
+    /// Files in the index
+    pub files: usize,
+    pub chunks: usize,
+    /// New or modified files that were read and embedded
+    pub embedded: usize,
+    /// Files dropped because they were deleted or became unindexable
+    pub removed: usize,
+    /// Files left out because they are too large or not UTF-8 text
+    pub skipped: usize,
+    pub elapsed_ms: u64,
+}
+
+/// A chunk matching a query
+#[derive(Debug)]
+pub struct Hit<'a> {
+    pub path: &'a str,
+    pub chunk: &'a Chunk,
+    pub score: f32,
+    /// The file changed or disappeared after it was indexed
+    pub stale: bool,
+}
+
+/// A file read for indexing, before embedding
+struct SourceFile {
+    path: String,
+    modified: u64,
+    size: u64,
+    text: String,
+}
+
+impl WorkspaceIndex {
+    /// Index every text file of `root` (the files git tracks, or a directory
+    /// walk outside a repository)
+    pub async fn build(
+        root: &Path,
+        embedder: &Embedder,
+        opts: IndexOptions,
+    ) -> Result<(Self, IndexSummary)> {
+        let mut index = Self {
+            root: root.to_path_buf(),
+            embedder: embedder.id(),
+            chunking: opts.chunking,
+            built_at: 0,
+            files: BTreeMap::new(),
+        };
+        let summary = index.update(embedder, opts).await?;
+        Ok((index, summary))
+    }
+
+    /// Bring the index up to date with the workspace: embed new and modified
+    /// files and drop deleted ones. A different embedder or chunking
+    /// re-embeds everything.
+    pub async fn update(
+        &mut self,
+        embedder: &Embedder,
+        opts: IndexOptions,
+    ) -> Result<IndexSummary> {
+        let started = Instant::now();
+        if self.embedder != embedder.id() || self.chunking != opts.chunking {
+            self.files.clear();
+            self.embedder = embedder.id();
+            self.chunking = opts.chunking;
+        }
+
+        let known: HashMap<String, (u64, u64)> = self
+            .files
+            .iter()
+            .map(|(path, file)| (path.clone(), (file.modified, file.size)))
+            .collect();
+        let scan = {
+            let root = self.root.clone();
+            tokio::task::spawn_blocking(move || scan_sources(&root, opts.max_file_bytes, &known))
+                .await
+                .context("Indexing task panicked")??
+        };
+
+        let mut pieces = Vec::new();
+        for (file, source) in scan.changed.iter().enumerate() {
+            for chunk in opts.chunking.split(&source.path, &source.text) {
+                pieces.push((file, chunk.start_line, chunk.end_line, chunk.text));
+            }
+        }
+        // The path helps when the question names a file or module
+        let texts: Vec<String> = pieces
+            .iter()
+            .map(|(file, _, _, text)| format!("{}\n{}", scan.changed[*file].path, text))
+            .collect();
+        let mut vectors = embedder.embed(&texts).await?.into_iter();
+
+        let before = self.files.len();
+        self.files.retain(|path, _| scan.present.contains(path));
+        let removed = before - self.files.len();
+        for source in &scan.changed {
+            self.files.insert(
+                source.path.clone(),
+                IndexedFile {
+                    modified: source.modified,
+                    size: source.size,
+                    chunks: Vec::new(),
+                },
+            );
+        }
+        for (file, start_line, end_line, text) in pieces {
+            let vector = vectors
+                .next()
+                .context("embedder returned too few vectors")?;
+            if let Some(entry) = self.files.get_mut(&scan.changed[file].path) {
+                entry.chunks.push(Chunk {
+                    start_line,
+                    end_line,
+                    text,
+                    vector,
+                });
+            }
+        }
+        self.built_at = unix_now();
+
+        Ok(IndexSummary {
+            files: self.files.len(),
+            chunks: self.chunk_count(),
+            embedded: scan.changed.len(),
+            removed,
+            skipped: scan.skipped,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Read a saved index; `None` if there is none yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read(path) {
+            Ok(raw) => serde_json::from_slice(&raw)
+                .map(Some)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(self).context("Failed to serialize index")?;
+        std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|f| f.chunks.len()).sum()
+    }
+
+    /// The `top_k` chunks most similar to `query`, best first
+    pub async fn search(
+        &self,
+        embedder: &Embedder,
+        query: &str,
+        top_k: usize,
+    ) -> Result<Vec<Hit<'_>>> {
+        if embedder.id() != self.embedder {
+            bail!(
+                "The workspace index was built with `{}` but retrieval is configured for `{}`; run gemini_index_workspace again",
+                self.embedder,
+                embedder.id()
+            );
+        }
+        let query = embedder
+            .embed(&[query.to_string()])
+            .await?
+            .pop()
+            .context("embedder returned no vector")?;
+
+        let mut hits: Vec<Hit<'_>> = self
+            .files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.chunks.iter().map(|chunk| Hit {
+                    path,
+                    chunk,
+                    score: embed::similarity(&query, &chunk.vector),
+                    stale: false,
+                })
+            })
+            .filter(|hit| hit.score > 0.0)
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(top_k);
+        for hit in &mut hits {
+            hit.stale = self.is_stale(hit.path);
+        }
+        Ok(hits)
+    }
+
+    /// Whether `path` changed on disk since it was indexed
+    fn is_stale(&self, path: &str) -> bool {
+        let Some(file) = self.files.get(path) else {
+            return true;
+        };
+        match std::fs::metadata(self.root.join(path)) {
+            Ok(meta) => (modified_secs(&meta), meta.len()) != (file.modified, file.size),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Where the index of `root` is saved inside the state directory
+pub fn index_path(state_dir: &Path, root: &Path) -> PathBuf {
+    let key = embed::fnv1a(root.to_string_lossy().as_bytes());
+    state_dir.join("index").join(format!("{:016x}.json", key))
+}
+
+/// Context block prepended to the prompt, or `None` without hits
+pub fn context_block(hits: &[Hit<'_>]) -> Option<String> {
+    if hits.is_empty() {
+        return None;
+    }
+    let mut block =
+        String::from("Relevant workspace excerpts (retrieved automatically, may be incomplete):\n");
+    for hit in hits {
+        block.push_str(&format!(
+            "\n--- {} lines {}-{}{} ---\n{}\n",
+            hit.path,
+            hit.chunk.start_line,
+            hit.chunk.end_line,
+            if hit.stale {
+                " (changed since indexed)"
+            } else {
+                ""
+            },
+            hit.chunk.text.trim_end()
+        ));
+    }
+    Some(block)
+}
+
+/// Workspace files found by [`scan_sources`]
+struct Scan {
+    /// Every indexable file, changed or not
+    present: HashSet<String>,
+    /// Files that are new or whose modification time or size differs from
+    /// `known`, with their text
+    changed: Vec<SourceFile>,
+    skipped: usize,
+}
+
+fn scan_sources(
+    root: &Path,
+    max_file_bytes: u64,
+    known: &HashMap<String, (u64, u64)>,
+) -> Result<Scan> {
+    let mut scan = Scan {
+        present: HashSet::new(),
+        changed: Vec::new(),
+        skipped: 0,
+    };
+    let policy = Policy::current();
+    for path in list_files(root)? {
+        if policy.denied_pattern(&path).is_some() {
+            scan.skipped += 1;
+            continue;
+        }
+        let full = root.join(&path);
+        let Ok(meta) = std::fs::metadata(&full) else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        if meta.len() > max_file_bytes {
+            scan.skipped += 1;
+            continue;
+        }
+        let path = path.to_string_lossy().replace('\\', "/");
+        let stamp = (modified_secs(&meta), meta.len());
+        if known.get(&path) == Some(&stamp) {
+            scan.present.insert(path);
+            continue;
+        }
+        let Ok(text) = std::fs::read_to_string(&full) else {
+            scan.skipped += 1;
+            continue;
+        };
+        if text.contains('\0') {
+            scan.skipped += 1;
+            continue;
+        }
+        scan.present.insert(path.clone());
+        scan.changed.push(SourceFile {
+            path,
+            modified: stamp.0,
+            size: stamp.1,
+            text,
+        });
+    }
+    Ok(scan)
+}
+
+fn modified_secs(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Files relative to `root`: tracked and untracked-but-not-ignored files in a
+/// git repository, otherwise a walk that skips hidden and build directories
+pub(crate) fn list_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let git = std::process::Command::new("git")
+        .args([
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ])
+        .current_dir(root)
+        .stderr(std::process::Stdio::null())
+        .output();
+    if let Ok(output) = git {
+        if output.status.success() {
+            return Ok(output
+                .stdout
+                .split(|&b| b == 0)
+                .filter(|p| !p.is_empty())
+                .map(|p| PathBuf::from(String::from_utf8_lossy(p).into_owned()))
+                .collect());
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        let entries = std::fs::read_dir(root.join(&dir))
+            .with_context(|| format!("Failed to list {}", root.join(&dir).display()))?;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !SKIP_DIRS.contains(&name.as_str()) {
+                    pending.push(dir.join(&name));
+                }
+            } else if file_type.is_file() {
+                files.push(dir.join(&name));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::ChunkStrategy;
+
+    fn options() -> IndexOptions {
+        IndexOptions {
+            chunking: ChunkingConfig {
+                max_lines: 3,
+                ..Default::default()
+            },
+            max_file_bytes: 1_000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_search_and_reload() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("workspace");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(
+            root.join("src/limits.rs"),
+            "/// Sliding window rate limiter\nfn rate_limit() {}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("src/session.rs"),
+            "fn resume_session(id: &str) {}\n// session labels\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("target/out.rs"), "fn rate_limit() {}\n").unwrap();
+        std::fs::write(root.join("big.txt"), "x".repeat(2_000)).unwrap();
+        std::fs::write(root.join("blob.bin"), b"\0\x01rate").unwrap();
+
+        let (index, summary) = WorkspaceIndex::build(&root, &Embedder::Hashing, options())
+            .await
+            .unwrap();
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.chunks, 2);
+        assert_eq!(summary.skipped, 2);
+
+        let hits = index
+            .search(
+                &Embedder::Hashing,
+                "how is the rate limiter implemented?",
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(hits[0].path, "src/limits.rs");
+        let block = context_block(&hits).unwrap();
+        assert!(block.contains("--- src/limits.rs lines 1-2 ---\n/// Sliding window"));
+
+        let path = index_path(&dir.path().join("state"), &root);
+        index.save(&path).unwrap();
+        let reloaded = WorkspaceIndex::load(&path).unwrap().unwrap();
+        assert_eq!(reloaded.chunk_count(), 2);
+        assert!(WorkspaceIndex::load(&dir.path().join("missing.json"))
+            .unwrap()
+            .is_none());
+
+        let other = Embedder::Command(vec!["embed".to_string()]);
+        assert!(reloaded.search(&other, "rate", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incremental_update() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("keep.rs"), "fn parse_config() {}\n").unwrap();
+        std::fs::write(root.join("edit.rs"), "fn rate_limit() {}\n").unwrap();
+        std::fs::write(root.join("gone.rs"), "fn old_code() {}\n").unwrap();
+        let (mut index, summary) = WorkspaceIndex::build(root, &Embedder::Hashing, options())
+            .await
+            .unwrap();
+        assert_eq!((summary.files, summary.embedded), (3, 3));
+
+        std::fs::write(root.join("edit.rs"), "fn rate_limit_per_minute() {}\n").unwrap();
+        std::fs::remove_file(root.join("gone.rs")).unwrap();
+        std::fs::write(root.join("new.rs"), "fn fresh() {}\n").unwrap();
+
+        let hits = index
+            .search(&Embedder::Hashing, "rate limit", 1)
+            .await
+            .unwrap();
+        assert_eq!(hits[0].path, "edit.rs");
+        assert!(hits[0].stale);
+        assert!(context_block(&hits)
+            .unwrap()
+            .contains("--- edit.rs lines 1-1 (changed since indexed) ---"));
+
+        let summary = index.update(&Embedder::Hashing, options()).await.unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.embedded, 2);
+        assert_eq!(summary.removed, 1);
+        assert!(index.files.contains_key("new.rs"));
+        assert!(!index.files.contains_key("gone.rs"));
+        let hits = index
+            .search(&Embedder::Hashing, "rate limit", 1)
+            .await
+            .unwrap();
+        assert!(!hits[0].stale);
+        assert!(hits[0].chunk.text.contains("per_minute"));
+
+        // Different chunking re-embeds everything
+        let opts = IndexOptions {
+            chunking: ChunkingConfig {
+                strategy: ChunkStrategy::Tokens,
+                ..Default::default()
+            },
+            ..options()
+        };
+        assert_eq!(
+            index
+                .update(&Embedder::Hashing, opts)
+                .await
+                .unwrap()
+                .embedded,
+            3
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! `gemini-mcp-rs init`: scaffold a starter `gemini-mcp.config.json` and
+//! `GEMINI.md`, interactively or from flags.
+
+use crate::gemini::{
+    DEFAULT_TIMEOUT_SECS, GEMINI_CONFIG_FILE, MAX_TIMEOUT_SECS, SERVER_CONFIG_FILE,
+};
+use crate::launcher::Launcher;
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Models offered by `init`. The Gemini CLI has no command to list models, so
+/// this list ships with the server; other model names are accepted with a
+/// warning.
+pub const KNOWN_MODELS: &[&str] = &[
+    "gemini-2.5-pro",
+    "gemini-2.5-flash",
+    "gemini-2.5-flash-lite",
+    "gemini-3-pro-preview",
+];
+
+const GEMINI_MD_TEMPLATE: &str = "# Project instructions for Gemini
+
+This file is prepended to every prompt sent through gemini-mcp-rs.
+
+## Project
+
+- What this repository is and how it is laid out.
+
+## Conventions
+
+- Coding style, test commands and anything Gemini should always follow.
+";
+
+/// Flags of the `init` subcommand
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    /// Directory the files are written to
+    pub dir: PathBuf,
+    /// Model added to `additional_args`; `None` keeps the CLI default
+    pub model: Option<String>,
+    pub timeout_secs: Option<u64>,
+    /// Also write a starter `GEMINI.md`
+    pub gemini_md: bool,
+    /// Overwrite existing files
+    pub force: bool,
+    /// Ask for values that were not given as flags
+    pub interactive: bool,
+}
+
+/// Run `init`, asking on stdin when `opts.interactive` and stdin is a terminal
+pub fn run(mut opts: InitOptions) -> Result<()> {
+    match find_gemini_binary() {
+        Some(path) => match binary_version(&path) {
+            Some(version) => println!("Found gemini {} at {}", version, path.display()),
+            None => println!(
+                "Found {} but `--version` failed; check that the Gemini CLI works",
+                path.display()
+            ),
+        },
+        None => println!(
+            "Warning: the `gemini` binary was not found on PATH. Install the Gemini CLI \
+             (npm install -g @google/gemini-cli) or set GEMINI_BIN to its path."
+        ),
+    }
+
+    if opts.interactive && std::io::stdin().is_terminal() {
+        let stdin = std::io::stdin();
+        ask_missing(&mut opts, &mut stdin.lock())?;
+    }
+
+    let timeout_secs = opts.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    validate(opts.model.as_deref(), timeout_secs)?;
+
+    let config_path = opts.dir.join(SERVER_CONFIG_FILE);
+    let config = render_config(opts.model.as_deref(), timeout_secs);
+    write_new(&config_path, &config, opts.force)?;
+    if opts.gemini_md {
+        write_new(
+            &opts.dir.join(GEMINI_CONFIG_FILE),
+            GEMINI_MD_TEMPLATE,
+            opts.force,
+        )?;
+    }
+
+    println!(
+        "\nNext: register the server with your MCP client, e.g.\n  \
+         {{\"mcpServers\": {{\"gemini\": {{\"command\": \"gemini-mcp-rs\"}}}}}}\n\
+         Run it from {} or set GEMINI_MCP_CONFIG_PATH={}",
+        opts.dir.display(),
+        config_path.display()
+    );
+    Ok(())
+}
+
+/// Locate the Gemini CLI: `GEMINI_BIN` if set, otherwise `gemini` on PATH
+pub fn find_gemini_binary() -> Option<PathBuf> {
+    let name = std::env::var("GEMINI_BIN").unwrap_or_else(|_| "gemini".to_string());
+    let candidate = Path::new(&name);
+    if candidate.components().count() > 1 {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+    let extensions: &[&str] = if cfg!(windows) {
+        // npm also installs an extensionless sh script, which Windows cannot run
+        &[".exe", ".cmd", ".bat", ".ps1"]
+    } else {
+        &[""]
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+        extensions
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", name, ext)))
+            .find(|path| path.is_file())
+    })
+}
+
+fn binary_version(path: &Path) -> Option<String> {
+    let output = Launcher::for_path(path.to_path_buf())
+        .std_command()
+        .arg("--version")
+        .output()
+        .ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+fn ask_missing(opts: &mut InitOptions, input: &mut impl BufRead) -> Result<()> {
+    if opts.model.is_none() {
+        println!("Models: {}", KNOWN_MODELS.join(", "));
+        let model = ask(input, "Model (empty for the Gemini CLI default)", "")?;
+        opts.model = (!model.is_empty()).then_some(model);
+    }
+    if opts.timeout_secs.is_none() {
+        let default = DEFAULT_TIMEOUT_SECS.to_string();
+        let timeout = ask(input, "Timeout per call in seconds", &default)?;
+        opts.timeout_secs = Some(
+            timeout
+                .parse()
+                .with_context(|| format!("`{}` is not a number of seconds", timeout))?,
+        );
+    }
+    Ok(())
+}
+
+fn ask(input: &mut impl BufRead, question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let answer = line.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+fn validate(model: Option<&str>, timeout_secs: u64) -> Result<()> {
+    if !(1..=MAX_TIMEOUT_SECS).contains(&timeout_secs) {
+        bail!("timeout must be between 1 and {} seconds", MAX_TIMEOUT_SECS);
+    }
+    if let Some(model) = model {
+        if model.is_empty() || model.starts_with('-') || model.contains(char::is_whitespace) {
+            bail!("`{}` is not a valid model name", model);
+        }
+        if !KNOWN_MODELS.contains(&model) {
+            println!(
+                "Warning: `{}` is not one of the known models ({}); keeping it anyway",
+                model,
+                KNOWN_MODELS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn render_config(model: Option<&str>, timeout_secs: u64) -> String {
+    let additional_args = match model {
+        Some(model) => vec!["--model", model],
+        None => Vec::new(),
+    };
+    let config = serde_json::json!({
+        "additional_args": additional_args,
+        "timeout_secs": timeout_secs,
+    });
+    format!("{:#}\n", config)
+}
+
+fn write_new(path: &Path, contents: &str, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        println!(
+            "Skipped {} (already exists; use --force to overwrite)",
+            path.display()
+        );
+        return Ok(());
+    }
+    std::fs::write(path, contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(dir: &Path) -> InitOptions {
+        InitOptions {
+            dir: dir.to_path_buf(),
+            model: Some("gemini-2.5-flash".to_string()),
+            timeout_secs: Some(300),
+            gemini_md: true,
+            force: false,
+            interactive: false,
+        }
+    }
+
+    #[test]
+    fn test_init_writes_config_and_keeps_existing_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(GEMINI_CONFIG_FILE), "mine").unwrap();
+
+        run(options(dir.path())).unwrap();
+
+        let config: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join(SERVER_CONFIG_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config["additional_args"],
+            serde_json::json!(["--model", "gemini-2.5-flash"])
+        );
+        assert_eq!(config["timeout_secs"], 300);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(GEMINI_CONFIG_FILE)).unwrap(),
+            "mine"
+        );
+
+        run(InitOptions {
+            force: true,
+            ..options(dir.path())
+        })
+        .unwrap();
+        assert!(std::fs::read_to_string(dir.path().join(GEMINI_CONFIG_FILE))
+            .unwrap()
+            .starts_with("# Project instructions"));
+    }
+
+    #[test]
+    fn test_ask_missing_uses_defaults() {
+        let mut opts = InitOptions {
+            model: None,
+            timeout_secs: None,
+            ..options(Path::new("."))
+        };
+        ask_missing(&mut opts, &mut "gemini-2.5-pro\n\n".as_bytes()).unwrap();
+        assert_eq!(opts.model.as_deref(), Some("gemini-2.5-pro"));
+        assert_eq!(opts.timeout_secs, Some(DEFAULT_TIMEOUT_SECS));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(None, 600).is_ok());
+        assert!(validate(Some("my-tuned-model"), 600).is_ok());
+        assert!(validate(Some("--yolo"), 600).is_err());
+        assert!(validate(None, 0).is_err());
+        assert!(validate(None, MAX_TIMEOUT_SECS + 1).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Parser for the single document printed by `gemini -o json`.
+//!
+//! Instead of one event per line, the CLI then prints one JSON object once
+//! the run ends, usually pretty-printed over many lines:
+//!
+//! ```json
+//! {
+//!   "session_id": "…",
+//!   "response": "The answer",
+//!   "stats": { "models": { "gemini-2.5-pro": { "tokens": { "prompt": 10, … } } },
+//!              "tools": { "totalCalls": 2, … } },
+//!   "error": { "type": "…", "message": "…" }
+//! }
+//! ```
+//!
+//! [`to_events`] turns it into the `stream-json` events the run would have
+//! printed, so both formats are read by the same code. [`DocumentBuffer`]
+//! collects the document's lines while the run prints them.
+
+use serde_json::{json, Map, Value};
+
+const KEY_RESPONSE: &str = "response";
+const KEY_ERROR: &str = "error";
+const KEY_STATS: &str = "stats";
+
+/// The `stream-json` events equivalent to `text`, or `None` when `text` is
+/// not a `-o json` document
+pub fn to_events(text: &str) -> Option<Vec<Value>> {
+    let doc: Value = serde_json::from_str(text.trim()).ok()?;
+    let doc = doc.as_object()?;
+    // A stream-json event that happens to be alone on stdout has a `type`
+    if doc.contains_key("type") || !(doc.contains_key(KEY_RESPONSE) || doc.contains_key(KEY_ERROR))
+    {
+        return None;
+    }
+
+    let stats = doc.get(KEY_STATS).and_then(Value::as_object);
+    let mut events = Vec::with_capacity(3);
+    let session_id = doc
+        .get("session_id")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let model = stats.and_then(main_model);
+    if !session_id.is_empty() || model.is_some() {
+        events.push(json!({ "type": "init", "session_id": session_id, "model": model }));
+    }
+    if let Some(response) = doc
+        .get(KEY_RESPONSE)
+        .and_then(Value::as_str)
+        .filter(|r| !r.is_empty())
+    {
+        events.push(json!({ "type": "message", "role": "assistant", "content": response }));
+    }
+
+    let mut result = json!({ "type": "result" });
+    match doc.get(KEY_ERROR).filter(|e| !e.is_null()) {
+        Some(error) => {
+            result["status"] = json!("error");
+            result["error"] = match error {
+                Value::String(message) => json!({ "message": message }),
+                other => other.clone(),
+            };
+        }
+        None => result["status"] = json!("success"),
+    }
+    if let Some(stats) = stats {
+        result["stats"] = result_stats(stats);
+    }
+    events.push(result);
+    Some(events)
+}
+
+/// Stdout lines of a run, kept up to a byte limit to be parsed as one
+/// document once stdout closes
+#[derive(Debug, Clone, Default)]
+pub struct DocumentBuffer {
+    text: String,
+    limit: usize,
+    overflowed: bool,
+}
+
+impl DocumentBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Add `line`. A document that outgrows the limit cannot be parsed once
+    /// cut, so its text is dropped.
+    pub fn push_line(&mut self, line: &str) {
+        if self.overflowed {
+            return;
+        }
+        if self.text.len() + line.len() + 1 > self.limit {
+            self.overflowed = true;
+            self.text = String::new();
+            return;
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Whether stdout outgrew the limit
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The events of the collected document, see [`to_events`]
+    pub fn events(&self) -> Option<Vec<Value>> {
+        if self.overflowed {
+            return None;
+        }
+        to_events(&self.text)
+    }
+}
+
+/// Token counts of `stats.models`, summed over all models
+fn result_stats(stats: &Map<String, Value>) -> Value {
+    let mut totals = [0u64; 3];
+    for tokens in models(stats).filter_map(|(_, m)| m.get("tokens")) {
+        for (total, key) in totals.iter_mut().zip(["prompt", "candidates", "total"]) {
+            *total += tokens.get(key).and_then(Value::as_u64).unwrap_or(0);
+        }
+    }
+    let mut out = json!({
+        "prompt_tokens": totals[0],
+        "candidate_tokens": totals[1],
+        "total_tokens": totals[2],
+    });
+    if let Some(calls) = stats
+        .get("tools")
+        .and_then(|t| t.get("totalCalls"))
+        .and_then(Value::as_u64)
+    {
+        out["tool_calls"] = json!(calls);
+    }
+    out
+}
+
+/// The model that used the most tokens; helper models such as the router
+/// use few
+fn main_model(stats: &Map<String, Value>) -> Option<String> {
+    models(stats)
+        .max_by_key(|(_, m)| {
+            m.get("tokens")
+                .and_then(|t| t.get("total"))
+                .and_then(Value::as_u64)
+                .unwrap_or(0)
+        })
+        .map(|(name, _)| name.clone())
+}
+
+fn models(stats: &Map<String, Value>) -> impl Iterator<Item = (&String, &Value)> {
+    stats
+        .get("models")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::GeminiEvent;
+
+    #[test]
+    fn test_document_becomes_events() {
+        let text = r#"{
+  "session_id": "s1",
+  "response": "Hello",
+  "stats": {
+    "models": {
+      "gemini-2.5-flash-lite": { "tokens": { "prompt": 5, "candidates": 1, "total": 6 } },
+      "gemini-2.5-pro": { "tokens": { "prompt": 100, "candidates": 20, "total": 130 } }
+    },
+    "tools": { "totalCalls": 2 }
+  }
+}"#;
+        let events = to_events(text).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["session_id"], "s1");
+        assert_eq!(events[0]["model"], "gemini-2.5-pro");
+        assert_eq!(events[1]["content"], "Hello");
+        match GeminiEvent::from_value(&events[2]).unwrap() {
+            GeminiEvent::Result(result) => {
+                assert_eq!(result.status.as_deref(), Some("success"));
+                let stats = result.stats.unwrap();
+                assert_eq!(stats.usage.prompt_tokens, 105);
+                assert_eq!(stats.usage.total_tokens, 136);
+                assert_eq!(stats.tool_calls, Some(2));
+            }
+            other => panic!("expected a result event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_document_is_a_failure() {
+        let events =
+            to_events(r#"{"response": "", "error": {"type": "ApiError", "message": "Quota"}}"#)
+                .unwrap();
+        assert_eq!(events.len(), 1);
+        let result = GeminiEvent::from_value(&events[0]).unwrap();
+        assert!(result.is_failure());
+        assert_eq!(result.failure_message(), Some("Quota"));
+    }
+
+    #[test]
+    fn test_other_output_is_not_a_document() {
+        assert_eq!(to_events("plain text"), None);
+        assert_eq!(
+            to_events(r#"{"type": "result", "status": "success"}"#),
+            None
+        );
+        assert_eq!(to_events(r#"{"unrelated": true}"#), None);
+        assert_eq!(to_events("[1, 2]"), None);
+    }
+    #[test]
+    fn test_long_document_is_collected_whole() {
+        let models: Vec<String> = (0..600)
+            .map(|n| {
+                format!(
+                    "      \"model-{}\": {{\n        \"tokens\": {{ \"prompt\": 1, \"total\": 1 }}\n      }}",
+                    n
+                )
+            })
+            .collect();
+        let text = format!(
+            "{{\n  \"response\": \"Hello\",\n  \"stats\": {{\n    \"models\": {{\n{}\n    }}\n  }}\n}}",
+            models.join(",\n")
+        );
+        assert!(text.lines().count() > 1000);
+
+        let mut buffer = DocumentBuffer::new(text.len() + 100);
+        for line in text.lines() {
+            buffer.push_line(line);
+        }
+        let events = buffer.events().unwrap();
+        assert_eq!(events.last().unwrap()["stats"]["prompt_tokens"], 600);
+
+        let mut small = DocumentBuffer::new(text.len() / 2);
+        for line in text.lines() {
+            small.push_line(line);
+        }
+        assert!(small.is_overflowed());
+        assert_eq!(small.events(), None);
+    }
+}
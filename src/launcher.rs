@@ -0,0 +1,188 @@
+//! How to start the Gemini CLI.
+//!
+//! On Unix `gemini` (or `GEMINI_BIN`) is executed directly. On Windows npm
+//! installs the CLI as `gemini.cmd` and `gemini.ps1` shims, which
+//! `CreateProcess` cannot start by their bare name. There the name is
+//! resolved with `where`, batch files are run through `cmd.exe` (by the
+//! standard library) and PowerShell scripts through `powershell.exe -File`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Arguments placed before the script path when running a `.ps1` shim
+const POWERSHELL_ARGS: &[&str] = &[
+    "-NoLogo",
+    "-NoProfile",
+    "-NonInteractive",
+    "-ExecutionPolicy",
+    "Bypass",
+    "-File",
+];
+
+/// What kind of file the CLI entry point is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LauncherKind {
+    /// Native executable (or any file on Unix)
+    Executable,
+    /// `.cmd`/`.bat` script; arguments cannot contain line breaks
+    Batch,
+    /// `.ps1` script
+    PowerShell,
+}
+
+/// Resolved Gemini CLI entry point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Launcher {
+    pub kind: LauncherKind,
+    /// Full path when it could be resolved, the configured name otherwise
+    pub path: PathBuf,
+}
+
+impl Launcher {
+    /// Classify `path` by its extension
+    pub fn for_path(path: PathBuf) -> Self {
+        let kind = match extension(&path).as_deref() {
+            Some("cmd" | "bat") => LauncherKind::Batch,
+            Some("ps1") => LauncherKind::PowerShell,
+            _ => LauncherKind::Executable,
+        };
+        Self { kind, path }
+    }
+
+    /// Command starting the CLI, ready for its arguments
+    pub fn std_command(&self) -> std::process::Command {
+        match self.kind {
+            LauncherKind::Executable | LauncherKind::Batch => {
+                std::process::Command::new(&self.path)
+            }
+            LauncherKind::PowerShell => {
+                let mut cmd = std::process::Command::new("powershell.exe");
+                cmd.args(POWERSHELL_ARGS).arg(&self.path);
+                cmd
+            }
+        }
+    }
+
+    /// [`Self::std_command`] for tokio
+    pub fn command(&self) -> tokio::process::Command {
+        tokio::process::Command::from(self.std_command())
+    }
+
+    /// Scripts mangle multi-line arguments, so prompts should go through
+    /// stdin
+    pub fn prefers_stdin(&self) -> bool {
+        self.kind != LauncherKind::Executable
+    }
+}
+
+/// Resolve `GEMINI_BIN` (default `gemini`). Results are cached per name.
+pub fn resolve() -> Launcher {
+    static CACHE: Mutex<Option<(String, Launcher)>> = Mutex::new(None);
+
+    let name = std::env::var("GEMINI_BIN").unwrap_or_else(|_| "gemini".to_string());
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached, launcher)) = cache.as_ref() {
+        if *cached == name {
+            return launcher.clone();
+        }
+    }
+    let launcher = resolve_name(&name);
+    *cache = Some((name, launcher.clone()));
+    launcher
+}
+
+/// Resolve an explicitly chosen executable the same way as `GEMINI_BIN`
+pub fn resolve_path(path: PathBuf) -> Launcher {
+    match path.to_str() {
+        Some(name) => resolve_name(name),
+        None => Launcher::for_path(path),
+    }
+}
+
+fn resolve_name(name: &str) -> Launcher {
+    #[cfg(windows)]
+    if Path::new(name).extension().is_none() {
+        let found = std::process::Command::new("where")
+            .arg(name)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| pick_candidate(&String::from_utf8_lossy(&output.stdout)));
+        if let Some(path) = found {
+            return Launcher::for_path(path);
+        }
+    }
+    Launcher::for_path(PathBuf::from(name))
+}
+
+/// The entry point to use from `where` output: the first executable or batch
+/// file, else a PowerShell script (listed, or next to an extensionless shim)
+#[cfg_attr(not(windows), allow(dead_code))]
+fn pick_candidate(where_output: &str) -> Option<PathBuf> {
+    let candidates: Vec<PathBuf> = where_output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let runnable = candidates.iter().find(|path| {
+        matches!(
+            extension(path).as_deref(),
+            Some("exe" | "com" | "cmd" | "bat")
+        )
+    });
+    let powershell = || {
+        candidates
+            .iter()
+            .find_map(|path| match extension(path).as_deref() {
+                Some("ps1") => Some(path.clone()),
+                None => Some(path.with_extension("ps1")).filter(|p| p.is_file()),
+                _ => None,
+            })
+    };
+    runnable.cloned().or_else(powershell)
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launcher_kind_from_extension() {
+        let kind = |path: &str| Launcher::for_path(PathBuf::from(path)).kind;
+        assert_eq!(kind("gemini"), LauncherKind::Executable);
+        assert_eq!(kind(r"C:\tools\gemini.EXE"), LauncherKind::Executable);
+        assert_eq!(kind(r"C:\npm\gemini.cmd"), LauncherKind::Batch);
+        assert_eq!(kind("gemini.bat"), LauncherKind::Batch);
+        assert_eq!(kind("gemini.ps1"), LauncherKind::PowerShell);
+
+        let ps = Launcher::for_path(PathBuf::from("gemini.ps1"));
+        assert!(ps.prefers_stdin());
+        let cmd = ps.std_command();
+        assert_eq!(cmd.get_program(), "powershell.exe");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args.last().unwrap().to_str(), Some("gemini.ps1"));
+        assert_eq!(args[args.len() - 2], "-File");
+    }
+
+    #[test]
+    fn test_pick_candidate() {
+        let listed = "C:\\npm\\gemini\r\nC:\\npm\\gemini.cmd\r\nC:\\npm\\gemini.ps1\r\n";
+        assert_eq!(
+            pick_candidate(listed),
+            Some(PathBuf::from("C:\\npm\\gemini.cmd"))
+        );
+        assert_eq!(
+            pick_candidate("C:\\npm\\gemini\nC:\\npm\\gemini.ps1\n"),
+            Some(PathBuf::from("C:\\npm\\gemini.ps1"))
+        );
+        assert_eq!(pick_candidate("C:\\npm\\gemini\n"), None);
+        assert_eq!(pick_candidate(""), None);
+    }
+}
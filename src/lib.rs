@@ -1,2 +1,108 @@
+pub mod acp;
+#[cfg(feature = "server")]
+pub mod actions;
+pub mod activity;
+#[cfg(feature = "server")]
+pub mod artifacts;
+#[cfg(feature = "server")]
+pub mod audit;
+pub mod auth;
+#[cfg(feature = "server")]
+pub mod batch;
+#[cfg(feature = "server")]
+pub mod budget;
+pub mod build_info;
+#[cfg(feature = "server")]
+pub mod bundle;
+pub mod cache;
+pub mod chaos;
+#[cfg(feature = "server")]
+pub mod chat;
+pub mod chunking;
+pub mod citation;
+#[cfg(feature = "server")]
+pub mod cli_chats;
+pub mod cli_compat;
+pub mod clock;
+#[cfg(feature = "server")]
+pub mod command_guard;
+#[cfg(feature = "server")]
+pub mod compare;
+#[cfg(feature = "server")]
+pub mod condition;
+pub mod config_check;
+pub mod config_layers;
+#[cfg(feature = "server")]
+pub mod consensus;
+#[cfg(feature = "server")]
+pub mod doctor;
+#[cfg(feature = "server")]
+pub mod embed;
+pub mod event;
+#[cfg(feature = "tree-sitter")]
+pub mod extract;
 pub mod gemini;
+pub mod glob;
+#[cfg(feature = "server")]
+pub mod guard;
+pub mod hooks;
+#[cfg(feature = "server")]
+pub mod index;
+#[cfg(feature = "server")]
+pub mod init;
+pub mod json_output;
+pub mod launcher;
+#[cfg(feature = "server")]
+pub mod logging;
+pub mod login;
+#[cfg(feature = "server")]
+pub mod maintenance;
+#[cfg(feature = "server")]
+pub mod memory;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod outputs;
+#[cfg(feature = "server")]
+pub mod pack;
+#[cfg(feature = "server")]
+pub mod patch;
+pub mod persona;
+pub mod placeholders;
+pub mod policy;
+pub mod pool;
+pub mod preset;
+mod process_tree;
+#[cfg(feature = "server")]
+pub mod prompt;
+#[cfg(feature = "server")]
+pub mod ratelimit;
+#[cfg(feature = "server")]
+pub mod repl;
+#[cfg(feature = "server")]
+pub mod search;
+#[cfg(feature = "server")]
+pub mod selftest;
+#[cfg(feature = "server")]
 pub mod server;
+#[cfg(feature = "server")]
+pub mod session;
+pub mod singleflight;
+#[cfg(feature = "server")]
+pub mod stats;
+pub mod stderr;
+#[cfg(feature = "sqlite")]
+pub mod store;
+#[cfg(feature = "server")]
+pub mod summary;
+#[cfg(feature = "tree-sitter")]
+pub mod syntax;
+pub mod template;
+#[cfg(feature = "http")]
+pub mod transport;
+#[cfg(feature = "server")]
+pub mod truncate;
+#[cfg(feature = "server")]
+pub mod verify;
+#[cfg(feature = "server")]
+pub mod workflow;
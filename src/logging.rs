@@ -0,0 +1,105 @@
+//! Log output of the server binary.
+//!
+//! Everything is logged through `tracing`. Human-readable lines go to stderr
+//! (stdout carries the MCP protocol); `logging.file` in the config adds a
+//! JSON-lines copy with the fields of the enclosing spans, such as the
+//! `request_id`, `session_id` and `pid` of a `gemini_call`. `RUST_LOG`
+//! overrides the configured level.
+
+use crate::gemini::{self, LoggingConfig};
+use std::fs::OpenOptions;
+use std::sync::Mutex;
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Filter when neither `RUST_LOG` nor `logging.level` is set: the server's
+/// own events, and only warnings from dependencies
+pub const DEFAULT_LEVEL: &str = "warn,gemini_mcp_rs=info";
+
+fn stderr_layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .with_target(false)
+}
+
+/// Install the global subscriber. Call once, before the server starts.
+pub fn init() {
+    // Problems with the config file itself are reported while it is read,
+    // before the configured subscriber exists
+    let config = tracing::subscriber::with_default(
+        tracing_subscriber::registry().with(stderr_layer()),
+        gemini::logging,
+    );
+
+    let filter = filter(&config);
+    let file_layer = config.file.as_ref().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(Mutex::new(file)),
+            ),
+            Err(err) => {
+                eprintln!(
+                    "gemini-mcp-rs: cannot open log file {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    });
+
+    // Fails only if a subscriber is already installed, e.g. in tests
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr_layer())
+        .with(file_layer)
+        .try_init();
+}
+
+/// `RUST_LOG` if set and valid, else `logging.level`, else [`DEFAULT_LEVEL`]
+fn filter(config: &LoggingConfig) -> EnvFilter {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return filter;
+    }
+    let level = config.level.as_deref().unwrap_or(DEFAULT_LEVEL);
+    EnvFilter::try_new(level).unwrap_or_else(|err| {
+        eprintln!(
+            "gemini-mcp-rs: invalid logging.level `{}` ({}); using `{}`",
+            level, err, DEFAULT_LEVEL
+        );
+        EnvFilter::new(DEFAULT_LEVEL)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_falls_back_to_default() {
+        if std::env::var_os("RUST_LOG").is_some() {
+            return;
+        }
+        let config = |level: Option<&str>| LoggingConfig {
+            level: level.map(str::to_string),
+            file: None,
+        };
+        let default = EnvFilter::new(DEFAULT_LEVEL).to_string();
+        assert_eq!(filter(&config(Some("debug"))).to_string(), "debug");
+        assert_eq!(filter(&config(None)).to_string(), default);
+        assert_eq!(
+            filter(&config(Some("gemini_mcp_rs=loud"))).to_string(),
+            default
+        );
+    }
+}
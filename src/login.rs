@@ -0,0 +1,161 @@
+//! Detection of the CLI asking to log in.
+//!
+//! Without cached credentials the CLI starts its OAuth flow: it prints a
+//! login URL and waits for the browser, or for an authorization code typed on
+//! stdin. Nobody can answer either in a headless run, so the CLI would sit
+//! there until the timeout. The run is stopped as soon as the prompt shows up
+//! and fails with [`AuthRequired`] instead.
+
+/// Lines announcing a login, in lowercase
+const PROMPT_MARKERS: &[&str] = &[
+    "login required",
+    "to authorize the application",
+    "authentication page in your browser",
+    "navigate to:",
+    "please set an auth method",
+];
+
+/// Lines after which the CLI blocks until the login completes, in lowercase
+const WAITING_MARKERS: &[&str] = &[
+    "waiting for authentication",
+    "enter the authorization code",
+    "please set an auth method",
+];
+
+/// Output lines kept for the error message
+const MAX_LINES: usize = 20;
+
+/// What to tell the user when the CLI needs a login
+pub const INSTRUCTIONS: &str = "The Gemini CLI is not logged in. Run `gemini` once in a terminal and complete the login (or open the login URL), or configure GEMINI_API_KEY or an auth profile for the server, then retry the call.";
+
+/// Error of a run stopped because the CLI asked to log in. Downcast the
+/// `anyhow::Error` to get at the login URL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthRequired {
+    /// URL the CLI asked to open, if it printed one
+    pub login_url: Option<String>,
+    /// What the CLI printed about the login
+    pub output: String,
+}
+
+impl std::fmt::Display for AuthRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.login_url {
+            Some(ref url) => write!(f, "Gemini CLI requires authentication (login URL: {})", url),
+            None => write!(f, "Gemini CLI requires authentication"),
+        }
+    }
+}
+
+impl std::error::Error for AuthRequired {}
+
+/// Watches the CLI's non-JSON output for a login prompt
+#[derive(Debug, Default)]
+pub struct LoginDetector {
+    prompted: bool,
+    login_url: Option<String>,
+    lines: Vec<String>,
+    /// Whether a `-o json` document started on stdout
+    in_document: bool,
+}
+
+impl LoginDetector {
+    /// Look at one output line; returns the error to stop the run with once
+    /// the CLI asked to log in and either printed the URL or started waiting
+    pub fn feed(&mut self, line: &str) -> Option<AuthRequired> {
+        let lower = line.to_lowercase();
+        let waiting = WAITING_MARKERS.iter().any(|m| lower.contains(m));
+        if waiting || PROMPT_MARKERS.iter().any(|m| lower.contains(m)) {
+            self.prompted = true;
+        }
+        if !self.prompted {
+            return None;
+        }
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && self.lines.len() < MAX_LINES {
+            self.lines.push(trimmed.to_string());
+        }
+        if self.login_url.is_none() {
+            self.login_url = find_url(line);
+        }
+        (waiting || self.login_url.is_some()).then(|| AuthRequired {
+            login_url: self.login_url.clone(),
+            output: self.lines.join("\n"),
+        })
+    }
+
+    /// Look at one stdout line of a `-o json` run. The document may quote a
+    /// login prompt in its answer, so only the lines before it are looked at.
+    pub fn feed_before_document(&mut self, line: &str) -> Option<AuthRequired> {
+        self.in_document |= line.trim_start().starts_with('{');
+        if self.in_document {
+            return None;
+        }
+        self.feed(line)
+    }
+}
+
+/// First http(s) URL in `line`, without trailing punctuation
+fn find_url(line: &str) -> Option<String> {
+    let start = line.find("https://").or_else(|| line.find("http://"))?;
+    let url = line[start..]
+        .split(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '<' || c == '>')
+        .next()?
+        .trim_end_matches(['.', ',', ')', ']', ';']);
+    Some(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_login_prompt() {
+        let mut detector = LoginDetector::default();
+        assert_eq!(detector.feed("Loaded cached credentials."), None);
+        assert_eq!(detector.feed("see https://example.com/docs"), None);
+        assert_eq!(detector.feed("Code Assist login required."), None);
+        assert_eq!(
+            detector.feed("Attempting to open authentication page in your browser."),
+            None
+        );
+        assert_eq!(detector.feed("Otherwise navigate to:"), None);
+        assert_eq!(detector.feed(""), None);
+        let error = detector
+            .feed("https://accounts.google.com/o/oauth2/v2/auth?client_id=1&scope=openid.")
+            .unwrap();
+        assert_eq!(
+            error.login_url.as_deref(),
+            Some("https://accounts.google.com/o/oauth2/v2/auth?client_id=1&scope=openid")
+        );
+        assert!(error.output.starts_with("Code Assist login required."));
+    }
+
+    #[test]
+    fn test_waiting_without_url() {
+        let mut detector = LoginDetector::default();
+        let error = detector.feed("Waiting for authentication...").unwrap();
+        assert_eq!(error.login_url, None);
+        assert_eq!(error.to_string(), "Gemini CLI requires authentication");
+    }
+    #[test]
+    fn test_json_document_is_not_a_prompt() {
+        let mut detector = LoginDetector::default();
+        assert_eq!(detector.feed_before_document("{"), None);
+        assert_eq!(
+            detector.feed_before_document(
+                r#"  "response": "Login required: navigate to: https://example.com/login","#
+            ),
+            None
+        );
+        assert_eq!(
+            detector.feed_before_document(r#"  "error": "Waiting for authentication""#),
+            None
+        );
+
+        let mut detector = LoginDetector::default();
+        assert!(detector
+            .feed_before_document("Waiting for authentication...")
+            .is_some());
+    }
+}
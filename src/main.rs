@@ -1,7 +1,13 @@
 use anyhow::Result;
-use clap::Parser;
-use gemini_mcp_rs::server::GeminiServer;
+use clap::{Parser, Subcommand, ValueEnum};
+use gemini_mcp_rs::{
+    batch, build_info, cli_compat, doctor, gemini, init, logging, repl, selftest,
+    server::GeminiServer,
+};
 use rmcp::{transport::stdio, ServiceExt};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 /// MCP server wrapping the Gemini CLI for AI-driven tasks
 #[derive(Parser)]
@@ -12,10 +18,53 @@ use rmcp::{transport::stdio, ServiceExt};
     long_about = None,
     after_help = "ENVIRONMENT VARIABLES:
   GEMINI_BIN                   Override the gemini binary path (default: 'gemini')
+  GEMINI_MCP_CONFIG_PATH       Project config file (default: ./gemini-mcp.config.json,
+                               ./gemini-mcp.toml or ./gemini-mcp.yaml)
+  GEMINI_MCP__<KEY>            Override a config value; '__' separates nested keys,
+                               e.g. GEMINI_MCP__RATE_LIMIT__GLOBAL_PER_MINUTE=30
+  RUST_LOG                     Log filter, e.g. 'debug' (overrides logging.level)
 
 USAGE:
-  This server communicates via stdio using the Model Context Protocol (MCP).
-  It should be configured in your MCP client (e.g., Claude Desktop) settings.
+  By default this server communicates via stdio using the Model Context Protocol
+  (MCP). It should be configured in your MCP client (e.g., Claude Desktop) settings.
+
+  To run it as a long-lived network service shared by several clients, build with
+  the `http` feature and pass --transport:
+    gemini-mcp-rs --transport sse --bind 127.0.0.1:8080              (GET /sse, POST /message)
+    gemini-mcp-rs --transport streamable-http --bind 127.0.0.1:8080  (/mcp)
+
+  Send SIGUSR1 to dump in-flight requests (with child PIDs), queued calls and free
+  run slots to stderr as JSON (also served at GET /diagnostics by the network
+  transports, next to Prometheus metrics at GET /metrics and the health report at
+  GET /healthz, which answers 503 when unhealthy).
+
+  On SIGINT or SIGTERM the server rejects new calls, waits up to
+  `shutdown_grace_secs` (default 30) for running Gemini processes to finish, then
+  kills the rest before exiting.
+
+  The config is merged from, lowest precedence first: built-in defaults,
+  ~/.config/gemini-mcp/config.{json,toml,yaml}, the project config,
+  GEMINI_MCP__* variables and --set KEY=VALUE flags. Run
+  `gemini-mcp-rs --print-config` to see the result and where it came from.
+
+  `gemini-mcp-rs version` prints the server, git commit and Gemini CLI versions;
+  `gemini-mcp-rs check` checks the config, the CLI and the state directory and
+  exits with 1 when a check fails.
+
+  To generate a starter gemini-mcp.config.json and GEMINI.md, run
+  `gemini-mcp-rs init` (add --yes --model NAME to skip the questions).
+
+  To verify the whole setup end to end, run `gemini-mcp-rs selftest`. It starts
+  the server, connects over stdio, lists the tools, calls gemini_health and sends
+  one trivial prompt, printing PASS/FAIL per step (exit code 1 on failure).
+
+  To try prompts locally without an MCP client, run `gemini-mcp-rs repl`. It keeps
+  session continuity between prompts and supports /model, /new and /session.
+
+  To process a dataset offline, pass a JSONL file of tool calls to `batch`:
+    gemini-mcp-rs batch --input prompts.jsonl --output results.jsonl --concurrency 4
+  Each input line is a 'gemini' tool call ({\"PROMPT\": ..., \"EXTRA_ARGS\": [...]}) with an
+  optional \"id\" that is echoed back; results are written in input order.
 
   Example MCP client configuration:
     {
@@ -29,26 +78,148 @@ USAGE:
 SUPPORTED PARAMETERS:
   The 'gemini' tool accepts the following parameters:
 
-  PROMPT (required)            Task instruction to send to Gemini
-  sandbox                      Run in sandbox mode (default: false)
+  PROMPT (required)            Task instruction to send to Gemini, or an array of
+                               text/file/image/url parts
+  SANDBOX                      Run tool executions in the CLI sandbox (default: config `sandbox`)
   SESSION_ID                   Resume an existing session (from previous response)
+  SESSION_LABEL                Human-readable session name mapped to a SESSION_ID
+  EXTRA_ARGS                   Per-call CLI arguments, checked against extra_args_allowlist
+  ENV                          Per-call CLI environment variables, checked against env_allowlist
+  RAW_RESULT                   Return minified JSON instead of TOON (default: false)
+  APPROVAL_MODE                default | auto_edit | yolo, capped by config `max_approval_mode`
+  INJECT_MEMORY                Prepend remembered facts to a new session (default: config)
+  USE_RETRIEVAL                Prepend relevant excerpts from the workspace index (default: false)
+  CONTEXT_GLOBS                Prepend workspace files matching these globs, e.g. [\"src/**/*.rs\"]
+                               (git-ignored files skipped; limits in config `context_pack`)
+  INCLUDE_DIRECTORIES          Extra workspace directories inside config `allowed_roots`
+                               (default: the working directory), not matching `denied_paths`
+  SAVE_BUNDLE                  Save the call for gemini_replay_request (default: config)
+  ALLOW_SENSITIVE              Send prompts that look like secrets or binary data (default: false)
+  AUTH_PROFILE                 Credentials to run as, from config `auth_profiles`
+  INCLUDE_THOUGHTS             Return the model's reasoning as `thoughts` (default: false)
+  SPLIT_MESSAGES               Also return each assistant message as `messages` (default: false)
+  EXTRACT_ARTIFACTS            Return fenced code blocks as `artifacts` (default: false)
+  WRITE_ARTIFACTS              Also save them under config `artifacts_dir` (default: false)
+  GROUNDING                    Allow Google Search grounding; sources returned as `citations`
+  TURNS                        Follow-up prompts run in the same session, e.g. [{\"PROMPT\": \"...\"}] (max 10)
+                               Entries may set STOP_IF, or WHEN with ELSE, on the previous answer
+                               ({\"REGEX\": ...} or {\"JSON_PATH\": \"$.a\", \"EQUALS\": ...})
   return_all_messages          Return all messages including reasoning (default: false)
   model                        Model to use (overrides Gemini CLI default)
   timeout_secs                 Timeout in seconds (default: 600, max: 3600)
 
 GEMINI.md SUPPORT:
-  If a GEMINI.md file exists in the working directory, its content will be
-  automatically prepended to the prompt as a system prompt. This allows you to
-  define project-specific instructions or context for all Gemini invocations.
-  Maximum file size: 100KB
+  GEMINI.md files are automatically prepended to the prompt as a system prompt.
+  The server reads ~/.gemini/GEMINI.md and every GEMINI.md from the project root
+  (nearest directory containing .git) down to the working directory, most general
+  first. Set \"hierarchical_gemini_md\": false in the config to only read
+  ./GEMINI.md. Maximum file size: 100KB per file
+  With \"placeholders\": {\"enabled\": true} in the config, {{date}}, {{cwd}},
+  {{git_branch}} and {{env:VAR}} (VAR listed in \"placeholders.env\") are expanded
+  in GEMINI.md and prompts; write \\{{date}} to keep one as written.
 
 RETURN STRUCTURE:
   The tool returns:
   - success: boolean indicating execution status
   - SESSION_ID: unique identifier for resuming conversations
   - agent_messages: concatenated assistant response text
+  - summary: short preview of the response (config `summary`)
+  - truncation: strategy and original length when the response was cut to
+    config `max_output_chars`
   - all_messages: (optional) complete JSON events when return_all_messages=true
   - error: error description when success=false
+  - usage: prompt/candidate/total token counts, when reported by the CLI
+  - warnings: non-fatal degradations (skipped GEMINI.md, truncated output,
+    model fallback), when any occurred
+  - turns: success/message/error/usage of every turn, when TURNS was given
+  - stopped: which STOP_IF ended a TURNS call early
+  - citations: sources of a grounded answer (url, title, start/end offsets)
+  - tool_activity: tools Gemini invoked (tool, args, success, duration_ms, error)
+  - thoughts: the model's reasoning, when INCLUDE_THOUGHTS=true
+  - messages: each assistant message (content, timestamp, tools_before), when
+    SPLIT_MESSAGES=true
+  - artifacts: fenced code blocks (language, content, filename, path), when
+    EXTRACT_ARTIFACTS or WRITE_ARTIFACTS=true
+  - bundle_id: saved request bundle, when SAVE_BUNDLE=true
+  - metadata: server_version, git_sha and cli_version that produced the output
+
+  Completed sessions are exposed as MCP resources:
+  gemini://sessions/<SESSION_ID>/transcript (assistant replies) and
+  gemini://sessions/<SESSION_ID>/events (raw CLI events).
+
+  Common tasks (review-file, explain-code, write-tests, continue-session and
+  configured \"templates\") are offered as MCP prompts with argument
+  completion.
+
+  The 'gemini_kill_request' admin tool terminates the CLI process of one in-flight
+  call by the request id shown in progress notifications, logs and diagnostics.
+
+  Every entry of \"presets\" in the config ({\"name\", \"args\", \"timeout_secs\",
+  \"description\"}) is registered as its own tool, e.g. 'gemini_flash', taking the
+  same parameters as 'gemini'.
+
+  The 'gemini_run_workflow' tool runs a YAML workflow (steps, variables, attachments,
+  when/stop_if conditions) from the workflows directory (config \"workflows_dir\",
+  default ./workflows next to the config file) and returns a per-step result array.
+
+  The 'gemini_chat' tool continues a conversation by LABEL in a fresh CLI session,
+  replaying a history the server keeps (bounded by config \"chat\": {\"max_turns\",
+  \"max_chars\"}) instead of resuming; RESET starts it over.
+
+  The 'gemini_apply_patch' tool applies a unified diff (PATCH) to files under the
+  working directory, all or nothing, reporting conflicting hunks; DRY_RUN only
+  checks.
+
+  The 'gemini_fetch_output' tool pages through the whole answer of a call whose
+  message was cut to config \"max_output_chars\" (SESSION_ID, OFFSET, LIMIT).
+
+  The 'gemini_replay_request' tool (or the 'replay' command) runs a call saved with
+  SAVE_BUNDLE again by BUNDLE_ID, from bundles/ in the state directory.
+
+  The 'gemini_compare' tool runs one PROMPT against 2-8 PROFILES (model, SESSION_ID,
+  EXTRA_ARGS) and returns the answers side by side with a line diff and a summary
+  of the disagreements.
+
+  The 'gemini_fanout' tool runs one PROMPT against several MODELS (default: config
+  \"fanout_models\") concurrently and returns every answer side by side.
+
+  The 'gemini_consensus' tool generates CANDIDATES (default 3) answers to PROMPT,
+  optionally over MODELS, then a judging call (JUDGE_MODEL) critiques and merges
+  them; it returns the final message, the critique and every candidate.
+
+  The 'gemini_memory_set', 'gemini_memory_get' and 'gemini_memory_list' tools keep
+  durable facts in the state directory; set \"inject_memory\": true (or
+  INJECT_MEMORY on a call) to prepend them to new sessions.
+
+  The 'gemini_index_workspace' tool embeds the working directory's text files
+  when \"retrieval\": {\"enabled\": true} is configured, re-embedding only
+  changed files on later calls (FULL rebuilds); calls with USE_RETRIEVAL then
+  get the most relevant chunks prepended.
+
+  The 'gemini_doctor' tool checks the CLI binary, its version, credentials
+  (PROBE_AUTH sends a test prompt), the config file and the state directory,
+  with a fix hint for every failing check.
+
+  For resilience testing, config \"chaos\": {\"enabled\": true, \"latency_rate\",
+  \"stderr_rate\", \"timeout_rate\"} randomly delays runs, adds spurious stderr
+  lines and forces timeouts.
+
+  The 'gemini_health' tool (also exposed as 'ping') runs `gemini --version`,
+  re-validates the config and reports the server and CLI versions, uptime and
+  in-flight calls; it returns an error result when unhealthy.
+
+  The 'gemini_usage' tool reports token totals and estimated cost per session
+  (optionally for one SESSION_ID) accumulated since the server started, and
+  today's spend against the config \"budget.daily_usd\".
+
+  The 'gemini_search_transcripts' tool finds sessions whose transcript contains
+  every word of QUERY and returns their SESSION_IDs with snippets.
+
+  The 'gemini_delete_session' tool removes a session (by SESSION_ID or
+  SESSION_LABEL); 'gemini_restore_session' brings it back within
+  \"session_retention_secs\" (default 7 days).
+  Sessions idle longer than \"session_ttl_secs\" are forgotten (and their CLI
+  chat files deleted with \"clean_cli_chats\": true).
 
 BEST PRACTICES:
   - Always capture and reuse SESSION_ID for multi-turn interactions
@@ -57,24 +228,394 @@ BEST PRACTICES:
   - Keep GEMINI.md under 100KB for optimal performance
 
 SECURITY:
+  - Paths from calls and answers (INCLUDE_DIRECTORIES, file parts, SYMBOLS,
+    CONTEXT_GLOBS, patches, artifacts) must lie inside config \"allowed_roots\"
+    and not match \"denied_paths\"
+  - --yolo, --approval-mode yolo, --allowed-tools and --telemetry-* flags are
+    refused in additional_args and EXTRA_ARGS unless \"allow_dangerous_args\"
+  - Config \"audit.path\" appends one JSON line per CLI run (client, prompt
+    hash, session, model, status, duration)
   - Timeouts are enforced to prevent unbounded execution
   - Sandbox mode restricts operations to isolated environments
   - Maximum timeout is 3600 seconds (1 hour)
 
 For more information, visit: https://github.com/missdeer/gemini-mcp-rs"
 )]
-struct Cli {}
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Project config file (instead of GEMINI_MCP_CONFIG_PATH or the
+    /// working directory's gemini-mcp.config.json)
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// Gemini CLI executable (instead of GEMINI_BIN or `gemini` on PATH)
+    #[arg(long, value_name = "PATH", global = true)]
+    gemini_bin: Option<PathBuf>,
+
+    /// Default timeout per Gemini call in seconds (config `timeout_secs`)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..=3600))]
+    timeout: Option<u64>,
+
+    /// Model of calls that choose none (config `model`)
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Log filter such as `debug` or `gemini_mcp_rs=trace` (config
+    /// `logging.level`; RUST_LOG still takes precedence)
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+
+    /// Transport used to serve MCP
+    #[arg(long, value_enum, default_value_t = Transport::Stdio)]
+    transport: Transport,
+
+    /// Address to listen on for the sse and streamable-http transports
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: SocketAddr,
+
+    /// Override a config value, e.g. `--set rate_limit.global_per_minute=30`;
+    /// takes precedence over config files and GEMINI_MCP__* variables
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    overrides: Vec<String>,
+
+    /// Print the effective config merged from every layer as JSON and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Interactive prompt loop running through the same pipeline as the MCP tool
+    Repl,
+    /// Generate a starter gemini-mcp.config.json and GEMINI.md
+    Init {
+        /// Directory to write the files to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        /// Model to pin in `additional_args` (default: ask, or the CLI default)
+        #[arg(long)]
+        model: Option<String>,
+        /// Timeout per Gemini call in seconds (default: ask, or 600)
+        #[arg(long)]
+        timeout_secs: Option<u64>,
+        /// Do not write a starter GEMINI.md
+        #[arg(long)]
+        no_gemini_md: bool,
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+        /// Do not ask questions; use flags and defaults only
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Start the server, connect to it over stdio and check tools, the Gemini
+    /// CLI and one prompt end to end
+    Selftest {
+        /// Prompt for the end-to-end call
+        #[arg(long, default_value = selftest::DEFAULT_PROMPT)]
+        prompt: String,
+        /// Only check the connection, the tool list and gemini_health
+        #[arg(long)]
+        skip_prompt: bool,
+        /// Timeout for the end-to-end call in seconds
+        #[arg(long, default_value_t = 120)]
+        timeout_secs: u64,
+    },
+    /// Run a JSONL file of `gemini` tool calls and write the results as JSONL
+    Batch {
+        /// Input file with one `{"PROMPT": ...}` object per line (`-` for stdin)
+        #[arg(long, default_value = "-")]
+        input: PathBuf,
+        /// Output file for the results, in input order (`-` for stdout)
+        #[arg(long, default_value = "-")]
+        output: PathBuf,
+        /// Maximum number of prompts processed at the same time
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+    },
+    /// Run a request bundle saved with SAVE_BUNDLE again and print the result
+    /// as JSON
+    Replay {
+        /// Bundle id from the `bundle_id` of the saved call
+        bundle_id: String,
+    },
+    /// Print the server version, the git commit it was built from and the
+    /// Gemini CLI version
+    Version,
+    /// Check the config, the Gemini CLI and the state directory without
+    /// starting the server; exits with 1 when a check fails
+    Check,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    Stdio,
+    Sse,
+    StreamableHttp,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse command-line arguments (this will handle -h/--help and --version)
-    let _cli = Cli::parse();
+    let cli = Cli::parse();
+
+    // Flags that stand in for environment variables are applied as such, so
+    // the child processes of `selftest` see them too
+    if let Some(ref path) = cli.config {
+        std::env::set_var("GEMINI_MCP_CONFIG_PATH", path);
+    }
+    if let Some(ref path) = cli.gemini_bin {
+        std::env::set_var("GEMINI_BIN", path);
+    }
+    // Flags that stand in for config values form the `--set` layer, below
+    // explicit `--set` assignments
+    let mut overrides = Vec::new();
+    if let Some(secs) = cli.timeout {
+        overrides.push(format!("timeout_secs={}", secs));
+    }
+    if let Some(ref model) = cli.model {
+        overrides.push(format!("model={}", serde_json::Value::from(model.as_str())));
+    }
+    if let Some(ref level) = cli.log_level {
+        overrides.push(format!(
+            "logging.level={}",
+            serde_json::Value::from(level.as_str())
+        ));
+    }
+    overrides.extend(cli.overrides.iter().cloned());
+    gemini::set_config_overrides(overrides);
+
+    if let Some(Command::Init {
+        dir,
+        model,
+        timeout_secs,
+        no_gemini_md,
+        force,
+        yes,
+    }) = cli.command
+    {
+        return init::run(init::InitOptions {
+            dir,
+            model,
+            timeout_secs,
+            gemini_md: !no_gemini_md,
+            force,
+            interactive: !yes,
+        });
+    }
+    if let Some(Command::Selftest {
+        prompt,
+        skip_prompt,
+        timeout_secs,
+    }) = cli.command
+    {
+        return selftest::run(selftest::SelftestOptions {
+            server: std::env::current_exe()?,
+            prompt: (!skip_prompt).then_some(prompt),
+            timeout_secs,
+        })
+        .await;
+    }
+    if let Some(Command::Version) = cli.command {
+        cli_compat::detect().await;
+        let info = build_info::current();
+        println!("gemini-mcp-rs {} ({})", info.server_version, info.git_sha);
+        println!(
+            "gemini CLI {}",
+            info.cli_version.as_deref().unwrap_or("not found")
+        );
+        return Ok(());
+    }
+    if let Some(Command::Check) = cli.command {
+        let report = doctor::diagnose(false).await;
+        for check in &report.checks {
+            println!(
+                "{} {}: {}",
+                check.status.as_str().to_uppercase(),
+                check.name,
+                check.detail
+            );
+            if let Some(ref hint) = check.hint {
+                println!("     hint: {}", hint);
+            }
+        }
+        if !report.ok {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    // A config with typos or invalid values stops the server here instead
+    // of being ignored in favour of the defaults
+    gemini::validate_config_file()?;
+    if cli.print_config {
+        let sources: Vec<String> = gemini::config_sources()
+            .iter()
+            .map(|source| source.display().to_string())
+            .collect();
+        let effective = serde_json::json!({
+            "sources": sources,
+            "config": gemini::config_snapshot(),
+        });
+        println!("{}", serde_json::to_string_pretty(&effective)?);
+        return Ok(());
+    }
+    logging::init();
+    if init::find_gemini_binary().is_none() {
+        tracing::warn!(
+            "the `gemini` binary was not found on PATH; set GEMINI_BIN \
+             or run `gemini-mcp-rs init` to check your setup"
+        );
+    } else {
+        cli_compat::detect().await;
+    }
 
     // Create an instance of our gemini server
-    let service = GeminiServer::new().serve(stdio()).await.inspect_err(|e| {
-        eprintln!("serving error: {:?}", e);
+    let server = GeminiServer::new();
+    spawn_diagnostics_on_sigusr1(&server);
+    server.spawn_index_watcher();
+    server.spawn_session_gc();
+
+    match cli.command {
+        Some(Command::Repl) => return repl::run(server).await,
+        Some(Command::Batch {
+            input,
+            output,
+            concurrency,
+        }) => {
+            batch::run(
+                server,
+                Some(&input),
+                Some(&output),
+                usize::from(concurrency),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Command::Replay { bundle_id }) => {
+            let output = server
+                .replay_bundle(&bundle_id)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.message.to_string()))?;
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            if !output.success {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(
+            Command::Init { .. } | Command::Selftest { .. } | Command::Version | Command::Check,
+        ) => unreachable!("handled above"),
+        None => {}
+    }
+
+    let stats = server.stats();
+    let drained = server.clone();
+
+    // On SIGINT/SIGTERM keep the transport up until in-flight calls have
+    // drained (or been killed) so their results still reach the client
+    let shutdown = {
+        let server = server.clone();
+        async move {
+            shutdown_signal().await;
+            server.drain(gemini::shutdown_grace_period()).await;
+        }
+    };
+
+    let served = match cli.transport {
+        Transport::Stdio => serve_stdio(server, shutdown).await,
+        #[cfg(feature = "http")]
+        Transport::Sse => gemini_mcp_rs::transport::serve_sse(server, cli.bind, shutdown).await,
+        #[cfg(feature = "http")]
+        Transport::StreamableHttp => {
+            gemini_mcp_rs::transport::serve_streamable_http(server, cli.bind, shutdown).await
+        }
+        #[cfg(not(feature = "http"))]
+        other => Err(anyhow::anyhow!(
+            "the {:?} transport requires building gemini-mcp-rs with the `http` feature",
+            other
+        )),
+    };
+
+    stats
+        .shutdown_report()
+        .emit(gemini::shutdown_report_path().as_deref());
+    gemini::shutdown_processes();
+
+    // The stdio transport's pending blocking read on stdin would otherwise
+    // keep the runtime alive after a signal-initiated shutdown
+    if drained.is_shutting_down() {
+        if let Err(err) = served {
+            tracing::error!("serving failed: {:?}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    served
+}
+
+/// Dump [`GeminiServer::diagnostics`] to stderr whenever SIGUSR1 arrives
+#[cfg(unix)]
+fn spawn_diagnostics_on_sigusr1(server: &GeminiServer) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(sigusr1) => sigusr1,
+        Err(err) => {
+            tracing::warn!("failed to install SIGUSR1 handler: {}", err);
+            return;
+        }
+    };
+    let server = server.clone();
+    tokio::spawn(async move {
+        while sigusr1.recv().await.is_some() {
+            server.diagnostics().emit();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_diagnostics_on_sigusr1(_server: &GeminiServer) {}
+
+/// Resolves on the first SIGINT or SIGTERM (Ctrl-C on non-Unix platforms)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(err) => tracing::warn!("failed to install SIGTERM handler: {}", err),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::warn!("failed to listen for Ctrl-C: {}", err);
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn serve_stdio(
+    server: GeminiServer,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let service = server.serve(stdio()).await.inspect_err(|e| {
+        tracing::error!("serving error: {:?}", e);
     })?;
 
+    // Stop serving on shutdown as well as when the client disconnects
+    let ct = service.cancellation_token();
+    tokio::spawn(async move {
+        shutdown.await;
+        ct.cancel();
+    });
+
     service.waiting().await?;
     Ok(())
 }
@@ -0,0 +1,288 @@
+//! Recurring maintenance windows / quiet hours, during which new calls are
+//! rejected with a retry hint or routed to another (cheaper) model.
+
+use crate::gemini::MaintenanceWindowConfig;
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 86_400;
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// What to do with a call that arrives now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Open,
+    Closed(InMaintenance),
+    /// Run the call on this model instead
+    Route {
+        window: String,
+        model: String,
+    },
+}
+
+/// A call rejected because a maintenance window is active
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InMaintenance {
+    /// The window as configured, e.g. `sat,sun 00:00-23:59 +02:00`
+    pub window: String,
+    /// Time until the window ends
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for InMaintenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mins = self.retry_after.as_secs().div_ceil(60);
+        write!(
+            f,
+            "Server is in a maintenance window ({}); retry in {}h{:02}m",
+            self.window,
+            mins / 60,
+            mins % 60
+        )
+    }
+}
+
+impl std::error::Error for InMaintenance {}
+
+#[derive(Debug)]
+struct Window {
+    /// Bit `n` set when the window starts on weekday `n` (0 = Monday)
+    days: u8,
+    start_secs: i64,
+    end_secs: i64,
+    offset_secs: i64,
+    route_to_model: Option<String>,
+    label: String,
+}
+
+/// The configured `maintenance_windows`, parsed once at startup
+#[derive(Debug, Default)]
+pub struct MaintenanceSchedule {
+    windows: Vec<Window>,
+}
+
+impl MaintenanceSchedule {
+    pub fn from_config(config: &[MaintenanceWindowConfig]) -> Result<Self> {
+        let windows = config
+            .iter()
+            .enumerate()
+            .map(|(i, window)| {
+                parse_window(window).with_context(|| format!("maintenance_windows[{}]", i))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { windows })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Rejecting windows take precedence over routing ones
+    pub fn check(&self, now: SystemTime) -> Decision {
+        let epoch_secs = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let mut route = None;
+        for window in &self.windows {
+            let Some(remaining) = window.remaining(epoch_secs) else {
+                continue;
+            };
+            match window.route_to_model {
+                None => {
+                    return Decision::Closed(InMaintenance {
+                        window: window.label.clone(),
+                        retry_after: Duration::from_secs(remaining as u64),
+                    })
+                }
+                Some(ref model) => {
+                    route.get_or_insert_with(|| Decision::Route {
+                        window: window.label.clone(),
+                        model: model.clone(),
+                    });
+                }
+            }
+        }
+        route.unwrap_or(Decision::Open)
+    }
+}
+
+impl Window {
+    /// Seconds until the window ends, if it is active at `epoch_secs`
+    fn remaining(&self, epoch_secs: i64) -> Option<i64> {
+        let local = epoch_secs + self.offset_secs;
+        // 1970-01-01 was a Thursday
+        let weekday = (local.div_euclid(SECS_PER_DAY) + 3).rem_euclid(7);
+        let previous = (weekday + 6) % 7;
+        let tod = local.rem_euclid(SECS_PER_DAY);
+        let starts_on = |day: i64| self.days & (1 << day) != 0;
+
+        if self.start_secs < self.end_secs {
+            (starts_on(weekday) && (self.start_secs..self.end_secs).contains(&tod))
+                .then(|| self.end_secs - tod)
+        } else if starts_on(weekday) && tod >= self.start_secs {
+            Some(SECS_PER_DAY - tod + self.end_secs)
+        } else if starts_on(previous) && tod < self.end_secs {
+            Some(self.end_secs - tod)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_window(config: &MaintenanceWindowConfig) -> Result<Window> {
+    let mut days = 0u8;
+    for day in &config.days {
+        let lower = day.trim().to_ascii_lowercase();
+        // Accept `mon`, `Monday` and anything in between
+        let Some(index) = WEEKDAYS
+            .iter()
+            .position(|d| lower.len() >= 3 && d.starts_with(&lower))
+        else {
+            bail!("unknown day `{}` (expected mon..sun)", day);
+        };
+        days |= 1 << index;
+    }
+    if config.days.is_empty() {
+        days = 0x7f;
+    }
+
+    let start_secs = parse_time(&config.start).context("invalid `start`")?;
+    let end_secs = parse_time(&config.end).context("invalid `end`")?;
+    if start_secs == end_secs {
+        bail!("`start` and `end` must differ");
+    }
+    let offset = config.utc_offset.as_deref().unwrap_or("+00:00");
+    let offset_secs = parse_offset(offset).context("invalid `utc_offset`")?;
+
+    let day_label = if config.days.is_empty() {
+        "daily".to_string()
+    } else {
+        config.days.join(",")
+    };
+    Ok(Window {
+        days,
+        start_secs,
+        end_secs,
+        offset_secs,
+        route_to_model: config
+            .route_to_model
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(str::to_string),
+        label: format!("{} {}-{} {}", day_label, config.start, config.end, offset),
+    })
+}
+
+/// `HH:MM` as seconds after midnight
+fn parse_time(value: &str) -> Result<i64> {
+    let (hours, minutes) = value
+        .trim()
+        .split_once(':')
+        .with_context(|| format!("`{}` is not HH:MM", value))?;
+    let hours: i64 = hours
+        .parse()
+        .with_context(|| format!("`{}` is not HH:MM", value))?;
+    let minutes: i64 = minutes
+        .parse()
+        .with_context(|| format!("`{}` is not HH:MM", value))?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        bail!("`{}` is out of range", value);
+    }
+    Ok(hours * 3600 + minutes * 60)
+}
+
+/// `+HH:MM` / `-HH:MM` as seconds east of UTC
+fn parse_offset(value: &str) -> Result<i64> {
+    let value = value.trim();
+    let (sign, rest) = match value.as_bytes().first() {
+        Some(b'+') => (1, &value[1..]),
+        Some(b'-') => (-1, &value[1..]),
+        _ => bail!("`{}` must start with + or -", value),
+    };
+    let secs = parse_time(rest)?;
+    if secs > 14 * 3600 {
+        bail!("`{}` is out of range", value);
+    }
+    Ok(sign * secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], start: &str, end: &str, offset: &str) -> MaintenanceWindowConfig {
+        MaintenanceWindowConfig {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+            utc_offset: Some(offset.to_string()),
+            route_to_model: None,
+        }
+    }
+
+    /// 2024-01-01 was a Monday
+    fn at(day: u64, hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_704_067_200 + day * 86_400 + hour * 3600 + minute * 60)
+    }
+
+    #[test]
+    fn test_business_hours_with_offset() {
+        let schedule = MaintenanceSchedule::from_config(&[window(
+            &["mon", "tue", "wed", "thu", "fri"],
+            "09:00",
+            "17:00",
+            "+02:00",
+        )])
+        .unwrap();
+
+        // Monday 08:30 UTC is 10:30 local
+        let Decision::Closed(closed) = schedule.check(at(0, 8, 30)) else {
+            panic!("expected the window to be active");
+        };
+        assert_eq!(closed.retry_after, Duration::from_secs(6 * 3600 + 30 * 60));
+        assert!(closed.to_string().contains("retry in 6h30m"));
+
+        // Monday 15:00 UTC is 17:00 local, Saturday is not listed
+        assert_eq!(schedule.check(at(0, 15, 0)), Decision::Open);
+        assert_eq!(schedule.check(at(5, 10, 0)), Decision::Open);
+    }
+
+    #[test]
+    fn test_window_spanning_midnight_and_routing() {
+        let mut night = window(&["fri"], "22:00", "06:00", "+00:00");
+        night.route_to_model = Some("gemini-2.5-flash".to_string());
+        let schedule = MaintenanceSchedule::from_config(&[night]).unwrap();
+
+        let route = Decision::Route {
+            window: "fri 22:00-06:00 +00:00".to_string(),
+            model: "gemini-2.5-flash".to_string(),
+        };
+        assert_eq!(schedule.check(at(4, 23, 0)), route);
+        assert_eq!(schedule.check(at(5, 5, 59)), route);
+        assert_eq!(schedule.check(at(5, 6, 0)), Decision::Open);
+        assert_eq!(schedule.check(at(3, 23, 0)), Decision::Open);
+    }
+
+    #[test]
+    fn test_invalid_windows_are_rejected() {
+        let err = |w| MaintenanceSchedule::from_config(&[w]).unwrap_err();
+        assert!(format!(
+            "{:#}",
+            err(window(&["someday"], "09:00", "17:00", "+00:00"))
+        )
+        .contains("unknown day"));
+        assert!(format!("{:#}", err(window(&[], "25:00", "17:00", "+00:00"))).contains("start"));
+        assert!(format!("{:#}", err(window(&[], "09:00", "09:00", "+00:00"))).contains("differ"));
+        assert!(format!("{:#}", err(window(&[], "09:00", "17:00", "02:00"))).contains("utc_offset"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Durable key-value facts shared by agents across sessions, stored as JSON in
+//! the state directory and exposed through the `gemini_memory_*` tools.
+
+use crate::clock::SharedClock;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File inside the state directory holding the facts
+pub const MEMORY_FILE: &str = "memory.json";
+
+const MAX_KEY_LEN: usize = 128;
+const MAX_VALUE_BYTES: usize = 4_000;
+const MAX_ENTRIES: usize = 1_000;
+
+/// One remembered fact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryEntry {
+    pub value: String,
+    /// Unix time of the last `set`
+    pub updated_at: u64,
+}
+
+/// Facts keyed by name, kept sorted so listings and the injected block are
+/// stable
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// `None` keeps the facts in memory only
+    path: Option<PathBuf>,
+    entries: Mutex<BTreeMap<String, MemoryEntry>>,
+    /// Source of `updated_at`
+    clock: SharedClock,
+}
+
+impl MemoryStore {
+    /// Load the facts saved at `path`; a missing file starts empty
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            entries: Mutex::new(entries),
+            clock: SharedClock::default(),
+        })
+    }
+
+    /// Timestamp facts with `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Store `value` under `key`, replacing any previous value. An empty
+    /// value removes the key. Returns whether the key existed before.
+    pub fn set(&self, key: &str, value: &str) -> Result<bool> {
+        validate_key(key)?;
+        if value.len() > MAX_VALUE_BYTES {
+            bail!("VALUE must be at most {} bytes", MAX_VALUE_BYTES);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let mut updated = entries.clone();
+        let existed = if value.trim().is_empty() {
+            updated.remove(key).is_some()
+        } else {
+            if !updated.contains_key(key) && updated.len() >= MAX_ENTRIES {
+                bail!("Memory is full ({} keys); remove some first", MAX_ENTRIES);
+            }
+            let entry = MemoryEntry {
+                value: value.to_string(),
+                updated_at: unix_secs(self.clock.now()),
+            };
+            updated.insert(key.to_string(), entry).is_some()
+        };
+        if let Some(ref path) = self.path {
+            save(path, &updated)?;
+        }
+        *entries = updated;
+        Ok(existed)
+    }
+
+    pub fn get(&self, key: &str) -> Option<MemoryEntry> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    /// Every fact whose key starts with `prefix`, ordered by key
+    pub fn list(&self, prefix: &str) -> Vec<(String, MemoryEntry)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// "Known facts" preamble for new sessions, or `None` when empty
+    pub fn facts_block(&self) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            return None;
+        }
+        let mut block = String::from("Known facts (remembered from earlier sessions):\n");
+        for (key, entry) in entries.iter() {
+            block.push_str(&format!("- {}: {}\n", key, entry.value.trim()));
+        }
+        Some(block)
+    }
+}
+
+fn validate_key(key: &str) -> Result<()> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || "-_./:".contains(c);
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !key.chars().all(valid_char) {
+        bail!(
+            "KEY must be 1-{} characters of letters, digits, `-`, `_`, `.`, `/` or `:`",
+            MAX_KEY_LEN
+        );
+    }
+    Ok(())
+}
+
+/// Write through a temporary file so a crash never leaves half a file behind
+fn save(path: &Path, entries: &BTreeMap<String, MemoryEntry>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(entries).context("Failed to serialize memory")?;
+    std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_get_list_and_persist() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("state").join(MEMORY_FILE);
+        let store = MemoryStore::open(path.clone()).unwrap();
+
+        assert!(!store.set("conventions/tests", "use cargo nextest").unwrap());
+        assert!(store.set("conventions/tests", "use cargo test").unwrap());
+        store.set("decisions/db", "SQLite, not Postgres").unwrap();
+
+        assert_eq!(
+            store.get("conventions/tests").unwrap().value,
+            "use cargo test"
+        );
+        let keys: Vec<String> = store
+            .list("conventions/")
+            .into_iter()
+            .map(|e| e.0)
+            .collect();
+        assert_eq!(keys, vec!["conventions/tests"]);
+
+        let reopened = MemoryStore::open(path).unwrap();
+        assert_eq!(reopened.list("").len(), 2);
+        assert!(reopened.set("decisions/db", "").unwrap());
+        assert_eq!(reopened.get("decisions/db"), None);
+    }
+
+    #[test]
+    fn test_invalid_keys_and_values_are_rejected() {
+        let store = MemoryStore::default();
+        assert!(store.set("", "x").is_err());
+        assert!(store.set("has space", "x").is_err());
+        assert!(store.set(&"k".repeat(MAX_KEY_LEN + 1), "x").is_err());
+        assert!(store.set("big", &"v".repeat(MAX_VALUE_BYTES + 1)).is_err());
+        assert!(store.list("").is_empty());
+    }
+
+    #[test]
+    fn test_facts_block() {
+        let store = MemoryStore::default();
+        assert_eq!(store.facts_block(), None);
+
+        store.set("style", "tabs, not spaces ").unwrap();
+        store.set("branch", "main").unwrap();
+        assert_eq!(
+            store.facts_block().unwrap(),
+            "Known facts (remembered from earlier sessions):\n- branch: main\n- style: tabs, not spaces\n"
+        );
+    }
+}
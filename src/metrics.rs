@@ -0,0 +1,340 @@
+//! Prometheus metrics for operators running the server as a shared service.
+//!
+//! Counters and histograms are collected in every mode; the network
+//! transports serve them in the text exposition format at `GET /metrics`.
+
+use crate::event::Usage;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the CLI run duration buckets
+const RUN_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+/// Upper bounds, in seconds, of the run slot wait buckets
+const QUEUE_WAIT_BUCKETS: &[f64] = &[0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Why a `gemini` call failed, as the `error_type` label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorType {
+    /// Rejected before running: invalid arguments, rate limit, maintenance
+    Rejected,
+    /// The CLI ran but reported an error or produced no answer
+    Cli,
+    Timeout,
+    /// Killed through `gemini_kill_request` or at shutdown
+    Killed,
+    /// Cancelled by the client (`notifications/cancelled`)
+    Cancelled,
+    /// Stopped because the CLI asked to log in
+    AuthRequired,
+    /// Stopped by a `stderr_hooks` pattern
+    Aborted,
+    /// Spawning or talking to the CLI failed
+    Internal,
+}
+
+impl ErrorType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rejected => "rejected",
+            Self::Cli => "cli",
+            Self::Timeout => "timeout",
+            Self::Killed => "killed",
+            Self::Cancelled => "cancelled",
+            Self::AuthRequired => "auth_required",
+            Self::Aborted => "aborted",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+/// Cumulative histogram with fixed buckets
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+#[derive(Debug, Default)]
+struct HistogramState {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            state: Mutex::new(HistogramState {
+                buckets: vec![0; bounds.len() + 1],
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(self.bounds.len());
+        let mut state = self.state.lock().unwrap();
+        state.buckets[bucket] += 1;
+        state.sum += secs;
+        state.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let state = self.state.lock().unwrap();
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (i, count) in state.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = self
+                .bounds
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(out, "{}_sum {}", name, state.sum);
+        let _ = writeln!(out, "{}_count {}", name, state.count);
+    }
+}
+
+/// Process-wide metrics shared by every clone of the server
+#[derive(Debug)]
+pub struct Metrics {
+    tool_calls: Mutex<BTreeMap<String, u64>>,
+    succeeded: AtomicU64,
+    failed: Mutex<BTreeMap<ErrorType, u64>>,
+    run_duration: Histogram,
+    queue_wait: Histogram,
+    prompt_tokens: AtomicU64,
+    candidate_tokens: AtomicU64,
+    total_tokens: AtomicU64,
+}
+
+/// Gauges sampled when the metrics are rendered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gauges {
+    /// Calls waiting for a run slot
+    pub queued: u64,
+    /// CLI processes currently running
+    pub active_processes: u64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            tool_calls: Mutex::new(BTreeMap::new()),
+            succeeded: AtomicU64::new(0),
+            failed: Mutex::new(BTreeMap::new()),
+            run_duration: Histogram::new(RUN_DURATION_BUCKETS),
+            queue_wait: Histogram::new(QUEUE_WAIT_BUCKETS),
+            prompt_tokens: AtomicU64::new(0),
+            candidate_tokens: AtomicU64::new(0),
+            total_tokens: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a call of MCP tool `tool`
+    pub fn tool_call(&self, tool: &str) {
+        *self
+            .tool_calls
+            .lock()
+            .unwrap()
+            .entry(tool.to_string())
+            .or_default() += 1;
+    }
+
+    /// Record the outcome of a `gemini` call
+    pub fn call_finished(&self, error: Option<ErrorType>) {
+        match error {
+            None => {
+                self.succeeded.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(error) => *self.failed.lock().unwrap().entry(error).or_default() += 1,
+        }
+    }
+
+    /// How long a CLI run took, from spawn to exit
+    pub fn observe_run(&self, duration: Duration) {
+        self.run_duration.observe(duration);
+    }
+
+    /// How long a call waited for a run slot
+    pub fn observe_queue_wait(&self, wait: Duration) {
+        self.queue_wait.observe(wait);
+    }
+
+    pub fn add_tokens(&self, usage: &Usage) {
+        self.prompt_tokens
+            .fetch_add(usage.prompt_tokens, Ordering::Relaxed);
+        self.candidate_tokens
+            .fetch_add(usage.candidate_tokens, Ordering::Relaxed);
+        self.total_tokens
+            .fetch_add(usage.total_tokens, Ordering::Relaxed);
+    }
+
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self, gauges: Gauges) -> String {
+        let mut out = String::new();
+        counter_header(
+            &mut out,
+            "gemini_mcp_tool_calls_total",
+            "MCP tool calls by tool",
+        );
+        for (tool, count) in self.tool_calls.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "gemini_mcp_tool_calls_total{{tool=\"{}\"}} {}",
+                escape(tool),
+                count
+            );
+        }
+
+        counter_header(
+            &mut out,
+            "gemini_mcp_calls_succeeded_total",
+            "gemini calls that returned an answer",
+        );
+        let _ = writeln!(
+            out,
+            "gemini_mcp_calls_succeeded_total {}",
+            self.succeeded.load(Ordering::Relaxed)
+        );
+        counter_header(
+            &mut out,
+            "gemini_mcp_calls_failed_total",
+            "gemini calls that failed, by error type",
+        );
+        for (error, count) in self.failed.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "gemini_mcp_calls_failed_total{{error_type=\"{}\"}} {}",
+                error.as_str(),
+                count
+            );
+        }
+
+        self.run_duration.render(
+            &mut out,
+            "gemini_mcp_run_duration_seconds",
+            "Duration of Gemini CLI runs",
+        );
+        self.queue_wait.render(
+            &mut out,
+            "gemini_mcp_queue_wait_seconds",
+            "Time gemini calls waited for a run slot",
+        );
+
+        counter_header(
+            &mut out,
+            "gemini_mcp_tokens_total",
+            "Tokens reported by the Gemini CLI, by kind",
+        );
+        for (kind, counter) in [
+            ("prompt", &self.prompt_tokens),
+            ("candidates", &self.candidate_tokens),
+            ("total", &self.total_tokens),
+        ] {
+            let _ = writeln!(
+                out,
+                "gemini_mcp_tokens_total{{kind=\"{}\"}} {}",
+                kind,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        for (name, help, value) in [
+            (
+                "gemini_mcp_active_processes",
+                "Gemini CLI processes currently running",
+                gauges.active_processes,
+            ),
+            (
+                "gemini_mcp_queued_calls",
+                "gemini calls waiting for a run slot",
+                gauges.queued,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+fn counter_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.tool_call("gemini");
+        metrics.tool_call("gemini");
+        metrics.tool_call("gemini_health");
+        metrics.call_finished(None);
+        metrics.call_finished(Some(ErrorType::Timeout));
+        metrics.observe_run(Duration::from_secs(3));
+        metrics.observe_run(Duration::from_secs(4000));
+        metrics.observe_queue_wait(Duration::from_millis(5));
+        metrics.add_tokens(&Usage {
+            prompt_tokens: 10,
+            candidate_tokens: 5,
+            total_tokens: 15,
+        });
+
+        let text = metrics.render(Gauges {
+            queued: 2,
+            active_processes: 1,
+        });
+        for line in [
+            "# TYPE gemini_mcp_tool_calls_total counter",
+            "gemini_mcp_tool_calls_total{tool=\"gemini\"} 2",
+            "gemini_mcp_tool_calls_total{tool=\"gemini_health\"} 1",
+            "gemini_mcp_calls_succeeded_total 1",
+            "gemini_mcp_calls_failed_total{error_type=\"timeout\"} 1",
+            "gemini_mcp_run_duration_seconds_bucket{le=\"1\"} 0",
+            "gemini_mcp_run_duration_seconds_bucket{le=\"5\"} 1",
+            "gemini_mcp_run_duration_seconds_bucket{le=\"1800\"} 1",
+            "gemini_mcp_run_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "gemini_mcp_run_duration_seconds_sum 4003",
+            "gemini_mcp_run_duration_seconds_count 2",
+            "gemini_mcp_queue_wait_seconds_bucket{le=\"0.01\"} 1",
+            "gemini_mcp_tokens_total{kind=\"candidates\"} 5",
+            "gemini_mcp_active_processes 1",
+            "gemini_mcp_queued_calls 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing `{}`", line);
+        }
+        assert_eq!(escape("a\"b\\"), "a\\\"b\\\\");
+    }
+}
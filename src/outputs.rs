@@ -0,0 +1,115 @@
+//! Whole answers kept for `gemini_fetch_output`.
+//!
+//! When an answer is cut down to `max_output_chars`, the server keeps the
+//! whole text under its session id so that the client can page through it
+//! with `OFFSET` and `LIMIT` instead of losing the rest. Only the latest
+//! answer of each session and a bounded number of sessions are kept, in
+//! memory; the oldest are dropped first and everything is lost when the
+//! server restarts.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Sessions whose answer is kept
+pub const MAX_OUTPUTS: usize = 32;
+/// Characters returned per page unless the call sets `LIMIT`
+pub const DEFAULT_PAGE_CHARS: usize = 20_000;
+/// Most characters one page may hold
+pub const MAX_PAGE_CHARS: usize = 100_000;
+
+/// One slice of a kept answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page {
+    #[serde(rename = "SESSION_ID")]
+    pub session_id: String,
+    /// Character offset of `content` in the whole answer
+    pub offset: usize,
+    pub content: String,
+    /// Length of the whole answer in characters
+    pub total_chars: usize,
+    /// `OFFSET` of the next page; absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+}
+
+/// The latest whole answer of the most recent sessions
+#[derive(Debug, Default)]
+pub struct OutputStore {
+    outputs: Mutex<VecDeque<(String, String)>>,
+}
+
+impl OutputStore {
+    /// Keep `text` as the answer of `session_id`, replacing its earlier one
+    pub fn insert(&self, session_id: &str, text: String) {
+        let mut outputs = self.outputs.lock().unwrap();
+        outputs.retain(|(id, _)| id != session_id);
+        outputs.push_back((session_id.to_string(), text));
+        while outputs.len() > MAX_OUTPUTS {
+            outputs.pop_front();
+        }
+    }
+
+    /// Drop the answer kept for `session_id`
+    pub fn remove(&self, session_id: &str) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| id != session_id);
+    }
+
+    /// `limit` characters of the answer of `session_id` from `offset`;
+    /// `None` if no answer is kept for it
+    pub fn page(&self, session_id: &str, offset: usize, limit: usize) -> Option<Page> {
+        let outputs = self.outputs.lock().unwrap();
+        let (_, text) = outputs.iter().find(|(id, _)| id == session_id)?;
+        let total_chars = text.chars().count();
+        let limit = limit.clamp(1, MAX_PAGE_CHARS);
+        let content: String = text.chars().skip(offset).take(limit).collect();
+        let end = offset.saturating_add(limit);
+        Some(Page {
+            session_id: session_id.to_string(),
+            offset,
+            content,
+            total_chars,
+            next_offset: (end < total_chars).then_some(end),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_through_an_answer() {
+        let store = OutputStore::default();
+        store.insert("s", "abcdéfghij".to_string());
+
+        let first = store.page("s", 0, 4).unwrap();
+        assert_eq!(first.content, "abcd");
+        assert_eq!(first.total_chars, 10);
+        assert_eq!(first.next_offset, Some(4));
+        let second = store.page("s", 4, 4).unwrap();
+        assert_eq!(second.content, "éfgh");
+        let last = store.page("s", 8, 4).unwrap();
+        assert_eq!((last.content.as_str(), last.next_offset), ("ij", None));
+        assert_eq!(store.page("s", 50, 4).unwrap().content, "");
+        assert!(store.page("other", 0, 4).is_none());
+
+        store.insert("s", "new".to_string());
+        assert_eq!(store.page("s", 0, 10).unwrap().content, "new");
+        store.remove("s");
+        assert!(store.page("s", 0, 10).is_none());
+    }
+
+    #[test]
+    fn test_oldest_sessions_are_dropped() {
+        let store = OutputStore::default();
+        for n in 0..=MAX_OUTPUTS {
+            store.insert(&n.to_string(), "text".to_string());
+        }
+        assert!(store.page("0", 0, 10).is_none());
+        assert!(store.page("1", 0, 10).is_some());
+    }
+}
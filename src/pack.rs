@@ -0,0 +1,215 @@
+//! Workspace files packed into the prompt (`CONTEXT_GLOBS`).
+//!
+//! Files of the working directory matching any of the call's globs are read
+//! and prepended to the prompt as one delimited block, so agents do not have
+//! to paste file contents themselves. Files ignored by git are never packed
+//! (see [`index::list_files`]), and limits on the number of files and on
+//! their size keep the block within the prompt budget; whatever does not fit
+//! is reported as skipped.
+
+use crate::gemini::ContextPackConfig;
+use crate::glob::matches;
+use crate::index;
+use crate::policy::Policy;
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Files packed unless `context_pack.max_files` is configured
+pub const DEFAULT_MAX_FILES: usize = 50;
+/// Larger files are skipped unless `context_pack.max_file_bytes` is configured
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 100_000;
+/// Size of the whole block unless `context_pack.max_total_bytes` is configured
+pub const DEFAULT_MAX_TOTAL_BYTES: u64 = 400_000;
+/// Most globs one call may give
+const MAX_GLOBS: usize = 32;
+
+/// Resolved limits of one pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_files: usize,
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl From<ContextPackConfig> for Limits {
+    fn from(config: ContextPackConfig) -> Self {
+        Self {
+            max_files: config.max_files.unwrap_or(DEFAULT_MAX_FILES),
+            max_file_bytes: config.max_file_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES),
+            max_total_bytes: config.max_total_bytes.unwrap_or(DEFAULT_MAX_TOTAL_BYTES),
+        }
+    }
+}
+
+/// The context block and what was left out of it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pack {
+    /// `None` when no file matched
+    pub block: Option<String>,
+    /// Paths packed, relative to the root
+    pub files: Vec<String>,
+    /// Matching files left out, each with the reason
+    pub skipped: Vec<String>,
+}
+
+impl Pack {
+    /// Warning summarizing the skipped files, if any
+    pub fn warning(&self) -> Option<String> {
+        if self.skipped.is_empty() {
+            return None;
+        }
+        let shown: Vec<&str> = self.skipped.iter().take(5).map(String::as_str).collect();
+        let more = self.skipped.len().saturating_sub(shown.len());
+        Some(format!(
+            "CONTEXT_GLOBS: {} matching file(s) not packed: {}{}",
+            self.skipped.len(),
+            shown.join(", "),
+            if more > 0 {
+                format!(" and {} more", more)
+            } else {
+                String::new()
+            }
+        ))
+    }
+}
+
+/// Pack the files below `root` matching any of `globs`, in path order.
+/// Files `policy` does not allow are skipped.
+pub fn pack(root: &Path, globs: &[String], limits: Limits, policy: &Policy) -> Result<Pack> {
+    let globs: Vec<&str> = globs
+        .iter()
+        .map(|g| g.trim().trim_start_matches("./"))
+        .filter(|g| !g.is_empty())
+        .collect();
+    if globs.len() > MAX_GLOBS {
+        bail!("CONTEXT_GLOBS accepts at most {} patterns", MAX_GLOBS);
+    }
+    if let Some(glob) = globs
+        .iter()
+        .find(|g| g.starts_with('/') || g.split('/').any(|part| part == ".."))
+    {
+        bail!(
+            "CONTEXT_GLOBS pattern {} must be relative to the working directory",
+            glob
+        );
+    }
+
+    let mut paths: Vec<String> = index::list_files(root)?
+        .into_iter()
+        .map(|path| {
+            path.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .filter(|path| globs.iter().any(|glob| matches(glob, path)))
+        .collect();
+    paths.sort();
+
+    let mut pack = Pack::default();
+    let mut block = String::from("Workspace files (packed from CONTEXT_GLOBS):\n");
+    let mut total = 0u64;
+    for path in paths {
+        if let Err(violation) = policy.check(&root.join(&path)) {
+            pack.skipped
+                .push(format!("{} ({})", path, violation.reason));
+            continue;
+        }
+        if pack.files.len() >= limits.max_files {
+            pack.skipped.push(format!("{} (max_files)", path));
+            continue;
+        }
+        let size = match std::fs::metadata(root.join(&path)) {
+            Ok(meta) => meta.len(),
+            Err(_) => continue,
+        };
+        if size > limits.max_file_bytes {
+            pack.skipped.push(format!("{} (max_file_bytes)", path));
+            continue;
+        }
+        if total + size > limits.max_total_bytes {
+            pack.skipped.push(format!("{} (max_total_bytes)", path));
+            continue;
+        }
+        let Ok(text) = std::fs::read_to_string(root.join(&path)) else {
+            pack.skipped.push(format!("{} (not UTF-8 text)", path));
+            continue;
+        };
+        if text.contains('\0') {
+            pack.skipped.push(format!("{} (binary)", path));
+            continue;
+        }
+        total += size;
+        block.push_str(&format!(
+            "\n===== {} =====\n{}\n===== end of {} =====\n",
+            path,
+            text.trim_end(),
+            path
+        ));
+        pack.files.push(path);
+    }
+    if !pack.files.is_empty() {
+        pack.block = Some(block);
+    }
+    Ok(pack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_respects_limits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let write = |path: &str, content: &[u8]| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write("src/a.rs", b"fn a() {}\n");
+        write("src/b.rs", b"fn b() {}\n");
+        write("src/big.rs", &[b'x'; 64]);
+        write("src/bin.rs", b"\0\0");
+        write("README.md", b"# Readme\n");
+
+        let limits = Limits {
+            max_files: 10,
+            max_file_bytes: 32,
+            max_total_bytes: 1_000,
+        };
+        let policy = Policy::new(&[dir.path().to_path_buf()], &[]);
+        let packed = pack(dir.path(), &["src/*.rs".to_string()], limits, &policy).unwrap();
+        assert_eq!(packed.files, ["src/a.rs", "src/b.rs"]);
+        assert_eq!(
+            packed.skipped,
+            ["src/big.rs (max_file_bytes)", "src/bin.rs (binary)"]
+        );
+        let block = packed.block.unwrap();
+        assert!(block.contains("===== src/a.rs =====\nfn a() {}\n===== end of src/a.rs ====="));
+        assert!(!block.contains("Readme"));
+
+        let one = Limits {
+            max_files: 1,
+            ..limits
+        };
+        let packed = pack(dir.path(), &["**/*.rs".to_string()], one, &policy).unwrap();
+        assert_eq!(packed.files, ["src/a.rs"]);
+        assert!(packed
+            .warning()
+            .unwrap()
+            .starts_with("CONTEXT_GLOBS: 3 matching"));
+
+        assert!(pack(dir.path(), &["../*.rs".to_string()], limits, &policy).is_err());
+        let denied = Policy::new(&[dir.path().to_path_buf()], &["b.rs".to_string()]);
+        let packed = pack(dir.path(), &["src/*.rs".to_string()], limits, &denied).unwrap();
+        assert_eq!(packed.files, ["src/a.rs"]);
+        assert_eq!(
+            packed.skipped[0],
+            "src/b.rs (matches the denied pattern `b.rs`)"
+        );
+        assert_eq!(
+            pack(dir.path(), &["*.py".to_string()], limits, &policy).unwrap(),
+            Pack::default()
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//! Unified diffs applied to the workspace (`gemini_apply_patch`).
+//!
+//! Diffs written by a model are often slightly off, so hunk line counts are
+//! ignored and each hunk is located by its context: first at the line its
+//! header names (shifted by the hunks before it), then anywhere after the
+//! previous hunk, then ignoring trailing whitespace. A patch is applied all
+//! or nothing: if any hunk of any file does not match, no file is touched
+//! and every conflict is reported.
+
+use crate::policy::Policy;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::path::{Component, Path, PathBuf};
+
+/// One file of a unified diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FilePatch {
+    /// `None` for a file the diff creates
+    old_path: Option<String>,
+    /// `None` for a file the diff deletes
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    /// 1-based line of the hunk in the original file
+    old_start: usize,
+    header: String,
+    lines: Vec<HunkLine>,
+    /// The last line of the old side has no trailing newline
+    old_no_newline: bool,
+    /// The last line of the new side has no trailing newline
+    new_no_newline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// What happened, or would happen, to one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Modified,
+    Created,
+    Deleted,
+    Renamed,
+    /// A hunk did not match, or the file is missing or already exists
+    Conflict,
+}
+
+/// Outcome for one file of the patch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileOutcome {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+    pub status: FileStatus,
+    pub hunks: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+}
+
+impl FileOutcome {
+    fn conflict(&mut self, message: String) {
+        self.status = FileStatus::Conflict;
+        self.conflicts.push(message);
+    }
+}
+
+/// Outcome of the whole patch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatchReport {
+    /// Every file was (or, in a dry run, would be) changed
+    pub clean: bool,
+    pub dry_run: bool,
+    pub files: Vec<FileOutcome>,
+}
+
+/// A file change ready to be written
+struct Change {
+    target: PathBuf,
+    /// `None` deletes `target`
+    content: Option<String>,
+    /// Removed after a rename
+    source: Option<PathBuf>,
+}
+
+/// Apply `diff` to files below `root`. With `dry_run`, or when any hunk
+/// conflicts, nothing is written. Malformed diffs, paths leaving `root` and
+/// paths `policy` does not allow are errors.
+pub fn apply(root: &Path, diff: &str, dry_run: bool, policy: &Policy) -> Result<PatchReport> {
+    let patches = parse(diff)?;
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve {}", root.display()))?;
+
+    let mut files = Vec::new();
+    let mut changes = Vec::new();
+    for patch in &patches {
+        let (outcome, change) = plan(&root, patch, policy)?;
+        files.push(outcome);
+        changes.extend(change);
+    }
+    let clean = files.iter().all(|f| f.status != FileStatus::Conflict);
+
+    if clean && !dry_run {
+        for change in &changes {
+            match &change.content {
+                Some(content) => {
+                    if let Some(parent) = change.target.parent() {
+                        std::fs::create_dir_all(parent)
+                            .with_context(|| format!("Failed to create {}", parent.display()))?;
+                    }
+                    std::fs::write(&change.target, content)
+                        .with_context(|| format!("Failed to write {}", change.target.display()))?;
+                }
+                None => std::fs::remove_file(&change.target)
+                    .with_context(|| format!("Failed to delete {}", change.target.display()))?,
+            }
+            if let Some(source) = &change.source {
+                std::fs::remove_file(source)
+                    .with_context(|| format!("Failed to delete {}", source.display()))?;
+            }
+        }
+    }
+    Ok(PatchReport {
+        clean,
+        dry_run,
+        files,
+    })
+}
+
+/// The outcome of one file and, unless it conflicts, its change
+fn plan(root: &Path, patch: &FilePatch, policy: &Policy) -> Result<(FileOutcome, Option<Change>)> {
+    let display = patch
+        .new_path
+        .as_deref()
+        .or(patch.old_path.as_deref())
+        .unwrap_or_default()
+        .to_string();
+    let mut outcome = FileOutcome {
+        path: display.clone(),
+        renamed_from: None,
+        status: FileStatus::Modified,
+        hunks: patch.hunks.len(),
+        conflicts: Vec::new(),
+    };
+    let original = match &patch.old_path {
+        Some(old) => {
+            let path = resolve(root, old, policy)?;
+            match std::fs::read_to_string(&path) {
+                Ok(text) => Some((path, text)),
+                Err(err) => {
+                    outcome.conflict(format!("cannot read {}: {}", old, err));
+                    return Ok((outcome, None));
+                }
+            }
+        }
+        None => None,
+    };
+    let target = match &patch.new_path {
+        Some(new) => Some(resolve(root, new, policy)?),
+        None => None,
+    };
+
+    let (text, had_newline) = match &original {
+        Some((_, text)) => (text.as_str(), text.ends_with('\n') || text.is_empty()),
+        None => {
+            if target.as_ref().is_some_and(|t| t.exists()) {
+                outcome.conflict(format!("{} already exists", display));
+                return Ok((outcome, None));
+            }
+            ("", true)
+        }
+    };
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut trailing_newline = had_newline;
+    let mut offset: isize = 0;
+    let mut floor = 0;
+    for (index, hunk) in patch.hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let Some(at) = locate(&lines, &old, expected, floor) else {
+            outcome.conflict(format!(
+                "hunk {} ({}) does not match {}",
+                index + 1,
+                hunk.header,
+                display
+            ));
+            continue;
+        };
+        let new: Vec<String> = hunk.new_lines().into_iter().map(str::to_string).collect();
+        let added = new.len();
+        let touches_end = at + old.len() == lines.len();
+        lines.splice(at..at + old.len(), new);
+        offset += added as isize - old.len() as isize;
+        floor = at + added;
+        if touches_end || (original.is_none() && floor == lines.len()) {
+            if hunk.new_no_newline {
+                trailing_newline = false;
+            } else if hunk.old_no_newline {
+                trailing_newline = true;
+            }
+        }
+    }
+    if outcome.status == FileStatus::Conflict {
+        return Ok((outcome, None));
+    }
+
+    let content = match &target {
+        None => {
+            if !lines.is_empty() {
+                outcome.conflict(format!(
+                    "{} is deleted but would keep {} lines",
+                    display,
+                    lines.len()
+                ));
+                return Ok((outcome, None));
+            }
+            outcome.status = FileStatus::Deleted;
+            None
+        }
+        Some(_) => {
+            let mut content = lines.join("\n");
+            if trailing_newline && !lines.is_empty() {
+                content.push('\n');
+            }
+            Some(content)
+        }
+    };
+    let source = match (&original, &target) {
+        (Some((source, _)), Some(target)) if source != target => {
+            outcome.status = FileStatus::Renamed;
+            outcome.renamed_from = patch.old_path.clone();
+            Some(source.clone())
+        }
+        (None, _) => {
+            outcome.status = FileStatus::Created;
+            None
+        }
+        _ => None,
+    };
+    let change = Change {
+        target: target.unwrap_or_else(|| original.map(|(path, _)| path).unwrap_or_default()),
+        content,
+        source,
+    };
+    Ok((outcome, Some(change)))
+}
+
+/// Start of `old` in `lines`: at `expected`, else the nearest match at or
+/// after `floor`, else the same ignoring trailing whitespace
+fn locate(lines: &[String], old: &[&str], expected: usize, floor: usize) -> Option<usize> {
+    if old.is_empty() {
+        return Some(expected.clamp(floor, lines.len()));
+    }
+    let last = lines.len().checked_sub(old.len())?;
+    let matches_at = |at: usize, exact: bool| {
+        lines[at..at + old.len()]
+            .iter()
+            .zip(old)
+            .all(|(line, want)| {
+                if exact {
+                    line == want
+                } else {
+                    line.trim_end() == want.trim_end()
+                }
+            })
+    };
+    for exact in [true, false] {
+        let mut candidates: Vec<usize> = (floor..=last).collect();
+        candidates.sort_by_key(|&at| at.abs_diff(expected));
+        if let Some(at) = candidates.into_iter().find(|&at| matches_at(at, exact)) {
+            return Some(at);
+        }
+    }
+    None
+}
+
+/// `path` below `root`, rejecting absolute paths, `..`, symlinks that lead
+/// outside it and paths `policy` does not allow
+fn resolve(root: &Path, path: &str, policy: &Policy) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    let inside = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside || path.is_empty() {
+        bail!("{} must be a relative path inside {}", path, root.display());
+    }
+    let full = root.join(relative);
+    let existing = full
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(root)
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve {}", path))?;
+    if !existing.starts_with(root) {
+        bail!("{} leads outside {}", path, root.display());
+    }
+    policy.check(&full)?;
+    Ok(full)
+}
+
+/// Files and hunks of a unified diff, as produced by `diff -u` or `git diff`
+fn parse(diff: &str) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines.get(i + 1).and_then(|l| l.strip_prefix("+++ ")),
+        ) {
+            patches.push(FilePatch {
+                old_path: diff_path(old, "a/"),
+                new_path: diff_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+            i += 2;
+            continue;
+        }
+        if line.starts_with("@@") {
+            let Some(patch) = patches.last_mut() else {
+                bail!("Hunk `{}` comes before any ---/+++ file header", line);
+            };
+            let hunk = parse_hunk(&lines, &mut i)?;
+            patch.hunks.push(hunk);
+            continue;
+        }
+        i += 1;
+    }
+    if patches.is_empty() {
+        bail!("No ---/+++ file headers found; PATCH must be a unified diff");
+    }
+    if let Some(patch) = patches.iter().find(|p| p.hunks.is_empty()) {
+        bail!(
+            "{} has no hunks",
+            patch
+                .new_path
+                .as_deref()
+                .or(patch.old_path.as_deref())
+                .unwrap_or("/dev/null")
+        );
+    }
+    if patches
+        .iter()
+        .any(|p| p.old_path.is_none() && p.new_path.is_none())
+    {
+        bail!("A diff from /dev/null to /dev/null changes nothing");
+    }
+    Ok(patches)
+}
+
+/// The hunk whose header is at `lines[*i]`; leaves `*i` after its last line
+fn parse_hunk(lines: &[&str], i: &mut usize) -> Result<Hunk> {
+    let header = lines[*i].trim();
+    let old_start = header
+        .strip_prefix("@@ -")
+        .and_then(|rest| rest.split([',', ' ']).next())
+        .and_then(|start| start.parse::<usize>().ok())
+        .with_context(|| format!("Malformed hunk header `{}`", header))?;
+    let mut hunk = Hunk {
+        old_start,
+        header: header
+            .split("@@")
+            .nth(1)
+            .map_or(header, str::trim)
+            .to_string(),
+        lines: Vec::new(),
+        old_no_newline: false,
+        new_no_newline: false,
+    };
+    *i += 1;
+    while let Some(&line) = lines.get(*i) {
+        let next_file =
+            line.starts_with("--- ") && lines.get(*i + 1).is_some_and(|l| l.starts_with("+++ "));
+        if line.starts_with("@@") || line.starts_with("diff ") || next_file {
+            break;
+        }
+        match line.chars().next() {
+            Some(' ') => hunk.lines.push(HunkLine::Context(line[1..].to_string())),
+            Some('-') => hunk.lines.push(HunkLine::Remove(line[1..].to_string())),
+            Some('+') => hunk.lines.push(HunkLine::Add(line[1..].to_string())),
+            Some('\\') => match hunk.lines.last() {
+                Some(HunkLine::Remove(_)) => hunk.old_no_newline = true,
+                Some(HunkLine::Add(_)) => hunk.new_no_newline = true,
+                Some(HunkLine::Context(_)) => {
+                    hunk.old_no_newline = true;
+                    hunk.new_no_newline = true;
+                }
+                None => {}
+            },
+            // Blank context lines lose their leading space in many editors
+            None => hunk.lines.push(HunkLine::Context(String::new())),
+            Some(_) => break,
+        }
+        *i += 1;
+    }
+    while hunk.lines.last() == Some(&HunkLine::Context(String::new())) {
+        hunk.lines.pop();
+    }
+    if hunk.lines.is_empty() {
+        bail!("Hunk `{}` is empty", header);
+    }
+    Ok(hunk)
+}
+
+/// Path of a `---`/`+++` header without timestamp and `a/`/`b/` prefix;
+/// `None` for `/dev/null`
+fn diff_path(header: &str, prefix: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn workspace(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn policy(dir: &TempDir) -> Policy {
+        Policy::new(&[dir.path().to_path_buf()], &[])
+    }
+
+    fn read(dir: &TempDir, path: &str) -> String {
+        std::fs::read_to_string(dir.path().join(path)).unwrap()
+    }
+
+    #[test]
+    fn test_apply_modifies_creates_and_deletes() {
+        let dir = workspace(&[
+            ("src/lib.rs", "fn a() {}\n\nfn b() {\n    old();\n}\n"),
+            ("old.txt", "bye\n"),
+        ]);
+        // Wrong line numbers and counts, as models often write them
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -10,3 +10,3 @@ fn a() {}
+ fn b() {
+-    old();
++    new();
+ }
+--- /dev/null
++++ b/docs/new.md
+@@ -0,0 +1,2 @@
++# New
++text
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+";
+        let dry = apply(dir.path(), diff, true, &policy(&dir)).unwrap();
+        assert!(dry.clean);
+        assert_eq!(
+            read(&dir, "src/lib.rs"),
+            "fn a() {}\n\nfn b() {\n    old();\n}\n"
+        );
+
+        let report = apply(dir.path(), diff, false, &policy(&dir)).unwrap();
+        let statuses: Vec<_> = report.files.iter().map(|f| f.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                FileStatus::Modified,
+                FileStatus::Created,
+                FileStatus::Deleted
+            ]
+        );
+        assert_eq!(
+            read(&dir, "src/lib.rs"),
+            "fn a() {}\n\nfn b() {\n    new();\n}\n"
+        );
+        assert_eq!(read(&dir, "docs/new.md"), "# New\ntext\n");
+        assert!(!dir.path().join("old.txt").exists());
+    }
+
+    #[test]
+    fn test_conflicts_leave_files_untouched() {
+        let dir = workspace(&[("a.txt", "one\ntwo\n"), ("b.txt", "x\n")]);
+        let diff = "\
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-x
++y
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ one
+-three
++four
+";
+        let report = apply(dir.path(), diff, false, &policy(&dir)).unwrap();
+        assert!(!report.clean);
+        assert_eq!(report.files[0].status, FileStatus::Modified);
+        assert_eq!(report.files[1].status, FileStatus::Conflict);
+        assert_eq!(
+            report.files[1].conflicts,
+            ["hunk 1 (-1,2 +1,2) does not match a.txt"]
+        );
+        assert_eq!(read(&dir, "b.txt"), "x\n");
+    }
+
+    #[test]
+    fn test_rejects_paths_outside_root_and_non_diffs() {
+        let dir = workspace(&[]);
+        let escape = "--- /dev/null\n+++ b/../evil.txt\n@@ -0,0 +1 @@\n+x\n";
+        assert!(apply(dir.path(), escape, true, &policy(&dir)).is_err());
+        assert!(apply(dir.path(), "just some prose", true, &policy(&dir)).is_err());
+        assert!(apply(dir.path(), "--- a/x\n+++ b/x\n", true, &policy(&dir)).is_err());
+
+        let secret = "--- /dev/null\n+++ b/.github/workflows/ci.yml\n@@ -0,0 +1 @@\n+x\n";
+        let denied = Policy::new(&[dir.path().to_path_buf()], &[".github".to_string()]);
+        let err = apply(dir.path(), secret, true, &denied).unwrap_err();
+        assert!(err.to_string().starts_with("Policy violation: "));
+    }
+
+    #[test]
+    fn test_no_newline_at_end_of_file() {
+        let dir = workspace(&[("a.txt", "one\ntwo")]);
+        let diff = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ one
+-two
+\\ No newline at end of file
++three
+";
+        assert!(apply(dir.path(), diff, false, &policy(&dir)).unwrap().clean);
+        assert_eq!(read(&dir, "a.txt"), "one\nthree\n");
+    }
+}
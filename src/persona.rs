@@ -0,0 +1,130 @@
+//! Named persona presets selected with `PERSONA`.
+//!
+//! A persona is a block of standing instructions prepended to the prompt,
+//! so several client agents can get consistently different behaviour from one
+//! server without repeating those instructions in every call. The built-in
+//! presets can be overridden, and new ones added, under `personas` in the
+//! server config.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Presets available without any configuration
+pub const BUILTIN: &[(&str, &str)] = &[
+    (
+        "terse-reviewer",
+        "You are a terse senior code reviewer. Point out bugs, risky changes and \
+         missing tests first, then style issues. Use short bullet points, cite \
+         file and line where possible, and skip praise and restatements of the code.",
+    ),
+    (
+        "teaching-assistant",
+        "You are a patient teaching assistant. Explain the reasoning behind each \
+         answer step by step, define terms the first time they appear, and end \
+         with a short summary of the key idea. Prefer small examples over long \
+         theory.",
+    ),
+    (
+        "security-auditor",
+        "You are a security auditor. Look for injection, authentication and \
+         authorization flaws, unsafe deserialization, secrets in code, and \
+         missing input validation. Rate each finding (critical, high, medium, \
+         low), explain how it could be exploited and how to fix it. Do not \
+         report style issues.",
+    ),
+];
+
+/// Instructions of persona `name`; configured presets shadow the built-in
+/// ones
+pub fn resolve(name: &str, configured: &BTreeMap<String, String>) -> Result<String> {
+    let name = name.trim();
+    if let Some(instructions) = configured.get(name) {
+        return Ok(instructions.trim().to_string());
+    }
+    if let Some((_, instructions)) = BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+        return Ok(instructions.to_string());
+    }
+    bail!(
+        "Unknown PERSONA `{}`; available: {}",
+        name,
+        names(configured).join(", ")
+    )
+}
+
+/// Names of all personas, built-in and configured, sorted
+pub fn names(configured: &BTreeMap<String, String>) -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(configured.keys().cloned())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Reject configured personas with unusable names or empty instructions
+pub fn validate(configured: &BTreeMap<String, String>) -> Result<()> {
+    for (name, instructions) in configured {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+        if name.is_empty() || name.len() > MAX_NAME_LEN || !name.chars().all(valid_char) {
+            bail!(
+                "persona name `{}` must be 1-{} characters of letters, digits, `-`, `_` or `.`",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        if instructions.trim().is_empty() {
+            bail!("persona `{}` has no instructions", name);
+        }
+    }
+    Ok(())
+}
+
+/// Preamble block carrying the persona's instructions
+pub fn block(name: &str, instructions: &str) -> String {
+    format!(
+        "System instructions (persona `{}`), follow them for the whole answer:\n{}\n",
+        name.trim(),
+        instructions.trim()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_prefers_configured_presets() {
+        let mut configured = BTreeMap::new();
+        configured.insert("terse-reviewer".to_string(), "  Be brief. ".to_string());
+        configured.insert("pirate".to_string(), "Talk like a pirate.".to_string());
+
+        assert_eq!(resolve("terse-reviewer", &configured).unwrap(), "Be brief.");
+        assert!(resolve(" security-auditor ", &configured)
+            .unwrap()
+            .starts_with("You are a security auditor."));
+        let err = resolve("poet", &configured).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown PERSONA `poet`; available: pirate, security-auditor, \
+             teaching-assistant, terse-reviewer"
+        );
+        assert!(block("pirate", "Talk like a pirate.").contains("persona `pirate`"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut configured = BTreeMap::new();
+        configured.insert("ok_name-1".to_string(), "x".to_string());
+        assert!(validate(&configured).is_ok());
+        configured.insert("bad name".to_string(), "x".to_string());
+        assert!(validate(&configured).is_err());
+
+        let mut empty = BTreeMap::new();
+        empty.insert("blank".to_string(), " ".to_string());
+        assert!(validate(&empty).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Placeholders expanded in the final prompt (`placeholders`).
+//!
+//! GEMINI.md files and tool prompts may contain `{{date}}`, `{{cwd}}`,
+//! `{{git_branch}}` and `{{env:VAR}}`, replaced just before the prompt is
+//! handed to the CLI so project instructions can carry dynamic context
+//! without a wrapper script. `{{env:VAR}}` only reads variables listed in
+//! `placeholders.env`, so a prompt cannot pull the server's credentials into
+//! the conversation. A backslash keeps a placeholder as written
+//! (`\{{date}}`), and anything else in braces is left alone.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values of the built-in placeholders for one prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    /// Current UTC date, `YYYY-MM-DD`
+    pub date: String,
+    pub cwd: PathBuf,
+    /// Checked-out branch of the repository containing `cwd`; `None` outside
+    /// a repository or on a detached HEAD
+    pub git_branch: Option<String>,
+}
+
+impl Context {
+    /// The placeholders' values for a prompt run in `cwd` at `now`
+    pub fn new(cwd: PathBuf, now: SystemTime) -> Self {
+        Self {
+            date: date(now),
+            git_branch: git_branch(&cwd),
+            cwd,
+        }
+    }
+}
+
+/// `text` with every known placeholder replaced. `env` lists the variables
+/// `{{env:VAR}}` may read; others are left in place with a warning.
+pub fn expand(text: &str, context: &Context, env: &[String], warnings: &mut Vec<String>) -> String {
+    if !text.contains("{{") {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let escaped = rest[..start].ends_with('\\');
+        out.push_str(&rest[..start - usize::from(escaped)]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let name = &after[..end];
+        let whole = &rest[start..start + 2 + end + 2];
+        rest = &after[end + 2..];
+        if escaped {
+            out.push_str(whole);
+            continue;
+        }
+        match value(name.trim(), context, env, warnings) {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(whole),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn value(
+    name: &str,
+    context: &Context,
+    env: &[String],
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    match name {
+        "date" => Some(context.date.clone()),
+        "cwd" => Some(context.cwd.display().to_string()),
+        "git_branch" => Some(context.git_branch.clone().unwrap_or_default()),
+        _ => {
+            let var = name.strip_prefix("env:")?.trim();
+            if !env.iter().any(|allowed| allowed == var) {
+                warnings.push(format!(
+                    "{{{{env:{}}}}} was not expanded: {} is not listed in placeholders.env",
+                    var, var
+                ));
+                return None;
+            }
+            Some(std::env::var(var).unwrap_or_default())
+        }
+    }
+}
+
+/// `now` as a UTC calendar date
+pub(crate) fn date(now: SystemTime) -> String {
+    let days = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // Proleptic Gregorian calendar from days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Branch checked out in the repository containing `dir`, read from
+/// `.git/HEAD` (or the worktree's HEAD a `.git` file points to)
+fn git_branch(dir: &Path) -> Option<String> {
+    let dot_git = dir
+        .ancestors()
+        .map(|d| d.join(".git"))
+        .find(|p| p.exists())?;
+    let git_dir = if dot_git.is_file() {
+        let pointer = std::fs::read_to_string(&dot_git).ok()?;
+        let target = PathBuf::from(pointer.trim().strip_prefix("gitdir:")?.trim());
+        if target.is_absolute() {
+            target
+        } else {
+            dot_git.parent()?.join(target)
+        }
+    } else {
+        dot_git
+    };
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    head.trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn context() -> Context {
+        Context {
+            date: "2024-02-29".to_string(),
+            cwd: PathBuf::from("/work/app"),
+            git_branch: Some("main".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_expands_known_placeholders() {
+        std::env::set_var("GEMINI_MCP_PLACEHOLDER_TEST", "ci");
+        let env = vec!["GEMINI_MCP_PLACEHOLDER_TEST".to_string()];
+        let mut warnings = Vec::new();
+        let expanded = expand(
+            "Today is {{date}} in {{ cwd }} on {{git_branch}} ({{env:GEMINI_MCP_PLACEHOLDER_TEST}}). \
+             Keep \\{{date}}, {{unknown}}, {{env:HOME}} and {{open",
+            &context(),
+            &env,
+            &mut warnings,
+        );
+        assert_eq!(
+            expanded,
+            "Today is 2024-02-29 in /work/app on main (ci). \
+             Keep {{date}}, {{unknown}}, {{env:HOME}} and {{open"
+        );
+        assert_eq!(
+            warnings,
+            ["{{env:HOME}} was not expanded: HOME is not listed in placeholders.env"]
+        );
+    }
+
+    #[test]
+    fn test_date_and_git_branch() {
+        assert_eq!(date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(
+            date(UNIX_EPOCH + Duration::from_secs(1_709_208_000)),
+            "2024-02-29"
+        );
+
+        let repo = tempfile::TempDir::new().unwrap();
+        let sub = repo.path().join("src");
+        std::fs::create_dir_all(repo.path().join(".git")).unwrap();
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(repo.path().join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(git_branch(&sub).as_deref(), Some("feature/x"));
+        std::fs::write(repo.path().join(".git/HEAD"), "3f2a9c1d04be\n").unwrap();
+        assert_eq!(git_branch(&sub), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Filesystem policy: where the server may read and write on a caller's
+//! behalf (`allowed_roots`, `denied_paths`).
+//!
+//! Every feature that takes a path from a call or from the model's answer
+//! checks it here: `INCLUDE_DIRECTORIES`, file and image `PROMPT` parts,
+//! `SYMBOLS`, workflow attachments, `CONTEXT_GLOBS`, the retrieval index,
+//! `gemini_apply_patch` and `WRITE_ARTIFACTS`. A path must resolve, after
+//! following symlinks, to somewhere inside an allowed root, and must not
+//! match a denied pattern. Denied patterns use the `CONTEXT_GLOBS` syntax and
+//! match anywhere below the root, so `.env` denies every `.env` file and
+//! `.git` everything inside any `.git` directory; as in `.gitignore`, a
+//! pattern starting with `/` only matches from the root.
+
+use crate::gemini;
+use crate::glob;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// A path the policy does not allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Policy violation: {} {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Allowed roots and denied patterns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    /// Canonical roots; roots that do not exist are dropped
+    roots: Vec<PathBuf>,
+    denied: Vec<String>,
+}
+
+impl Policy {
+    pub fn new(roots: &[PathBuf], denied: &[String]) -> Self {
+        Self {
+            roots: roots
+                .iter()
+                .filter_map(|root| root.canonicalize().ok())
+                .collect(),
+            denied: denied
+                .iter()
+                .map(|pattern| pattern.trim().trim_end_matches('/').to_string())
+                .filter(|pattern| !pattern.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The policy of the server config
+    pub fn current() -> Self {
+        Self::new(&gemini::allowed_roots(), gemini::denied_paths())
+    }
+
+    /// `path` (relative to the working directory unless absolute) resolved
+    /// through symlinks, if the policy allows it. Paths that do not exist
+    /// yet are resolved from their nearest existing ancestor.
+    pub fn check(&self, path: &Path) -> Result<PathBuf, Violation> {
+        let resolved = resolve(path);
+        let Some(relative) = self
+            .roots
+            .iter()
+            .find_map(|root| resolved.strip_prefix(root).ok())
+        else {
+            return Err(Violation {
+                path: resolved,
+                reason: format!(
+                    "is outside the allowed roots ({})",
+                    self.roots
+                        .iter()
+                        .map(|root| root.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        };
+        if let Some(pattern) = self.denied_pattern(relative) {
+            return Err(Violation {
+                reason: format!("matches the denied pattern `{}`", pattern),
+                path: resolved,
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// The first denied pattern matching `path`, relative to an allowed
+    /// root, or one of its directories
+    pub fn denied_pattern(&self, path: &Path) -> Option<&str> {
+        if self.denied.is_empty() {
+            return None;
+        }
+        let parts: Vec<String> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        self.denied
+            .iter()
+            .find(|pattern| {
+                let anchored = pattern.strip_prefix('/');
+                (0..parts.len()).any(|start| {
+                    (start + 1..=parts.len()).any(|end| {
+                        let joined = parts[start..end].join("/");
+                        match anchored {
+                            Some(pattern) => start == 0 && glob::matches(pattern, &joined),
+                            None => glob::matches(pattern, &joined),
+                        }
+                    })
+                })
+            })
+            .map(String::as_str)
+    }
+}
+
+/// `path` made absolute, with its longest existing prefix canonicalized and
+/// the rest normalized lexically
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+    let existing = absolute
+        .ancestors()
+        .find_map(|ancestor| Some((ancestor, ancestor.canonicalize().ok()?)));
+    let (mut resolved, rest) = match existing {
+        Some((ancestor, canonical)) => (
+            canonical,
+            absolute.strip_prefix(ancestor).unwrap_or(Path::new("")),
+        ),
+        None => (PathBuf::new(), absolute.as_path()),
+    };
+    for component in rest.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(part) => resolved.push(part),
+            Component::RootDir | Component::Prefix(_) => resolved.push(component),
+            Component::CurDir => {}
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_must_stay_inside_roots() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("repo");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "x").unwrap();
+        let policy = Policy::new(&[root.clone(), dir.path().join("missing")], &[]);
+        let root = root.canonicalize().unwrap();
+
+        assert_eq!(
+            policy.check(&root.join("src/../src/new.rs")).unwrap(),
+            root.join("src/new.rs")
+        );
+        let err = policy.check(&root.join("../secret.txt")).unwrap_err();
+        assert!(err.to_string().starts_with("Policy violation: "));
+        assert!(err.reason.starts_with("is outside the allowed roots"));
+        assert!(policy.check(&root.join("new/../../secret.txt")).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("link")).unwrap();
+            assert!(policy.check(&root.join("link")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_denied_patterns_match_anywhere() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let denied = [".env", ".git", "*.pem", "secrets/**", "/etc/**"].map(String::from);
+        let policy = Policy::new(&[dir.path().to_path_buf()], &denied);
+        let root = dir.path().canonicalize().unwrap();
+
+        for path in [
+            "app/.env",
+            ".git/config",
+            "keys/server.pem",
+            "deploy/secrets/token",
+        ] {
+            let err = policy.check(&root.join(path)).unwrap_err();
+            assert!(err.reason.contains("denied pattern"), "{}", path);
+        }
+        assert!(policy.check(&root.join("src/lib.rs")).is_ok());
+        assert!(policy.check(&root.join("app/.envrc")).is_ok());
+        assert_eq!(
+            policy.denied_pattern(Path::new("etc/passwd")),
+            Some("/etc/**")
+        );
+        assert_eq!(policy.denied_pattern(Path::new("srv/etc/passwd")), None);
+    }
+}
@@ -0,0 +1,311 @@
+//! Optional pool of pre-started CLI processes (`pool` in the server config).
+//!
+//! Starting the Node-based CLI costs one to three seconds per call, most of
+//! it spent before the CLI reads its prompt. With the pool enabled, one-shot
+//! runs get their prompt through stdin, and every such run starts another
+//! process with the same command line, environment and working directory in
+//! the background, left waiting on stdin. The next matching run writes its
+//! prompt to that process instead of starting one. Resumed sessions are never
+//! pooled. Waiting processes are killed once idle for `idle_timeout_secs`,
+//! and the oldest ones when more than `size` are waiting.
+
+use crate::process_tree::ProcessTree;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+
+/// The `pool` section of the server config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Most processes kept waiting at once, across all command lines
+    pub size: Option<usize>,
+    /// How long a process may wait before it is killed
+    pub idle_timeout_secs: Option<u64>,
+}
+
+const DEFAULT_SIZE: usize = 2;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 300;
+
+/// Counters reported by the `gemini_stats` tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Processes currently waiting for a prompt
+    pub warm: usize,
+    pub size: usize,
+    pub idle_timeout_secs: u64,
+    /// Runs that took a waiting process
+    pub hits: u64,
+    /// Runs that had to start their own
+    pub misses: u64,
+    /// Waiting processes killed for idling, to make room, or found dead
+    pub recycled: u64,
+}
+
+/// What a waiting process must share with a run to take its prompt
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    argv: Vec<OsString>,
+    env: Vec<(OsString, Option<OsString>)>,
+    cwd: Option<PathBuf>,
+}
+
+impl PoolKey {
+    /// Key of the processes `cmd` starts
+    pub fn of(cmd: &Command) -> Self {
+        let cmd = cmd.as_std();
+        let mut env: Vec<_> = cmd
+            .get_envs()
+            .map(|(key, value)| (key.to_os_string(), value.map(|v| v.to_os_string())))
+            .collect();
+        env.sort();
+        Self {
+            argv: std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_os_string())
+                .collect(),
+            env,
+            cwd: cmd.get_current_dir().map(|dir| dir.to_path_buf()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Warm {
+    child: Child,
+    started: Instant,
+}
+
+impl Warm {
+    /// Kill the process and everything it started
+    fn recycle(self) {
+        ProcessTree::attach(&self.child).kill();
+        // `kill_on_drop` reaps the process
+        drop(self.child);
+    }
+}
+
+/// Waiting processes by key
+#[derive(Debug)]
+pub struct WarmPool {
+    size: usize,
+    idle_timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    warm: HashMap<PoolKey, Vec<Warm>>,
+    stats: PoolStats,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.warm.values().map(Vec::len).sum()
+    }
+
+    /// Remove the processes `stale` picks out
+    fn remove(&mut self, stale: impl Fn(&mut Warm) -> bool) -> Vec<Warm> {
+        let mut removed = Vec::new();
+        for waiting in self.warm.values_mut() {
+            let mut i = 0;
+            while i < waiting.len() {
+                if stale(&mut waiting[i]) {
+                    removed.push(waiting.remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.warm.retain(|_, waiting| !waiting.is_empty());
+        self.stats.recycled += removed.len() as u64;
+        removed
+    }
+}
+
+impl WarmPool {
+    pub fn new(config: &PoolConfig) -> Self {
+        Self {
+            size: config.size.filter(|&n| n > 0).unwrap_or(DEFAULT_SIZE),
+            idle_timeout: Duration::from_secs(
+                config
+                    .idle_timeout_secs
+                    .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+            ),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A process for `key` that is still waiting, oldest first
+    pub fn take(&self, key: &PoolKey) -> Option<Child> {
+        let mut state = self.state.lock().unwrap();
+        let mut dead = Vec::new();
+        let mut taken = None;
+        if let Some(waiting) = state.warm.get_mut(key) {
+            while !waiting.is_empty() {
+                let mut warm = waiting.remove(0);
+                let alive = matches!(warm.child.try_wait(), Ok(None));
+                if alive && warm.started.elapsed() < self.idle_timeout {
+                    taken = Some(warm.child);
+                    break;
+                }
+                dead.push(warm);
+            }
+            if waiting.is_empty() {
+                state.warm.remove(key);
+            }
+        }
+        state.stats.recycled += dead.len() as u64;
+        match taken {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1,
+        }
+        drop(state);
+        dead.into_iter().for_each(Warm::recycle);
+        taken
+    }
+
+    /// Start a process from `cmd` to wait for the next run with `key`,
+    /// killing the oldest waiting process when the pool is full. Must be
+    /// called within a Tokio runtime.
+    pub fn refill(&'static self, key: PoolKey, cmd: &mut Command) {
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                tracing::warn!("failed to start a pooled gemini process: {}", err);
+                return;
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut evicted = Vec::new();
+        while state.len() >= self.size {
+            let oldest = state.warm.values().flatten().map(|warm| warm.started).min();
+            match oldest {
+                Some(oldest) => evicted.extend(state.remove(|warm| warm.started == oldest)),
+                None => break,
+            }
+        }
+        state.warm.entry(key).or_default().push(Warm {
+            child,
+            started: Instant::now(),
+        });
+        drop(state);
+        evicted.into_iter().for_each(Warm::recycle);
+
+        let idle_timeout = self.idle_timeout;
+        tokio::spawn(async move {
+            tokio::time::sleep(idle_timeout).await;
+            self.expire();
+        });
+    }
+
+    /// Kill the processes idle for the idle timeout or longer
+    pub fn expire(&self) {
+        let idle_timeout = self.idle_timeout;
+        let expired = self
+            .state
+            .lock()
+            .unwrap()
+            .remove(|warm| warm.started.elapsed() >= idle_timeout);
+        expired.into_iter().for_each(Warm::recycle);
+    }
+
+    /// Kill every waiting process, e.g. on shutdown. A process whose stdin
+    /// closed would otherwise run with an empty prompt.
+    pub fn clear(&self) {
+        let all = self.state.lock().unwrap().remove(|_| true);
+        all.into_iter().for_each(Warm::recycle);
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+        PoolStats {
+            warm: state.len(),
+            size: self.size,
+            idle_timeout_secs: self.idle_timeout.as_secs(),
+            ..state.stats
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn echo_stdin() -> Command {
+        let mut cmd = Command::new("cat");
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        crate::process_tree::configure(&mut cmd);
+        cmd
+    }
+
+    fn pool(size: usize, idle_timeout_secs: u64) -> &'static WarmPool {
+        Box::leak(Box::new(WarmPool::new(&PoolConfig {
+            enabled: true,
+            size: Some(size),
+            idle_timeout_secs: Some(idle_timeout_secs),
+        })))
+    }
+
+    #[tokio::test]
+    async fn test_waiting_process_takes_the_prompt() {
+        let pool = pool(2, 60);
+        let mut cmd = echo_stdin();
+        let key = PoolKey::of(&cmd);
+        assert!(pool.take(&key).is_none());
+        pool.refill(key.clone(), &mut cmd);
+
+        let mut other = echo_stdin();
+        other.arg("-u");
+        assert!(pool.take(&PoolKey::of(&other)).is_none());
+
+        let mut child = pool.take(&key).unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"hello").await.unwrap();
+        drop(stdin);
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, "hello");
+
+        let stats = pool.stats();
+        assert_eq!((stats.warm, stats.hits, stats.misses), (0, 1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_oldest_and_idle_processes_are_recycled() {
+        let pool = pool(1, 60);
+        let mut first = echo_stdin();
+        let mut second = echo_stdin();
+        second.arg("-");
+        pool.refill(PoolKey::of(&first), &mut first);
+        pool.refill(PoolKey::of(&second), &mut second);
+        assert_eq!(pool.stats().warm, 1);
+        assert!(pool.take(&PoolKey::of(&first)).is_none());
+        assert_eq!(pool.stats().recycled, 1);
+
+        let idle = self::pool(2, 0);
+        idle.refill(PoolKey::of(&first), &mut first);
+        idle.expire();
+        let stats = idle.stats();
+        assert_eq!((stats.warm, stats.recycled), (0, 1));
+
+        pool.clear();
+        assert_eq!(pool.stats().warm, 0);
+    }
+}
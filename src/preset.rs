@@ -0,0 +1,116 @@
+//! Model presets, each registered as its own MCP tool.
+//!
+//! A preset is the `gemini` tool with fixed CLI arguments and timeout, e.g. a
+//! `gemini_flash` tool for quick questions next to a `gemini_pro` tool for
+//! hard ones. Agents pick between them by name and description instead of
+//! remembering which `EXTRA_ARGS` select which model. Presets are declared
+//! under `presets` in the server config.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+/// Longest tool name accepted by MCP clients
+const MAX_NAME_LEN: usize = 64;
+
+/// One preset, as configured under `presets`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPreset {
+    /// Tool name, e.g. `gemini_flash`
+    pub name: String,
+    /// Tool description; a generated one naming the arguments otherwise
+    pub description: Option<String>,
+    /// CLI arguments of every call, e.g. `["--model", "gemini-2.5-flash"]`.
+    /// Like `additional_args` they are not checked against the allowlist.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Timeout of the preset's calls (default `timeout_secs`)
+    pub timeout_secs: Option<u64>,
+}
+
+impl ModelPreset {
+    /// Description of the preset's tool
+    pub fn tool_description(&self) -> String {
+        match self.description.as_deref().map(str::trim) {
+            Some(description) if !description.is_empty() => description.to_string(),
+            _ if self.args.is_empty() => format!(
+                "Invokes the Gemini CLI through the `{}` preset. Takes the same parameters and returns the same output as the gemini tool.",
+                self.name
+            ),
+            _ => format!(
+                "Invokes the Gemini CLI with `{}`. Takes the same parameters and returns the same output as the gemini tool.",
+                self.args.join(" ")
+            ),
+        }
+    }
+}
+
+/// Reject presets with unusable or duplicate names and zero timeouts.
+/// Clashes with the server's built-in tools are checked when the tools are
+/// registered.
+pub fn validate(presets: &[ModelPreset]) -> Result<()> {
+    let mut seen = BTreeSet::new();
+    for preset in presets {
+        let name = preset.name.as_str();
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+        if name.is_empty() || name.len() > MAX_NAME_LEN || !name.chars().all(valid_char) {
+            bail!(
+                "preset name `{}` must be 1-{} characters of letters, digits, `-`, `_` or `.`",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        if !seen.insert(name) {
+            bail!("preset `{}` is declared more than once", name);
+        }
+        if preset.timeout_secs == Some(0) {
+            bail!("preset `{}` has a timeout of zero", name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(name: &str, args: &[&str]) -> ModelPreset {
+        ModelPreset {
+            name: name.to_string(),
+            description: None,
+            args: args.iter().map(|a| a.to_string()).collect(),
+            timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let flash = preset("gemini_flash", &["--model", "gemini-2.5-flash"]);
+        let pro = preset("gemini_pro", &["--model", "gemini-2.5-pro"]);
+        assert!(validate(&[flash.clone(), pro]).is_ok());
+        assert!(validate(&[flash.clone(), flash.clone()]).is_err());
+        assert!(validate(&[preset("gemini flash", &[])]).is_err());
+        assert!(validate(&[ModelPreset {
+            timeout_secs: Some(0),
+            ..flash
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn test_tool_description() {
+        let flash = preset("gemini_flash", &["--model", "gemini-2.5-flash"]);
+        assert!(flash
+            .tool_description()
+            .starts_with("Invokes the Gemini CLI with `--model gemini-2.5-flash`."));
+        let described = ModelPreset {
+            description: Some(" Fast answers to small questions. ".to_string()),
+            ..flash
+        };
+        assert_eq!(
+            described.tool_description(),
+            "Fast answers to small questions."
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Terminating the whole Gemini CLI process tree.
+//!
+//! Killing only the `gemini` process leaves the sub-processes the Node CLI
+//! starts (sandboxes, MCP stdio servers) running. On Unix the CLI is spawned
+//! as the leader of its own process group; on Windows it is assigned to a Job
+//! Object. [`ProcessTree::kill`] then terminates every process in the group or
+//! job.
+
+use tokio::process::{Child, Command};
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Prepare `cmd` so the spawned process and its descendants can be killed
+/// together
+pub(crate) fn configure(cmd: &mut Command) {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    // Batch shims would otherwise open a console window per run
+    #[cfg(windows)]
+    cmd.creation_flags(CREATE_NO_WINDOW);
+}
+
+/// Handle on the process tree of a spawned CLI run. Unless [`Self::disarm`]
+/// is called after the run completes normally, dropping the handle (e.g. when
+/// the call is cancelled) kills the tree.
+#[derive(Debug)]
+pub(crate) struct ProcessTree {
+    #[cfg(unix)]
+    pgid: Option<libc::pid_t>,
+    #[cfg(windows)]
+    job: Option<windows::Job>,
+    armed: bool,
+}
+
+impl ProcessTree {
+    /// Track the tree rooted at `child`, which must have been spawned from a
+    /// command passed to [`configure`]
+    pub(crate) fn attach(child: &Child) -> Self {
+        Self {
+            #[cfg(unix)]
+            pgid: child.id().and_then(|pid| libc::pid_t::try_from(pid).ok()),
+            #[cfg(windows)]
+            job: windows::Job::assign(child),
+            armed: true,
+        }
+    }
+
+    /// Kill every process in the tree. Safe to call more than once.
+    pub(crate) fn kill(&mut self) {
+        if !self.armed {
+            return;
+        }
+        self.armed = false;
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            // SAFETY: killpg has no memory-safety preconditions
+            unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(ref job) = self.job {
+            job.terminate();
+        }
+    }
+
+    /// The run finished on its own; leave any remaining processes alone
+    pub(crate) fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ptr;
+    use tokio::process::Child;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject,
+    };
+
+    /// Owned Job Object handle
+    #[derive(Debug)]
+    pub(super) struct Job(HANDLE);
+
+    // SAFETY: a job handle may be used and closed from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        /// Create a job and put `child` in it. Processes the child starts
+        /// afterwards join the job automatically.
+        pub(super) fn assign(child: &Child) -> Option<Self> {
+            let process = child.raw_handle()? as HANDLE;
+            // SAFETY: null attributes and name create an anonymous job
+            let handle = unsafe { CreateJobObjectW(ptr::null(), ptr::null()) };
+            if handle.is_null() {
+                return None;
+            }
+            let job = Self(handle);
+            // SAFETY: both handles are valid for the duration of the call
+            if unsafe { AssignProcessToJobObject(job.0, process) } == 0 {
+                return None;
+            }
+            Some(job)
+        }
+
+        pub(super) fn terminate(&self) {
+            // SAFETY: the handle is owned and open
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: the handle is owned and closed exactly once
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use std::time::Duration;
+
+    /// Running and not yet a zombie waiting to be reaped
+    fn alive(pid: libc::pid_t) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .ok()
+            .and_then(|stat| {
+                let state = stat.rsplit_once(')')?.1.trim_start().chars().next()?;
+                Some(state != 'Z' && state != 'X')
+            })
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn test_kill_terminates_grandchildren() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("sleep 30 & echo $!; wait")
+            .stdout(Stdio::piped());
+        configure(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+        let mut tree = ProcessTree::attach(&child);
+
+        let stdout = child.stdout.take().unwrap();
+        let mut lines = tokio::io::AsyncBufReadExt::lines(tokio::io::BufReader::new(stdout));
+        let grandchild: libc::pid_t = lines.next_line().await.unwrap().unwrap().parse().unwrap();
+        assert!(alive(grandchild));
+
+        tree.kill();
+        child.wait().await.unwrap();
+        for _ in 0..50 {
+            if !alive(grandchild) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive(grandchild));
+    }
+}
@@ -0,0 +1,160 @@
+//! `PROMPT` given as an array of typed parts.
+//!
+//! Structured callers can send `PROMPT` as
+//! `[{"type": "text", "text": "Review"}, {"type": "file", "path": "src/lib.rs"}]`
+//! instead of concatenating strings with markers themselves. The parts are
+//! assembled into one prompt, separated by blank lines. Files and images are
+//! referenced with the CLI's `@path` syntax so the CLI reads them itself
+//! (images are sent to the model as inline data); URLs are listed for its
+//! `web_fetch` tool.
+
+use crate::policy::Policy;
+use rmcp::schemars;
+use serde::{de, Deserialize, Deserializer};
+use std::path::{Component, Path};
+
+/// Extensions the CLI sends to the model as images
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "svg", "bmp"];
+
+/// A prompt string, or parts assembled into one
+#[derive(Debug, Clone, Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum PromptInput {
+    Text(String),
+    Parts(Vec<PromptPart>),
+}
+
+/// One part of a `PROMPT` array
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PromptPart {
+    /// Text included as is
+    Text { text: String },
+    /// Workspace file whose content the CLI attaches, relative to the
+    /// working directory
+    File { path: String },
+    /// Workspace image (png, jpg, gif, webp, svg, bmp) the model sees
+    Image { path: String },
+    /// Web page the model may fetch
+    Url { url: String },
+}
+
+impl PromptPart {
+    fn render(&self) -> Result<String, String> {
+        match self {
+            Self::Text { text } => Ok(text.clone()),
+            Self::File { path } => Ok(format!("@{}", workspace_path(path)?)),
+            Self::Image { path } => {
+                let extension = Path::new(path.trim())
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+                if !extension.is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str())) {
+                    return Err(format!(
+                        "image part {} must be one of: {}",
+                        path,
+                        IMAGE_EXTENSIONS.join(", ")
+                    ));
+                }
+                Ok(format!("@{}", workspace_path(path)?))
+            }
+            Self::Url { url } => {
+                let url = url.trim();
+                if !(url.starts_with("https://") || url.starts_with("http://"))
+                    || url.contains(char::is_whitespace)
+                {
+                    return Err(format!("url part {:?} must be an http(s) URL", url));
+                }
+                Ok(format!("URL: {}", url))
+            }
+        }
+    }
+}
+
+/// `path` checked to stay inside the working directory and to be allowed by
+/// the [`Policy`], with spaces escaped the way `@` references expect
+fn workspace_path(path: &str) -> Result<String, String> {
+    let trimmed = path.trim();
+    if trimmed.is_empty() {
+        return Err("file and image parts need a path".to_string());
+    }
+    let inside = Path::new(trimmed)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(format!(
+            "{} must be a relative path inside the working directory",
+            trimmed
+        ));
+    }
+    Policy::current()
+        .check(Path::new(trimmed))
+        .map_err(|violation| violation.to_string())?;
+    Ok(trimmed.replace(' ', "\\ "))
+}
+
+/// Assemble `parts` into one prompt
+pub fn assemble(parts: &[PromptPart]) -> Result<String, String> {
+    if parts.is_empty() {
+        return Err("PROMPT must not be an empty array".to_string());
+    }
+    let rendered = parts
+        .iter()
+        .map(PromptPart::render)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rendered.join("\n\n"))
+}
+
+/// `deserialize_with` for `PROMPT` fields accepting a string or parts
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match PromptInput::deserialize(deserializer)? {
+        PromptInput::Text(text) => Ok(text),
+        PromptInput::Parts(parts) => assemble(&parts).map_err(de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize)]
+    struct Args {
+        #[serde(rename = "PROMPT", deserialize_with = "deserialize")]
+        prompt: String,
+    }
+
+    fn prompt(value: serde_json::Value) -> Result<String, String> {
+        serde_json::from_value::<Args>(json!({ "PROMPT": value }))
+            .map(|args| args.prompt)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_parts_are_assembled() {
+        assert_eq!(prompt(json!("plain")).unwrap(), "plain");
+        assert_eq!(
+            prompt(json!([
+                {"type": "text", "text": "Review this change"},
+                {"type": "file", "path": "src/my file.rs"},
+                {"type": "image", "path": "docs/Diagram.PNG"},
+                {"type": "url", "url": "https://example.com/spec"}
+            ]))
+            .unwrap(),
+            "Review this change\n\n@src/my\\ file.rs\n\n@docs/Diagram.PNG\n\nURL: https://example.com/spec"
+        );
+    }
+
+    #[test]
+    fn test_invalid_parts_are_rejected() {
+        for value in [
+            json!([]),
+            json!([{"type": "file", "path": "../secret"}]),
+            json!([{"type": "file", "path": "/etc/passwd"}]),
+            json!([{"type": "image", "path": "notes.txt"}]),
+            json!([{"type": "url", "url": "file:///etc/passwd"}]),
+            json!([{"type": "video", "path": "a.mp4"}]),
+        ] {
+            assert!(prompt(value.clone()).is_err(), "{}", value);
+        }
+    }
+}
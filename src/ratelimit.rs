@@ -0,0 +1,374 @@
+//! Per-minute caps on calls and tokens (`rate_limit`).
+//!
+//! Every cap is a token bucket holding up to a minute's allowance and
+//! refilling continuously, so short bursts pass while the sustained rate
+//! stays under the cap. Calls are counted when they start; tokens are only
+//! known once a run finishes, so they are charged afterwards and a bucket
+//! may go into debt, holding back the next calls until it has refilled.
+//! Caps apply across the server, per MCP client (by the name it connects
+//! with) and per resumed session.
+
+use crate::gemini::RateLimitConfig;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Which cap rejected a call
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitScope {
+    Global,
+    Client(String),
+    Session(String),
+}
+
+/// What a cap counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Calls,
+    Tokens,
+}
+
+impl Unit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Calls => "calls",
+            Self::Tokens => "tokens",
+        }
+    }
+}
+
+/// A call rejected because a per-minute cap was reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    pub scope: RateLimitScope,
+    pub unit: Unit,
+    pub limit: u64,
+    /// Time until the cap admits a call again
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Seconds to wait, rounded up and at least 1
+    pub fn retry_after_secs(&self) -> u64 {
+        let secs = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        secs.max(1)
+    }
+
+    /// Machine-readable details for the MCP error's `data`
+    pub fn data(&self) -> Value {
+        let (scope, name) = match self.scope {
+            RateLimitScope::Global => ("global", None),
+            RateLimitScope::Client(ref name) => ("client", Some(name)),
+            RateLimitScope::Session(ref id) => ("session", Some(id)),
+        };
+        let mut data = json!({
+            "error": "rate_limited",
+            "scope": scope,
+            "unit": self.unit.as_str(),
+            "limit_per_minute": self.limit,
+            "retry_after_secs": self.retry_after_secs(),
+        });
+        if let Some(name) = name {
+            data[scope] = json!(name);
+        }
+        data
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let retry = self.retry_after_secs();
+        let unit = self.unit.as_str();
+        match self.scope {
+            RateLimitScope::Global => write!(
+                f,
+                "Rate limit exceeded: at most {} {} per minute; retry in {}s",
+                self.limit, unit, retry
+            ),
+            RateLimitScope::Client(ref name) => write!(
+                f,
+                "Rate limit exceeded for client {}: at most {} {} per minute; retry in {}s",
+                name, self.limit, unit, retry
+            ),
+            RateLimitScope::Session(ref id) => write!(
+                f,
+                "Rate limit exceeded for SESSION_ID {}: at most {} turns per minute; retry in {}s",
+                id, self.limit, retry
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// One call or token, in the bucket's fixed-point scale: a bucket with a cap
+/// of `limit` per minute refills `limit` of these per nanosecond, which
+/// keeps the arithmetic exact
+const UNIT: i128 = WINDOW.as_nanos() as i128;
+
+/// Allowance left under one cap, in [`UNIT`]s
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Negative after a run used more tokens than were left
+    level: i128,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_nanos() as i128;
+        self.level = (self.level + i128::from(limit) * elapsed).min(capacity(limit));
+        self.updated = now;
+    }
+
+    /// Time until the bucket holds one unit
+    fn wait(&self, limit: u64) -> Duration {
+        let missing = (UNIT - self.level).max(0) as u128;
+        let limit = u128::from(limit);
+        Duration::from_nanos(missing.div_ceil(limit) as u64)
+    }
+}
+
+fn capacity(limit: u64) -> i128 {
+    i128::from(limit) * UNIT
+}
+
+/// Token-bucket limiter with global, per-client and per-session caps, so one
+/// busy client or session cannot use up the whole server's quota
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<(RateLimitScope, Unit), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit a call from `client` for `session_id` (or a new session),
+    /// counting it against every call cap. A rejected call is not counted.
+    pub fn check(&self, client: Option<&str>, session_id: Option<&str>) -> Result<(), RateLimited> {
+        self.check_at(client, session_id, Instant::now())
+    }
+
+    /// Charge the `tokens` a finished run of `client` used
+    pub fn charge_tokens(&self, client: Option<&str>, tokens: u64) {
+        self.charge_at(client, tokens, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        client: Option<&str>,
+        session_id: Option<&str>,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let mut buckets = self.buckets.lock().unwrap();
+        self.prune(&mut buckets, now);
+
+        let mut scopes = vec![RateLimitScope::Global];
+        scopes.extend(client.map(|name| RateLimitScope::Client(name.to_string())));
+        scopes.extend(session_id.map(|id| RateLimitScope::Session(id.to_string())));
+        let mut admitted = Vec::new();
+        for scope in scopes {
+            for unit in [Unit::Calls, Unit::Tokens] {
+                let Some(limit) = self.limit(&scope, unit) else {
+                    continue;
+                };
+                let key = (scope.clone(), unit);
+                let bucket = bucket(&mut buckets, key.clone(), limit, now);
+                if bucket.level < UNIT {
+                    return Err(RateLimited {
+                        retry_after: bucket.wait(limit),
+                        scope,
+                        unit,
+                        limit,
+                    });
+                }
+                if unit == Unit::Calls {
+                    admitted.push(key);
+                }
+            }
+        }
+        for key in admitted {
+            if let Some(bucket) = buckets.get_mut(&key) {
+                bucket.level -= UNIT;
+            }
+        }
+        Ok(())
+    }
+
+    fn charge_at(&self, client: Option<&str>, tokens: u64, now: Instant) {
+        if tokens == 0 {
+            return;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut scopes = vec![RateLimitScope::Global];
+        scopes.extend(client.map(|name| RateLimitScope::Client(name.to_string())));
+        for scope in scopes {
+            if let Some(limit) = self.limit(&scope, Unit::Tokens) {
+                bucket(&mut buckets, (scope, Unit::Tokens), limit, now).level -=
+                    i128::from(tokens) * UNIT;
+            }
+        }
+    }
+
+    /// The per-minute cap of `unit` in `scope`, if any
+    fn limit(&self, scope: &RateLimitScope, unit: Unit) -> Option<u64> {
+        let config = &self.config;
+        match (scope, unit) {
+            (RateLimitScope::Global, Unit::Calls) => config.global_per_minute.map(u64::from),
+            (RateLimitScope::Global, Unit::Tokens) => config.global_tokens_per_minute,
+            (RateLimitScope::Client(name), Unit::Calls) => config
+                .clients
+                .get(name)
+                .and_then(|c| c.per_minute)
+                .or(config.per_client_per_minute)
+                .map(u64::from),
+            (RateLimitScope::Client(name), Unit::Tokens) => config
+                .clients
+                .get(name)
+                .and_then(|c| c.tokens_per_minute)
+                .or(config.per_client_tokens_per_minute),
+            (RateLimitScope::Session(_), Unit::Calls) => {
+                config.per_session_per_minute.map(u64::from)
+            }
+            (RateLimitScope::Session(_), Unit::Tokens) => None,
+        }
+    }
+
+    /// Forget refilled buckets of clients and sessions; they start out full
+    fn prune(&self, buckets: &mut HashMap<(RateLimitScope, Unit), Bucket>, now: Instant) {
+        buckets.retain(|(scope, unit), bucket| {
+            let Some(limit) = self.limit(scope, *unit) else {
+                return false;
+            };
+            bucket.refill(limit, now);
+            *scope == RateLimitScope::Global || bucket.level < capacity(limit)
+        });
+    }
+}
+
+/// The bucket of `key`, refilled to `now`; a new bucket starts full
+fn bucket(
+    buckets: &mut HashMap<(RateLimitScope, Unit), Bucket>,
+    key: (RateLimitScope, Unit),
+    limit: u64,
+    now: Instant,
+) -> &mut Bucket {
+    let bucket = buckets.entry(key).or_insert(Bucket {
+        level: capacity(limit),
+        updated: now,
+    });
+    bucket.refill(limit, now);
+    bucket
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::ClientRateLimit;
+
+    fn limiter(global: Option<u32>, per_session: Option<u32>) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            global_per_minute: global,
+            per_session_per_minute: per_session,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_per_session_cap_does_not_starve_other_sessions() {
+        let limiter = limiter(Some(10), Some(2));
+        let now = Instant::now();
+
+        assert!(limiter.check_at(None, Some("a"), now).is_ok());
+        assert!(limiter.check_at(None, Some("a"), now).is_ok());
+        let err = limiter.check_at(None, Some("a"), now).unwrap_err();
+        assert_eq!(err.scope, RateLimitScope::Session("a".to_string()));
+        assert_eq!(err.retry_after, Duration::from_secs(30));
+
+        assert!(limiter.check_at(None, Some("b"), now).is_ok());
+        assert!(limiter.check_at(None, None, now).is_ok());
+
+        // The bucket refills: half a minute later session `a` gets a turn
+        assert!(limiter
+            .check_at(None, Some("a"), now + Duration::from_secs(30))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_global_cap() {
+        let limiter = limiter(Some(2), None);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(None, Some("a"), now).is_ok());
+        assert!(limiter.check_at(None, Some("b"), now).is_ok());
+        let err = limiter
+            .check_at(None, None, now + Duration::from_secs(15))
+            .unwrap_err();
+        assert_eq!(err.scope, RateLimitScope::Global);
+        assert_eq!(err.retry_after, Duration::from_secs(15));
+        assert_eq!(err.retry_after_secs(), 15);
+        assert_eq!(err.data()["scope"], "global");
+        assert!(limiter
+            .check_at(None, None, now + Duration::from_secs(30))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_client_caps_and_tokens() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_client_per_minute: Some(1),
+            per_client_tokens_per_minute: Some(1_000),
+            clients: [(
+                "ci".to_string(),
+                ClientRateLimit {
+                    per_minute: Some(5),
+                    tokens_per_minute: None,
+                },
+            )]
+            .into(),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        assert!(limiter.check_at(Some("ide"), None, now).is_ok());
+        let err = limiter.check_at(Some("ide"), None, now).unwrap_err();
+        assert_eq!(err.scope, RateLimitScope::Client("ide".to_string()));
+        assert_eq!(err.unit, Unit::Calls);
+        assert_eq!(err.data()["client"], "ide");
+        assert!(limiter.check_at(Some("other"), None, now).is_ok());
+        for _ in 0..5 {
+            assert!(limiter.check_at(Some("ci"), None, now).is_ok());
+        }
+
+        // A run that used three minutes' worth of tokens holds the client
+        // back until the debt is refilled, two minutes later
+        let later = now + WINDOW;
+        limiter.charge_at(Some("ide"), 2_999, now);
+        let err = limiter.check_at(Some("ide"), None, later).unwrap_err();
+        assert_eq!(err.unit, Unit::Tokens);
+        assert_eq!(err.retry_after, WINDOW);
+        assert!(err.to_string().contains("at most 1000 tokens per minute"));
+        assert!(limiter.check_at(Some("ide"), None, later + WINDOW).is_ok());
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limiter = limiter(None, None);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert!(limiter.check_at(Some("c"), Some("a"), now).is_ok());
+        }
+        limiter.charge_at(Some("c"), 1_000_000, now);
+        assert!(limiter.check_at(Some("c"), None, now).is_ok());
+    }
+}
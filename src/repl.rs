@@ -0,0 +1,222 @@
+//! Local interactive loop (`gemini-mcp-rs repl`) running prompts through the
+//! same pipeline as the `gemini` MCP tool.
+
+use crate::auth::{self, AuthProfile};
+use crate::gemini;
+use crate::server::{GeminiArgs, GeminiServer};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::Write;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "Commands:
+  /model [NAME]   Show or set the model for following prompts (`/model default` clears it)
+  /profile [NAME] Show or set the auth profile (`/profile default` clears it)
+  /new            Start a new session
+  /session        Show the current SESSION_ID
+  /help           Show this help
+  /quit, /exit    Leave the REPL
+Anything else is sent to Gemini as a prompt.";
+
+/// A parsed line of REPL input
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    Prompt(String),
+    Model(Option<String>),
+    Profile(Option<String>),
+    New,
+    Session,
+    Help,
+    Quit,
+    Empty,
+    Unknown(String),
+}
+
+fn parse_input(line: &str) -> Input {
+    let line = line.trim();
+    if line.is_empty() {
+        return Input::Empty;
+    }
+    let Some(command) = line.strip_prefix('/') else {
+        return Input::Prompt(line.to_string());
+    };
+
+    let (name, rest) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, rest)| (name, rest.trim()));
+    match name {
+        "model" => Input::Model((!rest.is_empty()).then(|| rest.to_string())),
+        "profile" => Input::Profile((!rest.is_empty()).then(|| rest.to_string())),
+        "new" => Input::New,
+        "session" => Input::Session,
+        "help" => Input::Help,
+        "quit" | "exit" => Input::Quit,
+        other => Input::Unknown(other.to_string()),
+    }
+}
+
+/// REPL state carried between prompts
+#[derive(Debug, Default)]
+struct State {
+    session_id: Option<String>,
+    model: Option<String>,
+    /// `AUTH_PROFILE` of following prompts
+    auth_profile: Option<String>,
+}
+
+impl State {
+    fn args(&self, prompt: String) -> GeminiArgs {
+        GeminiArgs {
+            prompt,
+            session_id: self.session_id.clone(),
+            extra_args: self
+                .model
+                .as_ref()
+                .map(|model| vec!["--model".to_string(), model.clone()])
+                .unwrap_or_default(),
+            auth_profile: self.auth_profile.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The profile `/profile NAME` switches to, checked against the configured
+/// profiles like a call's `AUTH_PROFILE`; `default` goes back to the server's
+/// default profile
+fn choose_profile(
+    name: &str,
+    configured: &BTreeMap<String, AuthProfile>,
+) -> Result<Option<String>> {
+    if name == "default" {
+        return Ok(None);
+    }
+    auth::resolve(name, configured)?;
+    Ok(Some(name.trim().to_string()))
+}
+
+/// Run the REPL on stdin/stdout until EOF or `/quit`
+pub async fn run(server: GeminiServer) -> Result<()> {
+    let mut state = State::default();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    println!("gemini-mcp-rs REPL. Type /help for commands.");
+    loop {
+        print!("gemini> ");
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            return Ok(());
+        };
+
+        match parse_input(&line) {
+            Input::Empty => {}
+            Input::Help => println!("{}", HELP),
+            Input::Quit => return Ok(()),
+            Input::New => {
+                state.session_id = None;
+                println!("Started a new session.");
+            }
+            Input::Session => match state.session_id {
+                Some(ref id) => println!("SESSION_ID: {}", id),
+                None => println!("No session yet."),
+            },
+            Input::Model(None) => match state.model {
+                Some(ref model) => println!("Model: {}", model),
+                None => println!("Model: server default"),
+            },
+            Input::Model(Some(model)) => {
+                state.model = (model != "default").then_some(model);
+                println!(
+                    "Model set to {}",
+                    state.model.as_deref().unwrap_or("server default")
+                );
+            }
+            Input::Profile(None) => match state.auth_profile {
+                Some(ref profile) => println!("Auth profile: {}", profile),
+                None => println!("Auth profile: server default"),
+            },
+            Input::Profile(Some(name)) => match choose_profile(&name, gemini::auth_profiles()) {
+                Ok(profile) => {
+                    state.auth_profile = profile;
+                    println!(
+                        "Auth profile set to {}",
+                        state.auth_profile.as_deref().unwrap_or("server default")
+                    );
+                }
+                Err(e) => eprintln!("error: {}", e),
+            },
+            Input::Unknown(name) => println!("Unknown command /{}. Type /help.", name),
+            Input::Prompt(prompt) => match server.execute(state.args(prompt)).await {
+                Ok(result) => {
+                    if !result.session_id.is_empty() {
+                        state.session_id = Some(result.session_id);
+                    }
+                    if !result.agent_messages.is_empty() {
+                        println!("{}", result.agent_messages);
+                    }
+                    if let Some(error) = result.error {
+                        eprintln!("error: {}", error);
+                    }
+                }
+                Err(e) => eprintln!("error: {}", e.message),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(parse_input("  "), Input::Empty);
+        assert_eq!(
+            parse_input("explain this"),
+            Input::Prompt("explain this".to_string())
+        );
+        assert_eq!(
+            parse_input("/model gemini-2.5-flash"),
+            Input::Model(Some("gemini-2.5-flash".to_string()))
+        );
+        assert_eq!(parse_input("/model"), Input::Model(None));
+        assert_eq!(
+            parse_input("/profile work"),
+            Input::Profile(Some("work".to_string()))
+        );
+        assert_eq!(parse_input("/profile"), Input::Profile(None));
+        assert_eq!(parse_input("/exit"), Input::Quit);
+        assert_eq!(parse_input("/bogus"), Input::Unknown("bogus".to_string()));
+    }
+
+    #[test]
+    fn test_state_args_carry_session_and_model() {
+        let state = State {
+            session_id: Some("89473362-3f12-46e8-adce-05388980dcca".to_string()),
+            model: Some("gemini-2.5-flash".to_string()),
+            auth_profile: Some("work".to_string()),
+        };
+
+        let args = state.args("next".to_string());
+        assert_eq!(args.prompt, "next");
+        assert_eq!(args.session_id, state.session_id);
+        assert_eq!(args.extra_args, vec!["--model", "gemini-2.5-flash"]);
+        assert_eq!(args.auth_profile.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn test_profile_must_be_configured() {
+        let configured: BTreeMap<String, AuthProfile> =
+            serde_json::from_str(r#"{"work": {"type": "oauth", "home": "/srv/gemini-work"}}"#)
+                .unwrap();
+
+        assert_eq!(
+            choose_profile("work", &configured).unwrap().as_deref(),
+            Some("work")
+        );
+        assert_eq!(choose_profile("default", &configured).unwrap(), None);
+        let err = choose_profile("personal", &configured).unwrap_err();
+        assert!(err.to_string().contains("available: work"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Full-text search over session transcripts, used by the
+//! `gemini_search_transcripts` tool to find an earlier conversation by what
+//! was said in it.
+//!
+//! Matching is a plain substring search: a turn matches when it contains
+//! every word of the query, ignoring ASCII case.
+
+use crate::session::SessionRecord;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default and largest number of sessions returned
+pub const DEFAULT_LIMIT: usize = 10;
+pub const MAX_LIMIT: usize = 100;
+/// Matching turns quoted per session
+const MAX_SNIPPETS: usize = 3;
+/// Bytes of context kept on each side of the first match in a snippet
+const SNIPPET_CONTEXT: usize = 80;
+
+/// A matching turn, quoted around the first match
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snippet {
+    /// 1-based turn number in the session
+    pub turn: usize,
+    pub text: String,
+}
+
+/// A session with at least one matching turn
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionMatch {
+    #[serde(rename = "SESSION_ID")]
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Unix time (seconds) of the session's last turn
+    pub last_used: u64,
+    /// How many turns match; sessions with more come first
+    pub matching_turns: usize,
+    /// The first few matching turns
+    pub snippets: Vec<Snippet>,
+}
+
+/// Sessions whose transcript matches `query`, best first: most matching
+/// turns, then most recently used. Returns at most `limit` sessions and the
+/// total number that matched.
+pub fn search(
+    sessions: &[(String, SessionRecord)],
+    query: &str,
+    limit: usize,
+) -> (Vec<SessionMatch>, usize) {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if terms.is_empty() {
+        return (Vec::new(), 0);
+    }
+
+    let mut matches: Vec<(SystemTime, SessionMatch)> = sessions
+        .iter()
+        .filter_map(|(session_id, record)| {
+            let mut matching_turns = 0;
+            let mut snippets = Vec::new();
+            for (index, turn) in record.transcript.iter().enumerate() {
+                let lower = turn.to_ascii_lowercase();
+                if !terms.iter().all(|term| lower.contains(term.as_str())) {
+                    continue;
+                }
+                matching_turns += 1;
+                if snippets.len() < MAX_SNIPPETS {
+                    let at = lower.find(terms[0].as_str()).unwrap_or(0);
+                    snippets.push(Snippet {
+                        turn: index + 1,
+                        text: snippet(turn, at, terms[0].len()),
+                    });
+                }
+            }
+            (matching_turns > 0).then(|| {
+                (
+                    record.last_used,
+                    SessionMatch {
+                        session_id: session_id.clone(),
+                        model: record.model.clone(),
+                        last_used: record
+                            .last_used
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs()),
+                        matching_turns,
+                        snippets,
+                    },
+                )
+            })
+        })
+        .collect();
+    matches.sort_by(|(a_used, a), (b_used, b)| {
+        b.matching_turns
+            .cmp(&a.matching_turns)
+            .then_with(|| b_used.cmp(a_used))
+    });
+
+    let total = matches.len();
+    let matches = matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, session)| session)
+        .collect();
+    (matches, total)
+}
+
+/// `text` around the `len` bytes at `at`, on one line, with `...` where it
+/// was cut
+fn snippet(text: &str, at: usize, len: usize) -> String {
+    let mut start = at.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (at + len + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    let mut snippet = text[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < text.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Usage;
+    use std::time::Duration;
+
+    fn session(id: &str, used_secs: u64, transcript: &[&str]) -> (String, SessionRecord) {
+        let at = UNIX_EPOCH + Duration::from_secs(used_secs);
+        (
+            id.to_string(),
+            SessionRecord {
+                model: None,
+                created_at: at,
+                last_used: at,
+                turns: transcript.len() as u64,
+                usage: Usage::default(),
+                transcript: transcript.iter().map(|t| t.to_string()).collect(),
+                events: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_sessions_are_ranked_by_matching_turns_then_recency() {
+        let sessions = vec![
+            session("old", 10, &["The Race condition is in the file watcher."]),
+            session("new", 20, &["A race condition, again.", "Unrelated."]),
+            session(
+                "busy",
+                5,
+                &["race condition one", "race condition two", "no match"],
+            ),
+            session("none", 30, &["Nothing about races here.", "condition"]),
+        ];
+
+        let (matches, total) = search(&sessions, "race CONDITION", DEFAULT_LIMIT);
+        assert_eq!(total, 3);
+        let ids: Vec<_> = matches.iter().map(|m| m.session_id.as_str()).collect();
+        assert_eq!(ids, vec!["busy", "new", "old"]);
+        assert_eq!(matches[0].matching_turns, 2);
+        assert_eq!(
+            matches[0].snippets,
+            vec![
+                Snippet {
+                    turn: 1,
+                    text: "race condition one".to_string()
+                },
+                Snippet {
+                    turn: 2,
+                    text: "race condition two".to_string()
+                },
+            ]
+        );
+
+        let (matches, total) = search(&sessions, "race condition", 1);
+        assert_eq!((matches.len(), total), (1, 3));
+        assert_eq!(search(&sessions, "   ", DEFAULT_LIMIT), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_snippet_is_cut_on_char_boundaries() {
+        let text = format!("{}needle{}", "é".repeat(100), "\n ü".repeat(100));
+        let at = text.find("needle").unwrap();
+        let snippet = snippet(&text, at, "needle".len());
+        assert!(snippet.starts_with("..."));
+        assert!(snippet.ends_with("..."));
+        assert!(snippet.contains("needle ü ü"));
+        assert!(!snippet.contains('\n'));
+    }
+}
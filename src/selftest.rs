@@ -0,0 +1,246 @@
+//! `gemini-mcp-rs selftest`: start the server as a child process, talk to it
+//! over stdio exactly like an MCP client does, and report which parts of the
+//! setup work.
+
+use anyhow::{bail, Context, Result};
+use rmcp::model::{CallToolRequestParam, CallToolResult};
+use rmcp::service::RunningService;
+use rmcp::{RoleClient, ServiceExt};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::future::Future;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Prompt sent by the end-to-end check unless `--prompt` is given
+pub const DEFAULT_PROMPT: &str = "Reply with the single word OK.";
+
+/// Tools every server build must expose
+const REQUIRED_TOOLS: &[&str] = &["gemini", "gemini_health"];
+
+/// Time allowed for connecting, listing tools and the health check
+const STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Flags of the `selftest` subcommand
+#[derive(Debug, Clone)]
+pub struct SelftestOptions {
+    /// Server binary to start, normally the running executable
+    pub server: PathBuf,
+    /// Prompt for the end-to-end call; `None` skips it
+    pub prompt: Option<String>,
+    pub timeout_secs: u64,
+}
+
+/// Run every check, printing `PASS`/`FAIL` per step. Fails if any check did.
+pub async fn run(opts: SelftestOptions) -> Result<()> {
+    let mut child = Command::new(&opts.server)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", opts.server.display()))?;
+    let stdout = child.stdout.take().context("server stdout not captured")?;
+    let stdin = child.stdin.take().context("server stdin not captured")?;
+
+    let mut report = Report::default();
+    let client = match step(STEP_TIMEOUT, ().serve((stdout, stdin))).await {
+        Ok(client) => {
+            let detail = client.peer_info().map_or_else(
+                || "connected".to_string(),
+                |info| format!("{} {}", info.server_info.name, info.server_info.version),
+            );
+            report.record("connect", Ok(detail));
+            client
+        }
+        Err(err) => {
+            report.record("connect", Err(err));
+            return report.finish();
+        }
+    };
+
+    let tools = step(STEP_TIMEOUT, client.list_all_tools())
+        .await
+        .and_then(|tools| check_tools(tools.iter().map(|t| t.name.as_ref())));
+    report.record("list tools", tools);
+
+    let health = step(
+        STEP_TIMEOUT,
+        call(&client, "gemini_health", serde_json::json!({})),
+    )
+    .await
+    .and_then(check_health);
+    report.record("gemini_health", health);
+
+    if let Some(prompt) = opts.prompt {
+        let args = serde_json::json!({
+            "PROMPT": prompt,
+            "RAW_RESULT": true,
+            "timeout_secs": opts.timeout_secs,
+        });
+        // Leave the server's own timeout time to report before giving up
+        let limit = Duration::from_secs(opts.timeout_secs) + STEP_TIMEOUT;
+        let answer = step(limit, call(&client, "gemini", args))
+            .await
+            .and_then(check_prompt);
+        report.record("prompt", answer);
+    }
+
+    let _ = client.cancel().await;
+    let _ = tokio::time::timeout(Duration::from_secs(5), child.wait()).await;
+    report.finish()
+}
+
+async fn call(
+    client: &RunningService<RoleClient, ()>,
+    name: &'static str,
+    arguments: serde_json::Value,
+) -> Result<CallToolResult, rmcp::ServiceError> {
+    client
+        .call_tool(CallToolRequestParam {
+            name: Cow::Borrowed(name),
+            arguments: arguments.as_object().cloned(),
+        })
+        .await
+}
+
+async fn step<T, E>(limit: Duration, fut: impl Future<Output = Result<T, E>>) -> Result<T>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match tokio::time::timeout(limit, fut).await {
+        Ok(result) => Ok(result?),
+        Err(_) => bail!("no answer within {}s", limit.as_secs()),
+    }
+}
+
+fn check_tools<'a>(names: impl Iterator<Item = &'a str>) -> Result<String> {
+    let names: Vec<&str> = names.collect();
+    let missing: Vec<&str> = REQUIRED_TOOLS
+        .iter()
+        .copied()
+        .filter(|tool| !names.contains(tool))
+        .collect();
+    if !missing.is_empty() {
+        bail!("missing tools: {}", missing.join(", "));
+    }
+    Ok(format!("{} tools", names.len()))
+}
+
+fn check_health(result: CallToolResult) -> Result<String> {
+    let text = text_of(&result);
+    if result.is_error == Some(true) {
+        bail!("{}", text);
+    }
+    Ok(text)
+}
+
+/// The `RAW_RESULT` shape of the `gemini` tool
+#[derive(Debug, Deserialize)]
+struct RawOutput {
+    success: bool,
+    message: String,
+    error: Option<String>,
+}
+
+fn check_prompt(result: CallToolResult) -> Result<String> {
+    let text = text_of(&result);
+    if result.is_error == Some(true) {
+        bail!("{}", text);
+    }
+    let output: RawOutput = serde_json::from_str(&text)
+        .with_context(|| format!("unexpected gemini tool output: {}", text))?;
+    if !output.success {
+        bail!(
+            "{}",
+            output.error.unwrap_or_else(|| "call failed".to_string())
+        );
+    }
+    let answer = output.message.trim();
+    if answer.is_empty() {
+        bail!("Gemini returned an empty answer");
+    }
+    Ok(format!("answered {:?}", answer))
+}
+
+fn text_of(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .filter_map(|c| c.as_text().map(|t| t.text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    failed: usize,
+    total: usize,
+}
+
+impl Report {
+    fn record(&mut self, name: &str, outcome: Result<String>) {
+        self.total += 1;
+        match outcome {
+            Ok(detail) => println!("PASS {}: {}", name, one_line(&detail)),
+            Err(err) => {
+                self.failed += 1;
+                println!("FAIL {}: {}", name, one_line(&format!("{:#}", err)));
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.failed > 0 {
+            bail!(
+                "selftest failed: {} of {} checks failed",
+                self.failed,
+                self.total
+            );
+        }
+        println!("All {} checks passed", self.total);
+        Ok(())
+    }
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::Content;
+
+    #[test]
+    fn test_check_tools_reports_missing() {
+        assert_eq!(
+            check_tools(["gemini", "gemini_usage", "gemini_health"].into_iter()).unwrap(),
+            "3 tools"
+        );
+        let err = check_tools(["gemini"].into_iter()).unwrap_err();
+        assert_eq!(err.to_string(), "missing tools: gemini_health");
+    }
+
+    #[test]
+    fn test_check_prompt() {
+        let text = |json: &str| CallToolResult::success(vec![Content::text(json)]);
+
+        assert_eq!(
+            check_prompt(text(
+                r#"{"success":true,"session_id":"s","message":"OK\n","error":null}"#
+            ))
+            .unwrap(),
+            "answered \"OK\""
+        );
+        let err = check_prompt(text(
+            r#"{"success":false,"session_id":"","message":"","error":"quota exceeded"}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "quota exceeded");
+        assert!(check_prompt(CallToolResult::error(vec![Content::text("boom")])).is_err());
+        assert!(check_prompt(text("success: true")).is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use crate::clock::SharedClock;
+use crate::event::Usage;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of raw CLI events kept per session; older ones are dropped
+const MAX_SESSION_EVENTS: usize = 10_000;
+
+/// What the server knows about a Gemini session it has seen
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    /// Model version reported by the CLI when the session was created
+    pub model: Option<String>,
+    pub created_at: SystemTime,
+    pub last_used: SystemTime,
+    pub turns: u64,
+    /// Running token totals across all turns of the session
+    pub usage: Usage,
+    /// Assistant reply of each turn, oldest first
+    pub transcript: Vec<String>,
+    /// Raw CLI events of all turns (most recent [`MAX_SESSION_EVENTS`])
+    pub events: Vec<Value>,
+}
+
+/// A resume that reported a different model than the one the session was pinned to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelSwitch {
+    pub pinned: String,
+    pub reported: String,
+}
+
+/// A soft-deleted session, kept until it is restored or purged
+#[derive(Debug, Clone)]
+pub struct DeletedSession {
+    pub record: SessionRecord,
+    /// Labels that pointed to the session when it was deleted
+    pub labels: Vec<String>,
+    pub deleted_at: SystemTime,
+}
+
+/// In-memory registry of sessions created or resumed through this server
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+    /// Human-readable `SESSION_LABEL` -> session id
+    labels: Mutex<HashMap<String, String>>,
+    /// Soft-deleted sessions, by id
+    deleted: Mutex<HashMap<String, DeletedSession>>,
+    /// Source of `created_at` and `last_used`
+    clock: SharedClock,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry that timestamps runs with `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<SessionRecord> {
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    /// Model the session was pinned to when it was created
+    pub fn pinned_model(&self, session_id: &str) -> Option<String> {
+        self.get(session_id).and_then(|record| record.model)
+    }
+
+    /// Insert a session loaded from persistent storage, replacing any
+    /// in-memory record with the same id
+    pub fn restore(&self, session_id: String, record: SessionRecord) {
+        self.sessions.lock().unwrap().insert(session_id, record);
+    }
+
+    /// Session id a `SESSION_LABEL` currently points to
+    pub fn session_for_label(&self, label: &str) -> Option<String> {
+        self.labels.lock().unwrap().get(label).cloned()
+    }
+
+    /// Point `label` at `session_id`, replacing any previous binding
+    pub fn bind_label(&self, label: &str, session_id: &str) {
+        self.labels
+            .lock()
+            .unwrap()
+            .insert(label.to_string(), session_id.to_string());
+    }
+
+    /// Number of known sessions
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Ids of all known sessions, oldest first
+    pub fn session_ids(&self) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut ids: Vec<_> = sessions.iter().collect();
+        ids.sort_by_key(|(_, record)| record.created_at);
+        ids.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Ids of all known sessions, most recently used first
+    pub fn recent_ids(&self) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut ids: Vec<_> = sessions.iter().collect();
+        ids.sort_by_key(|(_, record)| std::cmp::Reverse(record.last_used));
+        ids.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Bound `SESSION_LABEL`s, those of the most recently used sessions first
+    pub fn labels(&self) -> Vec<String> {
+        let labels = self.labels.lock().unwrap();
+        let sessions = self.sessions.lock().unwrap();
+        let mut labels: Vec<_> = labels
+            .iter()
+            .map(|(label, id)| (label.clone(), sessions.get(id).map(|r| r.last_used)))
+            .collect();
+        labels.sort_by(|(a, a_used), (b, b_used)| b_used.cmp(a_used).then_with(|| a.cmp(b)));
+        labels.into_iter().map(|(label, _)| label).collect()
+    }
+
+    /// All known sessions, oldest first
+    pub fn list(&self) -> Vec<(String, SessionRecord)> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, record)| (id.clone(), record.clone()))
+            .collect();
+        sessions.sort_by_key(|(_, record)| record.created_at);
+        sessions
+    }
+
+    /// Soft-delete a session: it disappears from listings, resources and
+    /// label lookups, but can be brought back with [`Self::undelete`] until
+    /// [`Self::purge_deleted`] drops it. Returns the unbound labels, or
+    /// `None` if the session is unknown.
+    pub fn delete(&self, session_id: &str, deleted_at: SystemTime) -> Option<Vec<String>> {
+        let record = self.sessions.lock().unwrap().remove(session_id)?;
+        let mut labels = Vec::new();
+        self.labels.lock().unwrap().retain(|label, id| {
+            if id == session_id {
+                labels.push(label.clone());
+            }
+            id != session_id
+        });
+        labels.sort();
+        self.deleted.lock().unwrap().insert(
+            session_id.to_string(),
+            DeletedSession {
+                record,
+                labels: labels.clone(),
+                deleted_at,
+            },
+        );
+        Some(labels)
+    }
+
+    /// Bring back a soft-deleted session. Labels that were bound to another
+    /// session in the meantime stay there; the rest are rebound. Returns the
+    /// restored record and labels.
+    pub fn undelete(&self, session_id: &str) -> Option<(SessionRecord, Vec<String>)> {
+        let deleted = self.deleted.lock().unwrap().remove(session_id)?;
+        let mut labels = Vec::new();
+        {
+            let mut bound = self.labels.lock().unwrap();
+            for label in deleted.labels {
+                if !bound.contains_key(&label) {
+                    bound.insert(label.clone(), session_id.to_string());
+                    labels.push(label);
+                }
+            }
+        }
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), deleted.record.clone());
+        Some((deleted.record, labels))
+    }
+
+    /// When a session was soft-deleted, if it is in the trash
+    pub fn deleted_at(&self, session_id: &str) -> Option<SystemTime> {
+        self.deleted
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|deleted| deleted.deleted_at)
+    }
+
+    /// Forget soft-deleted sessions older than `retention`. Returns their ids.
+    pub fn purge_deleted(&self, retention: Duration, now: SystemTime) -> Vec<String> {
+        let mut purged = Vec::new();
+        self.deleted.lock().unwrap().retain(|id, deleted| {
+            let expired = now
+                .duration_since(deleted.deleted_at)
+                .is_ok_and(|age| age >= retention);
+            if expired {
+                purged.push(id.clone());
+            }
+            !expired
+        });
+        purged.sort();
+        purged
+    }
+
+    /// Forget sessions unused for `ttl`, except those in `busy`, and unbind
+    /// their labels. Returns their ids.
+    pub fn expire_idle(&self, ttl: Duration, now: SystemTime, busy: &[String]) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.sessions.lock().unwrap().retain(|id, record| {
+            let idle = !busy.contains(id)
+                && now
+                    .duration_since(record.last_used)
+                    .is_ok_and(|age| age >= ttl);
+            if idle {
+                expired.push(id.clone());
+            }
+            !idle
+        });
+        if !expired.is_empty() {
+            self.labels
+                .lock()
+                .unwrap()
+                .retain(|_, id| !expired.contains(id));
+        }
+        expired.sort();
+        expired
+    }
+
+    /// Record a completed run and add its token usage to the session totals.
+    /// The first model reported for a session pins it; a later run reporting a
+    /// different model is returned as a [`ModelSwitch`].
+    pub fn record_run(
+        &self,
+        session_id: &str,
+        model: Option<&str>,
+        usage: Option<Usage>,
+    ) -> Option<ModelSwitch> {
+        if session_id.is_empty() {
+            return None;
+        }
+
+        let now = self.clock.now();
+        let mut sessions = self.sessions.lock().unwrap();
+        let record = sessions
+            .entry(session_id.to_string())
+            .or_insert_with(|| SessionRecord {
+                model: None,
+                created_at: now,
+                last_used: now,
+                turns: 0,
+                usage: Usage::default(),
+                transcript: Vec::new(),
+                events: Vec::new(),
+            });
+        record.turns += 1;
+        if let Some(usage) = usage {
+            record.usage += usage;
+        }
+        record.last_used = now;
+
+        match (&record.model, model) {
+            (None, Some(reported)) => {
+                record.model = Some(reported.to_string());
+                None
+            }
+            (Some(pinned), Some(reported)) if pinned != reported => Some(ModelSwitch {
+                pinned: pinned.clone(),
+                reported: reported.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Append a turn's assistant reply and raw events to a session's
+    /// transcript. Call after [`SessionRegistry::record_run`].
+    pub fn append_transcript(&self, session_id: &str, message: &str, events: &[Value]) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(record) = sessions.get_mut(session_id) else {
+            return;
+        };
+        record.transcript.push(message.to_string());
+        record.events.extend_from_slice(events);
+        if record.events.len() > MAX_SESSION_EVENTS {
+            let excess = record.events.len() - MAX_SESSION_EVENTS;
+            record.events.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_reported_model_pins_session() {
+        let registry = SessionRegistry::new();
+
+        assert_eq!(
+            registry.record_run("s1", Some("gemini-2.5-pro"), None),
+            None
+        );
+        assert_eq!(
+            registry.pinned_model("s1").as_deref(),
+            Some("gemini-2.5-pro")
+        );
+        assert_eq!(
+            registry.record_run("s1", Some("gemini-2.5-pro"), None),
+            None
+        );
+        assert_eq!(registry.get("s1").unwrap().turns, 2);
+    }
+
+    #[test]
+    fn test_model_switch_is_reported_without_repinning() {
+        let registry = SessionRegistry::new();
+        registry.record_run("s1", Some("gemini-2.5-pro"), None);
+
+        let switch = registry.record_run("s1", Some("gemini-2.5-flash"), None);
+        assert_eq!(
+            switch,
+            Some(ModelSwitch {
+                pinned: "gemini-2.5-pro".to_string(),
+                reported: "gemini-2.5-flash".to_string(),
+            })
+        );
+        assert_eq!(
+            registry.pinned_model("s1").as_deref(),
+            Some("gemini-2.5-pro")
+        );
+    }
+
+    #[test]
+    fn test_usage_accumulates_per_session() {
+        let registry = SessionRegistry::new();
+        let usage = Usage {
+            prompt_tokens: 10,
+            candidate_tokens: 5,
+            total_tokens: 15,
+        };
+
+        registry.record_run("s1", None, Some(usage));
+        registry.record_run("s1", None, Some(usage));
+        registry.record_run("s2", None, None);
+
+        assert_eq!(registry.get("s1").unwrap().usage.total_tokens, 30);
+        assert!(registry.get("s2").unwrap().usage.is_empty());
+        assert_eq!(registry.list().len(), 2);
+    }
+
+    #[test]
+    fn test_transcript_accumulates_turns() {
+        let registry = SessionRegistry::new();
+        registry.append_transcript("s1", "ignored", &[]);
+        assert!(registry.get("s1").is_none());
+
+        registry.record_run("s1", None, None);
+        registry.append_transcript("s1", "first", &[serde_json::json!({"type": "init"})]);
+        registry.record_run("s1", None, None);
+        registry.append_transcript("s1", "second", &[]);
+
+        let record = registry.get("s1").unwrap();
+        assert_eq!(record.transcript, vec!["first", "second"]);
+        assert_eq!(record.events.len(), 1);
+    }
+
+    #[test]
+    fn test_labels_map_to_session_ids() {
+        let registry = SessionRegistry::new();
+        assert_eq!(registry.session_for_label("nightly-report"), None);
+
+        registry.bind_label("nightly-report", "s1");
+        registry.bind_label("nightly-report", "s2");
+        assert_eq!(
+            registry.session_for_label("nightly-report").as_deref(),
+            Some("s2")
+        );
+    }
+
+    #[test]
+    fn test_recent_sessions_and_labels() {
+        let registry = SessionRegistry::new();
+        let at = |secs: u64| SessionRecord {
+            model: None,
+            created_at: SystemTime::UNIX_EPOCH,
+            last_used: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs),
+            turns: 1,
+            usage: Usage::default(),
+            transcript: Vec::new(),
+            events: Vec::new(),
+        };
+        registry.restore("old".to_string(), at(10));
+        registry.restore("new".to_string(), at(20));
+        registry.bind_label("nightly", "old");
+        registry.bind_label("review", "new");
+        registry.bind_label("gone", "unknown");
+
+        assert_eq!(registry.recent_ids(), vec!["new", "old"]);
+        assert_eq!(registry.labels(), vec!["review", "nightly", "gone"]);
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let registry = SessionRegistry::new();
+        let used_at = |secs: u64| SessionRecord {
+            model: None,
+            created_at: SystemTime::UNIX_EPOCH,
+            last_used: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            turns: 1,
+            usage: Usage::default(),
+            transcript: Vec::new(),
+            events: Vec::new(),
+        };
+        registry.restore("idle".to_string(), used_at(0));
+        registry.restore("busy".to_string(), used_at(0));
+        registry.restore("recent".to_string(), used_at(50));
+        registry.bind_label("nightly", "idle");
+        registry.bind_label("review", "recent");
+
+        let ttl = Duration::from_secs(60);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        assert_eq!(
+            registry.expire_idle(ttl, now, &["busy".to_string()]),
+            vec!["idle"]
+        );
+        assert!(registry.get("idle").is_none());
+        assert_eq!(registry.session_for_label("nightly"), None);
+        assert_eq!(
+            registry.session_for_label("review").as_deref(),
+            Some("recent")
+        );
+        assert_eq!(registry.session_ids().len(), 2);
+    }
+
+    #[test]
+    fn test_deleted_session_can_be_restored_until_purged() {
+        let registry = SessionRegistry::new();
+        registry.record_run("s1", Some("gemini-2.5-pro"), None);
+        registry.record_run("s2", None, None);
+        registry.bind_label("nightly", "s1");
+        registry.bind_label("review", "s1");
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+
+        assert_eq!(registry.delete("unknown", at), None);
+        assert_eq!(
+            registry.delete("s1", at),
+            Some(vec!["nightly".to_string(), "review".to_string()])
+        );
+        assert!(registry.get("s1").is_none());
+        assert_eq!(registry.session_for_label("nightly"), None);
+        assert_eq!(registry.session_ids(), vec!["s2"]);
+        assert_eq!(registry.deleted_at("s1"), Some(at));
+
+        // A label reused while the session was deleted keeps its new target
+        registry.bind_label("review", "s2");
+        let (record, labels) = registry.undelete("s1").unwrap();
+        assert_eq!(record.model.as_deref(), Some("gemini-2.5-pro"));
+        assert_eq!(labels, vec!["nightly"]);
+        assert_eq!(registry.session_for_label("review").as_deref(), Some("s2"));
+        assert!(registry.get("s1").is_some());
+        assert!(registry.undelete("s1").is_none());
+
+        registry.delete("s1", at);
+        let retention = Duration::from_secs(60);
+        assert!(registry
+            .purge_deleted(retention, at + Duration::from_secs(59))
+            .is_empty());
+        assert_eq!(
+            registry.purge_deleted(retention, at + Duration::from_secs(60)),
+            vec!["s1"]
+        );
+        assert!(registry.undelete("s1").is_none());
+    }
+
+    #[test]
+    fn test_empty_session_id_is_ignored() {
+        let registry = SessionRegistry::new();
+        assert_eq!(registry.record_run("", Some("gemini-2.5-pro"), None), None);
+        assert!(registry.get("").is_none());
+    }
+}
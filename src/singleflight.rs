@@ -0,0 +1,144 @@
+//! Coalescing of identical in-flight runs.
+//!
+//! When several callers send the same session-less prompt at once, the first
+//! one (the leader) spawns the CLI and the others (followers) wait for its
+//! result instead of starting duplicate processes. Keys are removed as soon
+//! as the leader finishes, so later calls run again (or hit the response
+//! cache).
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// In-flight runs by key
+#[derive(Debug)]
+pub struct Singleflight<K, T> {
+    flights: Mutex<HashMap<K, watch::Receiver<Option<T>>>>,
+    coalesced: AtomicU64,
+}
+
+/// A caller's part in a flight
+#[derive(Debug)]
+pub enum Flight<'a, K: Eq + Hash, T> {
+    /// Do the work and hand the result to [`Leader::finish`]
+    Leader(Leader<'a, K, T>),
+    /// Wait for the leader's result
+    Follower(Follower<T>),
+}
+
+/// The caller doing the work. Dropping it without finishing (e.g. when the
+/// call is cancelled) releases the key and wakes the followers.
+#[derive(Debug)]
+pub struct Leader<'a, K: Eq + Hash, T> {
+    owner: &'a Singleflight<K, T>,
+    key: K,
+    result: watch::Sender<Option<T>>,
+}
+
+/// A caller waiting for a leader
+#[derive(Debug)]
+pub struct Follower<T> {
+    result: watch::Receiver<Option<T>>,
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Default for Singleflight<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Singleflight<K, T> {
+    pub fn new() -> Self {
+        Self {
+            flights: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// Lead a new flight for `key`, or follow the one already in the air
+    pub fn join(&self, key: K) -> Flight<'_, K, T> {
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(result) = flights.get(&key) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            return Flight::Follower(Follower {
+                result: result.clone(),
+            });
+        }
+        let (result, receiver) = watch::channel(None);
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(Leader {
+            owner: self,
+            key,
+            result,
+        })
+    }
+
+    /// Callers that shared another caller's run so far
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+impl<K: Eq + Hash, T> Leader<'_, K, T> {
+    /// Hand `result` to every follower
+    pub fn finish(self, result: T) {
+        self.result.send_replace(Some(result));
+    }
+}
+
+impl<K: Eq + Hash, T> Drop for Leader<'_, K, T> {
+    fn drop(&mut self) {
+        self.owner
+            .flights
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
+}
+
+impl<T: Clone> Follower<T> {
+    /// The leader's result, or `None` if the leader gave up without one and
+    /// the caller should join again
+    pub async fn wait(mut self) -> Option<T> {
+        let result = self.result.wait_for(Option::is_some).await.ok()?;
+        result.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_followers_share_the_leaders_result() {
+        let flights: Singleflight<&str, u32> = Singleflight::new();
+        let Flight::Leader(leader) = flights.join("prompt") else {
+            panic!("first caller must lead");
+        };
+        let Flight::Follower(follower) = flights.join("prompt") else {
+            panic!("second caller must follow");
+        };
+        assert!(matches!(flights.join("other"), Flight::Leader(_)));
+
+        let waiting = tokio::spawn(follower.wait());
+        leader.finish(42);
+        assert_eq!(waiting.await.unwrap(), Some(42));
+        assert_eq!(flights.coalesced(), 1);
+        // The key is released once the leader is done
+        assert!(matches!(flights.join("prompt"), Flight::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_flight_releases_followers() {
+        let flights: Singleflight<&str, u32> = Singleflight::new();
+        let leader = flights.join("prompt");
+        let Flight::Follower(follower) = flights.join("prompt") else {
+            panic!("second caller must follow");
+        };
+        drop(leader);
+        assert_eq!(follower.wait().await, None);
+        assert!(matches!(flights.join("prompt"), Flight::Leader(_)));
+    }
+}
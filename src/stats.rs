@@ -0,0 +1,401 @@
+use crate::clock::{IdGenerator, SequentialIds, SharedClock};
+use crate::gemini::RunHandle;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Process-wide counters shared by every clone of the server
+#[derive(Debug)]
+pub struct ServerStats {
+    clock: SharedClock,
+    /// Source of request ids
+    ids: Arc<dyn IdGenerator>,
+    started_at: Instant,
+    total_requests: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    in_flight: AtomicU64,
+    /// Calls killed because they outlived the shutdown grace period
+    killed_on_shutdown: AtomicU64,
+    active: Mutex<HashMap<u64, ActiveRequest>>,
+}
+
+/// Whether an in-flight call is waiting for a run slot or running the CLI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestState {
+    Queued,
+    Running,
+}
+
+#[derive(Debug)]
+struct ActiveRequest {
+    started_at: Instant,
+    session_id: Option<String>,
+    state: RequestState,
+    run: Option<RunHandle>,
+}
+
+impl ActiveRequest {
+    fn info(&self, id: u64, now: Instant) -> ActiveRequestInfo {
+        ActiveRequestInfo {
+            id,
+            session_id: self.session_id.clone(),
+            state: self.state,
+            pid: self.run.as_ref().and_then(RunHandle::pid),
+            elapsed_ms: now.saturating_duration_since(self.started_at).as_millis() as u64,
+        }
+    }
+}
+
+/// Snapshot of one in-flight call, as shown in the diagnostics dump
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRequestInfo {
+    pub id: u64,
+    pub session_id: Option<String>,
+    pub state: RequestState,
+    /// PID of the CLI process, once spawned
+    pub pid: Option<u32>,
+    pub elapsed_ms: u64,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Self::with_sources(SharedClock::default(), Arc::new(SequentialIds::default()))
+    }
+
+    /// Counters that take time from `clock` and request ids from `ids`
+    pub fn with_sources(clock: SharedClock, ids: Arc<dyn IdGenerator>) -> Self {
+        Self {
+            started_at: clock.instant(),
+            clock,
+            ids,
+            total_requests: AtomicU64::new(0),
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            killed_on_shutdown: AtomicU64::new(0),
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a new tool call, initially queued. The call counts as in
+    /// flight until the returned guard is dropped.
+    pub fn begin_request(self: &Arc<Self>, session_id: Option<&str>) -> RequestGuard {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let id = self.ids.next_id();
+        self.active.lock().unwrap().insert(
+            id,
+            ActiveRequest {
+                started_at: self.clock.instant(),
+                session_id: session_id.map(str::to_string),
+                state: RequestState::Queued,
+                run: None,
+            },
+        );
+        RequestGuard {
+            stats: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// In-flight calls, oldest first
+    pub fn active_requests(&self) -> Vec<ActiveRequestInfo> {
+        let now = self.clock.instant();
+        let mut requests: Vec<_> = self
+            .active
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, request)| request.info(*id, now))
+            .collect();
+        requests.sort_by_key(|request| request.id);
+        requests
+    }
+
+    /// Kill the CLI run of an in-flight call. Returns the call as it was when
+    /// killed, or `None` if no such call is in flight.
+    pub fn kill_request(&self, id: u64) -> Option<ActiveRequestInfo> {
+        let active = self.active.lock().unwrap();
+        let request = active.get(&id)?;
+        if let Some(ref run) = request.run {
+            run.kill();
+        }
+        Some(request.info(id, self.clock.instant()))
+    }
+
+    /// Kill every in-flight call at the end of the shutdown grace period.
+    /// Returns how many calls were killed.
+    pub fn kill_all_on_shutdown(&self) -> u64 {
+        let active = self.active.lock().unwrap();
+        for run in active.values().filter_map(|request| request.run.as_ref()) {
+            run.kill();
+        }
+        let killed = active.len() as u64;
+        self.killed_on_shutdown.fetch_add(killed, Ordering::Relaxed);
+        killed
+    }
+
+    pub fn total_requests(&self) -> u64 {
+        self.total_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn succeeded(&self) -> u64 {
+        self.succeeded.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.clock
+            .instant()
+            .saturating_duration_since(self.started_at)
+            .as_secs()
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Build the report emitted when the server shuts down. Requests killed
+    /// after the grace period or still in flight at this point are counted
+    /// as killed.
+    pub fn shutdown_report(&self) -> ShutdownReport {
+        ShutdownReport {
+            uptime_secs: self.uptime_secs(),
+            total_requests: self.total_requests(),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            // Calls killed at the deadline may not have unwound yet; no new
+            // calls are admitted once shutdown starts
+            in_flight_killed: self
+                .killed_on_shutdown
+                .load(Ordering::Relaxed)
+                .max(self.in_flight()),
+        }
+    }
+}
+
+/// Tracks a single in-flight tool call
+#[derive(Debug)]
+pub struct RequestGuard {
+    stats: Arc<ServerStats>,
+    id: u64,
+}
+
+impl RequestGuard {
+    /// Server-assigned id of this call
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Attach the handle of the CLI run so its PID shows up in diagnostics and
+    /// the run can be killed by id
+    pub fn attach_run(&self, run: RunHandle) {
+        if let Some(request) = self.stats.active.lock().unwrap().get_mut(&self.id) {
+            request.run = Some(run);
+        }
+    }
+
+    /// The call got a run slot and is now executing the CLI
+    pub fn mark_running(&self) {
+        if let Some(request) = self.stats.active.lock().unwrap().get_mut(&self.id) {
+            request.state = RequestState::Running;
+        }
+    }
+
+    /// Record the outcome of the call
+    pub fn finish(self, success: bool) {
+        let counter = if success {
+            &self.stats.succeeded
+        } else {
+            &self.stats.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.stats.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Point-in-time state of a running server, dumped on SIGUSR1 (or via the
+/// HTTP diagnostics endpoint) to debug a hung instance without restarting it
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    pub uptime_secs: u64,
+    pub total_requests: u64,
+    pub in_flight: u64,
+    /// Calls waiting for a run slot
+    pub queued: usize,
+    /// Configured `max_concurrent_runs`, if any
+    pub max_concurrent_runs: Option<usize>,
+    /// Free run slots; `None` when runs are unlimited
+    pub available_permits: Option<usize>,
+    /// Sessions known to this server
+    pub sessions: usize,
+    pub active_requests: Vec<ActiveRequestInfo>,
+}
+
+impl Diagnostics {
+    /// Log the snapshot to stderr as a single JSON line
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => tracing::info!("diagnostics: {}", json),
+            Err(err) => tracing::warn!("failed to serialize diagnostics: {}", err),
+        }
+    }
+}
+
+/// Summary of the server's lifetime, logged on shutdown
+#[derive(Debug, Clone, Serialize)]
+pub struct ShutdownReport {
+    pub uptime_secs: u64,
+    pub total_requests: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub in_flight_killed: u64,
+}
+
+impl ShutdownReport {
+    /// Log the report to stderr and, if `path` is given, write it as JSON
+    pub fn emit(&self, path: Option<&Path>) {
+        match serde_json::to_string(self) {
+            Ok(json) => tracing::info!("shutdown report: {}", json),
+            Err(err) => tracing::warn!("failed to serialize shutdown report: {}", err),
+        }
+
+        let Some(path) = path else {
+            return;
+        };
+        let written = serde_json::to_vec_pretty(self)
+            .map_err(std::io::Error::other)
+            .and_then(|bytes| std::fs::write(path, bytes));
+        if let Err(err) = written {
+            tracing::warn!(
+                "failed to write shutdown report {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_guard_tracks_outcomes() {
+        let stats = Arc::new(ServerStats::new());
+
+        stats.begin_request(None).finish(true);
+        stats.begin_request(None).finish(false);
+        let pending = stats.begin_request(Some("s1"));
+
+        let report = stats.shutdown_report();
+        assert_eq!(report.total_requests, 3);
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.in_flight_killed, 1);
+
+        drop(pending);
+        assert_eq!(stats.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_active_requests_track_state() {
+        let stats = Arc::new(ServerStats::new());
+        let first = stats.begin_request(Some("s1"));
+        let second = stats.begin_request(None);
+        second.mark_running();
+
+        let active = stats.active_requests();
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[0].id, first.id());
+        assert_eq!(active[0].session_id.as_deref(), Some("s1"));
+        assert_eq!(active[0].state, RequestState::Queued);
+        assert_eq!(active[1].state, RequestState::Running);
+
+        first.finish(true);
+        assert_eq!(stats.active_requests().len(), 1);
+    }
+
+    #[test]
+    fn test_injected_clock_and_ids_are_used() {
+        use crate::clock::{Clock, ManualClock};
+        use std::time::{Duration, SystemTime};
+
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let stats = Arc::new(ServerStats::with_sources(
+            SharedClock::from(Arc::clone(&clock) as Arc<dyn Clock>),
+            Arc::new(SequentialIds::starting_at(100)),
+        ));
+        let request = stats.begin_request(None);
+        clock.advance(Duration::from_millis(2_500));
+
+        assert_eq!(request.id(), 100);
+        assert_eq!(stats.active_requests()[0].elapsed_ms, 2_500);
+        assert_eq!(stats.uptime_secs(), 2);
+    }
+
+    #[test]
+    fn test_kill_request_triggers_run_handle() {
+        let stats = Arc::new(ServerStats::new());
+        let request = stats.begin_request(None);
+        let run = RunHandle::new();
+        request.attach_run(run.clone());
+
+        assert!(stats.kill_request(request.id() + 1).is_none());
+        let killed = stats.kill_request(request.id()).unwrap();
+        assert_eq!(killed.id, request.id());
+        assert!(run.is_killed());
+    }
+
+    #[test]
+    fn test_kill_all_on_shutdown() {
+        let stats = Arc::new(ServerStats::new());
+        let runs: Vec<_> = (0..2)
+            .map(|_| {
+                let request = stats.begin_request(None);
+                let run = RunHandle::new();
+                request.attach_run(run.clone());
+                (request, run)
+            })
+            .collect();
+
+        assert_eq!(stats.kill_all_on_shutdown(), 2);
+        assert!(runs.iter().all(|(_, run)| run.is_killed()));
+
+        drop(runs);
+        assert_eq!(stats.shutdown_report().in_flight_killed, 2);
+    }
+
+    #[test]
+    fn test_shutdown_report_written_to_path() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("report.json");
+
+        ServerStats::new().shutdown_report().emit(Some(&path));
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["total_requests"], 0);
+        assert!(written.get("uptime_secs").is_some());
+    }
+}
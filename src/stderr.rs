@@ -0,0 +1,137 @@
+//! Bounded capture of the CLI's stderr.
+//!
+//! A run's stderr goes into its error message when the CLI fails, so it is
+//! kept up to a byte limit. The text is cut on a char boundary, and whatever
+//! does not fit is counted so the report can say how much is missing.
+
+/// Stderr lines joined by newlines, up to `limit` bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StderrCapture {
+    text: String,
+    limit: usize,
+    lines: usize,
+    /// Bytes of all lines and their separating newlines
+    seen: usize,
+    /// Bytes of `seen` not kept in `text`
+    dropped: usize,
+}
+
+impl StderrCapture {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Add `line`. Once a line no longer fits, it is cut and every later line
+    /// is only counted.
+    pub fn push_line(&mut self, line: &str) {
+        let separator = if self.lines > 0 { "\n" } else { "" };
+        self.lines += 1;
+        let len = separator.len() + line.len();
+        self.seen += len;
+        if self.dropped > 0 {
+            self.dropped += len;
+            return;
+        }
+        let room = self.limit - self.text.len();
+        if len <= room {
+            self.text.push_str(separator);
+            self.text.push_str(line);
+            return;
+        }
+        if room > separator.len() {
+            let mut end = room - separator.len();
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.text.push_str(separator);
+            self.text.push_str(&line[..end]);
+        }
+        self.dropped += self.seen - self.text.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines == 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.dropped > 0
+    }
+
+    /// Bytes written to stderr, kept or not
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// The kept text, followed by a note of how much was dropped
+    pub fn report(&self) -> String {
+        if self.is_truncated() {
+            format!(
+                "{}\n... (stderr truncated: {} of {} bytes dropped)",
+                self.text, self.dropped, self.seen
+            )
+        } else {
+            self.text.clone()
+        }
+    }
+
+    /// Warning for a run whose stderr did not fit
+    pub fn warning(&self) -> Option<String> {
+        self.is_truncated().then(|| {
+            format!(
+                "stderr truncated at {} bytes; {} of {} bytes dropped",
+                self.limit, self.dropped, self.seen
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_within_the_limit_are_kept() {
+        let mut capture = StderrCapture::new(100);
+        assert!(capture.is_empty());
+        capture.push_line("");
+        capture.push_line("first");
+        capture.push_line("");
+        capture.push_line("third");
+        assert_eq!(capture.report(), "\nfirst\n\nthird");
+        assert_eq!(capture.seen(), 13);
+        assert!(!capture.is_truncated());
+        assert_eq!(capture.warning(), None);
+    }
+
+    #[test]
+    fn test_truncation_keeps_whole_chars() {
+        let mut capture = StderrCapture::new(8);
+        capture.push_line("ab");
+        // "\n" plus "żółw" is 1 + 7 bytes; 5 bytes of the line would fit,
+        // which ends inside "ł"
+        capture.push_line("żółw");
+        capture.push_line("later");
+        assert_eq!(capture.seen(), 2 + 8 + 6);
+        assert_eq!(capture.dropped(), 3 + 6);
+        assert!(capture
+            .report()
+            .starts_with("ab\nżó\n... (stderr truncated"));
+        assert!(capture.warning().unwrap().contains("9 of 16 bytes dropped"));
+    }
+
+    #[test]
+    fn test_a_full_capture_drops_the_separator_too() {
+        let mut capture = StderrCapture::new(3);
+        capture.push_line("abc");
+        capture.push_line("d");
+        assert_eq!(capture.dropped(), 2);
+        assert!(capture.report().starts_with("abc\n..."));
+    }
+}
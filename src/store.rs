@@ -0,0 +1,435 @@
+//! SQLite-backed run history (`sqlite` feature).
+//!
+//! Every run is appended to a `runs` table. On startup the table is folded
+//! back into [`SessionRecord`]s so model pinning, usage totals and transcript
+//! resources survive a restart. Soft-deleted sessions are marked in
+//! `deleted_sessions` and their runs are only removed when they are purged.
+//! Each run also records the server version, git commit and CLI version that
+//! produced it.
+
+use crate::build_info;
+use crate::event::Usage;
+use crate::session::SessionRecord;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id       TEXT    NOT NULL,
+    prompt           TEXT    NOT NULL,
+    response         TEXT    NOT NULL,
+    model            TEXT,
+    success          INTEGER NOT NULL,
+    error            TEXT,
+    duration_ms      INTEGER NOT NULL,
+    prompt_tokens    INTEGER NOT NULL DEFAULT 0,
+    candidate_tokens INTEGER NOT NULL DEFAULT 0,
+    total_tokens     INTEGER NOT NULL DEFAULT 0,
+    created_at_ms    INTEGER NOT NULL,
+    server_version   TEXT,
+    git_sha          TEXT,
+    cli_version      TEXT
+);
+CREATE INDEX IF NOT EXISTS runs_session_id ON runs (session_id);
+CREATE TABLE IF NOT EXISTS session_labels (
+    label      TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS deleted_sessions (
+    session_id    TEXT PRIMARY KEY,
+    deleted_at_ms INTEGER NOT NULL
+);
+";
+
+/// Columns added to `runs` after its first release, created on databases
+/// that predate them
+const ADDED_RUN_COLUMNS: [&str; 3] = ["server_version", "git_sha", "cli_version"];
+
+/// One completed run, as written to the store
+#[derive(Debug, Clone, Default)]
+pub struct RunRow<'a> {
+    /// Empty when the CLI failed before reporting a session
+    pub session_id: &'a str,
+    pub prompt: &'a str,
+    pub response: &'a str,
+    pub model: Option<&'a str>,
+    pub success: bool,
+    pub error: Option<&'a str>,
+    pub duration: Duration,
+    pub usage: Option<Usage>,
+}
+
+/// Append-only run history in a local SQLite database
+#[derive(Debug)]
+pub struct RunStore {
+    conn: Mutex<Connection>,
+}
+
+impl RunStore {
+    /// Open (creating if needed) the database at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open session store {}", path.display()))?;
+        Self::init(conn)
+    }
+
+    /// In-memory store, mainly for tests
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().context("Failed to open in-memory store")?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("Failed to initialize session store schema")?;
+        add_missing_columns(&conn).context("Failed to migrate session store schema")?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn record_run(&self, run: &RunRow<'_>) -> Result<()> {
+        let usage = run.usage.unwrap_or_default();
+        let build = build_info::current();
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO runs (session_id, prompt, response, model, success, error,
+                    duration_ms, prompt_tokens, candidate_tokens, total_tokens, created_at_ms,
+                    server_version, git_sha, cli_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    run.session_id,
+                    run.prompt,
+                    run.response,
+                    run.model,
+                    run.success,
+                    run.error,
+                    run.duration.as_millis() as i64,
+                    usage.prompt_tokens as i64,
+                    usage.candidate_tokens as i64,
+                    usage.total_tokens as i64,
+                    to_millis(SystemTime::now()),
+                    build.server_version,
+                    build.git_sha,
+                    build.cli_version,
+                ],
+            )
+            .context("Failed to record run in session store")?;
+        Ok(())
+    }
+
+    /// Persist a `SESSION_LABEL` binding, replacing any previous one
+    pub fn bind_label(&self, label: &str, session_id: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO session_labels (label, session_id) VALUES (?1, ?2)",
+                params![label, session_id],
+            )
+            .context("Failed to record session label in session store")?;
+        Ok(())
+    }
+
+    /// All stored `(label, session_id)` bindings
+    pub fn load_labels(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT label, session_id FROM session_labels")
+            .context("Failed to query session labels")?;
+        let labels = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Failed to query session labels")?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to read session label row")?;
+        Ok(labels)
+    }
+
+    /// Mark a session as soft-deleted at `deleted_at`
+    pub fn mark_deleted(&self, session_id: &str, deleted_at: SystemTime) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO deleted_sessions (session_id, deleted_at_ms) VALUES (?1, ?2)",
+                params![session_id, to_millis(deleted_at)],
+            )
+            .context("Failed to mark session deleted in session store")?;
+        Ok(())
+    }
+
+    /// Clear the soft-delete mark of a restored session
+    pub fn unmark_deleted(&self, session_id: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM deleted_sessions WHERE session_id = ?1",
+                params![session_id],
+            )
+            .context("Failed to restore session in session store")?;
+        Ok(())
+    }
+
+    /// All soft-deleted sessions with the time they were deleted
+    pub fn load_deleted(&self) -> Result<Vec<(String, SystemTime)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT session_id, deleted_at_ms FROM deleted_sessions")
+            .context("Failed to query deleted sessions")?;
+        let deleted = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, from_millis(row.get::<_, i64>(1)?)))
+            })
+            .context("Failed to query deleted sessions")?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to read deleted session row")?;
+        Ok(deleted)
+    }
+
+    /// Remove every run, label and mark of a session for good
+    pub fn purge_session(&self, session_id: &str) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .context("Failed to purge session from session store")?;
+        for table in ["runs", "session_labels", "deleted_sessions"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE session_id = ?1", table),
+                params![session_id],
+            )
+            .context("Failed to purge session from session store")?;
+        }
+        tx.commit()
+            .context("Failed to purge session from session store")?;
+        Ok(())
+    }
+
+    /// Rebuild session records from the stored runs, oldest run first
+    pub fn load_sessions(&self) -> Result<Vec<(String, SessionRecord)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT session_id, response, model, prompt_tokens, candidate_tokens,
+                        total_tokens, created_at_ms
+                 FROM runs WHERE session_id != '' ORDER BY id",
+            )
+            .context("Failed to query session store")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    Usage {
+                        prompt_tokens: row.get::<_, i64>(3)? as u64,
+                        candidate_tokens: row.get::<_, i64>(4)? as u64,
+                        total_tokens: row.get::<_, i64>(5)? as u64,
+                    },
+                    from_millis(row.get::<_, i64>(6)?),
+                ))
+            })
+            .context("Failed to query session store")?;
+
+        let mut order = Vec::new();
+        let mut sessions: HashMap<String, SessionRecord> = HashMap::new();
+        for row in rows {
+            let (session_id, response, model, usage, at) =
+                row.context("Failed to read session store row")?;
+            let record = sessions.entry(session_id.clone()).or_insert_with(|| {
+                order.push(session_id);
+                SessionRecord {
+                    model: None,
+                    created_at: at,
+                    last_used: at,
+                    turns: 0,
+                    usage: Usage::default(),
+                    transcript: Vec::new(),
+                    events: Vec::new(),
+                }
+            });
+            if record.model.is_none() {
+                record.model = model;
+            }
+            record.last_used = at;
+            record.turns += 1;
+            record.usage += usage;
+            record.transcript.push(response);
+        }
+
+        Ok(order
+            .into_iter()
+            .filter_map(|id| sessions.remove(&id).map(|record| (id, record)))
+            .collect())
+    }
+}
+
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let existing = conn
+        .prepare("SELECT name FROM pragma_table_info('runs')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for column in ADDED_RUN_COLUMNS {
+        if !existing.iter().any(|name| name == column) {
+            conn.execute(&format!("ALTER TABLE runs ADD COLUMN {} TEXT", column), [])?;
+        }
+    }
+    Ok(())
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_fold_into_sessions() {
+        let store = RunStore::open_in_memory().unwrap();
+        let usage = Usage {
+            prompt_tokens: 10,
+            candidate_tokens: 5,
+            total_tokens: 15,
+        };
+        let run = |session_id, response, model| RunRow {
+            session_id,
+            prompt: "p",
+            response,
+            model,
+            success: true,
+            usage: Some(usage),
+            ..Default::default()
+        };
+
+        store
+            .record_run(&run("s1", "one", Some("gemini-2.5-pro")))
+            .unwrap();
+        store.record_run(&run("s2", "other", None)).unwrap();
+        store
+            .record_run(&run("s1", "two", Some("gemini-2.5-flash")))
+            .unwrap();
+        store.record_run(&run("", "failed early", None)).unwrap();
+
+        let sessions = store.load_sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+
+        let (id, s1) = &sessions[0];
+        assert_eq!(id, "s1");
+        assert_eq!(s1.model.as_deref(), Some("gemini-2.5-pro"));
+        assert_eq!(s1.turns, 2);
+        assert_eq!(s1.usage.total_tokens, 30);
+        assert_eq!(s1.transcript, vec!["one", "two"]);
+        assert_eq!(sessions[1].0, "s2");
+    }
+
+    #[test]
+    fn test_deleted_sessions_are_marked_until_purged() {
+        let store = RunStore::open_in_memory().unwrap();
+        for session_id in ["s1", "s2"] {
+            store
+                .record_run(&RunRow {
+                    session_id,
+                    prompt: "p",
+                    response: "r",
+                    success: true,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        store.bind_label("nightly", "s1").unwrap();
+        let at = from_millis(5_000);
+
+        store.mark_deleted("s1", at).unwrap();
+        store.mark_deleted("s2", at).unwrap();
+        store.unmark_deleted("s2").unwrap();
+        assert_eq!(store.load_deleted().unwrap(), vec![("s1".to_string(), at)]);
+        // Marked sessions are still loaded so they can be restored
+        assert_eq!(store.load_sessions().unwrap().len(), 2);
+
+        store.purge_session("s1").unwrap();
+        assert!(store.load_deleted().unwrap().is_empty());
+        assert!(store.load_labels().unwrap().is_empty());
+        let sessions = store.load_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].0, "s2");
+    }
+
+    #[test]
+    fn test_old_databases_gain_build_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT, session_id TEXT NOT NULL,
+                prompt TEXT NOT NULL, response TEXT NOT NULL, model TEXT,
+                success INTEGER NOT NULL, error TEXT, duration_ms INTEGER NOT NULL,
+                prompt_tokens INTEGER NOT NULL DEFAULT 0,
+                candidate_tokens INTEGER NOT NULL DEFAULT 0,
+                total_tokens INTEGER NOT NULL DEFAULT 0, created_at_ms INTEGER NOT NULL
+            );",
+        )
+        .unwrap();
+        let store = RunStore::init(conn).unwrap();
+        store
+            .record_run(&RunRow {
+                session_id: "s1",
+                prompt: "p",
+                response: "r",
+                success: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(store.load_sessions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_store_persists_to_disk() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("runs.sqlite3");
+
+        RunStore::open(&path)
+            .unwrap()
+            .record_run(&RunRow {
+                session_id: "s1",
+                prompt: "p",
+                response: "r",
+                success: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        RunStore::open(&path)
+            .unwrap()
+            .bind_label("nightly", "s1")
+            .unwrap();
+
+        let reopened = RunStore::open(&path).unwrap();
+        assert_eq!(reopened.load_sessions().unwrap().len(), 1);
+        let (server_version, git_sha): (String, String) = reopened
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT server_version, git_sha FROM runs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(server_version, build_info::SERVER_VERSION);
+        assert_eq!(git_sha, build_info::GIT_SHA);
+        assert_eq!(
+            reopened.load_labels().unwrap(),
+            vec![("nightly".to_string(), "s1".to_string())]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! The short `summary` of an answer in the `gemini` tool output, so clients
+//! that only show a preview need not download and truncate the whole
+//! message.
+//!
+//! By default the summary is an excerpt: the beginning and the end of the
+//! answer. With `"mode": "model"` a second, cheap Gemini call writes a
+//! one-liner instead, falling back to the excerpt if it fails.
+
+use crate::gemini::{self, Options, SummaryConfig, SummaryMode};
+use crate::verify;
+
+/// Longest summary unless `summary.max_chars` is configured
+pub const DEFAULT_MAX_CHARS: usize = 200;
+/// Marks the text left out of an excerpt
+const ELLIPSIS: &str = " … ";
+
+/// Summary of `message` as configured, with a warning when the model
+/// summary failed. `None` when summaries are off or the message is empty.
+pub async fn summarize(message: &str, config: &SummaryConfig) -> (Option<String>, Option<String>) {
+    let max_chars = config
+        .max_chars
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_CHARS);
+    let collapsed = collapse(message);
+    if config.mode == SummaryMode::Off || collapsed.is_empty() {
+        return (None, None);
+    }
+    // A short answer is its own summary; no need to pay for a model call
+    if config.mode == SummaryMode::Excerpt || collapsed.chars().count() <= max_chars {
+        return (Some(excerpt(&collapsed, max_chars)), None);
+    }
+
+    match ask_model(config, message, max_chars).await {
+        Ok(line) => (Some(line), None),
+        Err(err) => (
+            Some(excerpt(&collapsed, max_chars)),
+            Some(format!("model summary failed, using an excerpt: {:#}", err)),
+        ),
+    }
+}
+
+/// The first two thirds and the last third of `text`'s `max_chars`
+/// characters, cut at word boundaries where possible
+pub fn excerpt(text: &str, max_chars: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max_chars {
+        return text.to_string();
+    }
+    let budget = max_chars.saturating_sub(ELLIPSIS.chars().count()).max(2);
+    let head_len = budget * 2 / 3;
+    let tail_len = budget - head_len;
+
+    let head: String = chars[..head_len].iter().collect();
+    let head = match head.rfind(' ') {
+        Some(cut) if cut > head.len() / 2 => &head[..cut],
+        _ => head.as_str(),
+    };
+    let tail: String = chars[chars.len() - tail_len..].iter().collect();
+    let tail = match tail.find(' ') {
+        Some(cut) if cut < tail.len() / 2 => &tail[cut + 1..],
+        _ => tail.as_str(),
+    };
+    format!("{}{}{}", head.trim_end(), ELLIPSIS, tail.trim_start())
+}
+
+/// `text` on one line with runs of whitespace collapsed
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+async fn ask_model(
+    config: &SummaryConfig,
+    message: &str,
+    max_chars: usize,
+) -> anyhow::Result<String> {
+    let model = config
+        .model
+        .clone()
+        .filter(|m| !m.trim().is_empty())
+        .or_else(gemini::verify_model)
+        .unwrap_or_else(|| verify::DEFAULT_MODEL.to_string());
+    let opts = Options::builder()
+        .prompt(format!(
+            "Summarize the following answer in one plain sentence of at most {} characters. \
+             Reply with the sentence only.\n\n<answer>\n{}\n</answer>",
+            max_chars, message
+        ))
+        .additional_args(gemini::default_additional_args())
+        .keep_raw_events(false)
+        .model(model)
+        .build()?;
+    let result = gemini::run(opts).await?;
+    if !result.success {
+        anyhow::bail!(result.error.unwrap_or_else(|| "unknown error".to_string()));
+    }
+    let line = collapse(&result.agent_messages);
+    if line.is_empty() {
+        anyhow::bail!("the model returned an empty summary");
+    }
+    Ok(excerpt(&line, max_chars))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excerpt_keeps_beginning_and_end() {
+        assert_eq!(excerpt("short answer", 200), "short answer");
+
+        let text = (1..=100)
+            .map(|n| format!("w{}", n))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let summary = excerpt(&text, 60);
+        assert!(summary.chars().count() <= 60, "{}", summary);
+        assert!(summary.starts_with("w1 w2 "));
+        assert!(summary.ends_with(" w99 w100"));
+        assert!(summary.contains(" … "));
+        // Cut between words, not inside them
+        assert!(summary
+            .split(' ')
+            .all(|w| w == "…" || text.split(' ').any(|t| t == w)));
+
+        let wide = "é".repeat(500);
+        assert_eq!(excerpt(&wide, 20).chars().count(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_summaries_can_be_turned_off() {
+        let off = SummaryConfig {
+            mode: SummaryMode::Off,
+            ..Default::default()
+        };
+        assert_eq!(summarize("anything", &off).await, (None, None));
+
+        let default = SummaryConfig::default();
+        assert_eq!(summarize("  \n ", &default).await, (None, None));
+        assert_eq!(
+            summarize("Done.\n\nAll   tests pass.", &default).await,
+            (Some("Done. All tests pass.".to_string()), None)
+        );
+
+        // Short answers never reach the model
+        let model = SummaryConfig {
+            mode: SummaryMode::Model,
+            ..Default::default()
+        };
+        assert_eq!(summarize("Done.", &model).await.0.as_deref(), Some("Done."));
+    }
+}
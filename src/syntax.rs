@@ -0,0 +1,33 @@
+//! Tree-sitter grammars for syntax-aware processing of source files
+//! (requires the `tree-sitter` feature).
+
+use std::path::Path;
+use tree_sitter::{Language, Parser, Tree};
+
+/// Grammar for `path`, chosen by file extension
+pub fn language_for(path: &str) -> Option<Language> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    let language = match extension {
+        "rs" => tree_sitter_rust::LANGUAGE,
+        "py" | "pyi" => tree_sitter_python::LANGUAGE,
+        "js" | "jsx" | "mjs" | "cjs" => tree_sitter_javascript::LANGUAGE,
+        "ts" | "mts" | "cts" => tree_sitter_typescript::LANGUAGE_TYPESCRIPT,
+        "tsx" => tree_sitter_typescript::LANGUAGE_TSX,
+        "go" => tree_sitter_go::LANGUAGE,
+        _ => return None,
+    };
+    Some(language.into())
+}
+
+/// Comments and attributes that belong to the item following them
+pub fn is_leading(kind: &str) -> bool {
+    kind.ends_with("comment") || kind == "attribute_item" || kind == "decorator"
+}
+
+/// Parse `text` with the grammar for `path`; `None` for unsupported files
+pub fn parse(path: &str, text: &str) -> Option<Tree> {
+    let language = language_for(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language).ok()?;
+    parser.parse(text, None)
+}
@@ -0,0 +1,428 @@
+//! Conversation starters, advertised to clients as MCP prompts.
+//!
+//! A template is a `gemini` prompt with `{{argument}}` placeholders. The
+//! server renders it (`prompts/get`) into a message asking the client's model
+//! to call the `gemini` tool with ready-made arguments, so clients with prompt
+//! pickers can start common tasks without writing tool JSON by hand.
+//! Arguments of kind `model`, `persona`, `session` or `label` also set the matching
+//! tool parameter, and their values can be completed (`completion/complete`).
+//! Built-in templates can be overridden, and new ones added, under
+//! `templates` in the server config.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+const MAX_NAME_LEN: usize = 64;
+/// Most completion values returned at once, as allowed by MCP
+pub const MAX_COMPLETIONS: usize = 100;
+
+/// Models offered as completions for `model` arguments
+pub const KNOWN_MODELS: &[&str] = &[
+    "gemini-2.5-pro",
+    "gemini-2.5-flash",
+    "gemini-2.5-flash-lite",
+    "gemini-3-pro-preview",
+];
+
+/// One template, as configured under `templates`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub description: String,
+    /// Prompt text with `{{argument}}` placeholders
+    pub prompt: String,
+    #[serde(default)]
+    pub arguments: Vec<TemplateArgument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateArgument {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub kind: ArgumentKind,
+    /// Allowed values, offered as completions
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+/// What an argument's value means beyond its place in the prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentKind {
+    /// Only substituted into the prompt
+    #[default]
+    Text,
+    /// Also selects the model (`EXTRA_ARGS: ["--model", …]`)
+    Model,
+    /// Also selects a `PERSONA`
+    Persona,
+    /// Also resumes a session (`SESSION_ID`)
+    Session,
+    /// Also resumes or names a session by label (`SESSION_LABEL`)
+    Label,
+}
+
+/// A rendered template: the prompt and the `gemini` tool arguments to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub prompt: String,
+    pub tool_arguments: Map<String, Value>,
+}
+
+fn argument(name: &str, description: &str, required: bool, kind: ArgumentKind) -> TemplateArgument {
+    TemplateArgument {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        required,
+        kind,
+        values: Vec::new(),
+    }
+}
+
+/// Templates available without any configuration
+pub fn builtin() -> BTreeMap<String, Template> {
+    let path = || {
+        argument(
+            "path",
+            "File or directory to work on",
+            true,
+            ArgumentKind::Text,
+        )
+    };
+    let model = || argument("model", "Gemini model to use", false, ArgumentKind::Model);
+    let mut templates = BTreeMap::new();
+    templates.insert(
+        "review-file".to_string(),
+        Template {
+            description: "Review a file for bugs, risky changes and missing tests".to_string(),
+            prompt: "Review {{path}} for bugs, risky changes and missing tests.\n\
+                     Pay particular attention to: {{focus}}"
+                .to_string(),
+            arguments: vec![
+                path(),
+                argument(
+                    "focus",
+                    "What to look at most closely",
+                    false,
+                    ArgumentKind::Text,
+                ),
+                argument("persona", "Reviewer persona", false, ArgumentKind::Persona),
+                model(),
+            ],
+        },
+    );
+    templates.insert(
+        "explain-code".to_string(),
+        Template {
+            description: "Explain how a piece of code works".to_string(),
+            prompt: "Explain how the code in {{path}} works: its purpose, main data flow \
+                     and non-obvious details.\n\
+                     Tailor the explanation to {{audience}} readers."
+                .to_string(),
+            arguments: vec![
+                path(),
+                TemplateArgument {
+                    values: vec![
+                        "beginner".to_string(),
+                        "intermediate".to_string(),
+                        "expert".to_string(),
+                    ],
+                    ..argument(
+                        "audience",
+                        "Reader's experience level",
+                        false,
+                        ArgumentKind::Text,
+                    )
+                },
+                model(),
+            ],
+        },
+    );
+    templates.insert(
+        "write-tests".to_string(),
+        Template {
+            description: "Write unit tests for a file".to_string(),
+            prompt: "Write unit tests for {{path}} covering normal cases, edge cases and \
+                     error handling. Follow the project's existing test conventions.\n\
+                     Use the {{framework}} test framework."
+                .to_string(),
+            arguments: vec![
+                path(),
+                argument(
+                    "framework",
+                    "Test framework to use",
+                    false,
+                    ArgumentKind::Text,
+                ),
+                model(),
+            ],
+        },
+    );
+    templates.insert(
+        "continue-session".to_string(),
+        Template {
+            description: "Send a follow-up to an earlier session".to_string(),
+            prompt: "{{instruction}}".to_string(),
+            arguments: vec![
+                argument("session", "Session to resume", true, ArgumentKind::Session),
+                argument("instruction", "What to ask next", true, ArgumentKind::Text),
+            ],
+        },
+    );
+    templates
+}
+
+/// Built-in and configured templates; configured ones shadow built-ins
+pub fn all(configured: &BTreeMap<String, Template>) -> BTreeMap<String, Template> {
+    let mut templates = builtin();
+    templates.extend(configured.iter().map(|(name, t)| (name.clone(), t.clone())));
+    templates
+}
+
+/// Reject configured templates with unusable names, duplicate arguments or
+/// placeholders that name no argument
+pub fn validate(configured: &BTreeMap<String, Template>) -> Result<()> {
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    };
+    for (name, template) in configured {
+        if !valid_name(name) {
+            bail!(
+                "template name `{}` must be 1-{} characters of letters, digits, `-`, `_` or `.`",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        if template.prompt.trim().is_empty() {
+            bail!("template `{}` has an empty prompt", name);
+        }
+        for (i, arg) in template.arguments.iter().enumerate() {
+            if !valid_name(&arg.name) {
+                bail!(
+                    "template `{}` has an invalid argument name `{}`",
+                    name,
+                    arg.name
+                );
+            }
+            if template.arguments[..i].iter().any(|a| a.name == arg.name) {
+                bail!("template `{}` declares argument `{}` twice", name, arg.name);
+            }
+        }
+        for placeholder in placeholders(&template.prompt) {
+            if !template.arguments.iter().any(|a| a.name == placeholder) {
+                bail!(
+                    "template `{}` uses `{{{{{}}}}}` but declares no such argument",
+                    name,
+                    placeholder
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Names inside `{{…}}` in `text`, in order
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        names.push(rest[start + 2..start + 2 + len].trim());
+        rest = &rest[start + 2 + len + 2..];
+    }
+    names
+}
+
+/// Fill in `template` with `values`. Lines whose placeholders are all empty
+/// are dropped, so optional arguments can carry their own sentence.
+pub fn render(template: &Template, values: &BTreeMap<String, String>) -> Result<Rendered> {
+    if let Some(unknown) = values
+        .keys()
+        .find(|name| !template.arguments.iter().any(|a| &a.name == *name))
+    {
+        bail!("Unknown template argument `{}`", unknown);
+    }
+    let value = |name: &str| values.get(name).map(|v| v.trim()).unwrap_or("");
+    for arg in &template.arguments {
+        let v = value(&arg.name);
+        if arg.required && v.is_empty() {
+            bail!("Missing required template argument `{}`", arg.name);
+        }
+        if !v.is_empty() && !arg.values.is_empty() && !arg.values.iter().any(|a| a == v) {
+            bail!(
+                "Template argument `{}` must be one of: {}",
+                arg.name,
+                arg.values.join(", ")
+            );
+        }
+    }
+
+    let mut lines = Vec::new();
+    for line in template.prompt.lines() {
+        let names = placeholders(line);
+        if !names.is_empty() && names.iter().all(|name| value(name).is_empty()) {
+            continue;
+        }
+        let mut line = line.to_string();
+        for name in names {
+            line = line.replace(&format!("{{{{{}}}}}", name), value(name));
+        }
+        lines.push(line);
+    }
+    let prompt = lines.join("\n").trim().to_string();
+
+    let mut tool_arguments = Map::new();
+    tool_arguments.insert("PROMPT".to_string(), json!(prompt));
+    for arg in &template.arguments {
+        let v = value(&arg.name);
+        if v.is_empty() {
+            continue;
+        }
+        match arg.kind {
+            ArgumentKind::Text => {}
+            ArgumentKind::Model => {
+                tool_arguments.insert("EXTRA_ARGS".to_string(), json!(["--model", v]));
+            }
+            ArgumentKind::Persona => {
+                tool_arguments.insert("PERSONA".to_string(), json!(v));
+            }
+            ArgumentKind::Session => {
+                tool_arguments.insert("SESSION_ID".to_string(), json!(v));
+            }
+            ArgumentKind::Label => {
+                tool_arguments.insert("SESSION_LABEL".to_string(), json!(v));
+            }
+        }
+    }
+    Ok(Rendered {
+        prompt,
+        tool_arguments,
+    })
+}
+
+/// The user message of a rendered template
+pub fn message(rendered: &Rendered) -> String {
+    let arguments = serde_json::to_string_pretty(&rendered.tool_arguments)
+        .unwrap_or_else(|_| Value::Object(rendered.tool_arguments.clone()).to_string());
+    format!(
+        "Call the `gemini` tool with these arguments:\n\n```json\n{}\n```",
+        arguments
+    )
+}
+
+/// `candidates` starting with `prefix` (case-insensitive), in their given
+/// order without duplicates, at most [`MAX_COMPLETIONS`]. Also returns the
+/// total count.
+pub fn matching(candidates: Vec<String>, prefix: &str) -> (Vec<String>, usize) {
+    let prefix = prefix.trim().to_lowercase();
+    let mut values: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if candidate.to_lowercase().starts_with(&prefix) && !values.contains(&candidate) {
+            values.push(candidate);
+        }
+    }
+    let total = values.len();
+    values.truncate(MAX_COMPLETIONS);
+    (values, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let templates = builtin();
+        let review = &templates["review-file"];
+
+        let rendered = render(review, &values(&[("path", "src/lib.rs")])).unwrap();
+        assert_eq!(
+            rendered.prompt,
+            "Review src/lib.rs for bugs, risky changes and missing tests."
+        );
+        assert_eq!(
+            Value::Object(rendered.tool_arguments.clone()),
+            json!({"PROMPT": rendered.prompt})
+        );
+
+        let rendered = render(
+            review,
+            &values(&[
+                ("path", "src/lib.rs"),
+                ("focus", "error handling"),
+                ("persona", "security-auditor"),
+                ("model", "gemini-2.5-pro"),
+            ]),
+        )
+        .unwrap();
+        assert!(rendered
+            .prompt
+            .ends_with("Pay particular attention to: error handling"));
+        assert_eq!(
+            rendered.tool_arguments["PERSONA"],
+            json!("security-auditor")
+        );
+        assert_eq!(
+            rendered.tool_arguments["EXTRA_ARGS"],
+            json!(["--model", "gemini-2.5-pro"])
+        );
+        assert!(message(&rendered).contains("\"PERSONA\": \"security-auditor\""));
+
+        assert!(render(review, &values(&[])).is_err());
+        assert!(render(review, &values(&[("path", "x"), ("colour", "red")])).is_err());
+        let explain = &templates["explain-code"];
+        assert!(render(explain, &values(&[("path", "x"), ("audience", "toddler")])).is_err());
+    }
+
+    #[test]
+    fn test_validate_and_matching() {
+        let mut configured = BTreeMap::new();
+        configured.insert(
+            "triage".to_string(),
+            Template {
+                description: "Triage an issue".to_string(),
+                prompt: "Triage {{issue}} with {{priority}}".to_string(),
+                arguments: vec![argument("issue", "Issue", true, ArgumentKind::Text)],
+            },
+        );
+        let err = validate(&configured).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "template `triage` uses `{{priority}}` but declares no such argument"
+        );
+        assert!(validate(&builtin()).is_ok());
+        assert!(all(&configured).contains_key("review-file"));
+
+        let (values, total) = matching(
+            vec![
+                "gemini-2.5-pro".into(),
+                "gemini-2.5-flash".into(),
+                "Gemini-2.5-pro".into(),
+                "other".into(),
+            ],
+            "GEMINI-2.5-P",
+        );
+        assert_eq!(values, vec!["gemini-2.5-pro", "Gemini-2.5-pro"]);
+        assert_eq!(total, 2);
+    }
+}
@@ -0,0 +1,129 @@
+//! Network transports for running the server as a long-lived service.
+//!
+//! Each function serves until the `shutdown` future resolves and then returns,
+//! so the caller can drain in-flight calls and emit the shutdown report just
+//! like in stdio mode.
+
+use crate::server::GeminiServer;
+use anyhow::{Context, Result};
+use rmcp::transport::sse_server::{SseServer, SseServerConfig};
+use rmcp::transport::streamable_http_server::{
+    session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+};
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio_util::sync::CancellationToken;
+
+/// Path the streamable HTTP endpoint is mounted on
+pub const STREAMABLE_HTTP_PATH: &str = "/mcp";
+
+/// Admin endpoint returning [`GeminiServer::diagnostics`] as JSON
+pub const DIAGNOSTICS_PATH: &str = "/diagnostics";
+
+/// Prometheus scrape endpoint ([`GeminiServer::prometheus_metrics`])
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Liveness and readiness probe returning [`GeminiServer::health`] as JSON,
+/// `503 Service Unavailable` when unhealthy
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Content type of the Prometheus text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The [`DIAGNOSTICS_PATH`], [`METRICS_PATH`] and [`HEALTH_PATH`] admin
+/// endpoints
+fn admin_routes(server: GeminiServer) -> axum::Router {
+    let diagnostics_server = server.clone();
+    let health_server = server.clone();
+    axum::Router::new()
+        .route(
+            HEALTH_PATH,
+            axum::routing::get(move || async move {
+                let report = health_server.health().await;
+                let status = if report.ok {
+                    axum::http::StatusCode::OK
+                } else {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                };
+                (status, axum::Json(report))
+            }),
+        )
+        .route(
+            DIAGNOSTICS_PATH,
+            axum::routing::get(move || async move { axum::Json(diagnostics_server.diagnostics()) }),
+        )
+        .route(
+            METRICS_PATH,
+            axum::routing::get(move || async move {
+                (
+                    [(axum::http::header::CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+                    server.prometheus_metrics(),
+                )
+            }),
+        )
+}
+
+/// Serve MCP over SSE (`GET /sse` + `POST /message`) on `bind`, plus the
+/// admin endpoints
+pub async fn serve_sse(
+    server: GeminiServer,
+    bind: SocketAddr,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let ct = CancellationToken::new();
+    let (sse, sse_router) = SseServer::new(SseServerConfig {
+        bind,
+        sse_path: "/sse".to_string(),
+        post_path: "/message".to_string(),
+        ct: ct.clone(),
+        sse_keep_alive: None,
+    });
+    let router = sse_router.merge(admin_routes(server.clone()));
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind SSE transport on {}", bind))?;
+    tracing::info!("serving SSE on http://{}/sse", bind);
+
+    sse.with_service(move || server.clone());
+    let stop = ct.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        stop.cancel();
+    });
+    axum::serve(listener, router)
+        .with_graceful_shutdown(ct.cancelled_owned())
+        .await
+        .context("SSE server failed")
+}
+
+/// Serve MCP over streamable HTTP at [`STREAMABLE_HTTP_PATH`] on `bind`, plus
+/// the admin endpoints
+pub async fn serve_streamable_http(
+    server: GeminiServer,
+    bind: SocketAddr,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let admin = admin_routes(server.clone());
+    let service = StreamableHttpService::new(
+        move || Ok(server.clone()),
+        LocalSessionManager::default().into(),
+        StreamableHttpServerConfig::default(),
+    );
+    let router = axum::Router::new()
+        .nest_service(STREAMABLE_HTTP_PATH, service)
+        .merge(admin);
+
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind streamable HTTP transport on {}", bind))?;
+    tracing::info!(
+        "serving streamable HTTP on http://{}{}",
+        bind,
+        STREAMABLE_HTTP_PATH
+    );
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown)
+        .await
+        .context("Streamable HTTP server failed")
+}
@@ -0,0 +1,217 @@
+//! Answers longer than `max_output_chars`, cut down before they are returned
+//! so that a giant response does not flood the client's context window.
+//!
+//! `head`, `tail` and `middle-ellipsis` keep part of the answer and say how
+//! much was left out, preferring to cut at line breaks. With
+//! `summarize-with-flash` a second, cheap Gemini call condenses the answer
+//! instead, falling back to `middle-ellipsis` if it fails.
+
+use crate::gemini::{self, Options, TruncationStrategy};
+use crate::verify;
+use serde::Serialize;
+
+/// How far back from a cut a line break is looked for, as a fraction of the
+/// kept text
+const LINE_BREAK_SLACK: usize = 5;
+
+/// Reported in the tool output when the answer was cut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Truncation {
+    /// Strategy that produced `message`; `middle-ellipsis` when the
+    /// summary failed
+    pub strategy: TruncationStrategy,
+    /// Length of the whole answer in characters
+    pub original_chars: usize,
+    /// Whether the server kept the whole answer for `gemini_fetch_output`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fetchable: bool,
+}
+
+/// `message` cut down to `max_chars` characters with `strategy`, what was
+/// done, and a warning when the summary failed. Messages within the limit
+/// are returned as they are.
+pub async fn truncate(
+    message: String,
+    max_chars: Option<usize>,
+    strategy: TruncationStrategy,
+) -> (String, Option<Truncation>, Option<String>) {
+    let original_chars = message.chars().count();
+    let Some(max_chars) = max_chars.filter(|&max| original_chars > max) else {
+        return (message, None, None);
+    };
+    let truncation = |strategy| Truncation {
+        strategy,
+        original_chars,
+        fetchable: false,
+    };
+
+    if strategy != TruncationStrategy::SummarizeWithFlash {
+        return (
+            cut(&message, max_chars, strategy),
+            Some(truncation(strategy)),
+            None,
+        );
+    }
+    match condense(&message, max_chars).await {
+        Ok(condensed) => (condensed, Some(truncation(strategy)), None),
+        Err(err) => (
+            cut(&message, max_chars, TruncationStrategy::MiddleEllipsis),
+            Some(truncation(TruncationStrategy::MiddleEllipsis)),
+            Some(format!(
+                "summarizing the long answer failed, kept its beginning and end: {:#}",
+                err
+            )),
+        ),
+    }
+}
+
+/// Keep the part of `text` selected by `strategy` within `max_chars`
+/// characters, including the note on what was left out
+pub fn cut(text: &str, max_chars: usize, strategy: TruncationStrategy) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max_chars {
+        return text.to_string();
+    }
+    // The note is sized for the largest count it can show, so the result
+    // never exceeds `max_chars`
+    let budget = max_chars.saturating_sub(note(chars.len(), strategy).chars().count());
+    let omitted = chars.len() - budget;
+    let note = note(omitted, strategy);
+    match strategy {
+        TruncationStrategy::Head => format!("{}{}", head(&chars, budget), note),
+        TruncationStrategy::Tail => format!("{}{}", note, tail(&chars, budget)),
+        TruncationStrategy::MiddleEllipsis | TruncationStrategy::SummarizeWithFlash => {
+            let head_len = budget.div_ceil(2);
+            format!(
+                "{}{}{}",
+                head(&chars, head_len),
+                note,
+                tail(&chars, budget - head_len)
+            )
+        }
+    }
+}
+
+fn note(omitted: usize, strategy: TruncationStrategy) -> String {
+    match strategy {
+        TruncationStrategy::Head => format!("\n\n[… {} more characters truncated]", omitted),
+        TruncationStrategy::Tail => format!("[{} earlier characters truncated …]\n\n", omitted),
+        _ => format!("\n\n[… {} characters truncated …]\n\n", omitted),
+    }
+}
+
+/// The first `len` characters, ending at a line break if one is close
+fn head(chars: &[char], len: usize) -> String {
+    let kept = &chars[..len];
+    let floor = len - len / LINE_BREAK_SLACK;
+    let end = kept
+        .iter()
+        .rposition(|&c| c == '\n')
+        .filter(|&i| i >= floor)
+        .unwrap_or(len);
+    kept[..end].iter().collect()
+}
+
+/// The last `len` characters, starting after a line break if one is close
+fn tail(chars: &[char], len: usize) -> String {
+    let kept = &chars[chars.len() - len..];
+    let start = kept
+        .iter()
+        .position(|&c| c == '\n')
+        .filter(|&i| i < len / LINE_BREAK_SLACK)
+        .map_or(0, |i| i + 1);
+    kept[start..].iter().collect()
+}
+
+async fn condense(message: &str, max_chars: usize) -> anyhow::Result<String> {
+    let model = gemini::verify_model().unwrap_or_else(|| verify::DEFAULT_MODEL.to_string());
+    let opts = Options::builder()
+        .prompt(format!(
+            "Condense the following answer to at most {} characters. Keep code, file paths, \
+             commands and conclusions; drop repetition and filler. Reply with the condensed \
+             answer only.\n\n<answer>\n{}\n</answer>",
+            max_chars, message
+        ))
+        .additional_args(gemini::default_additional_args())
+        .keep_raw_events(false)
+        .model(model)
+        .build()?;
+    let result = gemini::run(opts).await?;
+    if !result.success {
+        anyhow::bail!(result.error.unwrap_or_else(|| "unknown error".to_string()));
+    }
+    let condensed = result.agent_messages.trim();
+    if condensed.is_empty() {
+        anyhow::bail!("the model returned an empty summary");
+    }
+    // The model does not always keep to the limit
+    Ok(cut(
+        condensed,
+        max_chars,
+        TruncationStrategy::MiddleEllipsis,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cut_strategies() {
+        let text = (1..=200)
+            .map(|n| format!("line {}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        for strategy in [
+            TruncationStrategy::Head,
+            TruncationStrategy::Tail,
+            TruncationStrategy::MiddleEllipsis,
+        ] {
+            let cut = cut(&text, 300, strategy);
+            assert!(cut.chars().count() <= 300, "{:?}: {}", strategy, cut);
+            assert!(cut.contains("characters truncated"), "{:?}", strategy);
+        }
+        let head = cut(&text, 300, TruncationStrategy::Head);
+        assert!(head.starts_with("line 1\nline 2\n"));
+        // Cut at a line break, not inside a line
+        assert!(head
+            .split("\n\n[")
+            .next()
+            .unwrap()
+            .lines()
+            .all(|line| text.lines().any(|l| l == line)));
+        assert!(cut(&text, 300, TruncationStrategy::Tail).ends_with("line 199\nline 200"));
+        let middle = cut(&text, 300, TruncationStrategy::MiddleEllipsis);
+        assert!(middle.starts_with("line 1\n") && middle.ends_with("line 200"));
+
+        assert_eq!(cut("short", 300, TruncationStrategy::Head), "short");
+        assert!(
+            cut(&"é".repeat(1_000), 100, TruncationStrategy::Tail)
+                .chars()
+                .count()
+                <= 100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_truncate_reports_the_cut() {
+        let (message, truncation, warning) =
+            truncate("a".repeat(500), Some(100), TruncationStrategy::Head).await;
+        assert!(message.chars().count() <= 100);
+        assert_eq!(
+            truncation,
+            Some(Truncation {
+                strategy: TruncationStrategy::Head,
+                original_chars: 500,
+                fetchable: false,
+            })
+        );
+        assert_eq!(warning, None);
+
+        let (message, truncation, _) =
+            truncate("short".to_string(), Some(100), TruncationStrategy::Head).await;
+        assert_eq!((message.as_str(), truncation), ("short", None));
+        let (_, truncation, _) = truncate("a".repeat(500), None, TruncationStrategy::Head).await;
+        assert_eq!(truncation, None);
+    }
+}
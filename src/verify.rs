@@ -0,0 +1,262 @@
+//! Opt-in verification of an answer (`VERIFY`).
+//!
+//! A second, cheap Gemini call checks the answer's claims against the
+//! sources attached to the prompt (`USE_RETRIEVAL` excerpts and `SYMBOLS`
+//! definitions). Independently, every file path the answer mentions is
+//! looked up in the workspace, so invented file references are caught even
+//! when no sources were attached.
+
+use crate::gemini::{self, Options};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Model of the verification call unless `verify_model` is configured
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+
+/// Extensions recognised as file references even without a directory part
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cfg", "cpp", "cs", "css", "go", "h", "hpp", "html", "ini", "java", "js", "json",
+    "jsx", "kt", "lock", "md", "php", "py", "rb", "rs", "scss", "sh", "sql", "swift", "toml", "ts",
+    "tsx", "txt", "vue", "xml", "yaml", "yml",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// Every checked claim is backed by the sources
+    Supported,
+    /// Some claims are unsupported or reference missing files
+    Partial,
+    /// The answer contradicts the sources or is mostly unsupported
+    Unsupported,
+    /// Nothing to check against, or the verification call failed
+    Unverified,
+}
+
+/// Verification report attached to the `gemini` tool output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerificationReport {
+    pub verdict: Verdict,
+    /// The verifier's confidence in its verdict, 0 to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    /// Claims the verifier could not match to the sources
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<String>,
+    /// Paths mentioned in the answer that do not exist in the workspace
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_files: Vec<String>,
+    /// Model that verified the claims; absent when only file references
+    /// were checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reply format requested from the verifier
+#[derive(Debug, Deserialize)]
+struct ModelVerdict {
+    verdict: Verdict,
+    confidence: Option<f64>,
+    #[serde(default)]
+    issues: Vec<String>,
+}
+
+/// Check `answer` against `sources`, resolving file references in `root`
+pub async fn verify(sources: &[String], answer: &str, root: &Path) -> VerificationReport {
+    let mut listing = None;
+    let missing_files = file_references(answer)
+        .into_iter()
+        .filter(|path| !exists(root, path, &mut listing))
+        .collect();
+    let mut report = VerificationReport {
+        verdict: Verdict::Unverified,
+        confidence: None,
+        issues: Vec::new(),
+        missing_files,
+        model: None,
+        error: None,
+    };
+    if sources.is_empty() {
+        if !report.missing_files.is_empty() {
+            report.verdict = Verdict::Partial;
+        }
+        return report;
+    }
+
+    let model = gemini::verify_model().unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    report.model = Some(model.clone());
+    match ask_verifier(&model, sources, answer).await {
+        Ok(verdict) => {
+            report.verdict = verdict.verdict;
+            report.confidence = verdict.confidence.map(|c| c.clamp(0.0, 1.0));
+            report.issues = verdict.issues;
+        }
+        Err(err) => report.error = Some(format!("{:#}", err)),
+    }
+    if report.verdict == Verdict::Supported && !report.missing_files.is_empty() {
+        report.verdict = Verdict::Partial;
+    }
+    report
+}
+
+async fn ask_verifier(
+    model: &str,
+    sources: &[String],
+    answer: &str,
+) -> anyhow::Result<ModelVerdict> {
+    let opts = Options::builder()
+        .prompt(prompt(sources, answer))
+        .additional_args(gemini::default_additional_args())
+        .keep_raw_events(false)
+        .model(model)
+        .build()?;
+    let result = gemini::run(opts).await?;
+    if !result.success {
+        anyhow::bail!(
+            "verification call failed: {}",
+            result.error.as_deref().unwrap_or("unknown error")
+        );
+    }
+    parse_verdict(&result.agent_messages).ok_or_else(|| {
+        anyhow::anyhow!("verifier reply is not a verdict: {}", result.agent_messages)
+    })
+}
+
+fn prompt(sources: &[String], answer: &str) -> String {
+    format!(
+        "Check the ANSWER below against the SOURCES it was written from. Do not use any other \
+         knowledge and do not run tools.\n\
+         Reply with only a JSON object: {{\"verdict\": \"supported\" | \"partial\" | \"unsupported\", \
+         \"confidence\": <number from 0 to 1>, \"issues\": [<string>, ...]}}.\n\
+         List as issues every claim the sources do not back, and every file, function or type \
+         name that does not appear in them.\n\n\
+         SOURCES:\n{}\n\nANSWER:\n{}\n",
+        sources.join("\n"),
+        answer.trim()
+    )
+}
+
+/// Whether `path` names a workspace file, either relative to `root` or as
+/// the trailing components of one (`server.rs` for `src/server.rs`)
+fn exists(root: &Path, path: &str, listing: &mut Option<Vec<PathBuf>>) -> bool {
+    let path = Path::new(path.trim_start_matches("./"));
+    if root.join(path).exists() {
+        return true;
+    }
+    if path.is_absolute() {
+        return false;
+    }
+    listing
+        .get_or_insert_with(|| crate::index::list_files(root).unwrap_or_default())
+        .iter()
+        .any(|file| file.ends_with(path))
+}
+
+/// The JSON object in the verifier's reply, which may be wrapped in prose or
+/// a code fence
+fn parse_verdict(reply: &str) -> Option<ModelVerdict> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    serde_json::from_str(reply.get(start..=end)?).ok()
+}
+
+/// Relative or absolute file paths mentioned in `text`, without `:line`
+/// suffixes, in order of first appearance
+fn file_references(text: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    let separators = |c: char| c.is_whitespace() || "`'\"".contains(c);
+    for token in text.split(separators) {
+        let token = token
+            .trim_start_matches(['(', '[', '<', '*'])
+            .trim_end_matches([')', ']', '>', '*', ',', '.', ':', ';', '!', '?']);
+        // `CACHE.lock()` is a method call, not a file
+        if token.contains('(') {
+            continue;
+        }
+        // `src/main.rs:42` or `src/main.rs:42:7`
+        let mut path = token;
+        while let Some((head, tail)) = path.rsplit_once(':') {
+            if tail.is_empty() || !tail.bytes().all(|b| b.is_ascii_digit()) {
+                break;
+            }
+            path = head;
+        }
+        if path.contains("://") || !looks_like_path(path) {
+            continue;
+        }
+        if !found.iter().any(|f| f == path) {
+            found.push(path.to_string());
+        }
+    }
+    found
+}
+
+pub(crate) fn looks_like_path(token: &str) -> bool {
+    let Some((stem, extension)) = token.rsplit_once('.') else {
+        return false;
+    };
+    let name = stem.rsplit('/').next().unwrap_or(stem);
+    if name.is_empty() && !stem.is_empty() {
+        return false;
+    }
+    let extension_ok = extension.len() <= 6
+        && extension.starts_with(|c: char| c.is_ascii_alphabetic())
+        && extension.bytes().all(|b| b.is_ascii_alphanumeric());
+    extension_ok
+        && (token.contains('/') || SOURCE_EXTENSIONS.contains(&extension))
+        && token
+            .chars()
+            .all(|c| c.is_alphanumeric() || "/._-".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_references() {
+        let answer =
+            "The timeout lives in `src/gemini.rs:1042` and is read by main.rs, via CACHE.lock(). \
+            See https://example.com/docs.html, e.g. the README.md (v1.2), and/or \
+            ./scripts/build.sh or src/gemini.rs again.";
+        assert_eq!(
+            file_references(answer),
+            vec![
+                "src/gemini.rs",
+                "main.rs",
+                "README.md",
+                "./scripts/build.sh"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_verdict() {
+        let reply = "Here you go:\n```json\n{\"verdict\": \"partial\", \"confidence\": 0.7, \"issues\": [\"no `retry` function in the sources\"]}\n```";
+        let verdict = parse_verdict(reply).unwrap();
+        assert_eq!(verdict.verdict, Verdict::Partial);
+        assert_eq!(verdict.confidence, Some(0.7));
+        assert_eq!(verdict.issues.len(), 1);
+        assert!(parse_verdict("looks fine to me").is_none());
+        assert!(parse_verdict("{\"verdict\": \"great\"}").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_without_sources_checks_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "").unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/server.rs"), "").unwrap();
+
+        let report = verify(&[], "Edit lib.rs, server.rs, then util.rs.", dir.path()).await;
+        assert_eq!(report.verdict, Verdict::Partial);
+        assert_eq!(report.missing_files, vec!["util.rs"]);
+        assert_eq!(report.model, None);
+
+        let report = verify(&[], "Edit lib.rs.", dir.path()).await;
+        assert_eq!(report.verdict, Verdict::Unverified);
+        assert!(report.missing_files.is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+//! Workflows: multi-step Gemini runs defined in YAML files and executed by
+//! the `gemini_run_workflow` tool.
+//!
+//! A workflow file `<name>.yaml` in the workflows directory lists steps that
+//! run one after another, by default in the same session. Step prompts can
+//! use the workflow's `{{variables}}` and the answers of earlier steps
+//! (`{{steps.<step>}}`), attach workspace files, and skip or end the run
+//! with the same conditions as `TURNS`:
+//!
+//! ```yaml
+//! description: Review a module, then fix what the review found
+//! variables:
+//!   module: { description: File to review, required: true }
+//! steps:
+//!   - name: review
+//!     prompt: "Review {{module}}. Reply with JSON {\"issues\": <count>} at the end."
+//!     attachments: ["{{module}}"]
+//!   - name: fix
+//!     stop_if: { json_path: "$.issues", equals: 0 }
+//!     prompt: "Fix the issues you found:\n{{steps.review}}"
+//! ```
+
+use crate::condition::{self, Condition};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Most steps in one workflow
+pub const MAX_STEPS: usize = 20;
+const MAX_NAME_LEN: usize = 64;
+/// Prefix of placeholders that insert an earlier step's answer
+const STEP_PREFIX: &str = "steps.";
+
+/// One workflow file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workflow {
+    #[serde(default)]
+    pub description: String,
+    /// Values supplied by the caller, by name
+    #[serde(default)]
+    pub variables: BTreeMap<String, Variable>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Variable {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    /// Value used when the caller gives none
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub name: String,
+    pub prompt: String,
+    /// Workspace files appended to the prompt; may use placeholders
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// Run the step only if the previous answer matches
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// End the workflow before this step if the previous answer matches
+    #[serde(default)]
+    pub stop_if: Option<Condition>,
+    #[serde(default)]
+    pub session: StepSession,
+    /// Model of this step (passed as `--model`)
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Which session a step runs in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepSession {
+    /// Resume the session of the previous executed step
+    #[default]
+    Continue,
+    /// Start a new session
+    New,
+}
+
+/// A step with its conditions compiled
+#[derive(Debug)]
+pub struct CompiledStep<'a> {
+    pub step: &'a Step,
+    pub when: Option<condition::Compiled>,
+    pub stop_if: Option<condition::Compiled>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Path of workflow `name` in `dir`, `<name>.yaml` or `<name>.yml`
+fn file(dir: &Path, name: &str) -> Option<PathBuf> {
+    ["yaml", "yml"]
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .find(|path| path.is_file())
+}
+
+/// Names of the workflows in `dir`, sorted
+pub fn names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let ext = path.extension()?.to_str()?;
+            if ext != "yaml" && ext != "yml" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?;
+            valid_name(name).then(|| name.to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Read and validate workflow `name` from `dir`
+pub fn load(dir: &Path, name: &str) -> Result<Workflow> {
+    let name = name.trim();
+    let path = valid_name(name).then(|| file(dir, name)).flatten();
+    let Some(path) = path else {
+        let available = names(dir);
+        if available.is_empty() {
+            bail!(
+                "Unknown workflow `{}`; {} has no workflows",
+                name,
+                dir.display()
+            );
+        }
+        bail!(
+            "Unknown workflow `{}`; available: {}",
+            name,
+            available.join(", ")
+        );
+    };
+    let raw = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let workflow: Workflow = serde_yaml::from_str(&raw)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    workflow
+        .compile()
+        .with_context(|| format!("Invalid workflow {}", path.display()))?;
+    Ok(workflow)
+}
+
+impl Workflow {
+    /// Validate names, prompts, conditions and placeholders, and compile the
+    /// steps' conditions
+    pub fn compile(&self) -> Result<Vec<CompiledStep<'_>>> {
+        if self.steps.is_empty() || self.steps.len() > MAX_STEPS {
+            bail!("a workflow needs 1-{} steps", MAX_STEPS);
+        }
+        if let Some(name) = self.variables.keys().find(|name| !valid_name(name)) {
+            bail!("invalid variable name `{}`", name);
+        }
+        let mut compiled = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            if !valid_name(&step.name) {
+                bail!(
+                    "step name `{}` must be 1-{} characters of letters, digits, `-`, `_` or `.`",
+                    step.name,
+                    MAX_NAME_LEN
+                );
+            }
+            let earlier = &self.steps[..i];
+            if earlier.iter().any(|s| s.name == step.name) {
+                bail!("step `{}` is declared twice", step.name);
+            }
+            if step.prompt.trim().is_empty() {
+                bail!("step `{}` has an empty prompt", step.name);
+            }
+            for text in std::iter::once(&step.prompt).chain(&step.attachments) {
+                for placeholder in placeholders(text) {
+                    let known = match placeholder.strip_prefix(STEP_PREFIX) {
+                        Some(step_name) => earlier.iter().any(|s| s.name == step_name),
+                        None => self.variables.contains_key(placeholder),
+                    };
+                    if !known {
+                        bail!(
+                            "step `{}` uses `{{{{{}}}}}`, which is neither a variable nor an earlier step",
+                            step.name,
+                            placeholder
+                        );
+                    }
+                }
+            }
+            let compile = |condition: &Option<Condition>, what: &str| {
+                condition
+                    .as_ref()
+                    .map(Condition::compile)
+                    .transpose()
+                    .with_context(|| format!("step `{}`: invalid `{}`", step.name, what))
+            };
+            compiled.push(CompiledStep {
+                step,
+                when: compile(&step.when, "when")?,
+                stop_if: compile(&step.stop_if, "stop_if")?,
+            });
+        }
+        Ok(compiled)
+    }
+
+    /// The caller's `values` completed with defaults; unknown and missing
+    /// required variables are errors
+    pub fn bind(&self, values: BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+        if let Some(unknown) = values
+            .keys()
+            .find(|name| !self.variables.contains_key(*name))
+        {
+            bail!("Unknown workflow variable `{}`", unknown);
+        }
+        let mut bound = BTreeMap::new();
+        for (name, variable) in &self.variables {
+            let value = values
+                .get(name)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .or_else(|| variable.default.clone());
+            match value {
+                Some(value) => {
+                    bound.insert(name.clone(), value);
+                }
+                None if variable.required => {
+                    bail!("Missing required workflow variable `{}`", name)
+                }
+                None => {
+                    bound.insert(name.clone(), String::new());
+                }
+            }
+        }
+        Ok(bound)
+    }
+}
+
+/// Names inside `{{…}}` in `text`, in order
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        names.push(rest[start + 2..start + 2 + len].trim());
+        rest = &rest[start + 2 + len + 2..];
+    }
+    names
+}
+
+/// `text` with variables and the answers of earlier steps filled in.
+/// Placeholders of steps that were skipped become empty.
+pub fn render(
+    text: &str,
+    variables: &BTreeMap<String, String>,
+    answers: &BTreeMap<String, String>,
+) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + len].trim();
+        let value = match name.strip_prefix(STEP_PREFIX) {
+            Some(step) => answers.get(step),
+            None => variables.get(name),
+        };
+        rendered.push_str(value.map(String::as_str).unwrap_or(""));
+        rest = &rest[start + 2 + len + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REVIEW: &str = r#"
+description: Review, then fix
+variables:
+  module: { required: true }
+  focus: { default: "correctness" }
+steps:
+  - name: review
+    prompt: "Review {{module}} for {{ focus }}."
+    attachments: ["{{module}}"]
+  - name: fix
+    stop_if: { json_path: "$.issues", equals: 0 }
+    prompt: "Fix these issues:\n{{steps.review}}"
+    session: continue
+  - name: notes
+    when: { regex: "(?i)fixed" }
+    session: new
+    model: gemini-2.5-flash
+    prompt: "Write release notes for: {{steps.fix}}"
+"#;
+
+    #[test]
+    fn test_workflow_compiles_and_renders() {
+        let workflow: Workflow = serde_yaml::from_str(REVIEW).unwrap();
+        let steps = workflow.compile().unwrap();
+        assert_eq!(steps.len(), 3);
+        assert!(steps[1]
+            .stop_if
+            .as_ref()
+            .unwrap()
+            .matches("{\"issues\": 0}"));
+        assert!(steps[2].when.as_ref().unwrap().matches("Fixed two bugs"));
+        assert_eq!(steps[2].step.session, StepSession::New);
+
+        let variables = workflow
+            .bind(BTreeMap::from([(
+                "module".to_string(),
+                "src/a.rs".to_string(),
+            )]))
+            .unwrap();
+        assert_eq!(variables["focus"], "correctness");
+        let mut answers = BTreeMap::new();
+        assert_eq!(
+            render(&workflow.steps[0].prompt, &variables, &answers),
+            "Review src/a.rs for correctness."
+        );
+        answers.insert("review".to_string(), "Two bugs.".to_string());
+        assert_eq!(
+            render(&workflow.steps[1].prompt, &variables, &answers),
+            "Fix these issues:\nTwo bugs."
+        );
+
+        assert!(workflow.bind(BTreeMap::new()).is_err());
+        assert!(workflow
+            .bind(BTreeMap::from([("other".to_string(), "x".to_string())]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_workflows_are_rejected() {
+        let compile = |yaml: &str| {
+            serde_yaml::from_str::<Workflow>(yaml)
+                .map_err(anyhow::Error::from)
+                .and_then(|w| w.compile().map(|_| ()))
+        };
+        assert!(compile("steps: []").is_err());
+        assert!(compile("steps: [{name: a, prompt: '{{later}}'}]").is_err());
+        assert!(
+            compile("steps: [{name: a, prompt: '{{steps.b}}'}, {name: b, prompt: x}]").is_err()
+        );
+        assert!(compile("steps: [{name: a, prompt: x}, {name: a, prompt: y}]").is_err());
+        assert!(compile("steps: [{name: a, prompt: x, when: {regex: '('}}]").is_err());
+        assert!(compile("steps: [{name: a, prompt: x, retries: 2}]").is_err());
+        assert!(compile("steps: [{name: a, prompt: x}, {name: b, prompt: '{{steps.a}}'}]").is_ok());
+    }
+
+    #[test]
+    fn test_load_from_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("review.yaml"), REVIEW).unwrap();
+        std::fs::write(dir.path().join("broken.yml"), "steps: [").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not a workflow").unwrap();
+
+        assert_eq!(names(dir.path()), vec!["broken", "review"]);
+        assert_eq!(load(dir.path(), "review").unwrap().steps.len(), 3);
+        assert!(load(dir.path(), "broken").is_err());
+        let err = load(dir.path(), "../review").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown workflow `../review`; available: broken, review"
+        );
+    }
+}
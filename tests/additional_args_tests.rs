@@ -5,7 +5,8 @@ use gemini_mcp_rs::gemini;
 use gemini_mcp_rs::gemini::Options;
 use std::env;
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
+
+mod common;
 
 #[tokio::test]
 async fn test_additional_args_are_passed_to_gemini_cli() {
@@ -15,8 +16,10 @@ async fn test_additional_args_are_passed_to_gemini_cli() {
     let log_path = temp_path.join("gemini_args.log");
 
     // Create a helper script that logs argv and emits a minimal JSON event
-    let script_path = temp_path.join("echo_args.sh");
-    let script_contents = r#"#!/bin/sh
+    let script_path = common::write_fake_gemini(
+        &temp_path,
+        "echo_args",
+        r#"#!/bin/sh
 LOG_FILE="${GEMINI_ARGS_LOG}"
 : > "$LOG_FILE"
 printf "%s" "$0" > "$LOG_FILE"
@@ -24,14 +27,12 @@ for arg in "$@"; do
   printf " %s" "$arg" >> "$LOG_FILE"
 done
 echo '{"session_id":"test-session","type":"message","role":"assistant","content":"ok"}'
-"#;
-
-    fs::write(&script_path, script_contents).expect("Failed to write script");
-    let mut perms = fs::metadata(&script_path)
-        .expect("Failed to get metadata")
-        .permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&script_path, perms).expect("Failed to set permissions");
+"#,
+        r#"@echo off
+echo %0 %*> "%GEMINI_ARGS_LOG%"
+echo {"session_id":"test-session","type":"message","role":"assistant","content":"ok"}
+"#,
+    );
 
     env::set_var("GEMINI_BIN", script_path.to_str().unwrap());
 
@@ -40,11 +41,11 @@ echo '{"session_id":"test-session","type":"message","role":"assistant","content"
 
     let additional = vec!["--model".to_string(), "gemini-3-pro-preview".to_string()];
 
-    let opts = Options {
-        prompt: "test additional args".to_string(),
-        session_id: None,
-        additional_args: additional.clone(),
-    };
+    let opts = Options::builder()
+        .prompt("test additional args")
+        .additional_args(additional.clone())
+        .build()
+        .expect("options should be valid");
 
     let result = gemini::run(opts).await.expect("run should return Ok");
 
@@ -0,0 +1,246 @@
+// Tests of the embeddable GeminiClient: binary, default args, env and cwd
+// are applied without any MCP server involved, events can be streamed and
+// runs cancelled or timed out with their partial output.
+
+use gemini_mcp_rs::event::{GeminiEvent, Role};
+use gemini_mcp_rs::gemini::{Cancelled, GeminiClient, Options};
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+mod common;
+
+#[tokio::test]
+async fn test_client_applies_binary_args_env_and_cwd() {
+    let dir = common::get_temp_dir().join(format!("gemini_client_{}", std::process::id()));
+    let workdir = dir.join("work");
+    fs::create_dir_all(&workdir).expect("Failed to create work dir");
+    fs::write(workdir.join("GEMINI.md"), "Answer in French.").unwrap();
+    let log_path = dir.join("args.log");
+
+    let script_path = common::write_fake_gemini(
+        &dir,
+        "client_cli",
+        r#"#!/bin/sh
+pwd > "$CLIENT_LOG"
+for arg in "$@"; do
+  printf "%s\n" "$arg" >> "$CLIENT_LOG"
+done
+echo "{\"session_id\":\"client-session\",\"type\":\"message\",\"role\":\"assistant\",\"content\":\"$CLIENT_GREETING\"}"
+"#,
+        r#"@echo off
+cd > "%CLIENT_LOG%"
+echo %*>> "%CLIENT_LOG%"
+echo {"session_id":"client-session","type":"message","role":"assistant","content":"%CLIENT_GREETING%"}
+"#,
+    );
+
+    let client = GeminiClient::builder()
+        .binary(&script_path)
+        .default_args(["--model", "gemini-2.5-flash"])
+        .timeout(Duration::from_secs(30))
+        .env("CLIENT_LOG", log_path.to_str().unwrap())
+        .env("CLIENT_GREETING", "bonjour")
+        .cwd(&workdir)
+        .build()
+        .expect("client should be valid");
+
+    let opts = Options::builder()
+        .prompt("Say hello")
+        .no_cache(true)
+        .build()
+        .expect("options should be valid");
+    let result = client.run(opts).await.expect("run should return Ok");
+
+    assert!(
+        result.success,
+        "fake CLI should succeed: {:?}",
+        result.error
+    );
+    assert_eq!(result.session_id, "client-session");
+    assert_eq!(result.agent_messages.trim(), "bonjour");
+
+    let log = fs::read_to_string(&log_path).expect("failed to read args log");
+    let mut lines = log.lines();
+    let cwd = lines.next().unwrap_or_default().trim();
+    assert!(cwd.ends_with("work"), "unexpected cwd: {}", cwd);
+    assert!(log.contains("--model"));
+    assert!(log.contains("gemini-2.5-flash"));
+    // GEMINI.md is discovered from the client's working directory
+    assert!(log.contains("Answer in French."));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_client_builder_validation() {
+    assert!(GeminiClient::builder()
+        .timeout(Duration::ZERO)
+        .build()
+        .is_err());
+    assert!(GeminiClient::builder()
+        .cwd("/definitely/not/a/real/dir")
+        .build()
+        .is_err());
+    assert!(GeminiClient::builder()
+        .env("BAD=NAME", "x")
+        .build()
+        .is_err());
+    assert!(GeminiClient::builder().build().is_ok());
+}
+
+#[tokio::test]
+async fn test_client_streams_events() {
+    let dir = common::get_temp_dir().join(format!("gemini_stream_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    let script_path = common::write_fake_gemini(
+        &dir,
+        "stream_cli",
+        r#"#!/bin/sh
+echo '{"type":"init","session_id":"stream-session","model":"gemini-2.5-pro"}'
+echo '{"type":"message","role":"assistant","content":"Hello","delta":true}'
+echo '{"type":"message","role":"assistant","content":" world","delta":true}'
+echo '{"type":"result","status":"success"}'
+if [ "$STREAM_FAIL" = "1" ]; then exit 3; fi
+"#,
+        r#"@echo off
+echo {"type":"init","session_id":"stream-session","model":"gemini-2.5-pro"}
+echo {"type":"message","role":"assistant","content":"Hello","delta":true}
+echo {"type":"message","role":"assistant","content":" world","delta":true}
+echo {"type":"result","status":"success"}
+if "%STREAM_FAIL%"=="1" exit /b 3
+"#,
+    );
+    let opts = || {
+        Options::builder()
+            .prompt("Say hello")
+            .build()
+            .expect("options should be valid")
+    };
+
+    let client = GeminiClient::builder()
+        .binary(&script_path)
+        .build()
+        .unwrap();
+    let events: Vec<GeminiEvent> = client.stream(opts()).collect().await;
+    assert_eq!(events.len(), 4, "unexpected events: {:?}", events);
+    assert!(matches!(events[0], GeminiEvent::Init(_)));
+    let text: String = events
+        .iter()
+        .filter_map(|event| match event {
+            GeminiEvent::Message(message) if message.role == Role::Assistant => {
+                Some(message.content.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(text, "Hello world");
+    assert!(matches!(events[3], GeminiEvent::Result(_)));
+
+    // A failed exit ends the stream with an error event
+    let failing = GeminiClient::builder()
+        .binary(&script_path)
+        .env("STREAM_FAIL", "1")
+        .build()
+        .unwrap();
+    let events: Vec<GeminiEvent> = failing.stream(opts()).collect().await;
+    let Some(GeminiEvent::Error(error)) = events.last() else {
+        panic!("expected a final error event: {:?}", events);
+    };
+    assert!(error.message.as_deref().unwrap().contains("exit code"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_cancelled_run_returns_partial_output() {
+    let dir = common::get_temp_dir().join(format!("gemini_cancel_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    let script_path = common::write_fake_gemini(
+        &dir,
+        "cancel_cli",
+        r#"#!/bin/sh
+echo '{"type":"init","session_id":"cancel-session"}'
+echo '{"type":"message","role":"assistant","content":"Halfway there"}'
+sleep 30
+"#,
+        r#"@echo off
+echo {"type":"init","session_id":"cancel-session"}
+echo {"type":"message","role":"assistant","content":"Halfway there"}
+ping -n 30 127.0.0.1 >nul
+"#,
+    );
+    let client = GeminiClient::builder()
+        .binary(&script_path)
+        .build()
+        .unwrap();
+    let token = CancellationToken::new();
+    let opts = Options::builder()
+        .prompt("Take your time")
+        .cancellation(token.clone())
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    let cancel = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        token.cancel();
+    });
+    let err = client.run(opts).await.expect_err("run should be cancelled");
+    cancel.await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let cancelled = err
+        .downcast_ref::<Cancelled>()
+        .expect("error should be Cancelled");
+    assert!(cancelled.pid.is_some());
+    assert!(!cancelled.partial.success);
+    assert!(cancelled.partial.partial);
+    assert_eq!(cancelled.partial.session_id, "cancel-session");
+    assert_eq!(cancelled.partial.agent_messages, "Halfway there");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_timed_out_run_returns_partial_result() {
+    let dir = common::get_temp_dir().join(format!("gemini_timeout_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    let script_path = common::write_fake_gemini(
+        &dir,
+        "slow_cli",
+        r#"#!/bin/sh
+echo '{"type":"init","session_id":"slow-session"}'
+echo '{"type":"message","role":"assistant","content":"First part"}'
+sleep 30
+"#,
+        r#"@echo off
+echo {"type":"init","session_id":"slow-session"}
+echo {"type":"message","role":"assistant","content":"First part"}
+ping -n 30 127.0.0.1 >nul
+"#,
+    );
+    let client = GeminiClient::builder()
+        .binary(&script_path)
+        .timeout(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let opts = Options::builder()
+        .prompt("Take your time")
+        .no_cache(true)
+        .build()
+        .unwrap();
+
+    let result = client
+        .run(opts)
+        .await
+        .expect("timeout should not be an error");
+    assert!(!result.success);
+    assert!(result.partial);
+    assert_eq!(result.session_id, "slow-session");
+    assert_eq!(result.agent_messages, "First part");
+    assert!(result.error.unwrap().contains("timed out after 1 seconds"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,4 +1,7 @@
 // Common test utilities and helpers
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
 
 /// Get a temporary directory for testing
 pub fn get_temp_dir() -> std::path::PathBuf {
@@ -7,10 +10,32 @@ pub fn get_temp_dir() -> std::path::PathBuf {
 
 /// Create a test options with default values
 pub fn create_test_options(prompt: &str) -> gemini_mcp_rs::gemini::Options {
-    gemini_mcp_rs::gemini::Options {
-        prompt: prompt.to_string(),
-        session_id: None,
-        additional_args: Vec::new(),
+    gemini_mcp_rs::gemini::Options::builder()
+        .prompt(prompt)
+        .build()
+        .expect("test options should be valid")
+}
+
+/// Write a fake Gemini CLI into `dir`: `sh` as an executable shell script on
+/// Unix, `cmd` as a batch file on Windows. Returns the path for GEMINI_BIN.
+pub fn write_fake_gemini(dir: &Path, name: &str, sh: &str, cmd: &str) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _ = cmd;
+        let path = dir.join(format!("{}.sh", name));
+        std::fs::write(&path, sh).expect("Failed to write script");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("Failed to set permissions");
+        path
+    }
+    #[cfg(windows)]
+    {
+        let _ = sh;
+        let path = dir.join(format!("{}.cmd", name));
+        std::fs::write(&path, cmd.replace('\n', "\r\n")).expect("Failed to write script");
+        path
     }
 }
 
@@ -20,7 +45,7 @@ pub fn generate_mock_session_id() -> String {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs();
+        .as_nanos();
     format!("test-session-{}", timestamp)
 }
 
@@ -52,4 +77,3 @@ mod tests {
         assert_ne!(id1, id2);
     }
 }
-
@@ -7,14 +7,18 @@ mod tests {
 
     #[test]
     fn test_options_validation() {
-        let opts = Options {
-            prompt: "test".to_string(),
-            session_id: Some("session-123".to_string()),
-            additional_args: vec!["--model".to_string(), "gemini-pro".to_string()],
-        };
+        let opts = Options::builder()
+            .prompt("test")
+            .session("89473362-3f12-46e8-adce-05388980dcca")
+            .additional_args(["--model", "gemini-pro"])
+            .build()
+            .expect("options should be valid");
 
         assert_eq!(opts.prompt, "test");
-        assert_eq!(opts.session_id, Some("session-123".to_string()));
+        assert_eq!(
+            opts.session_id,
+            Some("89473362-3f12-46e8-adce-05388980dcca".to_string())
+        );
         assert_eq!(opts.additional_args.len(), 2);
     }
 }
@@ -1,3 +1,5 @@
+#![cfg(feature = "server")]
+
 use gemini_mcp_rs::server::GeminiServer;
 use rmcp::{model::*, ServerHandler};
 
@@ -0,0 +1,60 @@
+// Tests verifying that prompts can be delivered through the CLI's stdin
+// instead of its argument list.
+
+use gemini_mcp_rs::gemini;
+use gemini_mcp_rs::gemini::{Options, PromptDelivery};
+use std::env;
+use std::fs;
+
+mod common;
+
+#[tokio::test]
+async fn test_prompt_is_piped_through_stdin() {
+    let temp_dir = tempfile::TempDir::new().expect("Failed to create temp dir");
+    let args_log = temp_dir.path().join("args.log");
+    let stdin_log = temp_dir.path().join("stdin.log");
+
+    // Helper script that records argv and stdin, then emits minimal events
+    let script_path = common::write_fake_gemini(
+        temp_dir.path(),
+        "read_stdin",
+        r#"#!/bin/sh
+printf "%s" "$*" > "$GEMINI_ARGS_LOG"
+cat > "$GEMINI_STDIN_LOG"
+echo '{"type":"init","session_id":"stdin-session","model":"m"}'
+echo '{"type":"message","role":"assistant","content":"ok"}'
+"#,
+        r#"@echo off
+echo %*> "%GEMINI_ARGS_LOG%"
+findstr "^" > "%GEMINI_STDIN_LOG%"
+echo {"type":"init","session_id":"stdin-session","model":"m"}
+echo {"type":"message","role":"assistant","content":"ok"}
+"#,
+    );
+
+    env::set_var("GEMINI_BIN", script_path.to_str().unwrap());
+    env::set_var("GEMINI_ARGS_LOG", args_log.to_str().unwrap());
+    env::set_var("GEMINI_STDIN_LOG", stdin_log.to_str().unwrap());
+
+    let prompt = "summarize the attached log\n".repeat(5_000);
+    let opts = Options::builder()
+        .prompt(prompt.clone())
+        .prompt_delivery(PromptDelivery::Stdin)
+        .build()
+        .expect("options should be valid");
+
+    let result = gemini::run(opts).await.expect("run should return Ok");
+
+    assert!(result.success, "helper script should succeed");
+    assert_eq!(result.session_id, "stdin-session");
+
+    let args = fs::read_to_string(&args_log).expect("failed to read args log");
+    assert_eq!(args.trim_end(), "-o stream-json");
+    // GEMINI.md content may be prepended, the prompt itself comes last
+    let stdin = fs::read_to_string(&stdin_log)
+        .expect("failed to read stdin log")
+        .replace("\r\n", "\n");
+    assert!(stdin.ends_with(&prompt));
+
+    env::remove_var("GEMINI_BIN");
+}